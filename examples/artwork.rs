@@ -0,0 +1,39 @@
+// Show where the player finds a track's album art (embedded picture or folder image) and make its thumbnail the
+// way the now-playing panel does, caching it in `--cache` (default `covers/thumbs`).
+//
+//   cargo run --example artwork -- <audio-file>... [--cache DIR]
+
+use std::path::PathBuf;
+
+use rust_audio_player_android::{find_artwork, thumbnail, ArtSource, THUMB_SIZE};
+
+fn run() -> Result<(), String> {
+    let mut args = std::env::args().skip(1);
+    let (mut files, mut cache) = (Vec::new(), PathBuf::from("covers").join("thumbs"));
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--cache" => cache = PathBuf::from(args.next().ok_or("--cache needs a value")?),
+            _ => files.push(PathBuf::from(arg)),
+        }
+    }
+    if files.is_empty() { return Err("Usage: artwork <audio-file>... [--cache DIR]".into()); }
+    for file in files {
+        match find_artwork(&file) {
+            Some(ArtSource::Embedded(data)) => println!("{}: embedded picture, {} bytes", file.display(), data.len()),
+            Some(ArtSource::Folder(image)) => println!("{}: folder image {}", file.display(), image.display()),
+            None => { println!("{}: no art", file.display()); continue; }
+        }
+        let started = std::time::Instant::now();
+        if let Some(thumb) = thumbnail(&file, &cache)? {
+            println!("  thumbnail (up to {THUMB_SIZE} px) {} in {} ms", thumb.display(), started.elapsed().as_millis());
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
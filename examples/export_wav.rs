@@ -0,0 +1,57 @@
+// Offline export: render a track through the player's DSP chain (EQ etc.) into a WAV file.
+//
+//   cargo run --example export_wav -- <input> <output.wav> [--format 16|24|32f] [--start SECS] [--end SECS] [--eq G1,..,G10]
+//                                            [--rate HZ] [--quality linear|sinc-medium|sinc-best] [--max-pause SECS]
+//                                            [--soundfont FILE.sf2]
+//
+// Without --eq the EQ curve saved by the player for the preferred output device is used.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use rust_audio_player_android::{export_wav, DspSettings, ExportOptions};
+
+fn parse_secs(v: Option<String>, flag: &str) -> Result<Duration, String> {
+    let v = v.ok_or(format!("{flag} needs a value"))?;
+    let secs: f64 = v.parse().map_err(|_| format!("Invalid number for {flag}: {v}"))?;
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("Invalid time for {flag}: {v}"))
+}
+
+fn run() -> Result<(), String> {
+    let mut args = std::env::args().skip(1);
+    let mut positional = Vec::new();
+    let mut options = ExportOptions { dsp: DspSettings::from_saved_settings(), ..Default::default() };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => options.format = args.next().ok_or("--format needs a value")?.parse()?,
+            "--start" => options.start = Some(parse_secs(args.next(), "--start")?),
+            "--end" => options.end = Some(parse_secs(args.next(), "--end")?),
+            "--rate" => {
+                let v = args.next().ok_or("--rate needs a value")?;
+                options.sample_rate = Some(v.parse().map_err(|_| format!("Invalid sample rate: {v}"))?);
+            }
+            "--max-pause" => options.dsp.max_pause = Some(parse_secs(args.next(), "--max-pause")?),
+            "--soundfont" => options.soundfont = Some(PathBuf::from(args.next().ok_or("--soundfont needs a value")?)),
+            "--quality" => options.dsp.resample_quality = args.next().ok_or("--quality needs a value")?.parse()?,
+            "--eq" => {
+                let v = args.next().ok_or("--eq needs a value")?;
+                let gains: Vec<f32> = v.split(',').map(|g| g.trim().parse::<f32>()).collect::<Result<_, _>>()
+                    .map_err(|_| format!("Invalid EQ gains: {v}"))?;
+                options.dsp.eq_gains = gains.try_into().map_err(|_| "--eq expects 10 comma-separated gains in dB".to_string())?;
+            }
+            _ => positional.push(PathBuf::from(arg)),
+        }
+    }
+    let [input, output] = <[PathBuf; 2]>::try_from(positional)
+        .map_err(|_| "Usage: export_wav <input> <output.wav> [--format 16|24|32f] [--start SECS] [--end SECS] [--eq G1,..,G10]".to_string())?;
+    let frames = export_wav(&input, &output, &options)?;
+    println!("Wrote {} ({frames} frames)", output.display());
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,84 @@
+// Print the lyrics the player finds for a file (sidecar `.lrc`, ID3 SYLT/USLT, Vorbis/MP4 lyrics tags), or an
+// `.lrc` file itself, with line and word times. `--at` shows what is highlighted at a position, the sung part in
+// brackets; `--offset` shifts the timing like the lyrics pane's buttons.
+//
+// `--stamp` syncs the lyrics the way the editor does, as if Stamp were tapped at each of the given times;
+// `--nudge LINE:MS` then moves a line, and `--save` or `--embed` writes the result as `.lrc` or into the MP3's tag.
+//
+//   cargo run --example lyrics -- <audio-or-lrc-file> [--at SECONDS]... [--offset MS]
+//   cargo run --example lyrics -- <audio-file> --stamp SECONDS,SECONDS,... [--nudge LINE:MS]... [--save | --embed]
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use rust_audio_player_android::{embed_lyrics, parse_lrc, read_lyrics, save_sidecar, LrcEditor};
+
+fn clock(t: Duration) -> String { format!("{:02}:{:02}.{:02}", t.as_secs() / 60, t.as_secs() % 60, t.subsec_millis() / 10) }
+
+fn run() -> Result<(), String> {
+    let mut args = std::env::args().skip(1);
+    let (mut path, mut at, mut offset) = (None, Vec::new(), 0i64);
+    let (mut stamps, mut nudges, mut save, mut embed) = (None, Vec::new(), false, false);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--at" => at.push(args.next().ok_or("--at needs a value")?.parse::<f64>().map_err(|_| "Invalid number for --at")?),
+            "--offset" => offset = args.next().ok_or("--offset needs a value")?.parse().map_err(|_| "Invalid number for --offset")?,
+            "--stamp" => stamps = Some(args.next().ok_or("--stamp needs a value")?.split(',')
+                .map(|t| t.trim().parse::<f64>().ok().and_then(|s| Duration::try_from_secs_f64(s).ok()))
+                .collect::<Option<Vec<_>>>().ok_or("Invalid time in --stamp")?),
+            "--nudge" => {
+                let value = args.next().ok_or("--nudge needs a value")?;
+                let (line, ms) = value.split_once(':').ok_or("--nudge takes LINE:MS")?;
+                nudges.push((line.parse::<usize>().map_err(|_| "Invalid line in --nudge")?, ms.trim_start_matches('+').parse::<i64>().map_err(|_| "Invalid number in --nudge")?));
+            }
+            "--save" => save = true,
+            "--embed" => embed = true,
+            _ => path = Some(PathBuf::from(arg)),
+        }
+    }
+    let path = path.ok_or("Usage: lyrics <audio-or-lrc-file> [--at SECONDS]... [--offset MS] [--stamp SECONDS,... [--nudge LINE:MS]... [--save | --embed]]")?;
+    let lyrics = if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("lrc")) {
+        Some(parse_lrc(&std::fs::read_to_string(&path).map_err(|e| format!("{}: {e}", path.display()))?))
+    } else {
+        read_lyrics(&path)
+    };
+    let mut lyrics = lyrics.filter(|l| !l.lines.is_empty()).ok_or("No lyrics found")?;
+    if let Some(stamps) = stamps {
+        let mut editor = LrcEditor::new(&path, &lyrics);
+        for t in stamps { editor.stamp(t); }
+        for (line, ms) in nudges {
+            editor.select(line.saturating_sub(1));
+            editor.nudge(ms);
+        }
+        lyrics = if save || embed { editor.finish()? } else { editor.preview() };
+        if save { println!("Saved {}", save_sidecar(&path, &lyrics)?.display()); }
+        if embed { embed_lyrics(&path, &lyrics)?; println!("Embedded in {}", path.display()); }
+    }
+    if at.is_empty() {
+        println!("{} line(s), {}, offset {} ms", lyrics.lines.len(), if lyrics.is_synced() { "synced" } else { "unsynced" }, lyrics.offset_ms);
+        for line in &lyrics.lines {
+            let start = line.start.map(clock).unwrap_or_else(|| "--:--.--".into());
+            let words = line.words.iter().map(|(t, i)| format!("{}@{}", clock(*t), i)).collect::<Vec<_>>().join(" ");
+            if words.is_empty() { println!("[{start}] {}", line.text); } else { println!("[{start}] {}  <{words}>", line.text); }
+        }
+    }
+    for secs in at {
+        let position = Duration::try_from_secs_f64(secs).map_err(|_| "Invalid --at")?;
+        match lyrics.current(position, offset) {
+            Some((index, sung)) => {
+                let text = &lyrics.lines[index].text;
+                let seek = lyrics.line_position(index, offset).map(clock).unwrap_or_default();
+                println!("{}: line {} [{}]{} (starts at {seek})", clock(position), index + 1, &text[..sung], &text[sung..]);
+            }
+            None => println!("{}: before the first line", clock(position)),
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
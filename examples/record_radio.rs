@@ -0,0 +1,40 @@
+// Record-only mode: save an internet radio or HLS stream to disk without playing it, one tagged file per song.
+//
+//   cargo run --example record_radio -- <url> [--minutes 60] [--dir music/Recordings]
+//
+// Only MP3 and AAC streams can be recorded (they are saved as they arrive, without re-encoding).
+
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+use rust_audio_player_android::record_stream;
+
+fn run() -> Result<(), String> {
+    let mut args = std::env::args().skip(1);
+    let (mut url, mut minutes, mut dir) = (None, 60.0f64, PathBuf::from("music/Recordings"));
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--minutes" => {
+                let v = args.next().ok_or("--minutes needs a value")?;
+                minutes = v.parse().ok().filter(|m: &f64| *m > 0.0).ok_or(format!("Invalid number for --minutes: {v}"))?;
+            }
+            "--dir" => dir = PathBuf::from(args.next().ok_or("--dir needs a value")?),
+            _ if url.is_none() => url = Some(arg),
+            _ => return Err(format!("Unexpected argument: {arg}")),
+        }
+    }
+    let url = url.ok_or("Usage: record_radio <url> [--minutes 60] [--dir music/Recordings]")?;
+    let duration = Duration::try_from_secs_f64(minutes * 60.0).map_err(|_| format!("Invalid number for --minutes: {minutes}"))?;
+    println!("Recording {url} to {} for {minutes} min", dir.display());
+    let files = record_stream(&url, &dir, duration, &AtomicBool::new(false))?;
+    for file in &files { println!("Wrote {}", file.display()); }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
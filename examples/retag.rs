@@ -0,0 +1,52 @@
+// Change the tags of files the way the tag editor does, then print what the library reads back. Fields not given
+// are kept, and an empty value removes a field. `--cover` embeds an image as the front cover; `--no-cover` removes
+// the pictures.
+//
+//   cargo run --example retag -- <audio-file>... [--title T] [--artist A] [--album A] [--album-artist A]
+//       [--track N[/TOTAL]] [--disc N[/TOTAL]] [--year Y] [--genre G] [--composer C] [--cover IMAGE | --no-cover]
+
+use std::path::PathBuf;
+
+use rust_audio_player_android::{read_track_info, write_tags, CoverEdit, TagEdit, TagField};
+
+fn run() -> Result<(), String> {
+    let mut args = std::env::args().skip(1);
+    let (mut files, mut edit) = (Vec::new(), TagEdit::default());
+    while let Some(arg) = args.next() {
+        let field = match arg.as_str() {
+            "--title" => TagField::Title,
+            "--artist" => TagField::Artist,
+            "--album" => TagField::Album,
+            "--album-artist" => TagField::AlbumArtist,
+            "--track" => TagField::Track,
+            "--disc" => TagField::Disc,
+            "--year" => TagField::Year,
+            "--genre" => TagField::Genre,
+            "--composer" => TagField::Composer,
+            "--cover" => {
+                let image = args.next().ok_or("--cover needs a value")?;
+                edit.cover = CoverEdit::Set(std::fs::read(&image).map_err(|e| format!("{image}: {e}"))?);
+                continue;
+            }
+            "--no-cover" => { edit.cover = CoverEdit::Remove; continue; }
+            _ => { files.push(PathBuf::from(arg)); continue; }
+        };
+        edit.set(field, &args.next().ok_or_else(|| format!("{arg} needs a value"))?);
+    }
+    if files.is_empty() { return Err("Usage: retag <audio-file>... [--title T] [--artist A] ... [--cover IMAGE | --no-cover]".into()); }
+    for file in files {
+        if !edit.is_empty() { write_tags(&file, &edit)?; }
+        let info = read_track_info(&file);
+        println!("{}", file.display());
+        for field in TagField::ALL { println!("  {:<13} {}", format!("{}:", field.label()), info.field(field)); }
+        println!("  {:<13} {}", "Format:", info.format_text());
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
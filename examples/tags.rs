@@ -0,0 +1,31 @@
+// Print the tags and stream properties the library reads for each file, as shown in its rows.
+//
+//   cargo run --example tags -- <audio-file>...
+
+use std::path::PathBuf;
+
+use rust_audio_player_android::read_track_info;
+
+fn main() {
+    let files: Vec<PathBuf> = std::env::args().skip(1).map(PathBuf::from).collect();
+    if files.is_empty() {
+        eprintln!("Usage: tags <audio-file>...");
+        std::process::exit(1);
+    }
+    for file in files {
+        let info = read_track_info(&file);
+        let number = |n: Option<u32>| n.map(|n| n.to_string()).unwrap_or_default();
+        println!("{}", file.display());
+        println!("  title:        {}", info.title);
+        println!("  artist:       {}", info.artist);
+        println!("  album:        {}", info.album);
+        println!("  album artist: {}", info.album_artist);
+        println!("  track/disc:   {} / {}", number(info.track), number(info.disc));
+        println!("  year:         {}", info.year.map(|y| y.to_string()).unwrap_or_default());
+        println!("  genre:        {}", info.genre);
+        println!("  composer:     {}", info.composer);
+        println!("  duration:     {}", info.duration.map(|d| format!("{:.2} s", d.as_secs_f64())).unwrap_or_default());
+        println!("  format:       {}", info.format_text());
+        println!("  row:          {} | {}", if info.title.is_empty() { "(file name)" } else { &info.title }, info.subtitle());
+    }
+}
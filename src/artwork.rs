@@ -0,0 +1,207 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::dsd::{is_dsd, read_dsd_tag};
+use crate::id3::read_id3;
+use crate::net::{hex, md5, COVER_CACHE_DIR};
+use crate::settings::config_dir;
+
+// Album art for the now-playing panel: the picture embedded in the file (ID3 APIC, FLAC PICTURE, MP4 `covr`; read
+// through Symphonia, or by hand for MP3 and DSF/DFF tags), else an image in the track's folder. Pictures are decoded
+// and downscaled off the UI thread, and the thumbnails are cached in `covers/thumbs` so art shows at once next time.
+
+/// Longest side of a thumbnail, in pixels.
+pub const THUMB_SIZE: u32 = 400;
+const THUMB_DIR: &str = "thumbs";
+// Folder images by preference; any supported extension and letter case
+const FOLDER_NAMES: [&str; 4] = ["cover", "folder", "front", "album"];
+const IMAGE_EXTS: [&str; 5] = ["jpg", "jpeg", "png", "gif", "webp"];
+
+/// Where a track's art comes from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ArtSource {
+    /// Picture data from the file's tags.
+    Embedded(Vec<u8>),
+    /// An image file next to the track.
+    Folder(PathBuf),
+}
+
+/// The art of `path`: embedded first, then a folder image.
+pub fn find_artwork(path: &Path) -> Option<ArtSource> {
+    embedded_picture(path).map(ArtSource::Embedded).or_else(|| folder_image(path.parent()?).map(ArtSource::Folder))
+}
+
+fn embedded_picture(path: &Path) -> Option<Vec<u8>> {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+    if is_dsd(path) { return read_dsd_tag(path)?.picture().map(<[u8]>::to_vec); }
+    if ext == "mp3" && let Some(picture) = read_id3(path).and_then(|tag| tag.picture().map(<[u8]>::to_vec)) { return Some(picture); }
+    symphonia_picture(path)
+}
+
+// The front cover among the pictures Symphonia reads, or the first one
+fn symphonia_picture(path: &Path) -> Option<Vec<u8>> {
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::{MetadataOptions, StandardVisualKey, Visual};
+    use symphonia::core::probe::Hint;
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) { hint.with_extension(ext); }
+    let mss = MediaSourceStream::new(Box::new(File::open(path).ok()?), Default::default());
+    let mut probed = symphonia::default::get_probe().format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default()).ok()?;
+    let mut visuals: Vec<Visual> = Vec::new();
+    if let Some(rev) = probed.metadata.get().as_ref().and_then(|m| m.current().cloned()) { visuals.extend(rev.visuals().iter().cloned()); }
+    if let Some(rev) = probed.format.metadata().current() { visuals.extend(rev.visuals().iter().cloned()); }
+    let front = visuals.iter().position(|v| v.usage == Some(StandardVisualKey::FrontCover)).unwrap_or(0);
+    visuals.into_iter().nth(front).map(|v| v.data.into_vec()).filter(|d| !d.is_empty())
+}
+
+/// `cover.jpg`, `folder.png`, `front.*`... in `dir`.
+pub(crate) fn folder_image(dir: &Path) -> Option<PathBuf> {
+    let images: Vec<(String, PathBuf)> = std::fs::read_dir(dir).ok()?.flatten().map(|e| e.path()).filter_map(|p| {
+        let ext = p.extension()?.to_str()?.to_ascii_lowercase();
+        let stem = p.file_stem()?.to_str()?.to_ascii_lowercase();
+        (IMAGE_EXTS.contains(&ext.as_str()) && p.is_file()).then_some((stem, p))
+    }).collect();
+    FOLDER_NAMES.iter().find_map(|name| images.iter().filter(|(stem, _)| stem == name).map(|(_, p)| p.clone()).min())
+}
+
+/// Decode `data` and scale it down to fit `size`, as JPEG.
+pub fn make_thumbnail(data: &[u8], size: u32) -> Result<Vec<u8>, String> {
+    let image = image::load_from_memory(data).map_err(|e| format!("Can't decode picture: {e}"))?;
+    let image = if image.width() > size || image.height() > size { image.resize(size, size, image::imageops::FilterType::Triangle) } else { image };
+    let mut out = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, 85).encode_image(&image.to_rgb8())
+        .map_err(|e| format!("Can't encode thumbnail: {e}"))?;
+    Ok(out)
+}
+
+/// The cached thumbnail of `path`'s art, made on first use. `None` when the track has no art.
+pub fn thumbnail(path: &Path, cache_dir: &Path) -> Result<Option<PathBuf>, String> {
+    let Some(source) = find_artwork(path) else { return Ok(None) };
+    // Embedded art is keyed by content, so an album's tracks share one thumbnail; folder images by file and date
+    let (key, data) = match source {
+        ArtSource::Embedded(data) => (hex(&md5(&data)), Some(data)),
+        ArtSource::Folder(image) => {
+            let meta = std::fs::metadata(&image).map_err(|e| format!("{}: {e}", image.display()))?;
+            let modified = meta.modified().ok().and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok()).map_or(0, |d| d.as_secs());
+            (hex(&md5(format!("{}|{modified}|{}", image.display(), meta.len()).as_bytes())), std::fs::read(&image).ok())
+        }
+    };
+    let thumb = cache_dir.join(format!("{key}-{THUMB_SIZE}.jpg"));
+    if thumb.is_file() { return Ok(Some(thumb)); }
+    let data = data.ok_or_else(|| format!("Can't read the cover image for {}", path.display()))?;
+    let jpeg = make_thumbnail(&data, THUMB_SIZE)?;
+    std::fs::create_dir_all(cache_dir).map_err(|e| format!("Can't create {}: {e}", cache_dir.display()))?;
+    let part = thumb.with_extension("part");
+    std::fs::write(&part, jpeg).and_then(|()| std::fs::rename(&part, &thumb)).map_err(|e| format!("Can't save {}: {e}", thumb.display()))?;
+    Ok(Some(thumb))
+}
+
+// A track and the thumbnail found for it
+type Found = (PathBuf, Option<PathBuf>);
+
+/// Finds the art of the playing track in the background.
+#[derive(Default)]
+pub(crate) struct ArtLoader {
+    track: Option<PathBuf>,
+    // A result for a track that is no longer playing is dropped
+    done: Arc<Mutex<Option<Found>>>,
+}
+
+impl ArtLoader {
+    /// Follow the playing `track` (a local file, or `None`). Returns the thumbnail to show once it is known, and
+    /// `Some(None)` when there is no art.
+    pub(crate) fn poll(&mut self, track: Option<&Path>) -> Option<Option<PathBuf>> {
+        if self.track.as_deref() != track {
+            self.track = track.map(Path::to_path_buf);
+            let Some(track) = self.track.clone() else { return Some(None) };
+            let done = self.done.clone();
+            let cache_dir = config_dir().map(|dir| dir.join(COVER_CACHE_DIR).join(THUMB_DIR));
+            let spawned = std::thread::Builder::new().name("artwork".into()).spawn(move || {
+                let thumb = match &cache_dir {
+                    Some(dir) => thumbnail(&track, dir).unwrap_or_else(|e| { log::warn!("{e}"); None }),
+                    None => None,
+                };
+                *done.lock().unwrap() = Some((track, thumb));
+            });
+            if let Err(e) = spawned { log::warn!("Can't look up artwork: {e}"); return Some(None); }
+        }
+        let (track, thumb) = self.done.lock().unwrap().take()?;
+        (self.track.as_ref() == Some(&track)).then_some(thumb)
+    }
+
+    /// Look the art up again on the next poll, after the track's tags have changed.
+    pub(crate) fn reload(&mut self) { self.track = None; }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("artwork-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = image::RgbImage::from_fn(width, height, |x, y| image::Rgb([x as u8, y as u8, 128]));
+        let mut out = std::io::Cursor::new(Vec::new());
+        image.write_to(&mut out, image::ImageFormat::Png).unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn folder_images_go_by_name_before_extension() {
+        let dir = temp_dir("names");
+        let found = || folder_image(&dir).map(|p| p.file_name().unwrap().to_string_lossy().into_owned());
+        for name in ["album.webp", "Front.JPG", "booklet.png", "cover.txt"] { std::fs::write(dir.join(name), b"").unwrap(); }
+        // A folder with an image's name isn't one
+        std::fs::create_dir(dir.join("folder.png")).unwrap();
+        assert_eq!(found().as_deref(), Some("Front.JPG"));
+        std::fs::write(dir.join("Folder.gif"), b"").unwrap();
+        assert_eq!(found().as_deref(), Some("Folder.gif"));
+        for name in ["cover.png", "cover.jpg"] { std::fs::write(dir.join(name), b"").unwrap(); }
+        // Several of the same name: the first by file name
+        assert_eq!(found().as_deref(), Some("cover.jpg"));
+        let empty = temp_dir("no-images");
+        std::fs::write(empty.join("track.flac"), b"").unwrap();
+        assert_eq!(folder_image(&empty), None);
+        std::fs::remove_dir_all(dir).unwrap();
+        std::fs::remove_dir_all(empty).unwrap();
+    }
+
+    #[test]
+    fn thumbnails_fit_the_size_and_keep_the_shape() {
+        let size = |jpeg: &[u8]| { let image = image::load_from_memory(jpeg).unwrap(); (image.width(), image.height()) };
+        assert_eq!(size(&make_thumbnail(&png(1000, 500), THUMB_SIZE).unwrap()), (400, 200));
+        assert_eq!(size(&make_thumbnail(&png(300, 900), THUMB_SIZE).unwrap()), (133, 400));
+        // Small pictures aren't blown up
+        assert_eq!(size(&make_thumbnail(&png(120, 80), THUMB_SIZE).unwrap()), (120, 80));
+        assert!(make_thumbnail(b"not a picture", THUMB_SIZE).is_err());
+    }
+
+    #[test]
+    fn cached_thumbnail_is_reused_until_the_image_changes() {
+        let dir = temp_dir("cache");
+        let (track, cover, cache) = (dir.join("track.flac"), dir.join("cover.png"), dir.join("thumbs"));
+        std::fs::write(&track, b"").unwrap();
+        assert_eq!(thumbnail(&track, &cache).unwrap(), None);
+        std::fs::write(&cover, png(800, 800)).unwrap();
+        let thumb = thumbnail(&track, &cache).unwrap().unwrap();
+        assert!(image::load_from_memory(&std::fs::read(&thumb).unwrap()).is_ok());
+        // A second look finds the cached file rather than encoding again
+        std::fs::write(&thumb, b"cached").unwrap();
+        assert_eq!(thumbnail(&track, &cache).unwrap(), Some(thumb.clone()));
+        assert_eq!(std::fs::read(&thumb).unwrap(), b"cached");
+        // A new cover is a new thumbnail
+        std::fs::write(&cover, png(200, 100)).unwrap();
+        let new_thumb = thumbnail(&track, &cache).unwrap().unwrap();
+        assert_ne!(new_thumb, thumb);
+        assert_eq!(image::load_from_memory(&std::fs::read(&new_thumb).unwrap()).unwrap().width(), 200);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
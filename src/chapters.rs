@@ -0,0 +1,375 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+// Chapter marks embedded in audio files: MP4 Nero `chpl` boxes and QuickTime chapter text tracks,
+// Matroska `Chapters` and Vorbis-comment `CHAPTERxx`/`CHAPTERxxNAME` pairs (FLAC, Ogg).
+// Containers are walked by hand so that only the small index structures are read, not the media data.
+// Sizes and counts come from the file, so they are checked against what is actually there before use.
+
+/// Most chapters read from a QuickTime chapter track; its tables are otherwise only bounded by the file.
+const MAX_CHAPTERS: usize = 10_000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Chapter {
+    /// Start in file time.
+    pub start: Duration,
+    pub title: String,
+}
+
+/// Chapters of `path`, sorted by start. Files without chapters (or that fail to parse) give an empty list.
+pub(crate) fn read_chapters(path: &Path) -> Vec<Chapter> {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+    let result = match ext.as_str() {
+        "m4a" | "m4b" | "mp4" | "aac" | "alac" | "mov" => File::open(path).ok().and_then(|mut f| mp4_chapters(&mut f)),
+        "mka" | "mkv" | "webm" => File::open(path).ok().and_then(|mut f| mkv_chapters(&mut f)),
+        _ => vorbis_chapters(path),
+    };
+    let mut chapters = result.unwrap_or_default();
+    chapters.sort_by_key(|c| c.start);
+    chapters.dedup_by_key(|c| c.start);
+    for (i, c) in chapters.iter_mut().enumerate() {
+        if c.title.trim().is_empty() { c.title = format!("Chapter {}", i + 1); }
+    }
+    chapters
+}
+
+fn be_u16(b: &[u8], at: usize) -> Option<u16> { Some(u16::from_be_bytes(b.get(at..at + 2)?.try_into().ok()?)) }
+fn be_u32(b: &[u8], at: usize) -> Option<u32> { Some(u32::from_be_bytes(b.get(at..at + 4)?.try_into().ok()?)) }
+fn be_u64(b: &[u8], at: usize) -> Option<u64> { Some(u64::from_be_bytes(b.get(at..at + 8)?.try_into().ok()?)) }
+
+// ===== MP4 =====
+/// Child boxes of an in-memory box body as (type, body).
+fn mp4_children(buf: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut out = Vec::new();
+    let mut pos = 0usize;
+    while pos + 8 <= buf.len() {
+        let Some(size) = be_u32(buf, pos) else { break };
+        let typ: [u8; 4] = buf[pos + 4..pos + 8].try_into().unwrap();
+        let (header, size) = match size {
+            1 => match be_u64(buf, pos + 8) { Some(s) => (16, s as usize), None => break },
+            0 => (8, buf.len() - pos),
+            s => (8, s as usize),
+        };
+        if size < header || pos.checked_add(size).is_none_or(|end| end > buf.len()) { break; }
+        out.push((typ, &buf[pos + header..pos + size]));
+        pos += size;
+    }
+    out
+}
+
+fn mp4_child<'a>(buf: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    let (first, rest) = path.split_first()?;
+    let body = mp4_children(buf).into_iter().find(|(t, _)| t == *first)?.1;
+    if rest.is_empty() { Some(body) } else { mp4_child(body, rest) }
+}
+
+fn mp4_chapters(f: &mut File) -> Option<Vec<Chapter>> {
+    // Find `moov` among the top-level boxes without reading `mdat`
+    let len = f.metadata().ok()?.len();
+    let mut pos = 0u64;
+    let moov = loop {
+        if pos + 8 > len { return None; }
+        f.seek(SeekFrom::Start(pos)).ok()?;
+        let mut hdr = [0u8; 16];
+        f.read_exact(&mut hdr[..8]).ok()?;
+        let (header, size) = match be_u32(&hdr, 0)? {
+            1 => { f.read_exact(&mut hdr[8..]).ok()?; (16, be_u64(&hdr, 8)?) }
+            0 => (8, len - pos),
+            s => (8, s as u64),
+        };
+        // A box can't run past the end of the file; a corrupt size would otherwise be allocated below
+        if size < header || size > len - pos { return None; }
+        if &hdr[4..8] == b"moov" {
+            let mut buf = vec![0u8; (size - header) as usize];
+            f.read_exact(&mut buf).ok()?;
+            break buf;
+        }
+        pos += size;
+    };
+    if let Some(chpl) = mp4_child(&moov, &[b"udta", b"chpl"]) && let Some(chapters) = parse_chpl(chpl) && !chapters.is_empty() {
+        return Some(chapters);
+    }
+    quicktime_chapters(f, &moov)
+}
+
+// Nero chapter list: version/flags, (v1: 4 reserved bytes), count, then (start in 100 ns units, title).
+fn parse_chpl(b: &[u8]) -> Option<Vec<Chapter>> {
+    let mut pos = if b.first()? > &0 { 8 } else { 4 };
+    let count = *b.get(pos)?;
+    pos += 1;
+    let mut chapters = Vec::new();
+    for _ in 0..count {
+        let start = be_u64(b, pos)?;
+        let len = *b.get(pos + 8)? as usize;
+        let title = String::from_utf8_lossy(b.get(pos + 9..pos + 9 + len)?).into_owned();
+        chapters.push(Chapter { start: Duration::from_nanos(start.saturating_mul(100)), title });
+        pos += 9 + len;
+    }
+    Some(chapters)
+}
+
+// A text track referenced from another track's `tref/chap`; each sample is one chapter title.
+fn quicktime_chapters(f: &mut File, moov: &[u8]) -> Option<Vec<Chapter>> {
+    let traks: Vec<&[u8]> = mp4_children(moov).into_iter().filter(|(t, _)| t == b"trak").map(|(_, b)| b).collect();
+    let track_id = |trak: &[u8]| {
+        let tkhd = mp4_child(trak, &[b"tkhd"])?;
+        be_u32(tkhd, if tkhd.first()? == &1 { 20 } else { 12 })
+    };
+    let chap_id = traks.iter().find_map(|t| be_u32(mp4_child(t, &[b"tref", b"chap"])?, 0))?;
+    let trak = traks.iter().find(|t| track_id(t) == Some(chap_id))?;
+    let mdhd = mp4_child(trak, &[b"mdia", b"mdhd"])?;
+    let timescale = be_u32(mdhd, if mdhd.first()? == &1 { 20 } else { 12 })?.max(1) as u64;
+    let stbl = mp4_child(trak, &[b"mdia", b"minf", b"stbl"])?;
+
+    // Sample sizes, chunk offsets and the sample-to-chunk mapping give each sample's file offset
+    let stsz = mp4_child(stbl, &[b"stsz"])?;
+    let (uniform, n) = (be_u32(stsz, 4)?, (be_u32(stsz, 8)? as usize).min(MAX_CHAPTERS));
+    // Sample start times from the time-to-sample table; entries can't outnumber what the box holds
+    let stts = mp4_child(stbl, &[b"stts"])?;
+    let mut starts = Vec::new();
+    let mut t = 0u64;
+    for i in 0..(be_u32(stts, 4)? as usize).min(stts.len().saturating_sub(8) / 8) {
+        let (count, delta) = (be_u32(stts, 8 + i * 8)? as usize, be_u32(stts, 12 + i * 8)? as u64);
+        for _ in 0..count.min(n - starts.len()) { starts.push(t); t = t.saturating_add(delta); }
+    }
+    let sizes: Vec<u32> = (0..n).map(|i| if uniform != 0 { Some(uniform) } else { be_u32(stsz, 12 + i * 4) }).collect::<Option<_>>()?;
+    let chunks: Vec<u64> = if let Some(stco) = mp4_child(stbl, &[b"stco"]) {
+        (0..be_u32(stco, 4)? as usize).map(|i| be_u32(stco, 8 + i * 4).map(u64::from)).collect::<Option<_>>()?
+    } else {
+        let co64 = mp4_child(stbl, &[b"co64"])?;
+        (0..be_u32(co64, 4)? as usize).map(|i| be_u64(co64, 8 + i * 8)).collect::<Option<_>>()?
+    };
+    let stsc = mp4_child(stbl, &[b"stsc"])?;
+    let runs: Vec<(usize, usize)> = (0..be_u32(stsc, 4)? as usize)
+        .map(|i| Some((be_u32(stsc, 8 + i * 12)? as usize, be_u32(stsc, 12 + i * 12)? as usize))).collect::<Option<_>>()?;
+    let mut offsets = Vec::with_capacity(n);
+    for (ci, &chunk_offset) in chunks.iter().enumerate() {
+        let per_chunk = runs.iter().rev().find(|(first, _)| *first <= ci + 1).map(|r| r.1).unwrap_or(1);
+        let mut off = chunk_offset;
+        for _ in 0..per_chunk {
+            let Some(&size) = sizes.get(offsets.len()) else { break };
+            offsets.push((off, size));
+            off = off.saturating_add(size as u64);
+        }
+    }
+
+    let mut chapters = Vec::new();
+    for (&start, &(offset, size)) in starts.iter().zip(&offsets) {
+        // Text samples: 16-bit length, then the text (UTF-8, or UTF-16 with a BOM)
+        let mut sample = vec![0u8; size.min(4096) as usize];
+        f.seek(SeekFrom::Start(offset)).ok()?;
+        f.read_exact(&mut sample).ok()?;
+        let len = (be_u16(&sample, 0)? as usize).min(sample.len() - 2);
+        let text = &sample[2..2 + len];
+        let title = if text.starts_with(&[0xfe, 0xff]) {
+            String::from_utf16_lossy(&text[2..].chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect::<Vec<_>>())
+        } else {
+            String::from_utf8_lossy(text).into_owned()
+        };
+        chapters.push(Chapter { start: Duration::from_secs_f64(start as f64 / timescale as f64), title });
+    }
+    Some(chapters)
+}
+
+// ===== Matroska =====
+// EBML variable-length integer; `keep_marker` is used for element IDs.
+fn read_vint(r: &mut impl Read, keep_marker: bool) -> Option<(u64, usize)> {
+    let mut first = [0u8; 1];
+    r.read_exact(&mut first).ok()?;
+    let len = first[0].leading_zeros() as usize + 1;
+    if len > 8 { return None; }
+    let mut value = if keep_marker { first[0] as u64 } else { (first[0] & 0xffu8.checked_shr(len as u32).unwrap_or(0)) as u64 };
+    let mut rest = [0u8; 7];
+    r.read_exact(&mut rest[..len - 1]).ok()?;
+    for &b in &rest[..len - 1] { value = (value << 8) | b as u64; }
+    Some((value, len))
+}
+
+const UNKNOWN_SIZE: u64 = u64::MAX;
+
+// Element header: (id, body size or UNKNOWN_SIZE, header length).
+fn read_element(r: &mut impl Read) -> Option<(u64, u64, usize)> {
+    let (id, id_len) = read_vint(r, true)?;
+    let (size, size_len) = read_vint(r, false)?;
+    let size = if size == (1u64 << (7 * size_len)) - 1 { UNKNOWN_SIZE } else { size };
+    Some((id, size, id_len + size_len))
+}
+
+fn mkv_chapters(f: &mut File) -> Option<Vec<Chapter>> {
+    const SEGMENT: u64 = 0x1853_8067;
+    const CHAPTERS: u64 = 0x1043_A770;
+    let len = f.metadata().ok()?.len();
+    let (id, size, header) = read_element(f)?;
+    if id != 0x1A45_DFA3 || size > len.saturating_sub(header as u64) { return None; }
+    f.seek(SeekFrom::Current(size as i64)).ok()?;
+    let (id, seg_size, _) = read_element(f)?;
+    if id != SEGMENT { return None; }
+    let seg_start = f.stream_position().ok()?;
+    let seg_end = if seg_size == UNKNOWN_SIZE { len } else { seg_start.saturating_add(seg_size).min(len) };
+    loop {
+        let pos = f.stream_position().ok()?;
+        if pos >= seg_end { return None; }
+        let (id, size, header) = read_element(f)?;
+        // An element of unknown size (a live-written cluster) can't be skipped; chapters normally come before the clusters
+        if size == UNKNOWN_SIZE { return None; }
+        // Nor can one that claims to run past the segment; a corrupt size would otherwise be allocated below
+        if size > seg_end.saturating_sub(pos + header as u64) { return None; }
+        if id == CHAPTERS {
+            let mut buf = vec![0u8; size as usize];
+            f.read_exact(&mut buf).ok()?;
+            return Some(parse_mkv_chapters(&buf));
+        }
+        f.seek(SeekFrom::Current(size as i64)).ok()?;
+    }
+}
+
+fn mkv_children(buf: &[u8]) -> Vec<(u64, &[u8])> {
+    let mut out = Vec::new();
+    let mut cursor = std::io::Cursor::new(buf);
+    while let Some((id, size, _)) = read_element(&mut cursor) {
+        let start = cursor.position() as usize;
+        let Some(body) = buf.get(start..start.saturating_add(size as usize)) else { break };
+        out.push((id, body));
+        cursor.set_position((start + body.len()) as u64);
+    }
+    out
+}
+
+fn be_uint(b: &[u8]) -> u64 { b.iter().fold(0u64, |v, &x| (v << 8) | x as u64) }
+
+fn parse_mkv_chapters(buf: &[u8]) -> Vec<Chapter> {
+    const EDITION: u64 = 0x45B9;
+    const ATOM: u64 = 0xB6;
+    // Use the first edition that has any visible chapters
+    for (_, edition) in mkv_children(buf).into_iter().filter(|(id, _)| *id == EDITION) {
+        let mut chapters = Vec::new();
+        for (_, atom) in mkv_children(edition).into_iter().filter(|(id, _)| *id == ATOM) {
+            let fields = mkv_children(atom);
+            let hidden = fields.iter().any(|(id, b)| *id == 0x98 && be_uint(b) == 1);
+            let Some(start) = fields.iter().find(|(id, _)| *id == 0x91).map(|(_, b)| be_uint(b)) else { continue };
+            let title = fields.iter().filter(|(id, _)| *id == 0x80)
+                .find_map(|(_, display)| mkv_children(display).into_iter().find(|(id, _)| *id == 0x85))
+                .map(|(_, s)| String::from_utf8_lossy(s).trim_end_matches('\0').to_string())
+                .unwrap_or_default();
+            if !hidden { chapters.push(Chapter { start: Duration::from_nanos(start), title }); }
+        }
+        if !chapters.is_empty() { return chapters; }
+    }
+    Vec::new()
+}
+
+// ===== Vorbis comments =====
+// `HH:MM:SS.sss` (hours optional).
+fn parse_clock(s: &str) -> Option<Duration> {
+    let mut secs = 0f64;
+    for part in s.trim().split(':') { secs = secs * 60.0 + part.parse::<f64>().ok()?; }
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+fn vorbis_chapters(path: &Path) -> Option<Vec<Chapter>> {
+    use std::collections::BTreeMap;
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::MetadataOptions;
+    use symphonia::core::probe::Hint;
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) { hint.with_extension(ext); }
+    let mss = MediaSourceStream::new(Box::new(File::open(path).ok()?), Default::default());
+    let mut probed = symphonia::default::get_probe().format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default()).ok()?;
+    let mut tags = Vec::new();
+    if let Some(rev) = probed.metadata.get().as_ref().and_then(|m| m.current().cloned()) { tags.extend(rev.tags().to_vec()); }
+    if let Some(rev) = probed.format.metadata().current() { tags.extend(rev.tags().to_vec()); }
+
+    // CHAPTER001=00:00:00.000 and CHAPTER001NAME=Title, grouped by number
+    let mut found: BTreeMap<String, (Option<Duration>, String)> = BTreeMap::new();
+    for tag in &tags {
+        let key = tag.key.to_ascii_uppercase();
+        let Some(rest) = key.strip_prefix("CHAPTER") else { continue };
+        let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+        if digits.is_empty() { continue; }
+        let entry = found.entry(digits.clone()).or_default();
+        match &rest[digits.len()..] {
+            "" => entry.0 = parse_clock(&tag.value.to_string()),
+            "NAME" => entry.1 = tag.value.to_string(),
+            _ => {}
+        }
+    }
+    Some(found.into_values().filter_map(|(start, title)| Some(Chapter { start: start?, title })).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chapters_of(name: &str, data: &[u8]) -> Vec<Chapter> {
+        let path = std::env::temp_dir().join(format!("chapters-{}-{name}", std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        let chapters = read_chapters(&path);
+        std::fs::remove_file(&path).unwrap();
+        chapters
+    }
+
+    fn mp4_box(typ: &[u8; 4], body: &[u8]) -> Vec<u8> { [&(body.len() as u32 + 8).to_be_bytes()[..], typ, body].concat() }
+
+    #[test]
+    fn reads_nero_chapters() {
+        let mut chpl = vec![0, 0, 0, 0, 2];
+        for (start, title) in [(0u64, "Intro"), (600_000_000, "Part two")] {
+            chpl.extend(start.to_be_bytes());
+            chpl.push(title.len() as u8);
+            chpl.extend(title.as_bytes());
+        }
+        let file = [mp4_box(b"ftyp", b"M4A "), mp4_box(b"moov", &mp4_box(b"udta", &mp4_box(b"chpl", &chpl)))].concat();
+        let chapters = chapters_of("nero.m4b", &file);
+        assert_eq!(chapters, vec![
+            Chapter { start: Duration::ZERO, title: "Intro".into() },
+            Chapter { start: Duration::from_secs(60), title: "Part two".into() },
+        ]);
+    }
+
+    #[test]
+    fn rejects_boxes_larger_than_the_file() {
+        // A `moov` claiming 4 GiB (32-bit size) and one claiming 2^62 bytes (64-bit size)
+        let mut file = mp4_box(b"ftyp", b"M4A ");
+        file.extend([0xff, 0xff, 0xff, 0xff]);
+        file.extend(b"moov");
+        assert!(chapters_of("huge.m4a", &file).is_empty());
+        let mut file = mp4_box(b"ftyp", b"M4A ");
+        file.extend([0, 0, 0, 1]);
+        file.extend(b"moov");
+        file.extend((1u64 << 62).to_be_bytes());
+        assert!(chapters_of("huge64.m4a", &file).is_empty());
+    }
+
+    #[test]
+    fn caps_chapter_track_sample_counts() {
+        // A chapter text track whose tables claim billions of samples
+        let full = |version_flags: u32, rest: &[u8]| [&version_flags.to_be_bytes()[..], rest].concat();
+        let tkhd = full(0, &[[0u8; 8].as_slice(), &2u32.to_be_bytes()].concat());
+        let mdhd = full(0, &[[0u8; 8].as_slice(), &1000u32.to_be_bytes()].concat());
+        let stts = full(0, &[1u32.to_be_bytes(), u32::MAX.to_be_bytes(), 1u32.to_be_bytes()].concat());
+        let stsz = full(0, &[4u32.to_be_bytes(), u32::MAX.to_be_bytes()].concat());
+        let stco = full(0, &[1u32.to_be_bytes(), 0u32.to_be_bytes()].concat());
+        let stsc = full(0, &[1u32.to_be_bytes(), 1u32.to_be_bytes(), u32::MAX.to_be_bytes(), 1u32.to_be_bytes()].concat());
+        let stbl = [mp4_box(b"stts", &stts), mp4_box(b"stsz", &stsz), mp4_box(b"stco", &stco), mp4_box(b"stsc", &stsc)].concat();
+        let text_trak = [mp4_box(b"tkhd", &tkhd), mp4_box(b"mdia", &[mp4_box(b"mdhd", &mdhd), mp4_box(b"minf", &mp4_box(b"stbl", &stbl))].concat())].concat();
+        let audio_trak = mp4_box(b"tref", &mp4_box(b"chap", &2u32.to_be_bytes()));
+        let moov = [mp4_box(b"trak", &audio_trak), mp4_box(b"trak", &text_trak)].concat();
+        let file = [mp4_box(b"ftyp", b"M4A "), mp4_box(b"moov", &moov)].concat();
+        // Every sample points at the start of the file; what matters is that this returns at all
+        assert!(chapters_of("samples.m4a", &file).len() <= MAX_CHAPTERS);
+    }
+
+    #[test]
+    fn rejects_matroska_chapters_larger_than_the_segment() {
+        // EBML header, a Segment of 16 bytes, and a Chapters element claiming 2^48 bytes inside it
+        let mut file = vec![0x1A, 0x45, 0xDF, 0xA3, 0x80];
+        file.extend([0x18, 0x53, 0x80, 0x67, 0x90]);
+        file.extend([0x10, 0x43, 0xA7, 0x70, 0x01, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+        file.extend([0u8; 3]);
+        assert!(chapters_of("huge.mka", &file).is_empty());
+    }
+}
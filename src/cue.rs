@@ -0,0 +1,172 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// CUE sheet parsing. Only the commands needed to split a single-file rip into tracks are understood
+// (FILE, TRACK, INDEX, TITLE, PERFORMER); everything else (REM, FLAGS, ISRC, ...) is ignored.
+
+/// One track of a CUE sheet, as a range of an audio file.
+#[derive(Clone, Debug)]
+pub(crate) struct CueTrack {
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub file: PathBuf,
+    pub start: Duration,
+    /// Start of the next track in the same file; `None` for the last one, which runs to the end of the file.
+    pub end: Option<Duration>,
+}
+
+/// The sheet's album-level TITLE/PERFORMER and its tracks.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CueSheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub tracks: Vec<CueTrack>,
+}
+
+/// Read and parse the CUE sheet at `path`. FILE entries are resolved relative to the sheet's folder.
+pub(crate) fn read_cue(path: &Path) -> Result<CueSheet, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+    // Sheets written by older rippers are usually Latin-1/Windows-1252 rather than UTF-8
+    let text = match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => e.into_bytes().iter().map(|&b| b as char).collect(),
+    };
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let sheet = parse_cue(&text, dir);
+    if sheet.tracks.is_empty() { return Err(format!("No tracks in {}", path.display())); }
+    Ok(sheet)
+}
+
+// Strip surrounding quotes from a CUE argument.
+fn unquote(s: &str) -> &str {
+    let s = s.trim();
+    s.strip_prefix('"').and_then(|s| s.strip_suffix('"')).unwrap_or(s)
+}
+
+// `mm:ss:ff` with 75 frames per second.
+fn parse_msf(s: &str) -> Option<Duration> {
+    let mut parts = s.trim().split(':').map(|p| p.parse::<u64>().ok());
+    let (m, sec, f) = (parts.next()??, parts.next()??, parts.next()??);
+    Some(Duration::from_secs(m * 60 + sec) + Duration::from_nanos(f * 1_000_000_000 / 75))
+}
+
+pub(crate) fn parse_cue(text: &str, dir: &Path) -> CueSheet {
+    let mut sheet = CueSheet::default();
+    let mut file: Option<PathBuf> = None;
+    let mut current: Option<CueTrack> = None;
+    for line in text.trim_start_matches('\u{feff}').lines() {
+        let line = line.trim();
+        let (cmd, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        match cmd.to_ascii_uppercase().as_str() {
+            "FILE" => {
+                // The file type (WAVE, MP3, ...) follows the name, which may itself contain spaces
+                let name = match rest.trim().strip_prefix('"') {
+                    Some(quoted) => quoted.split('"').next().unwrap_or(""),
+                    None => rest.split_whitespace().next().unwrap_or(""),
+                };
+                file = Some(dir.join(name));
+            }
+            "TRACK" => {
+                if let Some(t) = current.take() && t.start != Duration::MAX { sheet.tracks.push(t); }
+                let Some(file) = file.clone() else { continue };
+                let number = rest.split_whitespace().next().and_then(|n| n.parse().ok()).unwrap_or(sheet.tracks.len() as u32 + 1);
+                // Start stays unset until INDEX 01 is seen
+                current = Some(CueTrack { number, title: None, performer: None, file, start: Duration::MAX, end: None });
+            }
+            "INDEX" => {
+                let mut args = rest.split_whitespace();
+                if args.next().and_then(|n| n.parse::<u32>().ok()) == Some(1)
+                    && let Some(t) = current.as_mut()
+                    && let Some(start) = args.next().and_then(parse_msf) { t.start = start; }
+            }
+            "TITLE" => match current.as_mut() {
+                Some(t) => t.title = Some(unquote(rest).to_string()),
+                None => sheet.title = Some(unquote(rest).to_string()),
+            },
+            "PERFORMER" => match current.as_mut() {
+                Some(t) => t.performer = Some(unquote(rest).to_string()),
+                None => sheet.performer = Some(unquote(rest).to_string()),
+            },
+            _ => {}
+        }
+    }
+    if let Some(t) = current.take() && t.start != Duration::MAX { sheet.tracks.push(t); }
+    // A track ends where the next one in the same file starts (its pregap stays with the previous track,
+    // so consecutive tracks cover the file without gaps)
+    for i in 1..sheet.tracks.len() {
+        if sheet.tracks[i].file == sheet.tracks[i - 1].file { sheet.tracks[i - 1].end = Some(sheet.tracks[i].start); }
+    }
+    sheet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration { Duration::from_millis(ms) }
+
+    #[test]
+    fn msf_has_75_frames_per_second() {
+        assert_eq!(parse_msf("00:00:00"), Some(Duration::ZERO));
+        assert_eq!(parse_msf("00:00:75"), Some(Duration::from_secs(1)));
+        assert_eq!(parse_msf("01:02:15"), Some(ms(62_200)));
+        assert_eq!(parse_msf("74:59:74"), Some(Duration::from_secs(74 * 60 + 59) + Duration::from_nanos(74 * 1_000_000_000 / 75)));
+        assert_eq!(parse_msf("01:02"), None);
+        assert_eq!(parse_msf("aa:00:00"), None);
+    }
+
+    #[test]
+    fn tracks_start_at_index_01_and_end_at_the_next_track() {
+        let text = "\u{feff}REM GENRE Rock\r\nPERFORMER \"The Band\"\r\nTITLE \"Live, at Home\"\r\nFILE \"Live Album.flac\" WAVE\r\n  TRACK 01 AUDIO\r\n    TITLE Intro\r\n    INDEX 01 00:00:00\r\n  TRACK 02 AUDIO\r\n    TITLE \"Second Song\"\r\n    PERFORMER \"Guest\"\r\n    INDEX 00 03:58:00\r\n    INDEX 01 04:00:30\r\n  TRACK 03 AUDIO\r\n    INDEX 01 08:10:00\r\n";
+        let sheet = parse_cue(text, Path::new("/music"));
+        assert_eq!((sheet.title.as_deref(), sheet.performer.as_deref()), (Some("Live, at Home"), Some("The Band")));
+        let t = &sheet.tracks;
+        assert_eq!(t.iter().map(|t| t.number).collect::<Vec<_>>(), [1, 2, 3]);
+        assert!(t.iter().all(|t| t.file == Path::new("/music/Live Album.flac")));
+        // Unquoted and quoted titles; a track without its own performer has none
+        assert_eq!(t[0].title.as_deref(), Some("Intro"));
+        assert_eq!((t[1].title.as_deref(), t[1].performer.as_deref()), (Some("Second Song"), Some("Guest")));
+        assert_eq!((t[2].title.as_deref(), t[2].performer.as_deref()), (None, None));
+        // The pregap (INDEX 00) belongs to the previous track
+        assert_eq!((t[0].start, t[0].end), (Duration::ZERO, Some(ms(240_400))));
+        assert_eq!((t[1].start, t[1].end), (ms(240_400), Some(ms(490_000))));
+        // The last track runs to the end of the file
+        assert_eq!((t[2].start, t[2].end), (ms(490_000), None));
+    }
+
+    #[test]
+    fn each_file_block_has_its_own_tracks() {
+        let text = "FILE disc1.wav WAVE\nTRACK 01 AUDIO\nINDEX 01 00:00:00\nTRACK 02 AUDIO\nINDEX 01 03:00:00\nFILE \"disc 2.wav\" WAVE\nTRACK 03 AUDIO\nINDEX 01 00:00:00\nTRACK 04 AUDIO\nINDEX 01 02:30:00\n";
+        let t = parse_cue(text, Path::new("/cd")).tracks;
+        assert_eq!(t.len(), 4);
+        assert_eq!((t[0].file.as_path(), t[0].end), (Path::new("/cd/disc1.wav"), Some(ms(180_000))));
+        // The last track of the first file doesn't end where the second file starts
+        assert_eq!((t[1].file.as_path(), t[1].end), (Path::new("/cd/disc1.wav"), None));
+        assert_eq!((t[2].file.as_path(), t[2].start, t[2].end), (Path::new("/cd/disc 2.wav"), Duration::ZERO, Some(ms(150_000))));
+        assert_eq!((t[3].start, t[3].end), (ms(150_000), None));
+    }
+
+    #[test]
+    fn tracks_without_a_file_or_index_01_are_dropped() {
+        let text = "TRACK 01 AUDIO\nINDEX 01 00:00:00\nFILE a.wav WAVE\nTRACK 02 AUDIO\nINDEX 00 00:00:00\nTRACK 03 AUDIO\nINDEX 01 01:00:00\n";
+        let t = parse_cue(text, Path::new("/")).tracks;
+        assert_eq!(t.iter().map(|t| t.number).collect::<Vec<_>>(), [3]);
+    }
+
+    #[test]
+    fn latin1_sheet_is_read() {
+        let dir = std::env::temp_dir().join(format!("cue-{}-latin1", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("album.cue");
+        // "Café" and "Señor" in Latin-1
+        std::fs::write(&path, b"PERFORMER \"Se\xf1or\"\nFILE \"album.flac\" WAVE\nTRACK 01 AUDIO\nTITLE \"Caf\xe9\"\nINDEX 01 00:00:00\n").unwrap();
+        let sheet = read_cue(&path).unwrap();
+        assert_eq!(sheet.performer.as_deref(), Some("Señor"));
+        assert_eq!(sheet.tracks[0].title.as_deref(), Some("Café"));
+        assert_eq!(sheet.tracks[0].file, dir.join("album.flac"));
+        std::fs::write(&path, "REM nothing here\n").unwrap();
+        assert!(read_cue(&path).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,460 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rodio::Source;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymError;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::core::units::Time;
+
+use crate::dsd::{is_dsd, DsdSource};
+use crate::hls::{is_hls, open_hls};
+use crate::midi::{is_midi, MidiSource};
+use crate::net::{is_url, show_path};
+use crate::radio::{content_type_extension, HttpStream, StreamInfo, READ_TIMEOUT};
+
+// Opening tracks for playback and export: a Symphonia decoder for regular audio files, and the built-in
+// renderers for formats Symphonia doesn't handle. Corrupt packets are logged and skipped; a decoder that
+// stops making progress is given up on (see `open_track_guarded`).
+
+/// How long opening a file (or connecting to a stream) may take before it is treated as hung.
+const OPEN_TIMEOUT: Duration = Duration::from_secs(10);
+const STREAM_OPEN_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long playback waits for the decoder before giving up on the track. Network streams get longer, and their
+/// own read timeout normally gives a better reason first.
+const STALL_TIMEOUT: Duration = Duration::from_secs(5);
+const STREAM_STALL_TIMEOUT: Duration = Duration::from_secs(READ_TIMEOUT.as_secs() + 15);
+/// Corrupt packets in a row after which a file is considered unreadable rather than damaged in places.
+const MAX_BAD_PACKETS: u32 = 100;
+// Samples per chunk handed over by the decoding thread, and how many chunks it may run ahead.
+const CHUNK_SAMPLES: usize = 4096;
+const CHUNKS_AHEAD: usize = 16;
+
+/// Why a track's source ended before the end of the file, if it did, and how much silence was played while
+/// waiting for the decoder. Shared between the decoder and its owner.
+#[derive(Clone, Default)]
+pub(crate) struct DecodeStatus {
+    error: Arc<Mutex<Option<String>>>,
+    padding_nanos: Arc<AtomicU64>,
+}
+
+impl DecodeStatus {
+    /// Record a failure that ends the stream (the first one wins).
+    pub(crate) fn fail(&self, reason: String) {
+        let mut error = self.error.lock().unwrap();
+        if error.is_none() { *error = Some(reason); }
+    }
+
+    pub(crate) fn take_error(&self) -> Option<String> { self.error.lock().unwrap().take() }
+
+    /// Silence played in place of audio the decoder hadn't delivered yet. It isn't part of the file, so it
+    /// doesn't move the file position.
+    pub(crate) fn padding(&self) -> Duration { Duration::from_nanos(self.padding_nanos.load(Ordering::Relaxed)) }
+}
+
+/// A decoded track positioned at the requested start.
+pub(crate) struct OpenedTrack {
+    pub source: Box<dyn Source<Item = f32> + Send>,
+    /// Length of the whole file, when the container reports it.
+    pub duration: Option<Duration>,
+    /// Set when decoding ends early because the file is broken.
+    pub status: DecodeStatus,
+    /// Live station and title information for network streams.
+    pub stream: Option<StreamInfo>,
+}
+
+/// Open `path` and skip to `start` (file time). `soundfont` is used to render MIDI files.
+pub(crate) fn open_track(path: &Path, start: Duration, soundfont: Option<&Path>) -> Result<OpenedTrack, String> {
+    let status = DecodeStatus::default();
+    let mut stream = None;
+    let source: Box<dyn Source<Item = f32> + Send> = if is_url(path) {
+        let (http, info, ext) = open_stream(path)?;
+        stream = Some(info);
+        Box::new(SymphoniaSource::from_media(path, Box::new(http), Some(ext).filter(|e| !e.is_empty()), start, status.clone())?)
+    } else if is_midi(path) {
+        let soundfont = soundfont.ok_or("No SoundFont available for MIDI playback (put an .sf2 file in the music folder)")?;
+        Box::new(MidiSource::open(path, soundfont, start)?)
+    } else if is_dsd(path) {
+        Box::new(DsdSource::open(path, start, status.clone())?)
+    } else {
+        Box::new(SymphoniaSource::open(path, start, status.clone())?)
+    };
+    Ok(OpenedTrack { duration: source.total_duration(), source, status, stream })
+}
+
+/// Connect to the network stream at `path` (radio or HLS). Returns the stream, its live info and the format
+/// Symphonia should expect.
+pub(crate) fn open_stream(path: &Path) -> Result<(HttpStream, StreamInfo, &str), String> {
+    let url = path.to_string_lossy();
+    let (http, info, ext) = if is_hls(path, None) { open_hls(&url)? } else {
+        let (http, info) = HttpStream::open(&url)?;
+        let content_type = info.get().content_type;
+        if is_hls(path, content_type.as_deref()) {
+            drop(http);
+            open_hls(&url)?
+        } else {
+            // The server's Content-Type is more reliable than the URL, which often has no extension
+            let ext = content_type.as_deref().and_then(content_type_extension).or_else(|| path.extension().and_then(|e| e.to_str())).unwrap_or_default();
+            (http, info, ext)
+        }
+    };
+    info.update(|m| m.format = Some(ext.to_ascii_lowercase()).filter(|e| !e.is_empty()));
+    Ok((http, info, ext))
+}
+
+/// `open_track` for playback. Opening runs on a helper thread and is abandoned after `OPEN_TIMEOUT`, and the
+/// source decodes ahead on its own thread so a decoder that hangs mid-track ends the track (with the reason in
+/// `status`) instead of blocking the audio output. A hung thread can't be stopped; it is left behind.
+pub(crate) fn open_track_guarded(path: &Path, start: Duration, soundfont: Option<&Path>) -> Result<OpenedTrack, String> {
+    let (tx, rx) = mpsc::channel();
+    let (path_buf, soundfont) = (path.to_path_buf(), soundfont.map(Path::to_path_buf));
+    std::thread::Builder::new().name("open-track".into())
+        .spawn(move || { let _ = tx.send(open_track(&path_buf, start, soundfont.as_deref())); })
+        .map_err(|e| format!("Failed to start decoder: {e}"))?;
+    let (open_timeout, stall) = if is_url(path) { (STREAM_OPEN_TIMEOUT, STREAM_STALL_TIMEOUT) } else { (OPEN_TIMEOUT, STALL_TIMEOUT) };
+    let track = match rx.recv_timeout(open_timeout) {
+        Ok(result) => result?,
+        Err(_) => return Err(format!("Decoder did not respond within {}s while opening the file", open_timeout.as_secs())),
+    };
+    let source = DecodeAhead::spawn(track.source, track.status.clone(), stall)?;
+    Ok(OpenedTrack { source: Box::new(source), ..track })
+}
+
+/// Runs a source on a separate thread, handing samples over in chunks. It never blocks the audio output: while
+/// the decoder falls behind it plays silence (counted in the status' `padding`), and if no chunk arrives within
+/// `stall` the stream ends and the stall is reported through the status.
+struct DecodeAhead {
+    rx: Receiver<Vec<f32>>,
+    chunk: Vec<f32>,
+    pos: usize,
+    // Whole frames per chunk, so silence played while starved never splits a frame
+    chunk_len: usize,
+    // Since when the decoder has been behind, and the rest of the silent frame being played meanwhile
+    starved_since: Option<Instant>,
+    silence: usize,
+    // Silent frames played so far; the status gets the time they add up to
+    padded_frames: u64,
+    channels: u16,
+    sample_rate: u32,
+    total_duration: Option<Duration>,
+    status: DecodeStatus,
+    stall: Duration,
+    done: bool,
+}
+
+impl DecodeAhead {
+    fn spawn(mut source: Box<dyn Source<Item = f32> + Send>, status: DecodeStatus, stall: Duration) -> Result<Self, String> {
+        let (channels, sample_rate, total_duration) = (source.channels(), source.sample_rate(), source.total_duration());
+        let chunk_len = CHUNK_SAMPLES / channels.max(1) as usize * channels.max(1) as usize;
+        let (tx, rx) = mpsc::sync_channel(CHUNKS_AHEAD);
+        std::thread::Builder::new().name("decode".into()).spawn(move || loop {
+            let chunk: Vec<f32> = source.by_ref().take(chunk_len).collect();
+            let last = chunk.len() < chunk_len;
+            // The receiver is gone once the track is stopped or replaced
+            if tx.send(chunk).is_err() || last { break; }
+        }).map_err(|e| format!("Failed to start decoder: {e}"))?;
+        // Wait for the first chunk here rather than in the output, so playback doesn't start with a gap
+        let first = rx.recv_timeout(stall).map_err(|_| format!("Decoder stopped responding for {}s", stall.as_secs()))?;
+        let done = first.len() < chunk_len;
+        Ok(Self { rx, chunk: first, pos: 0, chunk_len, starved_since: None, silence: 0, padded_frames: 0, channels, sample_rate, total_duration, status, stall, done })
+    }
+
+    /// Start a frame of silence while the decoder catches up, or end the stream once it has stalled for too long.
+    fn starve(&mut self) -> Option<f32> {
+        let since = *self.starved_since.get_or_insert_with(Instant::now);
+        if since.elapsed() >= self.stall {
+            self.status.fail(format!("Decoder stopped responding for {}s", self.stall.as_secs()));
+            self.done = true;
+            return None;
+        }
+        self.silence = self.channels.max(1) as usize - 1;
+        self.padded_frames += 1;
+        self.status.padding_nanos.store(self.padded_frames * 1_000_000_000 / self.sample_rate.max(1) as u64, Ordering::Relaxed);
+        Some(0.0)
+    }
+}
+
+impl Iterator for DecodeAhead {
+    type Item = f32;
+    fn next(&mut self) -> Option<f32> {
+        if self.silence > 0 { self.silence -= 1; return Some(0.0); }
+        while self.pos >= self.chunk.len() {
+            if self.done { return None; }
+            // This runs in the audio callback, so it must not wait for the decoder
+            match self.rx.try_recv() {
+                Ok(chunk) => { self.done = chunk.len() < self.chunk_len; (self.chunk, self.pos) = (chunk, 0); self.starved_since = None; }
+                Err(TryRecvError::Disconnected) => { self.done = true; }
+                Err(TryRecvError::Empty) => return self.starve(),
+            }
+        }
+        let x = self.chunk[self.pos];
+        self.pos += 1;
+        Some(x)
+    }
+}
+
+impl Source for DecodeAhead {
+    fn current_span_len(&self) -> Option<usize> { None }
+    fn channels(&self) -> u16 { self.channels }
+    fn sample_rate(&self) -> u32 { self.sample_rate }
+    fn total_duration(&self) -> Option<Duration> { self.total_duration }
+}
+
+/// A file decoded with Symphonia. Unlike rodio's decoder it logs corrupt packets, keeps a fixed channel layout
+/// and reports why it stopped when that wasn't the end of the file.
+struct SymphoniaSource {
+    path: PathBuf,
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    duration: Option<Duration>,
+    /// Taken from the first decoded packet.
+    channels: u16,
+    sample_rate: u32,
+    buf: Vec<f32>,
+    pos: usize,
+    sample_buf: Option<SampleBuffer<f32>>,
+    /// Audio to drop after seeking (the seek lands on a packet boundary before the target), converted to
+    /// `skip_frames` once the sample rate is known.
+    skip: Duration,
+    skip_frames: u64,
+    bad_packets: u32,
+    status: DecodeStatus,
+}
+
+impl SymphoniaSource {
+    fn open(path: &Path, start: Duration, status: DecodeStatus) -> Result<Self, String> {
+        let file = std::fs::File::open(path).map_err(|e| format!("Failed to open file: {e}"))?;
+        let ext = path.extension().and_then(|e| e.to_str());
+        Self::from_media(path, Box::new(file), ext, start, status)
+    }
+
+    /// Decode `media`; `path` names it in logs and `ext` hints at the format.
+    fn from_media(path: &Path, media: Box<dyn MediaSource>, ext: Option<&str>, start: Duration, status: DecodeStatus) -> Result<Self, String> {
+        let mut hint = Hint::new();
+        if let Some(ext) = ext { hint.with_extension(ext); }
+        let mss = MediaSourceStream::new(media, Default::default());
+        let probed = symphonia::default::get_probe().format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())
+            .map_err(|e| format!("Failed to decode audio: {e}"))?;
+        let mut format = probed.format;
+        let track = format.default_track().filter(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .or_else(|| format.tracks().iter().find(|t| t.codec_params.codec != CODEC_TYPE_NULL))
+            .cloned().ok_or("Failed to decode audio: no audio track")?;
+        let decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())
+            .map_err(|e| format!("Failed to decode audio: {e}"))?;
+        let time_base = track.codec_params.time_base;
+        let duration = time_base.zip(track.codec_params.n_frames).map(|(tb, n)| time_to_duration(tb.calc_time(n)));
+
+        // Seek to the packet before `start` and decode the rest of the way; files that can't seek are decoded from the top
+        let mut skip = start;
+        if !start.is_zero() {
+            let time = Time::new(start.as_secs(), start.subsec_nanos() as f64 / 1e9);
+            match format.seek(SeekMode::Accurate, SeekTo::Time { time, track_id: Some(track.id) }) {
+                Ok(seeked) => skip = time_base.map_or(Duration::ZERO, |tb| time_to_duration(tb.calc_time(seeked.required_ts.saturating_sub(seeked.actual_ts)))),
+                Err(e) => log::debug!("{}: seek failed ({e}), decoding up to {start:?}", show_path(path)),
+            }
+        }
+        let mut source = Self {
+            path: path.to_path_buf(), format, decoder, track_id: track.id, duration,
+            channels: 0, sample_rate: 0, buf: Vec::new(), pos: 0, sample_buf: None, skip, skip_frames: 0, bad_packets: 0, status,
+        };
+        // The output format is only known once a packet is decoded
+        if !source.fill() {
+            return Err(source.status.take_error().unwrap_or_else(|| "Failed to decode audio: no audio data".to_string()));
+        }
+        Ok(source)
+    }
+
+    /// Count a corrupt packet. Returns `false` once there have been too many in a row.
+    fn tolerate(&mut self, reason: &SymError) -> bool {
+        self.bad_packets += 1;
+        log::warn!("{}: skipping corrupt data ({reason})", show_path(&self.path));
+        if self.bad_packets > MAX_BAD_PACKETS {
+            self.fail(format!("Too many decoding errors ({reason})"));
+            return false;
+        }
+        true
+    }
+
+    fn fail(&self, reason: String) {
+        log::warn!("{}: {reason}", show_path(&self.path));
+        self.status.fail(reason);
+    }
+
+    /// Decode the next packet with audio into `buf`. Returns `false` at the end of the stream or on a fatal error.
+    fn fill(&mut self) -> bool {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => return false,
+                Err(SymError::ResetRequired) => {
+                    // A new stream follows (e.g. chained Ogg); continue with a decoder for its parameters
+                    let params = self.format.tracks().iter().find(|t| t.id == self.track_id).map(|t| t.codec_params.clone());
+                    match params.map(|p| symphonia::default::get_codecs().make(&p, &DecoderOptions::default())) {
+                        Some(Ok(decoder)) => { self.decoder = decoder; continue; }
+                        _ => { self.fail("Stream changed to an unsupported format".to_string()); return false; }
+                    }
+                }
+                Err(e @ SymError::DecodeError(_)) => { if self.tolerate(&e) { continue } else { return false } }
+                Err(e) => { self.fail(format!("Read error: {e}")); return false; }
+            };
+            if packet.track_id() != self.track_id { continue; }
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(e @ (SymError::DecodeError(_) | SymError::IoError(_))) => { if self.tolerate(&e) { continue } else { return false } }
+                Err(e) => { self.fail(format!("Decoding failed: {e}")); return false; }
+            };
+            self.bad_packets = 0;
+            if decoded.frames() == 0 { continue; }
+            let spec = *decoded.spec();
+            let src_channels = spec.channels.count().max(1);
+            if self.channels == 0 {
+                (self.channels, self.sample_rate) = (src_channels as u16, spec.rate);
+                self.skip_frames = (self.skip.as_secs_f64() * spec.rate as f64).round() as u64;
+            }
+            if self.sample_buf.as_ref().is_none_or(|b| b.capacity() < decoded.capacity() * src_channels) {
+                self.sample_buf = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+            }
+            let sample_buf = self.sample_buf.as_mut().unwrap();
+            sample_buf.copy_interleaved_ref(decoded);
+            let samples = sample_buf.samples();
+            let channels = self.channels as usize;
+            // Keep the layout of the start of the file if a later stream differs; the output can't change mid-track
+            self.buf.clear();
+            if src_channels == channels {
+                self.buf.extend_from_slice(samples);
+            } else {
+                for frame in samples.chunks_exact(src_channels) {
+                    self.buf.extend((0..channels).map(|c| frame[c.min(src_channels - 1)]));
+                }
+            }
+            let dropped = self.skip_frames.min((self.buf.len() / channels) as u64);
+            self.skip_frames -= dropped;
+            self.pos = dropped as usize * channels;
+            if self.pos < self.buf.len() { return true; }
+        }
+    }
+}
+
+fn time_to_duration(time: Time) -> Duration { Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac) }
+
+impl Iterator for SymphoniaSource {
+    type Item = f32;
+    fn next(&mut self) -> Option<f32> {
+        if self.pos >= self.buf.len() && !self.fill() { return None; }
+        let x = self.buf[self.pos];
+        self.pos += 1;
+        Some(x)
+    }
+}
+
+impl Source for SymphoniaSource {
+    fn current_span_len(&self) -> Option<usize> { None }
+    fn channels(&self) -> u16 { self.channels }
+    fn sample_rate(&self) -> u32 { self.sample_rate }
+    fn total_duration(&self) -> Option<Duration> { self.duration }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{reply, serve};
+
+    // A 128 kbit/s, 44.1 kHz stereo MP3 frame of 1152 samples: silent when zeroed, undecodable when filled with 0xff
+    fn mp3_frame(corrupt: bool) -> Vec<u8> {
+        let mut frame = vec![if corrupt { 0xff } else { 0 }; 417];
+        frame[..4].copy_from_slice(&[0xff, 0xfb, 0x90, 0x64]);
+        frame
+    }
+
+    /// `good` frames, `bad` corrupt ones, then `good` frames again.
+    fn mp3(good: usize, bad: usize) -> Vec<u8> {
+        let good = mp3_frame(false).repeat(good);
+        [good.clone(), mp3_frame(true).repeat(bad), good].concat()
+    }
+
+    fn write_temp(name: &str, data: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("decode-{}-{name}", std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn corrupt_packets_are_skipped() {
+        let path = write_temp("damaged.mp3", &mp3(3, MAX_BAD_PACKETS as usize));
+        let track = open_track(&path, Duration::ZERO, None).unwrap();
+        assert_eq!(track.source.count(), 6 * 1152 * 2);
+        assert_eq!(track.status.take_error(), None);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn too_many_corrupt_packets_end_the_track() {
+        let path = write_temp("broken.mp3", &mp3(3, MAX_BAD_PACKETS as usize + 1));
+        let track = open_track(&path, Duration::ZERO, None).unwrap();
+        assert_eq!(track.source.count(), 3 * 1152 * 2);
+        assert!(track.status.take_error().is_some_and(|e| e.starts_with("Too many decoding errors")));
+        // A file that is broken from the start doesn't open at all
+        std::fs::write(&path, mp3(0, MAX_BAD_PACKETS as usize + 1)).unwrap();
+        assert!(open_track(&path, Duration::ZERO, None).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn broken_stream_ends_with_the_reason() {
+        let base = serve(|request, stream| match request.path() {
+            "/broken.mp3" => reply(stream, 200, "audio/mpeg", &mp3(3, MAX_BAD_PACKETS as usize + 1)),
+            _ => reply(stream, 404, "text/plain", b"no such stream"),
+        });
+        let track = open_track_guarded(Path::new(&format!("{base}/broken.mp3")), Duration::ZERO, None).unwrap();
+        // Silence played while the decoder was behind doesn't count as audio
+        let samples = track.source.count();
+        let padded = (track.status.padding().as_secs_f64() * 44_100.0).round() as usize * 2;
+        assert_eq!(samples - padded, 3 * 1152 * 2);
+        assert!(track.status.take_error().is_some_and(|e| e.starts_with("Too many decoding errors")));
+        assert!(open_track_guarded(Path::new(&format!("{base}/missing.mp3")), Duration::ZERO, None).is_err());
+    }
+
+    // Two chunks of audio, then a decoder that hangs until `release` is dropped
+    struct Hang { left: usize, release: Receiver<()> }
+    impl Iterator for Hang {
+        type Item = f32;
+        fn next(&mut self) -> Option<f32> {
+            if self.left == 0 { let _ = self.release.recv(); return None; }
+            self.left -= 1;
+            Some(0.5)
+        }
+    }
+    impl Source for Hang {
+        fn current_span_len(&self) -> Option<usize> { None }
+        fn channels(&self) -> u16 { 2 }
+        fn sample_rate(&self) -> u32 { 8000 }
+        fn total_duration(&self) -> Option<Duration> { None }
+    }
+
+    #[test]
+    fn hung_decoder_plays_silence_then_gives_up() {
+        let (release, rx) = mpsc::channel();
+        let status = DecodeStatus::default();
+        let stall = Duration::from_secs(1);
+        let source = DecodeAhead::spawn(Box::new(Hang { left: 2 * CHUNK_SAMPLES, release: rx }), status.clone(), stall).unwrap();
+        let started = Instant::now();
+        // The output keeps getting whole frames of silence whenever the decoder is behind, until the stall timeout
+        let (mut audio, mut silence) = (0, 0);
+        for x in source { if x == 0.5 { audio += 1 } else { silence += 1 } }
+        assert!(started.elapsed() >= stall);
+        assert_eq!(audio, 2 * CHUNK_SAMPLES);
+        assert!(silence > 0 && silence % 2 == 0);
+        assert_eq!(status.padding(), Duration::from_nanos(silence as u64 / 2 * 1_000_000_000 / 8000));
+        assert_eq!(status.take_error(), Some("Decoder stopped responding for 1s".to_string()));
+        drop(release);
+    }
+}
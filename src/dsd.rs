@@ -0,0 +1,394 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::decode::DecodeStatus;
+use crate::id3::Id3Tag;
+
+// DSD playback: DSF (Sony) and DSDIFF/DFF (Philips) containers of raw 1-bit DSD, converted to PCM with a
+// low-pass FIR and decimation by 32 (DSD64 -> 88.2 kHz, DSD128 -> 176.4 kHz; higher rates decimate further
+// so the output stays at 176.4 kHz). DST-compressed DFF files are not supported.
+
+/// Highest PCM rate produced; DSD256 and above are decimated more.
+const MAX_OUTPUT_RATE: u32 = 176_400;
+// Bytes per channel read from the file at a time for DFF (DSF uses the file's own block size).
+const DFF_READ_BYTES: usize = 4096;
+// Limits on what a header may claim, so a corrupt one can't make the decoder allocate without bound. DSF uses
+// 4096-byte blocks.
+const MAX_CHANNELS: usize = 8;
+const MAX_BLOCK: usize = 64 * 1024;
+// The DSD idle pattern: equal ones and zeros, i.e. silence. Used to prime the filter.
+const DSD_SILENCE: u8 = 0x69;
+
+pub(crate) fn is_dsd(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("dsf") || e.eq_ignore_ascii_case("dff"))
+}
+
+#[derive(Clone, Copy)]
+enum Layout {
+    /// Per-channel blocks of `block` bytes, least significant bit first (DSF).
+    Blocks { block: usize, lsb_first: bool },
+    /// Bytes interleaved channel by channel, most significant bit first (DFF).
+    Interleaved,
+}
+
+struct DsdFile {
+    layout: Layout,
+    channels: usize,
+    rate: u32,
+    data_start: u64,
+    /// DSD bytes per channel.
+    bytes: u64,
+    /// Offset of an ID3v2 tag, if the file has one.
+    tag_offset: Option<u64>,
+}
+
+fn read_exact<const N: usize>(r: &mut impl Read) -> Result<[u8; N], String> {
+    let mut buf = [0u8; N];
+    r.read_exact(&mut buf).map_err(|e| format!("Truncated DSD file: {e}"))?;
+    Ok(buf)
+}
+
+fn check_format(channels: usize, rate: u32) -> Result<(), String> {
+    if channels == 0 || channels > MAX_CHANNELS { return Err(format!("Unsupported DSD channel count {channels}")); }
+    // DSD64 up to DSD1024, on either the 44.1 kHz or the 48 kHz family; anything else is a broken header
+    let valid = [44_100, 48_000].iter().any(|base| (0..5).any(|k| rate == (base * 64) << k));
+    if !valid { return Err(format!("Unsupported DSD rate {rate} Hz")); }
+    Ok(())
+}
+
+fn parse_dsf(r: &mut (impl Read + Seek)) -> Result<DsdFile, String> {
+    let header: [u8; 28] = read_exact(r)?;
+    if &header[..4] != b"DSD " { return Err("Not a DSF file".to_string()); }
+    let tag_offset = Some(u64::from_le_bytes(header[20..28].try_into().unwrap())).filter(|&p| p != 0);
+    let fmt: [u8; 52] = read_exact(r)?;
+    if &fmt[..4] != b"fmt " { return Err("DSF file has no fmt chunk".to_string()); }
+    let le32 = |i: usize| u32::from_le_bytes(fmt[i..i + 4].try_into().unwrap());
+    if le32(16) != 0 { return Err("Unsupported DSF format".to_string()); }
+    let (channels, rate, bits) = (le32(24) as usize, le32(28), le32(32));
+    let samples = u64::from_le_bytes(fmt[36..44].try_into().unwrap());
+    let block = le32(44) as usize;
+    let data: [u8; 12] = read_exact(r)?;
+    if &data[..4] != b"data" { return Err("DSF file has no data chunk".to_string()); }
+    if block == 0 || block > MAX_BLOCK { return Err("Invalid DSF header".to_string()); }
+    check_format(channels, rate)?;
+    let data_start = r.stream_position().map_err(|e| e.to_string())?;
+    Ok(DsdFile { layout: Layout::Blocks { block, lsb_first: bits == 1 }, channels, rate, data_start, bytes: samples / 8, tag_offset })
+}
+
+fn parse_dff(r: &mut (impl Read + Seek)) -> Result<DsdFile, String> {
+    let header: [u8; 16] = read_exact(r)?;
+    if &header[..4] != b"FRM8" || &header[12..16] != b"DSD " { return Err("Not a DSDIFF file".to_string()); }
+    // Sizes come from the file; adding them up must not overflow
+    let form_end = u64::from_be_bytes(header[4..12].try_into().unwrap()).saturating_add(12);
+    let (mut channels, mut rate, mut data, mut tag_offset) = (0usize, 0u32, None, None);
+    let mut pos = 16u64;
+    while pos.checked_add(12).is_some_and(|end| end <= form_end) {
+        r.seek(SeekFrom::Start(pos)).map_err(|e| e.to_string())?;
+        let Ok(chunk) = read_exact::<12>(r) else { break };
+        let size = u64::from_be_bytes(chunk[4..12].try_into().unwrap());
+        let body = pos + 12;
+        match &chunk[..4] {
+            b"PROP" => {
+                // "SND " followed by property sub-chunks
+                let mut sub = body + 4;
+                while sub.checked_add(12).is_some_and(|end| end <= body.saturating_add(size)) {
+                    r.seek(SeekFrom::Start(sub)).map_err(|e| e.to_string())?;
+                    let sub_chunk: [u8; 12] = read_exact(r)?;
+                    let sub_size = u64::from_be_bytes(sub_chunk[4..12].try_into().unwrap());
+                    match &sub_chunk[..4] {
+                        b"FS  " => rate = u32::from_be_bytes(read_exact(r)?),
+                        b"CHNL" => channels = u16::from_be_bytes(read_exact(r)?) as usize,
+                        b"CMPR" if &read_exact::<4>(r)? != b"DSD " => return Err("Compressed (DST) DFF files are not supported".to_string()),
+                        _ => {}
+                    }
+                    sub = sub.saturating_add(12).saturating_add(sub_size).saturating_add(sub_size & 1);
+                }
+            }
+            b"DSD " => data = Some((body, size)),
+            b"DST " => return Err("Compressed (DST) DFF files are not supported".to_string()),
+            b"ID3 " => tag_offset = Some(body),
+            _ => {}
+        }
+        pos = body.saturating_add(size).saturating_add(size & 1);
+    }
+    let (data_start, size) = data.ok_or("DFF file has no DSD data")?;
+    check_format(channels, rate)?;
+    Ok(DsdFile { layout: Layout::Interleaved, channels, rate, data_start, bytes: size / channels as u64, tag_offset })
+}
+
+fn open_dsd(path: &Path) -> Result<(BufReader<File>, DsdFile), String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open file: {e}"))?;
+    let mut r = BufReader::new(file);
+    let is_dff = path.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("dff"));
+    let info = if is_dff { parse_dff(&mut r)? } else { parse_dsf(&mut r)? };
+    Ok((r, info))
+}
+
+/// DSD rate (e.g. 2822400 for DSD64), channel count and length of a DSF/DFF file.
+pub(crate) fn read_dsd_format(path: &Path) -> Option<(u32, usize, Duration)> {
+    let (_, info) = open_dsd(path).ok()?;
+    Some((info.rate, info.channels, Duration::from_secs_f64(info.bytes as f64 * 8.0 / info.rate as f64)))
+}
+
+/// The ID3v2 tag of a DSF file (or a DFF file with an `ID3 ` chunk).
+pub(crate) fn read_dsd_tag(path: &Path) -> Option<Id3Tag> {
+    let (mut r, info) = open_dsd(path).ok()?;
+    r.seek(SeekFrom::Start(info.tag_offset?)).ok()?;
+    let mut data = Vec::new();
+    r.read_to_end(&mut data).ok()?;
+    Id3Tag::parse(&data)
+}
+
+/// Low-pass FIR over the 1-bit stream, evaluated a byte (8 taps) at a time through lookup tables.
+struct DsdFilter {
+    /// `tables[k][byte]`: contribution of taps `8k..8k+8` for that byte, most significant bit first.
+    tables: Vec<[f32; 256]>,
+}
+
+impl DsdFilter {
+    fn new(decimation: usize) -> Self {
+        // Blackman-windowed sinc; the cutoff at 30% of the output rate keeps the shaped noise above
+        // ~25 kHz (DSD64) out of the audio band while still passing it through unaliased
+        let taps = decimation * 32;
+        let cutoff = 0.3 / decimation as f64;
+        let centre = (taps - 1) as f64 / 2.0;
+        let mut coefs: Vec<f64> = (0..taps).map(|i| {
+            let x = i as f64 - centre;
+            let sinc = if x == 0.0 { 2.0 * cutoff } else { (2.0 * std::f64::consts::PI * cutoff * x).sin() / (std::f64::consts::PI * x) };
+            let w = i as f64 / (taps - 1) as f64 * 2.0 * std::f64::consts::PI;
+            sinc * (0.42 - 0.5 * w.cos() + 0.08 * (2.0 * w).cos())
+        }).collect();
+        let sum: f64 = coefs.iter().sum();
+        coefs.iter_mut().for_each(|c| *c /= sum);
+        let tables = coefs.chunks(8).map(|c| {
+            let mut table = [0f32; 256];
+            for (byte, out) in table.iter_mut().enumerate() {
+                *out = c.iter().enumerate().map(|(j, &k)| if byte >> (7 - j) & 1 != 0 { k } else { -k }).sum::<f64>() as f32;
+            }
+            table
+        }).collect();
+        Self { tables }
+    }
+
+    fn apply(&self, history: &[u8]) -> f32 {
+        self.tables.iter().zip(history).map(|(t, &b)| t[b as usize]).sum()
+    }
+}
+
+/// A DSF/DFF file converted to PCM.
+pub(crate) struct DsdSource {
+    reader: BufReader<File>,
+    info: DsdFile,
+    filter: Arc<DsdFilter>,
+    /// Bytes per channel consumed per output sample.
+    step: usize,
+    /// DSD bytes per channel not yet read from the file.
+    remaining: u64,
+    /// Bytes read but not yet filtered, per channel (MSB first), from `pending_pos` on.
+    pending: Vec<Vec<u8>>,
+    pending_pos: usize,
+    /// Filter history per channel, written twice so the window is always contiguous.
+    history: Vec<Vec<u8>>,
+    history_pos: usize,
+    frame: Vec<f32>,
+    frame_pos: usize,
+    status: DecodeStatus,
+}
+
+impl DsdSource {
+    pub(crate) fn open(path: &Path, start: Duration, status: DecodeStatus) -> Result<Self, String> {
+        let (mut reader, info) = open_dsd(path)?;
+        let mut decimation = 32;
+        while info.rate / decimation as u32 > MAX_OUTPUT_RATE { decimation *= 2; }
+        let step = decimation / 8;
+        let filter = Arc::new(DsdFilter::new(decimation));
+        let taps_bytes = filter.tables.len();
+
+        // Seek: whole output samples from the start of the data
+        let offset = ((start.as_secs_f64() * info.rate as f64 / 8.0) as u64 / step as u64 * step as u64).min(info.bytes);
+        let (file_offset, read_from, skip) = match info.layout {
+            Layout::Blocks { block, .. } => {
+                let block_index = offset / block as u64;
+                (block_index * (block * info.channels) as u64, block_index * block as u64, (offset % block as u64) as usize)
+            }
+            Layout::Interleaved => (offset * info.channels as u64, offset, 0),
+        };
+        reader.seek(SeekFrom::Start(info.data_start + file_offset)).map_err(|e| format!("Seek failed: {e}"))?;
+        let channels = info.channels;
+        let mut source = Self {
+            reader, filter, step,
+            remaining: info.bytes - read_from,
+            pending: vec![Vec::new(); channels],
+            pending_pos: 0,
+            history: vec![vec![DSD_SILENCE; taps_bytes * 2]; channels],
+            history_pos: 0,
+            frame: vec![0.0; channels],
+            frame_pos: channels,
+            info,
+            status,
+        };
+        if skip > 0 {
+            source.fill()?;
+            source.pending_pos = skip;
+        }
+        Ok(source)
+    }
+
+    /// Read the next block of DSD data into `pending`. Returns `false` at the end of the data.
+    fn fill(&mut self) -> Result<bool, String> {
+        if self.remaining == 0 { return Ok(false); }
+        let channels = self.info.channels;
+        for p in self.pending.iter_mut() { p.drain(..self.pending_pos.min(p.len())); }
+        self.pending_pos = 0;
+        match self.info.layout {
+            Layout::Blocks { block, lsb_first } => {
+                let mut buf = vec![0u8; block * channels];
+                self.reader.read_exact(&mut buf).map_err(|e| format!("Read failed: {e}"))?;
+                // The last block is padded; only `remaining` bytes of it are audio
+                let valid = (self.remaining as usize).min(block);
+                for (c, p) in self.pending.iter_mut().enumerate() {
+                    let bytes = &buf[c * block..c * block + valid];
+                    if lsb_first { p.extend(bytes.iter().map(|b| b.reverse_bits())); } else { p.extend_from_slice(bytes); }
+                }
+                self.remaining -= valid as u64;
+            }
+            Layout::Interleaved => {
+                let per_channel = (self.remaining as usize).min(DFF_READ_BYTES);
+                let mut buf = vec![0u8; per_channel * channels];
+                self.reader.read_exact(&mut buf).map_err(|e| format!("Read failed: {e}"))?;
+                for frame in buf.chunks_exact(channels) {
+                    for (p, &b) in self.pending.iter_mut().zip(frame) { p.push(b); }
+                }
+                self.remaining -= per_channel as u64;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Filter the next output frame into `frame`. Returns `false` at the end of the data.
+    fn next_frame(&mut self) -> bool {
+        while self.pending[0].len() < self.pending_pos + self.step {
+            match self.fill() {
+                Ok(true) => {}
+                Ok(false) => return false,
+                Err(e) => { self.status.fail(e); return false; }
+            }
+        }
+        let taps_bytes = self.filter.tables.len();
+        for c in 0..self.info.channels {
+            let history = &mut self.history[c];
+            let mut pos = self.history_pos;
+            for &b in &self.pending[c][self.pending_pos..self.pending_pos + self.step] {
+                history[pos] = b;
+                history[pos + taps_bytes] = b;
+                pos = (pos + 1) % taps_bytes;
+            }
+            self.frame[c] = self.filter.apply(&history[pos..pos + taps_bytes]);
+        }
+        self.history_pos = (self.history_pos + self.step) % taps_bytes;
+        self.pending_pos += self.step;
+        self.frame_pos = 0;
+        true
+    }
+}
+
+impl Iterator for DsdSource {
+    type Item = f32;
+    fn next(&mut self) -> Option<f32> {
+        if self.frame_pos >= self.frame.len() && !self.next_frame() { return None; }
+        let x = self.frame[self.frame_pos];
+        self.frame_pos += 1;
+        Some(x)
+    }
+}
+
+impl rodio::Source for DsdSource {
+    fn channels(&self) -> u16 { self.info.channels as u16 }
+    fn sample_rate(&self) -> u32 { self.info.rate / (self.step as u32 * 8) }
+    fn current_span_len(&self) -> Option<usize> { None }
+    fn total_duration(&self) -> Option<Duration> { Some(Duration::from_secs_f64(self.info.bytes as f64 * 8.0 / self.info.rate as f64)) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn dsf_header(channels: u32, rate: u32, block: u32) -> Vec<u8> {
+        let mut out = b"DSD ".to_vec();
+        out.extend(28u64.to_le_bytes());
+        out.extend(0u64.to_le_bytes());
+        out.extend(0u64.to_le_bytes());
+        out.extend(b"fmt ");
+        out.extend(52u64.to_le_bytes());
+        for v in [1, 0, 2, channels, rate, 1] { out.extend(v.to_le_bytes()); }
+        out.extend(2_822_400u64.to_le_bytes());
+        out.extend(block.to_le_bytes());
+        out.extend(0u32.to_le_bytes());
+        out.extend(b"data");
+        out.extend(12u64.to_le_bytes());
+        out
+    }
+
+    fn dff_header(channels: u16, rate: u32) -> Vec<u8> {
+        let mut prop = b"SND ".to_vec();
+        prop.extend(b"FS  ");
+        prop.extend(4u64.to_be_bytes());
+        prop.extend(rate.to_be_bytes());
+        prop.extend(b"CHNL");
+        prop.extend(2u64.to_be_bytes());
+        prop.extend(channels.to_be_bytes());
+        let mut body = b"DSD ".to_vec();
+        body.extend(b"PROP");
+        body.extend((prop.len() as u64).to_be_bytes());
+        body.extend(prop);
+        body.extend(b"DSD ");
+        body.extend(0u64.to_be_bytes());
+        let mut out = b"FRM8".to_vec();
+        out.extend((body.len() as u64).to_be_bytes());
+        out.extend(body);
+        out
+    }
+
+    #[test]
+    fn accepts_usual_dsf_headers() {
+        let info = parse_dsf(&mut Cursor::new(dsf_header(2, 2_822_400, 4096))).unwrap();
+        assert_eq!((info.channels, info.rate, info.bytes), (2, 2_822_400, 352_800));
+        assert!(matches!(info.layout, Layout::Blocks { block: 4096, lsb_first: true }));
+    }
+
+    #[test]
+    fn rejects_dsf_headers_that_would_allocate_without_bound() {
+        for (channels, rate, block) in [(0, 2_822_400, 4096), (9, 2_822_400, 4096), (u32::MAX, 2_822_400, 4096), (2, 2_822_400, 0), (2, 2_822_400, u32::MAX), (2, u32::MAX, 4096)] {
+            assert!(parse_dsf(&mut Cursor::new(dsf_header(channels, rate, block))).is_err(), "{channels} channels, {rate} Hz, {block} byte blocks");
+        }
+    }
+
+    #[test]
+    fn accepts_only_dsd_rates() {
+        for rate in [2_822_400, 3_072_000, 5_644_800, 11_289_600, 12_288_000, 45_158_400, 49_152_000] {
+            assert!(parse_dsf(&mut Cursor::new(dsf_header(2, rate, 4096))).is_ok(), "{rate} Hz");
+        }
+        // A rate of 1 Hz would have decimated to a PCM rate of 0
+        for rate in [0, 1, 44_100, 2_822_401, 1_411_200, 90_316_800] {
+            assert!(parse_dsf(&mut Cursor::new(dsf_header(2, rate, 4096))).is_err(), "{rate} Hz");
+            assert!(parse_dff(&mut Cursor::new(dff_header(2, rate))).is_err(), "{rate} Hz");
+        }
+    }
+
+    #[test]
+    fn checks_dff_properties() {
+        let info = parse_dff(&mut Cursor::new(dff_header(2, 5_644_800))).unwrap();
+        assert_eq!((info.channels, info.rate), (2, 5_644_800));
+        assert!(parse_dff(&mut Cursor::new(dff_header(1000, 2_822_400))).is_err());
+        assert!(parse_dff(&mut Cursor::new(dff_header(2, u32::MAX))).is_err());
+        // A chunk size near u64::MAX ends the walk instead of overflowing
+        let mut file = dff_header(2, 2_822_400);
+        let at = file.len() - 8;
+        file[at..].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(parse_dff(&mut Cursor::new(file)).is_ok());
+    }
+}
@@ -0,0 +1,502 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// ===== Equalizer implementation (10-band peaking filters) =====
+#[derive(Clone, Copy)]
+struct BiquadCoeffs { b0: f32, b1: f32, b2: f32, a1: f32, a2: f32 }
+#[derive(Clone, Copy, Default)]
+struct BiquadState { z1: f32, z2: f32 }
+impl BiquadState {
+    fn process(&mut self, x: f32, c: BiquadCoeffs) -> f32 {
+        let y = c.b0 * x + self.z1;
+        self.z1 = c.b1 * x - c.a1 * y + self.z2;
+        self.z2 = c.b2 * x - c.a2 * y;
+        y
+    }
+}
+fn peaking_eq(sr: f32, f0: f32, q: f32, gain_db: f32) -> BiquadCoeffs {
+    let a = 10f32.powf(gain_db / 40.0);
+    let w0 = 2.0 * std::f32::consts::PI * (f0 / sr);
+    let alpha = w0.sin() / (2.0 * q);
+    let cosw = w0.cos();
+    let b0 = 1.0 + alpha * a;
+    let b1 = -2.0 * cosw;
+    let b2 = 1.0 - alpha * a;
+    let a0 = 1.0 + alpha / a;
+    let a1 = -2.0 * cosw;
+    let a2 = 1.0 - alpha / a;
+    let inv_a0 = 1.0 / a0;
+    BiquadCoeffs { b0: b0 * inv_a0, b1: b1 * inv_a0, b2: b2 * inv_a0, a1: a1 * inv_a0, a2: a2 * inv_a0 }
+}
+
+/// The player's EQ gains, shared with the sources playing so slider moves are heard straight away.
+#[derive(Clone, Debug)]
+pub(crate) struct Equalizer { gains_db: Arc<Mutex<[f32; 10]>> }
+impl Default for Equalizer { fn default() -> Self { Self { gains_db: Arc::new(Mutex::new([0.0; 10])) } } }
+impl Equalizer { pub(crate) fn set_gains_db(&self, gains: [f32; 10]) { if let Ok(mut g) = self.gains_db.lock() { *g = gains; } } pub(crate) fn snapshot(&self) -> [f32; 10] { self.gains_db.lock().map(|g| *g).unwrap_or([0.0;10]) } }
+
+pub(crate) struct EqSource<S: rodio::Source<Item = f32>> {
+    inner: S,
+    gains_db: [f32; 10],
+    // Gains to follow, checked every `EQ_CHECK_INTERVAL` samples
+    live: Option<Equalizer>,
+    until_check: usize,
+    coeffs: [BiquadCoeffs; 10],
+    // Filter state per channel, and the channel of the next sample
+    state: Vec<[BiquadState; 10]>,
+    channel: usize,
+}
+impl<S: rodio::Source<Item = f32>> EqSource<S> {
+    pub(crate) fn new(inner: S, gains_db: [f32; 10]) -> Self {
+        let coeffs = eq_coeffs(inner.sample_rate() as f32, &gains_db);
+        let state = vec![[BiquadState::default(); 10]; inner.channels().max(1) as usize];
+        Self { inner, gains_db, live: None, until_check: 0, coeffs, state, channel: 0 }
+    }
+
+    /// An EQ that picks up changes to `eq` while it plays.
+    pub(crate) fn following(inner: S, eq: Equalizer) -> Self { Self { live: Some(eq.clone()), ..Self::new(inner, eq.snapshot()) } }
+}
+// About 10 ms at 48 kHz stereo
+const EQ_CHECK_INTERVAL: usize = 1024;
+fn eq_coeffs(sr: f32, gains_db: &[f32; 10]) -> [BiquadCoeffs; 10] {
+    let freqs = [31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0];
+    let q = 1.0;
+    std::array::from_fn(|i| peaking_eq(sr, freqs[i], q, gains_db[i]))
+}
+impl<S: rodio::Source<Item = f32>> EqSource<S> {
+    fn follow_live_gains(&mut self) {
+        let Some(eq) = &self.live else { return };
+        if self.until_check == 0 {
+            self.until_check = EQ_CHECK_INTERVAL;
+            let gains = eq.snapshot();
+            if gains != self.gains_db { self.coeffs = eq_coeffs(self.inner.sample_rate() as f32, &gains); self.gains_db = gains; }
+        }
+        self.until_check -= 1;
+    }
+}
+impl<S: rodio::Source<Item = f32>> Iterator for EqSource<S> { type Item = f32; fn next(&mut self) -> Option<Self::Item> { let mut x = self.inner.next()?; self.follow_live_gains(); for (filter, c) in self.state[self.channel].iter_mut().zip(self.coeffs) { x = filter.process(x, c); } self.channel = (self.channel + 1) % self.state.len(); Some(x) } }
+impl<S: rodio::Source<Item = f32>> rodio::Source for EqSource<S> { fn channels(&self) -> u16 { self.inner.channels() } fn sample_rate(&self) -> u32 { self.inner.sample_rate() } fn current_span_len(&self) -> Option<usize> { self.inner.current_span_len() } fn total_duration(&self) -> Option<Duration> { self.inner.total_duration() } }
+
+// ===== Sample rate conversion =====
+/// Interpolation used when the track's sample rate differs from the output rate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResampleQuality {
+    /// Linear interpolation: cheapest, audible aliasing on bright material.
+    Linear,
+    /// Kaiser-windowed sinc, 32 taps.
+    #[default]
+    SincMedium,
+    /// Kaiser-windowed sinc, 128 taps.
+    SincBest,
+}
+
+impl ResampleQuality {
+    pub const ALL: [ResampleQuality; 3] = [ResampleQuality::Linear, ResampleQuality::SincMedium, ResampleQuality::SincBest];
+    pub fn label(self) -> &'static str {
+        match self { ResampleQuality::Linear => "Fast (linear)", ResampleQuality::SincMedium => "Sinc (medium)", ResampleQuality::SincBest => "Sinc (best)" }
+    }
+    pub(crate) fn key(self) -> &'static str {
+        match self { ResampleQuality::Linear => "linear", ResampleQuality::SincMedium => "sinc-medium", ResampleQuality::SincBest => "sinc-best" }
+    }
+    pub(crate) fn from_key(key: &str) -> Option<Self> { Self::ALL.into_iter().find(|q| q.key() == key) }
+    // Half the filter length in input frames, and the Kaiser window beta
+    fn half_taps(self) -> usize { match self { ResampleQuality::Linear => 1, ResampleQuality::SincMedium => 16, ResampleQuality::SincBest => 64 } }
+    fn kaiser_beta(self) -> f64 { match self { ResampleQuality::Linear => 0.0, ResampleQuality::SincMedium => 6.0, ResampleQuality::SincBest => 9.0 } }
+}
+
+impl std::str::FromStr for ResampleQuality {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_key(&s.to_ascii_lowercase()).ok_or_else(|| format!("Unknown resample quality '{s}' (expected linear, sinc-medium or sinc-best)"))
+    }
+}
+
+// Zeroth-order modified Bessel function (series), for the Kaiser window.
+fn bessel_i0(x: f64) -> f64 {
+    let (mut sum, mut term, mut k) = (1.0, 1.0, 1.0);
+    while term > 1e-12 * sum { term *= (x / (2.0 * k)).powi(2); sum += term; k += 1.0; }
+    sum
+}
+
+const RESAMPLE_PHASES: usize = 256;
+
+/// Streaming resampler for interleaved f32 audio with a precomputed polyphase filter table.
+pub(crate) struct Resampler<S: rodio::Source<Item = f32>> {
+    inner: S,
+    channels: usize,
+    out_rate: u32,
+    // Input frames advanced per output frame
+    step: f64,
+    // Read position in input frames, relative to the first frame held in `history`
+    pos: f64,
+    history: std::collections::VecDeque<f32>,
+    half: usize,
+    // (RESAMPLE_PHASES + 1) rows of 2 * half taps
+    table: Vec<f32>,
+    frame: Vec<f32>,
+    frame_idx: usize,
+    // Input frames still to come from `inner`, plus the zero padding that flushes the filter at the end
+    pending_real: Option<u64>,
+    inner_done: bool,
+}
+
+impl<S: rodio::Source<Item = f32>> Resampler<S> {
+    pub(crate) fn new(inner: S, out_rate: u32, quality: ResampleQuality) -> Self {
+        let channels = inner.channels().max(1) as usize;
+        let in_rate = inner.sample_rate().max(1);
+        let half = quality.half_taps();
+        // Low-pass at the lower Nyquist frequency when downsampling so nothing folds back
+        let cutoff = (out_rate as f64 / in_rate as f64).min(1.0) * if quality == ResampleQuality::Linear { 1.0 } else { 0.97 };
+        let beta = quality.kaiser_beta();
+        let i0_beta = bessel_i0(beta);
+        let mut table = Vec::with_capacity((RESAMPLE_PHASES + 1) * 2 * half);
+        for p in 0..=RESAMPLE_PHASES {
+            let frac = p as f64 / RESAMPLE_PHASES as f64;
+            for j in 0..2 * half {
+                // Distance from the output instant to input tap j
+                let d = frac + (half as f64 - 1.0) - j as f64;
+                let h = if quality == ResampleQuality::Linear {
+                    (1.0 - d.abs()).max(0.0)
+                } else {
+                    let x = d * cutoff;
+                    let sinc = if x.abs() < 1e-9 { 1.0 } else { (std::f64::consts::PI * x).sin() / (std::f64::consts::PI * x) };
+                    let r = d / half as f64;
+                    let window = if r.abs() >= 1.0 { 0.0 } else { bessel_i0(beta * (1.0 - r * r).sqrt()) / i0_beta };
+                    cutoff * sinc * window
+                };
+                table.push(h as f32);
+            }
+        }
+        // Start with `half - 1` frames of silence so the first output sample lines up with the first input frame
+        let history = std::iter::repeat_n(0.0, (half - 1) * channels).collect();
+        Self {
+            inner, channels, out_rate,
+            step: in_rate as f64 / out_rate as f64,
+            pos: (half - 1) as f64,
+            history, half, table,
+            frame: vec![0.0; channels], frame_idx: channels,
+            pending_real: None, inner_done: false,
+        }
+    }
+
+    fn frames_held(&self) -> usize { self.history.len() / self.channels }
+
+    // Pull one input frame into the history, padding with silence after the end. Returns false when fully drained.
+    fn fill_frame(&mut self) -> bool {
+        if !self.inner_done {
+            let mut got = 0;
+            for _ in 0..self.channels {
+                match self.inner.next() { Some(x) => { self.history.push_back(x); got += 1; } None => break }
+            }
+            if got == self.channels { return true; }
+            // A partial trailing frame is completed with silence
+            if got > 0 { for _ in got..self.channels { self.history.push_back(0.0); } }
+            self.inner_done = true;
+            // Real input ends here; keep producing output until the read position passes it
+            self.pending_real = Some(self.frames_held() as u64);
+        }
+        if self.pos >= self.pending_real.unwrap_or(0) as f64 { return false; }
+        self.history.extend(std::iter::repeat_n(0.0, self.channels));
+        true
+    }
+
+    fn next_frame(&mut self) -> bool {
+        let base = self.pos.floor() as usize;
+        // Need taps base-half+1 ..= base+half
+        while self.frames_held() < base + self.half + 1 {
+            if !self.fill_frame() { return false; }
+        }
+        if let Some(end) = self.pending_real && self.pos >= end as f64 { return false; }
+        let frac = self.pos - base as f64;
+        let phase = frac * RESAMPLE_PHASES as f64;
+        let p = (phase as usize).min(RESAMPLE_PHASES - 1);
+        let t = (phase - p as f64) as f32;
+        let taps = 2 * self.half;
+        let (row0, row1) = (&self.table[p * taps..(p + 1) * taps], &self.table[(p + 1) * taps..(p + 2) * taps]);
+        let first = base + 1 - self.half;
+        for c in 0..self.channels {
+            let mut acc = 0.0f32;
+            for j in 0..taps {
+                let h = row0[j] + (row1[j] - row0[j]) * t;
+                acc += self.history[(first + j) * self.channels + c] * h;
+            }
+            self.frame[c] = acc;
+        }
+        self.frame_idx = 0;
+        self.pos += self.step;
+        // Drop history that no future output frame can reach (when downsampling steeply that can be all of it)
+        let keep_from = (self.pos.floor() as usize + 1).saturating_sub(self.half).min(self.frames_held());
+        if keep_from > 0 {
+            self.history.drain(..keep_from * self.channels);
+            self.pos -= keep_from as f64;
+            if let Some(end) = self.pending_real.as_mut() { *end = end.saturating_sub(keep_from as u64); }
+        }
+        true
+    }
+}
+
+impl<S: rodio::Source<Item = f32>> Iterator for Resampler<S> {
+    type Item = f32;
+    fn next(&mut self) -> Option<f32> {
+        if self.frame_idx >= self.channels && !self.next_frame() { return None; }
+        let x = self.frame[self.frame_idx];
+        self.frame_idx += 1;
+        Some(x)
+    }
+}
+
+impl<S: rodio::Source<Item = f32>> rodio::Source for Resampler<S> {
+    fn channels(&self) -> u16 { self.channels as u16 }
+    fn sample_rate(&self) -> u32 { self.out_rate }
+    fn current_span_len(&self) -> Option<usize> { None }
+    fn total_duration(&self) -> Option<Duration> { self.inner.total_duration() }
+}
+
+// ===== Smart speed =====
+/// Pause length kept by smart speed unless configured otherwise.
+pub(crate) const DEFAULT_MAX_PAUSE: Duration = Duration::from_millis(500);
+// Peak level below which speech counts as paused
+const PAUSE_THRESHOLD_DB: f32 = -45.0;
+
+/// Source time dropped by the pause compressor, shared with whoever needs to map output time back to file time.
+#[derive(Clone, Default)]
+pub(crate) struct SkipCounter(Arc<AtomicU64>);
+impl SkipCounter {
+    fn add_nanos(&self, nanos: u64) { self.0.fetch_add(nanos, Ordering::Relaxed); }
+    pub(crate) fn get(&self) -> Duration { Duration::from_nanos(self.0.load(Ordering::Relaxed)) }
+}
+
+/// Shortens pauses: once the signal has stayed below the threshold for `max_pause`, further silent frames are
+/// dropped until it comes back.
+pub(crate) struct PauseCompressor<S: rodio::Source<Item = f32>> {
+    inner: S,
+    threshold: f32,
+    max_pause_frames: u64,
+    silent_frames: u64,
+    frame: Vec<f32>,
+    pos: usize,
+    rate: u64,
+    // Frames dropped so far; the counter gets the time they add up to, so no rounding error builds up
+    dropped: u64,
+    skipped: SkipCounter,
+}
+impl<S: rodio::Source<Item = f32>> PauseCompressor<S> {
+    pub(crate) fn new(inner: S, max_pause: Duration, skipped: SkipCounter) -> Self {
+        let rate = inner.sample_rate().max(1);
+        Self {
+            threshold: 10f32.powf(PAUSE_THRESHOLD_DB / 20.0),
+            max_pause_frames: (max_pause.as_secs_f64() * rate as f64) as u64,
+            silent_frames: 0,
+            frame: Vec::with_capacity(8),
+            pos: 0,
+            rate: rate as u64,
+            dropped: 0,
+            skipped,
+            inner,
+        }
+    }
+}
+impl<S: rodio::Source<Item = f32>> Iterator for PauseCompressor<S> {
+    type Item = f32;
+    fn next(&mut self) -> Option<f32> {
+        if let Some(&x) = self.frame.get(self.pos) { self.pos += 1; return Some(x); }
+        loop {
+            // Work on whole frames so channels stay aligned when frames are dropped
+            self.frame.clear();
+            for _ in 0..self.inner.channels().max(1) { match self.inner.next() { Some(x) => self.frame.push(x), None => break } }
+            if self.frame.is_empty() { return None; }
+            if self.frame.iter().all(|x| x.abs() < self.threshold) {
+                self.silent_frames += 1;
+                if self.silent_frames > self.max_pause_frames {
+                    let nanos = |frames: u64| frames * 1_000_000_000 / self.rate;
+                    self.skipped.add_nanos(nanos(self.dropped + 1) - nanos(self.dropped));
+                    self.dropped += 1;
+                    continue;
+                }
+            } else {
+                self.silent_frames = 0;
+            }
+            self.pos = 1;
+            return Some(self.frame[0]);
+        }
+    }
+}
+impl<S: rodio::Source<Item = f32>> rodio::Source for PauseCompressor<S> {
+    fn channels(&self) -> u16 { self.inner.channels() }
+    fn sample_rate(&self) -> u32 { self.inner.sample_rate() }
+    fn current_span_len(&self) -> Option<usize> { None }
+    fn total_duration(&self) -> Option<Duration> { None }
+}
+
+// ===== Movable end point =====
+/// Where a playing source stops, in samples from its start. Can be moved while the source plays, e.g. when a
+/// silence analysis finishes or the next track turns out to continue in the same file.
+#[derive(Clone)]
+pub(crate) struct SourceEnd(Arc<AtomicU64>);
+impl Default for SourceEnd { fn default() -> Self { Self(Arc::new(AtomicU64::new(u64::MAX))) } }
+impl SourceEnd {
+    pub(crate) fn set(&self, samples: Option<u64>) { self.0.store(samples.unwrap_or(u64::MAX), Ordering::Relaxed); }
+}
+
+pub(crate) struct Until<S: rodio::Source<Item = f32>> { inner: S, end: SourceEnd, played: u64 }
+impl<S: rodio::Source<Item = f32>> Until<S> {
+    pub(crate) fn new(inner: S, end: SourceEnd) -> Self { Self { inner, end, played: 0 } }
+}
+impl<S: rodio::Source<Item = f32>> Iterator for Until<S> {
+    type Item = f32;
+    fn next(&mut self) -> Option<f32> {
+        if self.played >= self.end.0.load(Ordering::Relaxed) { return None; }
+        self.played += 1;
+        self.inner.next()
+    }
+}
+impl<S: rodio::Source<Item = f32>> rodio::Source for Until<S> {
+    fn channels(&self) -> u16 { self.inner.channels() }
+    fn sample_rate(&self) -> u32 { self.inner.sample_rate() }
+    fn current_span_len(&self) -> Option<usize> { self.inner.current_span_len() }
+    fn total_duration(&self) -> Option<Duration> { None }
+}
+
+// ===== Processing chain =====
+/// Settings for every DSP stage applied between the decoder and the output.
+#[derive(Clone, Debug, Default)]
+pub struct DspSettings {
+    /// Gains in dB for the 31 Hz … 16 kHz EQ bands.
+    pub eq_gains: [f32; 10],
+    /// The player's EQ, followed live in place of `eq_gains`.
+    pub(crate) live_eq: Option<Equalizer>,
+    /// Interpolation used when the output runs at a different rate than the track.
+    pub resample_quality: ResampleQuality,
+    /// Smart speed: longest pause kept in speech. `None` leaves pauses alone.
+    pub max_pause: Option<Duration>,
+}
+
+impl DspSettings {
+    /// The DSP configuration the player would use right now, taken from the saved settings.
+    pub fn from_saved_settings() -> Self {
+        let settings = crate::settings::Settings::load();
+        let key = settings.output_device.clone().unwrap_or_else(|| "default".to_string());
+        Self {
+            eq_gains: settings.eq_profiles.get(&key).copied().unwrap_or([0.0; 10]),
+            resample_quality: settings.resample_quality,
+            max_pause: settings.smart_speed.then(|| settings.smart_speed_max_pause.unwrap_or(DEFAULT_MAX_PAUSE)),
+            live_eq: None,
+        }
+    }
+}
+
+/// Run decoded samples through the same chain used for playback: pause compression (if enabled), the 10-band
+/// EQ at the track's rate, then conversion to `output_rate` when one is given and differs. Shared by
+/// `AudioEngine::play_from` and the offline exporter so both produce identical output. Source time dropped by
+/// pause compression is added to `skipped`.
+pub(crate) fn apply_dsp<S>(source: S, settings: &DspSettings, output_rate: Option<u32>, skipped: &SkipCounter) -> Box<dyn rodio::Source<Item = f32> + Send>
+where S: rodio::Source<Item = f32> + Send + 'static {
+    use rodio::Source as _;
+    let source: Box<dyn rodio::Source<Item = f32> + Send> = match settings.max_pause {
+        Some(max_pause) => Box::new(PauseCompressor::new(source, max_pause, skipped.clone())),
+        None => Box::new(source),
+    };
+    let source = match &settings.live_eq {
+        Some(eq) => EqSource::following(source, eq.clone()),
+        None => EqSource::new(source, settings.eq_gains),
+    };
+    match output_rate {
+        Some(rate) if rate != source.sample_rate() => Box::new(Resampler::new(source, rate, settings.resample_quality)),
+        _ => Box::new(source),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    // Interleaved stereo sine at `freq` Hz
+    fn tone(freq: f32, rate: u32, frames: usize) -> Vec<f32> {
+        (0..frames).flat_map(|i| { let v = 0.25 * (2.0 * std::f32::consts::PI * freq * i as f32 / rate as f32).sin(); [v, v] }).collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 { (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt() }
+
+    #[test]
+    fn eq_follows_live_gain_changes() {
+        let eq = Equalizer::default();
+        let mut source = EqSource::following(SamplesBuffer::new(2, 48_000, tone(1000.0, 48_000, 48_000)), eq.clone());
+        let flat: Vec<f32> = source.by_ref().take(9600).collect();
+        let mut gains = [0.0; 10];
+        gains[5] = 12.0;
+        eq.set_gains_db(gains);
+        // Skip past the next gain check and let the filter settle
+        let boosted: Vec<f32> = source.by_ref().skip(9600).take(9600).collect();
+        let ratio = rms(&boosted) / rms(&flat[4800..]);
+        assert!((3.5..4.5).contains(&ratio), "1 kHz band at +12 dB gave a gain of {ratio}");
+    }
+
+    // Mono sine at `freq` Hz
+    fn mono_tone(freq: f32, rate: u32, frames: usize) -> Vec<f32> {
+        (0..frames).map(|i| 0.5 * (std::f32::consts::TAU * freq * i as f32 / rate as f32).sin()).collect()
+    }
+
+    #[test]
+    fn resampler_output_length_follows_the_rate_ratio() {
+        for quality in ResampleQuality::ALL {
+            for (from, to) in [(44_100, 48_000), (48_000, 44_100), (8_000, 44_100), (96_000, 22_050)] {
+                let frames = from as usize / 2;
+                let out: Vec<f32> = Resampler::new(SamplesBuffer::new(2, from, tone(440.0, from, frames)), to, quality).collect();
+                assert_eq!(out.len() % 2, 0);
+                let expected = frames as f64 * to as f64 / from as f64;
+                assert!((out.len() as f64 / 2.0 - expected).abs() <= 1.0, "{quality:?} {from} -> {to}: {} frames, expected {expected}", out.len() / 2);
+            }
+        }
+    }
+
+    #[test]
+    fn resampler_keeps_the_pitch() {
+        for quality in ResampleQuality::ALL {
+            for (from, to) in [(44_100, 48_000), (48_000, 32_000)] {
+                let out: Vec<f32> = Resampler::new(SamplesBuffer::new(1, from, mono_tone(1000.0, from, from as usize)), to, quality).collect();
+                // Rising zero crossings over the middle half second, clear of the filter's edges
+                let middle = &out[to as usize / 4..to as usize * 3 / 4];
+                let rising = middle.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+                assert!((499..=501).contains(&rising), "{quality:?} {from} -> {to}: {rising} cycles in 0.5 s");
+            }
+        }
+    }
+
+    #[test]
+    fn resampler_keeps_channels_apart() {
+        // Left carries a tone, right a constant level
+        let input: Vec<f32> = mono_tone(1000.0, 44_100, 44_100).into_iter().flat_map(|x| [x, 0.25]).collect();
+        for quality in ResampleQuality::ALL {
+            let out: Vec<f32> = Resampler::new(SamplesBuffer::new(2, 44_100, input.clone()), 48_000, quality).collect();
+            let middle = &out[24_000..72_000];
+            assert!(middle.iter().skip(1).step_by(2).all(|x| (x - 0.25).abs() < 0.01), "{quality:?}: tone leaked into the right channel");
+            let left: Vec<f32> = middle.iter().step_by(2).copied().collect();
+            assert!(left.iter().any(|&x| x > 0.45) && left.iter().any(|&x| x < -0.45), "{quality:?}: left channel lost its tone");
+        }
+    }
+
+    #[test]
+    fn pause_compressor_cuts_long_pauses() {
+        // Half a second of speech, two seconds of pause, half a second of speech at 44.1 kHz stereo
+        let rate = 44_100;
+        let speech = tone(300.0, rate, rate as usize / 2);
+        let input: Vec<f32> = speech.iter().copied().chain(std::iter::repeat_n(0.0, 4 * rate as usize)).chain(speech.iter().copied()).collect();
+        let skipped = SkipCounter::default();
+        let out: Vec<f32> = PauseCompressor::new(SamplesBuffer::new(2, rate, input), Duration::from_millis(500), skipped.clone()).collect();
+        // 1.5 s of the pause is gone, give or take the quiet samples around the tone's zero crossings
+        let dropped = 3 * rate as usize - out.len() / 2;
+        assert!((rate as usize * 3 / 2..rate as usize * 3 / 2 + 4).contains(&dropped), "dropped {dropped} frames");
+        // The counter holds exactly the time of the dropped frames
+        assert_eq!(skipped.get(), Duration::from_nanos(dropped as u64 * 1_000_000_000 / rate as u64));
+        // What is left of the pause is `max_pause` long (the tone's own zero crossings are single samples)
+        let longest = out.chunks(2).fold((0, 0), |(run, longest), f| if f.iter().all(|&x| x == 0.0) { (run + 1, longest.max(run + 1)) } else { (0, longest) }).1;
+        assert!((rate as usize / 2..rate as usize / 2 + 2).contains(&longest), "pause of {longest} frames");
+        // A pause within the limit is left alone
+        let short: Vec<f32> = speech.iter().copied().chain(std::iter::repeat_n(0.0, rate as usize / 2)).chain(speech.iter().copied()).collect();
+        let skipped = SkipCounter::default();
+        assert_eq!(PauseCompressor::new(SamplesBuffer::new(2, rate, short.clone()), Duration::from_millis(500), skipped.clone()).count(), short.len());
+        assert_eq!(skipped.get(), Duration::ZERO);
+    }
+}
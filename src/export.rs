@@ -0,0 +1,280 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use rodio::Source;
+
+use crate::decode::open_track;
+use crate::dsp::{apply_dsp, DspSettings, SkipCounter};
+
+// Offline rendering: decode a file, run it through the playback DSP chain and write a WAV file.
+// No audio device is involved, so this also works headless (CI, golden-file checks).
+
+/// Sample encoding of the exported WAV file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WavFormat {
+    Pcm16,
+    #[default]
+    Pcm24,
+    Float32,
+}
+
+impl WavFormat {
+    fn bits(self) -> u16 { match self { WavFormat::Pcm16 => 16, WavFormat::Pcm24 => 24, WavFormat::Float32 => 32 } }
+}
+
+impl std::str::FromStr for WavFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "16" | "pcm16" => Ok(WavFormat::Pcm16),
+            "24" | "pcm24" => Ok(WavFormat::Pcm24),
+            "32f" | "f32" | "float" | "float32" => Ok(WavFormat::Float32),
+            other => Err(format!("Unknown WAV format '{other}' (expected 16, 24 or 32f)")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ExportOptions {
+    pub format: WavFormat,
+    /// Start of the exported range; `None` renders from the beginning.
+    pub start: Option<Duration>,
+    /// End of the exported range (file time); `None` renders to the end.
+    pub end: Option<Duration>,
+    /// Output sample rate; `None` keeps the track's rate.
+    pub sample_rate: Option<u32>,
+    pub dsp: DspSettings,
+    /// SoundFont used to render MIDI input.
+    pub soundfont: Option<PathBuf>,
+}
+
+/// Minimal streaming WAV writer; the RIFF sizes are patched in `finish`.
+pub(crate) struct WavWriter<W: Write + Seek> {
+    out: W,
+    format: WavFormat,
+    channels: u16,
+    data_bytes: u64,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub(crate) fn new(mut out: W, format: WavFormat, channels: u16, sample_rate: u32) -> std::io::Result<Self> {
+        let bits = format.bits();
+        let block_align = channels * (bits / 8);
+        let float = format == WavFormat::Float32;
+        out.write_all(b"RIFF")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&(if float { 18u32 } else { 16u32 }).to_le_bytes())?;
+        out.write_all(&(if float { 3u16 } else { 1u16 }).to_le_bytes())?;
+        out.write_all(&channels.to_le_bytes())?;
+        out.write_all(&sample_rate.to_le_bytes())?;
+        out.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        out.write_all(&block_align.to_le_bytes())?;
+        out.write_all(&bits.to_le_bytes())?;
+        if float {
+            // cbSize plus the fact chunk required for non-PCM formats
+            out.write_all(&0u16.to_le_bytes())?;
+            out.write_all(b"fact")?;
+            out.write_all(&4u32.to_le_bytes())?;
+            out.write_all(&0u32.to_le_bytes())?;
+        }
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        Ok(Self { out, format, channels, data_bytes: 0 })
+    }
+
+    pub(crate) fn write_sample(&mut self, x: f32) -> std::io::Result<()> {
+        match self.format {
+            WavFormat::Pcm16 => {
+                self.out.write_all(&((x.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes())?;
+                self.data_bytes += 2;
+            }
+            WavFormat::Pcm24 => {
+                let v = (x.clamp(-1.0, 1.0) * 8_388_607.0) as i32;
+                self.out.write_all(&v.to_le_bytes()[..3])?;
+                self.data_bytes += 3;
+            }
+            WavFormat::Float32 => {
+                self.out.write_all(&x.to_le_bytes())?;
+                self.data_bytes += 4;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn frames(&self) -> u64 { self.data_bytes / (self.channels as u64 * (self.format.bits() / 8) as u64) }
+
+    pub(crate) fn finish(mut self) -> std::io::Result<W> {
+        // WAV sizes are 32-bit; anything beyond 4 GiB is left saturated
+        let data = u32::try_from(self.data_bytes).unwrap_or(u32::MAX);
+        let float = self.format == WavFormat::Float32;
+        let header = if float { 58u32 } else { 44u32 };
+        if self.data_bytes % 2 == 1 { self.out.write_all(&[0])?; }
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(header - 8).saturating_add(data).saturating_add(data % 2).to_le_bytes())?;
+        if float {
+            self.out.seek(SeekFrom::Start(46))?;
+            self.out.write_all(&u32::try_from(self.frames()).unwrap_or(u32::MAX).to_le_bytes())?;
+        }
+        self.out.seek(SeekFrom::Start(header as u64 - 4))?;
+        self.out.write_all(&data.to_le_bytes())?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Render `input` through the DSP chain into a WAV file at `output`. Returns the number of frames written.
+pub fn export_wav(input: &Path, output: &Path, options: &ExportOptions) -> Result<u64, String> {
+    let start = options.start.unwrap_or(Duration::ZERO);
+    if let Some(end) = options.end && end <= start { return Err("End of the range must be after its start".to_string()); }
+
+    let track = open_track(input, start, options.soundfont.as_deref())?;
+    let (source, status) = (track.source, track.status);
+    let skipped = SkipCounter::default();
+    let mut source = match options.end {
+        // Pause compression changes how much output a range produces, so cut the range before the DSP chain
+        Some(end) if options.dsp.max_pause.is_some() => apply_dsp(source.take_duration(end - start), &options.dsp, options.sample_rate, &skipped),
+        _ => apply_dsp(source, &options.dsp, options.sample_rate, &skipped),
+    };
+    let (channels, sample_rate) = (source.channels(), source.sample_rate());
+    // Otherwise trim by sample count so the end point is exact regardless of span sizes
+    let limit = options.end.filter(|_| options.dsp.max_pause.is_none())
+        .map(|end| ((end - start).as_secs_f64() * sample_rate as f64).round() as u64 * channels as u64);
+
+    // Render next to the target and rename at the end so a failed export never leaves a truncated file behind
+    let tmp = output.with_extension("wav.part");
+    let out = File::create(&tmp).map_err(|e| format!("Failed to create {}: {e}", tmp.display()))?;
+    let result = (|| {
+        let mut writer = WavWriter::new(BufWriter::new(out), options.format, channels, sample_rate)?;
+        let mut written = 0u64;
+        while limit.is_none_or(|l| written < l) {
+            let Some(x) = source.next() else { break };
+            writer.write_sample(x)?;
+            written += 1;
+        }
+        let frames = writer.frames();
+        writer.finish()?;
+        Ok::<u64, std::io::Error>(frames)
+    })();
+    // A file that breaks off partway must not pass for a complete export
+    let result = result.map_err(|e| format!("Failed to write {}: {e}", output.display()))
+        .and_then(|frames| match status.take_error() {
+            Some(reason) => Err(format!("Failed to decode {}: {reason}", input.display())),
+            None => Ok(frames),
+        });
+    match result {
+        Ok(frames) => {
+            std::fs::rename(&tmp, output).map_err(|e| format!("Failed to write {}: {e}", output.display()))?;
+            Ok(frames)
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Expected render of `input` through a +12 dB 1 kHz band as 16-bit PCM. Set UPDATE_GOLDEN=1 to rewrite it.
+    const GOLDEN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/export-eq-1k-boost.wav");
+
+    /// A second of 8 kHz mono audio, a 1 kHz tone plus a quieter 125 Hz one, in a fresh folder.
+    fn input(name: &str) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("export-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("in.wav");
+        let mut wav = WavWriter::new(File::create(&path).unwrap(), WavFormat::Pcm16, 1, 8000).unwrap();
+        for n in 0..8000 {
+            let t = n as f32 / 8000.0;
+            wav.write_sample(0.2 * (std::f32::consts::TAU * 1000.0 * t).sin() + 0.1 * (std::f32::consts::TAU * 125.0 * t).sin()).unwrap();
+        }
+        wav.finish().unwrap();
+        (dir, path)
+    }
+
+    fn boost_1k() -> DspSettings { let mut dsp = DspSettings::default(); dsp.eq_gains[5] = 12.0; dsp }
+
+    fn u16_at(data: &[u8], at: usize) -> u16 { u16::from_le_bytes([data[at], data[at + 1]]) }
+    fn u32_at(data: &[u8], at: usize) -> u32 { u32::from_le_bytes(data[at..at + 4].try_into().unwrap()) }
+
+    fn pcm16(data: &[u8]) -> Vec<i16> { data[44..].chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect() }
+
+    fn rms(samples: &[i16]) -> f64 { (samples.iter().map(|&s| (s as f64).powi(2)).sum::<f64>() / samples.len() as f64).sqrt() }
+
+    #[test]
+    fn header_matches_each_format() {
+        let (dir, input) = input("formats");
+        for (format, tag, bits, header) in [(WavFormat::Pcm16, 1, 16, 44), (WavFormat::Pcm24, 1, 24, 44), (WavFormat::Float32, 3, 32, 58)] {
+            let output = dir.join(format!("{bits}.wav"));
+            let frames = export_wav(&input, &output, &ExportOptions { format, ..Default::default() }).unwrap();
+            assert_eq!(frames, 8000);
+            let data = std::fs::read(&output).unwrap();
+            assert_eq!((&data[..4], &data[8..16]), (&b"RIFF"[..], &b"WAVEfmt "[..]));
+            assert_eq!(u32_at(&data, 4) as usize, data.len() - 8);
+            assert_eq!((u16_at(&data, 20), u16_at(&data, 22), u32_at(&data, 24)), (tag, 1, 8000));
+            assert_eq!((u32_at(&data, 28), u16_at(&data, 32), u16_at(&data, 34)), (8000 * bits as u32 / 8, bits / 8, bits));
+            if format == WavFormat::Float32 { assert_eq!((&data[38..42], u32_at(&data, 46)), (&b"fact"[..], 8000)); }
+            assert_eq!(&data[header - 8..header - 4], b"data");
+            assert_eq!(u32_at(&data, header - 4) as usize, 8000 * bits as usize / 8);
+            assert_eq!(data.len(), header + 8000 * bits as usize / 8);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn trim_sets_the_sample_count() {
+        let (dir, input) = input("trim");
+        let output = dir.join("out.wav");
+        let options = ExportOptions { format: WavFormat::Pcm16, start: Some(Duration::from_millis(250)), end: Some(Duration::from_millis(600)), ..Default::default() };
+        assert_eq!(export_wav(&input, &output, &options), Ok(2800));
+        assert_eq!(pcm16(&std::fs::read(&output).unwrap()).len(), 2800);
+        // Only a start renders to the end of the file
+        let options = ExportOptions { format: WavFormat::Pcm16, start: Some(Duration::from_millis(250)), ..Default::default() };
+        assert_eq!(export_wav(&input, &output, &options), Ok(6000));
+        let options = ExportOptions { start: Some(Duration::from_millis(600)), end: Some(Duration::from_millis(250)), ..Default::default() };
+        assert!(export_wav(&input, &output, &options).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn flat_eq_keeps_the_input_and_a_boost_raises_its_band() {
+        let (dir, input) = input("eq");
+        let original = pcm16(&std::fs::read(&input).unwrap());
+        let (flat, boosted) = (dir.join("flat.wav"), dir.join("boosted.wav"));
+        export_wav(&input, &flat, &ExportOptions { format: WavFormat::Pcm16, ..Default::default() }).unwrap();
+        export_wav(&input, &boosted, &ExportOptions { format: WavFormat::Pcm16, dsp: boost_1k(), ..Default::default() }).unwrap();
+        let flat = pcm16(&std::fs::read(&flat).unwrap());
+        assert_eq!(flat.len(), original.len());
+        let worst = flat.iter().zip(&original).map(|(a, b)| (a - b).abs()).max().unwrap();
+        assert!(worst <= 1, "flat EQ changed a sample by {worst}");
+        // The 1 kHz tone dominates, so +12 dB on its band roughly quadruples the level
+        let boosted = pcm16(&std::fs::read(&boosted).unwrap());
+        let gain = rms(&boosted[800..]) / rms(&original[800..]);
+        assert!((3.0..4.2).contains(&gain), "gain {gain}");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn boosted_render_matches_golden_file() {
+        let (dir, input) = input("golden");
+        let output = dir.join("out.wav");
+        export_wav(&input, &output, &ExportOptions { format: WavFormat::Pcm16, dsp: boost_1k(), ..Default::default() }).unwrap();
+        let actual = std::fs::read(&output).unwrap();
+        if std::env::var_os("UPDATE_GOLDEN").is_some() { std::fs::write(GOLDEN, &actual).unwrap(); }
+        let expected = std::fs::read(GOLDEN).unwrap();
+        assert_eq!(actual[..44], expected[..44]);
+        // Allow a rounding step either way so the comparison survives float differences between platforms
+        let (actual, expected) = (pcm16(&actual), pcm16(&expected));
+        assert_eq!(actual.len(), expected.len());
+        if let Some(n) = (0..actual.len()).find(|&n| (actual[n] - expected[n]).abs() > 1) {
+            panic!("sample {n}: got {}, expected {}", actual[n], expected[n]);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
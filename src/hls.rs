@@ -0,0 +1,571 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::id3::Id3Tag;
+use crate::net::{fetch, redact_url, resolve_url};
+use crate::radio::{HttpStream, Shared, StreamInfo};
+
+// HTTP Live Streaming (HLS) audio. The master playlist picks a variant, the media playlist is polled for new
+// segments, and the segments are turned into one continuous byte stream for Symphonia: MPEG-TS is demuxed to its
+// AAC (ADTS) or MP3 elementary stream, packed audio loses its ID3 headers, and fMP4 is passed on after its init
+// segment. Titles come from `#EXTINF` and from ID3 timed metadata.
+
+/// Segments from the live edge at which live playback starts.
+const LIVE_START_SEGMENTS: usize = 3;
+const SEGMENT_ATTEMPTS: u32 = 3;
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Whether `path` (a URL) or the server's `content_type` names an HLS playlist.
+pub(crate) fn is_hls(path: &Path, content_type: Option<&str>) -> bool {
+    let url = path.to_string_lossy();
+    let url_path = url.split(['?', '#']).next().unwrap_or_default();
+    let mime = content_type.and_then(|ct| ct.split(';').next()).map(|ct| ct.trim().to_ascii_lowercase());
+    url_path.to_ascii_lowercase().ends_with(".m3u8")
+        || matches!(mime.as_deref(), Some("application/vnd.apple.mpegurl" | "application/x-mpegurl" | "audio/mpegurl" | "audio/x-mpegurl"))
+}
+
+/// Open the HLS stream at `url`. Returns the stream, its live info and the format Symphonia should expect.
+pub(crate) fn open_hls(url: &str) -> Result<(HttpStream, StreamInfo, &'static str), String> {
+    let (media_url, playlist) = load_media_playlist(url)?;
+    if playlist.segments.is_empty() { return Err(format!("{}: playlist has no segments", redact_url(url))); }
+    // Live streams join a few segments behind the edge so there is something buffered
+    let first = if playlist.ended { 0 } else { playlist.segments.len().saturating_sub(LIVE_START_SEGMENTS) };
+    let mut feeder = Feeder { media_url, next_seq: playlist.segments[first].seq + 1, demux: None, init_sent: false, format: None };
+    let info = StreamInfo::default();
+    // The first segment is fetched up front to learn the format and to report errors right away
+    let (audio, title) = feeder.load(&playlist.segments[first], playlist.map.as_ref())?;
+    let format = feeder.format.ok_or_else(|| format!("{}: unrecognised segment format", redact_url(url)))?;
+    info.update(|m| m.title = title);
+    let stream = HttpStream::fed_by(url, info.clone(), move |shared| {
+        if shared.push(&audio, 0) { feeder.run(&shared, playlist); }
+    })?;
+    Ok((stream, info, format.extension()))
+}
+
+/// A playlist entry: a URL, optionally limited to a byte range (offset, length).
+#[derive(Clone, PartialEq)]
+struct Resource { url: String, range: Option<(u64, u64)> }
+
+struct Segment { seq: u64, resource: Resource, title: Option<String> }
+
+struct MediaPlaylist {
+    target_duration: Duration,
+    segments: Vec<Segment>,
+    /// `#EXT-X-ENDLIST`: the playlist is complete (on demand rather than live).
+    ended: bool,
+    /// fMP4 initialisation segment.
+    map: Option<Resource>,
+}
+
+/// `KEY=value,KEY="quoted, value"` attribute lists.
+fn attributes(list: &str) -> Vec<(String, String)> {
+    let mut out = Vec::new();
+    let mut rest = list.trim();
+    while let Some((key, value)) = rest.split_once('=') {
+        let (value, next) = if let Some(quoted) = value.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            (&quoted[..end], quoted.get(end + 1..).unwrap_or_default())
+        } else {
+            value.split_once(',').map_or((value, ""), |(v, n)| (v, n))
+        };
+        out.push((key.trim().to_ascii_uppercase(), value.trim().to_string()));
+        rest = next.trim_start_matches(',').trim_start();
+    }
+    out
+}
+
+fn attribute<'a>(attrs: &'a [(String, String)], key: &str) -> Option<&'a str> { attrs.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str()) }
+
+/// `n[@o]` byte ranges; without an offset the range follows the previous one.
+fn byte_range(value: &str, previous_end: u64) -> Option<(u64, u64)> {
+    let (length, offset) = value.split_once('@').map_or((value, None), |(l, o)| (l, Some(o)));
+    Some((offset.map_or(Some(previous_end), |o| o.trim().parse().ok())?, length.trim().parse().ok()?))
+}
+
+/// Fetch `url` and, for a master playlist, the variant to play. Returns the media playlist and its URL.
+fn load_media_playlist(url: &str) -> Result<(String, MediaPlaylist), String> {
+    let text = fetch_playlist(url)?;
+    let media_url = match choose_variant(&text, url) {
+        Some(variant) => { log::info!("{}: playing variant {}", redact_url(url), redact_url(&variant)); variant }
+        None => return Ok((url.to_string(), parse_media_playlist(&text, url)?)),
+    };
+    let playlist = parse_media_playlist(&fetch_playlist(&media_url)?, &media_url)?;
+    Ok((media_url, playlist))
+}
+
+fn fetch_playlist(url: &str) -> Result<String, String> {
+    let data = fetch(url, None)?;
+    let text = String::from_utf8_lossy(&data).trim_start_matches('\u{feff}').to_string();
+    if !text.trim_start().starts_with("#EXTM3U") { return Err(format!("{}: not an HLS playlist", redact_url(url))); }
+    Ok(text)
+}
+
+/// For a master playlist, the URL of the media playlist to play: the audio rendition of the best variant if it
+/// has a separate one, else the variant itself. Audio-only variants are preferred. `None` for media playlists.
+fn choose_variant(text: &str, base: &str) -> Option<String> {
+    struct Variant { bandwidth: u64, audio_only: bool, audio_group: Option<String>, url: String }
+    let mut variants = Vec::new();
+    let mut renditions = Vec::new();
+    let mut pending = None;
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if let Some(attrs) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+            pending = Some(attributes(attrs));
+        } else if let Some(attrs) = line.strip_prefix("#EXT-X-MEDIA:") {
+            let attrs = attributes(attrs);
+            if attribute(&attrs, "TYPE") == Some("AUDIO") && let Some(uri) = attribute(&attrs, "URI") {
+                let default = attribute(&attrs, "DEFAULT") == Some("YES");
+                renditions.push((attribute(&attrs, "GROUP-ID").unwrap_or_default().to_string(), default, resolve_url(base, uri)));
+            }
+        } else if !line.starts_with('#') && let Some(attrs) = pending.take() {
+            let codecs = attribute(&attrs, "CODECS").unwrap_or_default().to_ascii_lowercase();
+            variants.push(Variant {
+                bandwidth: attribute(&attrs, "BANDWIDTH").and_then(|b| b.parse().ok()).unwrap_or(0),
+                audio_only: !codecs.is_empty() && codecs.split(',').all(|c| { let c = c.trim(); c.starts_with("mp4a") || c == "mp3" || c.starts_with("ac-3") || c.starts_with("ec-3") || c == "flac" }),
+                audio_group: attribute(&attrs, "AUDIO").map(str::to_string),
+                url: resolve_url(base, line),
+            });
+        }
+    }
+    let best = variants.iter().filter(|v| v.audio_only).max_by_key(|v| v.bandwidth).or_else(|| variants.iter().max_by_key(|v| v.bandwidth))?;
+    let rendition = best.audio_group.as_ref().and_then(|group| {
+        let group: Vec<_> = renditions.iter().filter(|(g, _, _)| g == group).collect();
+        group.iter().find(|(_, default, _)| *default).or(group.first()).map(|(_, _, url)| url.clone())
+    });
+    Some(rendition.unwrap_or_else(|| best.url.clone()))
+}
+
+fn parse_media_playlist(text: &str, base: &str) -> Result<MediaPlaylist, String> {
+    let mut playlist = MediaPlaylist { target_duration: Duration::from_secs(6), segments: Vec::new(), ended: false, map: None };
+    let mut seq = 0u64;
+    let (mut title, mut range, mut range_end) = (None, None, 0u64);
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if let Some(v) = line.strip_prefix("#EXT-X-TARGETDURATION:") {
+            if let Ok(secs) = v.trim().parse::<f64>() { playlist.target_duration = Duration::from_secs_f64(secs.clamp(1.0, 60.0)); }
+        } else if let Some(v) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+            seq = v.trim().parse().unwrap_or(0);
+        } else if let Some(v) = line.strip_prefix("#EXTINF:") {
+            title = v.split_once(',').map(|(_, t)| t.trim().to_string()).filter(|t| !t.is_empty());
+        } else if let Some(v) = line.strip_prefix("#EXT-X-BYTERANGE:") {
+            range = byte_range(v, range_end);
+        } else if let Some(v) = line.strip_prefix("#EXT-X-KEY:") {
+            let attrs = attributes(v);
+            let method = attribute(&attrs, "METHOD").unwrap_or("NONE");
+            if method != "NONE" { return Err(format!("Encrypted HLS streams ({method}) aren't supported")); }
+        } else if let Some(v) = line.strip_prefix("#EXT-X-MAP:") {
+            let attrs = attributes(v);
+            let Some(uri) = attribute(&attrs, "URI") else { continue };
+            playlist.map = Some(Resource { url: resolve_url(base, uri), range: attribute(&attrs, "BYTERANGE").and_then(|r| byte_range(r, 0)) });
+        } else if line == "#EXT-X-ENDLIST" {
+            playlist.ended = true;
+        } else if !line.starts_with('#') {
+            if let Some((offset, length)) = range { range_end = offset + length; }
+            playlist.segments.push(Segment { seq, resource: Resource { url: resolve_url(base, line), range: range.take() }, title: title.take() });
+            seq += 1;
+        }
+    }
+    Ok(playlist)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Container { Ts, Packed, Fmp4 }
+
+#[derive(Clone, Copy, PartialEq)]
+enum Codec { Aac, Mp3, Mp4 }
+
+impl Codec {
+    fn extension(self) -> &'static str { match self { Codec::Aac => "aac", Codec::Mp3 => "mp3", Codec::Mp4 => "mp4" } }
+}
+
+/// Downloads segments after the first and pushes their audio.
+struct Feeder {
+    media_url: String,
+    /// Media sequence number of the next segment to play.
+    next_seq: u64,
+    demux: Option<TsDemuxer>,
+    init_sent: bool,
+    format: Option<Codec>,
+}
+
+impl Feeder {
+    fn run(&mut self, shared: &Shared, mut playlist: MediaPlaylist) {
+        let url = self.media_url.clone();
+        let mut backoff = Duration::from_millis(500);
+        loop {
+            let mut progressed = false;
+            if let Some(first) = playlist.segments.first() && first.seq > self.next_seq {
+                // Fell behind the live window (e.g. after a long pause)
+                log::warn!("{}: segments {}..{} expired before they were played", redact_url(&url), self.next_seq, first.seq);
+                self.next_seq = first.seq;
+            }
+            let next_seq = self.next_seq;
+            for segment in playlist.segments.iter().filter(|s| s.seq >= next_seq) {
+                if shared.is_closed() { return; }
+                let mut attempt = 0;
+                let (audio, title) = loop {
+                    attempt += 1;
+                    match self.load(segment, playlist.map.as_ref()) {
+                        Ok(loaded) => break loaded,
+                        Err(e) if attempt < SEGMENT_ATTEMPTS => log::debug!("{e}; retrying"),
+                        Err(e) => { log::warn!("{e}; skipping the segment"); break (Vec::new(), None); }
+                    }
+                };
+                if let Some(title) = title { shared.title_from_here(title); }
+                if !shared.push(&audio, 0) { return; }
+                self.next_seq = segment.seq + 1;
+                progressed = true;
+            }
+            if playlist.ended { return shared.finish(None); }
+            // Poll again after a target duration, or sooner when the playlist hadn't moved on yet
+            let wait = if progressed { playlist.target_duration } else { playlist.target_duration / 2 };
+            if !sleep_unless_closed(shared, wait) { return; }
+            playlist = loop {
+                match fetch_playlist(&url).and_then(|text| parse_media_playlist(&text, &url)) {
+                    Ok(p) => { shared.info().update(|m| m.status = None); backoff = Duration::from_millis(500); break p; }
+                    // Encryption switched on mid-stream is not going to go away
+                    Err(e) if e.starts_with("Encrypted") => return shared.finish(Some(e)),
+                    Err(e) => {
+                        log::warn!("{e}; retrying in {:.1}s", backoff.as_secs_f32());
+                        shared.info().update(|m| m.status = Some(format!("Reconnecting ({e})")));
+                        if !sleep_unless_closed(shared, backoff) { return; }
+                        backoff = (backoff * 2).min(MAX_BACKOFF);
+                    }
+                }
+            };
+        }
+    }
+
+    /// Download a segment (and the init segment before the first fMP4 one). Returns its audio and any title.
+    fn load(&mut self, segment: &Segment, map: Option<&Resource>) -> Result<(Vec<u8>, Option<String>), String> {
+        let data = fetch(&segment.resource.url, segment.resource.range)?;
+        let container = match self.format {
+            None if map.is_some() || is_mp4_box(&data) => Container::Fmp4,
+            None if data.first() == Some(&0x47) && data.get(188).is_none_or(|&b| b == 0x47) => Container::Ts,
+            None => Container::Packed,
+            Some(Codec::Mp4) => Container::Fmp4,
+            Some(_) if self.demux.is_some() => Container::Ts,
+            Some(_) => Container::Packed,
+        };
+        let mut title = segment.title.clone();
+        let audio = match container {
+            Container::Fmp4 => {
+                self.format = Some(Codec::Mp4);
+                let mut out = Vec::new();
+                // Symphonia reads one init segment; later fragments must share it
+                if !self.init_sent && let Some(map) = map {
+                    out = fetch(&map.url, map.range)?;
+                    self.init_sent = true;
+                }
+                out.extend(data);
+                out
+            }
+            Container::Ts => {
+                let demux = self.demux.get_or_insert_with(TsDemuxer::default);
+                let mut tags = Vec::new();
+                let audio = demux.push(&data, &mut tags);
+                if let Some(tag_title) = tags.iter().rev().find_map(|t| id3_title(t)) { title = Some(tag_title); }
+                self.format = self.format.or(demux.codec);
+                audio
+            }
+            Container::Packed => {
+                let (tag, audio) = split_id3(&data);
+                if let Some(tag_title) = tag.and_then(id3_title) { title = Some(tag_title); }
+                // ADTS frames start with 0xFFF and layer 0; anything else is taken for MPEG audio
+                self.format = self.format.or_else(|| audio.get(..2).map(|h| if h[0] == 0xff && h[1] & 0xf6 == 0xf0 { Codec::Aac } else { Codec::Mp3 }));
+                audio.to_vec()
+            }
+        };
+        Ok((audio, title))
+    }
+}
+
+fn is_mp4_box(data: &[u8]) -> bool { data.get(4..8).is_some_and(|t| [b"ftyp", b"styp", b"moof", b"sidx", b"moov"].iter().any(|b| t == *b)) }
+
+fn sleep_unless_closed(shared: &Shared, duration: Duration) -> bool {
+    let until = Instant::now() + duration;
+    while Instant::now() < until {
+        if shared.is_closed() { return false; }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    !shared.is_closed()
+}
+
+/// Split leading ID3 tags (timestamps and metadata in packed audio segments) from the audio.
+fn split_id3(mut data: &[u8]) -> (Option<&[u8]>, &[u8]) {
+    let mut tag = None;
+    while data.len() >= 10 && &data[..3] == b"ID3" {
+        let size = data[6..10].iter().fold(0usize, |acc, &b| acc << 7 | (b & 0x7f) as usize);
+        let footer = if data[5] & 0x10 != 0 { 10 } else { 0 };
+        let end = (10 + size + footer).min(data.len());
+        tag = Some(&data[..end]);
+        data = &data[end..];
+    }
+    (tag, data)
+}
+
+/// "Artist - Title" from an ID3 tag, if it has a title.
+fn id3_title(data: &[u8]) -> Option<String> {
+    let tag = Id3Tag::parse(data)?;
+    let title = tag.text("TIT2")?;
+    Some(tag.text("TPE1").map_or_else(|| title.clone(), |artist| format!("{artist} - {title}")))
+}
+
+/// Extracts the first audio elementary stream (and ID3 timed metadata) from MPEG transport stream packets.
+#[derive(Default)]
+struct TsDemuxer {
+    pmt_pid: Option<u16>,
+    audio_pid: Option<u16>,
+    codec: Option<Codec>,
+    id3_pid: Option<u16>,
+    id3: Vec<u8>,
+}
+
+impl TsDemuxer {
+    /// Demux one segment. Returns the audio; complete ID3 tags are added to `tags`.
+    fn push(&mut self, data: &[u8], tags: &mut Vec<Vec<u8>>) -> Vec<u8> {
+        let mut audio = Vec::with_capacity(data.len());
+        let mut p = 0;
+        while p + 188 <= data.len() {
+            if data[p] != 0x47 {
+                // Lost sync: find the next packet start
+                p += data[p..].iter().position(|&b| b == 0x47).unwrap_or(data.len() - p).max(1);
+                continue;
+            }
+            let packet = &data[p..p + 188];
+            p += 188;
+            let start = packet[1] & 0x40 != 0;
+            let pid = u16::from(packet[1] & 0x1f) << 8 | u16::from(packet[2]);
+            let control = packet[3] >> 4 & 3;
+            if control & 1 == 0 { continue; }
+            let offset = if control & 2 != 0 { 5 + packet[4] as usize } else { 4 };
+            let Some(payload) = packet.get(offset..) else { continue };
+            if pid == 0 {
+                self.read_pat(psi_section(payload, start));
+            } else if Some(pid) == self.pmt_pid {
+                self.read_pmt(psi_section(payload, start));
+            } else if Some(pid) == self.audio_pid {
+                audio.extend_from_slice(if start { pes_payload(payload) } else { payload });
+            } else if Some(pid) == self.id3_pid {
+                if start {
+                    if !self.id3.is_empty() { tags.push(std::mem::take(&mut self.id3)); }
+                    self.id3.extend_from_slice(pes_payload(payload));
+                } else {
+                    self.id3.extend_from_slice(payload);
+                }
+            }
+        }
+        if !self.id3.is_empty() { tags.push(std::mem::take(&mut self.id3)); }
+        audio
+    }
+
+    fn read_pat(&mut self, section: &[u8]) {
+        let Some(body) = section_body(section, 8) else { return };
+        if let Some(entry) = body.chunks_exact(4).find(|e| u16::from_be_bytes([e[0], e[1]]) != 0) {
+            self.pmt_pid = Some(u16::from(entry[2] & 0x1f) << 8 | u16::from(entry[3]));
+        }
+    }
+
+    fn read_pmt(&mut self, section: &[u8]) {
+        let Some(info_len) = section.get(10..12).map(|b| (usize::from(b[0] & 0x0f) << 8) | usize::from(b[1])) else { return };
+        let Some(body) = section_body(section, 12 + info_len) else { return };
+        let mut i = 0;
+        while i + 5 <= body.len() {
+            let (stream_type, pid) = (body[i], u16::from(body[i + 1] & 0x1f) << 8 | u16::from(body[i + 2]));
+            i += 5 + ((usize::from(body[i + 3] & 0x0f) << 8) | usize::from(body[i + 4]));
+            let codec = match stream_type {
+                0x0f => Some(Codec::Aac),
+                0x03 | 0x04 => Some(Codec::Mp3),
+                0x15 => { self.id3_pid = Some(pid); None }
+                _ => None,
+            };
+            if let Some(codec) = codec && self.audio_pid.is_none_or(|p| p == pid) {
+                (self.audio_pid, self.codec) = (Some(pid), Some(codec));
+            }
+        }
+    }
+}
+
+/// The table section in a PSI packet's payload (after the pointer field when a section starts here).
+fn psi_section(payload: &[u8], start: bool) -> &[u8] {
+    if !start { return &[]; }
+    let pointer = payload.first().map_or(0, |&p| p as usize);
+    payload.get(1 + pointer..).unwrap_or_default()
+}
+
+/// A section's entries from `from`, without the trailing CRC.
+fn section_body(section: &[u8], from: usize) -> Option<&[u8]> {
+    let length = (usize::from(*section.get(1)? & 0x0f) << 8) | usize::from(*section.get(2)?);
+    section.get(from..(3 + length).saturating_sub(4))
+}
+
+/// The data of a PES packet, after its header.
+fn pes_payload(payload: &[u8]) -> &[u8] {
+    if payload.len() < 9 || payload[..3] != [0, 0, 1] { return payload; }
+    payload.get(9 + payload[8] as usize..).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{reply, serve};
+    use std::collections::HashMap;
+    use std::io::Read;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Serve `files` by path; anything else is a 404.
+    fn serve_files(files: Vec<(&str, Vec<u8>)>) -> String {
+        let files: HashMap<String, Vec<u8>> = files.into_iter().map(|(path, data)| (path.to_string(), data)).collect();
+        serve(move |request, stream| match files.get(&request.target) {
+            Some(data) => reply(stream, 200, "application/octet-stream", data),
+            None => reply(stream, 404, "text/plain", b""),
+        })
+    }
+
+    fn read_all(mut stream: HttpStream) -> Vec<u8> {
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).unwrap();
+        data
+    }
+
+    /// An ADTS-looking frame (sync word and layer 0), tagged with `n`.
+    fn adts(n: u8) -> Vec<u8> { vec![0xff, 0xf1, n, 0, 0, 0, 0] }
+
+    /// A minimal ID3v2.4 tag with an artist and title.
+    fn id3(artist: &str, title: &str) -> Vec<u8> {
+        let mut frames = Vec::new();
+        for (id, text) in [(b"TPE1", artist), (b"TIT2", title)] {
+            frames.extend(id);
+            frames.extend((text.len() as u32 + 1).to_be_bytes());
+            frames.extend([0, 0, 3]);
+            frames.extend(text.as_bytes());
+        }
+        [b"ID3\x04\x00\x00\x00\x00\x00".to_vec(), vec![frames.len() as u8], frames].concat()
+    }
+
+    #[test]
+    fn master_playlists_prefer_audio_only_variants_and_their_renditions() {
+        let master = "#EXTM3U\n\
+            #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aac\",NAME=\"Other\",URI=\"other/index.m3u8\"\n\
+            #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aac\",NAME=\"Main, stereo\",DEFAULT=YES,URI=\"main/index.m3u8\"\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=5000000,CODECS=\"avc1.64001f,mp4a.40.2\"\nvideo.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=64000,CODECS=\"mp4a.40.5\"\nlow.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=128000,CODECS=\"mp4a.40.2\",AUDIO=\"aac\"\nhigh.m3u8\n";
+        assert_eq!(choose_variant(master, "http://host/live/master.m3u8?token=1").as_deref(), Some("http://host/live/main/index.m3u8"));
+        let video_only = "#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=1\n/a.m3u8\n#EXT-X-STREAM-INF:BANDWIDTH=2\n/b.m3u8\n";
+        assert_eq!(choose_variant(video_only, "http://host/x.m3u8").as_deref(), Some("http://host/b.m3u8"));
+        assert_eq!(choose_variant("#EXTM3U\n#EXTINF:2,\nseg0.ts\n", "http://host/x.m3u8"), None);
+    }
+
+    #[test]
+    fn media_playlists_list_segments_with_titles_and_byte_ranges() {
+        let text = "#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXT-X-MEDIA-SEQUENCE:7\n#EXT-X-MAP:URI=\"init.mp4\",BYTERANGE=\"100@0\"\n\
+            #EXTINF:4.0,Artist - One\n#EXT-X-BYTERANGE:500@100\nall.m4s\n#EXTINF:4.0,\n#EXT-X-BYTERANGE:300\nall.m4s\n#EXT-X-ENDLIST\n";
+        let playlist = parse_media_playlist(text, "http://host/a/media.m3u8").unwrap();
+        assert!(playlist.ended);
+        assert_eq!(playlist.target_duration, Duration::from_secs(4));
+        assert!(playlist.map == Some(Resource { url: "http://host/a/init.mp4".into(), range: Some((0, 100)) }));
+        let segments: Vec<_> = playlist.segments.iter().map(|s| (s.seq, s.resource.range, s.title.as_deref())).collect();
+        assert_eq!(segments, [(7, Some((100, 500)), Some("Artist - One")), (8, Some((600, 300)), None)]);
+        let encrypted = "#EXTM3U\n#EXT-X-KEY:METHOD=AES-128,URI=\"key\"\n#EXTINF:4,\nseg.ts\n";
+        assert_eq!(parse_media_playlist(encrypted, "http://host/").err().as_deref(), Some("Encrypted HLS streams (AES-128) aren't supported"));
+    }
+
+    #[test]
+    fn packed_audio_loses_its_id3_headers_and_titles_follow_the_tags() {
+        let url = serve_files(vec![
+            ("/master.m3u8", b"#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=1,CODECS=\"mp4a.40.2\"\nmedia.m3u8\n".to_vec()),
+            ("/media.m3u8", b"#EXTM3U\n#EXT-X-TARGETDURATION:2\n#EXTINF:2,From Playlist\nseg0.aac\n#EXTINF:2,\nseg1.aac\n#EXT-X-ENDLIST\n".to_vec()),
+            ("/seg0.aac", adts(0)),
+            ("/seg1.aac", [id3("Artist", "Song"), adts(1)].concat()),
+        ]);
+        let (stream, info, format) = open_hls(&format!("{url}/master.m3u8")).unwrap();
+        assert_eq!(format, "aac");
+        assert_eq!(info.get().title.as_deref(), Some("From Playlist"));
+        assert_eq!(read_all(stream), [adts(0), adts(1)].concat());
+        assert_eq!(info.get().title.as_deref(), Some("Artist - Song"));
+    }
+
+    #[test]
+    fn fmp4_streams_send_the_init_segment_once() {
+        let url = serve_files(vec![
+            ("/media.m3u8", b"#EXTM3U\n#EXT-X-MAP:URI=\"init.mp4\"\n#EXTINF:2,\nseg0.m4s\n#EXTINF:2,\nseg1.m4s\n#EXT-X-ENDLIST\n".to_vec()),
+            ("/init.mp4", b"\0\0\0\x08ftyp".to_vec()),
+            ("/seg0.m4s", b"\0\0\0\x08moof0".to_vec()),
+            ("/seg1.m4s", b"\0\0\0\x08moof1".to_vec()),
+        ]);
+        let (stream, _, format) = open_hls(&format!("{url}/media.m3u8")).unwrap();
+        assert_eq!(format, "mp4");
+        assert_eq!(read_all(stream), b"\0\0\0\x08ftyp\0\0\0\x08moof0\0\0\0\x08moof1");
+    }
+
+    /// Split `payload` into 188-byte packets on `pid`, padding the last one with adaptation-field stuffing.
+    fn ts_packets(pid: u16, payload: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (i, chunk) in payload.chunks(184).enumerate() {
+            out.extend([0x47, if i == 0 { 0x40 } else { 0 } | (pid >> 8) as u8, pid as u8]);
+            let stuffing = 184 - chunk.len();
+            if stuffing == 0 { out.push(0x10); } else {
+                out.extend([0x30, stuffing as u8 - 1]);
+                if stuffing > 1 { out.push(0); out.extend(std::iter::repeat_n(0xff, stuffing - 2)); }
+            }
+            out.extend(chunk);
+        }
+        out
+    }
+
+    /// A PSI section (pointer field first) with a zero CRC, which the demuxer doesn't check.
+    fn psi(table_id: u8, body: &[u8]) -> Vec<u8> {
+        let length = 5 + body.len() + 4;
+        [&[0, table_id, 0xb0 | (length >> 8) as u8, length as u8, 0, 1, 0xc1, 0, 0][..], body, &[0; 4]].concat()
+    }
+
+    fn pes(stream_id: u8, data: &[u8]) -> Vec<u8> { [&[0, 0, 1, stream_id, 0, 0, 0x80, 0, 0][..], data].concat() }
+
+    #[test]
+    fn transport_streams_are_demuxed_to_audio_and_timed_metadata() {
+        // PAT -> PMT on 0x1000, which lists AAC on 0x101 and ID3 on 0x102
+        let tables = [
+            ts_packets(0, &psi(0x00, &[0, 1, 0xf0, 0x00])),
+            ts_packets(0x1000, &psi(0x02, &[0xe1, 0x01, 0xf0, 0x00, 0x0f, 0xe1, 0x01, 0xf0, 0x00, 0x15, 0xe1, 0x02, 0xf0, 0x00])),
+        ].concat();
+        let audio: Vec<u8> = (0..400).map(|i| i as u8).collect();
+        let segment = [tables, ts_packets(0x102, &pes(0xbd, &id3("Live", "Now"))), ts_packets(0x101, &pes(0xc0, &audio[..200])), ts_packets(0x101, &pes(0xc0, &audio[200..]))].concat();
+        let url = serve_files(vec![
+            ("/media.m3u8", b"#EXTM3U\n#EXTINF:2,\nseg0.ts\n#EXT-X-ENDLIST\n".to_vec()),
+            ("/seg0.ts", segment),
+        ]);
+        let (stream, info, format) = open_hls(&format!("{url}/media.m3u8")).unwrap();
+        assert_eq!(format, "aac");
+        assert_eq!(info.get().title.as_deref(), Some("Live - Now"));
+        assert_eq!(read_all(stream), audio);
+    }
+
+    #[test]
+    fn live_playlists_are_followed_and_missing_segments_skipped() {
+        let polls = AtomicUsize::new(0);
+        let url = serve(move |request, stream| {
+            let path = request.target.trim_start_matches('/');
+            if path == "live.m3u8" {
+                // The window moves on between polls, then the stream ends
+                let (first, end) = if polls.fetch_add(1, Ordering::SeqCst) == 0 { (0, "") } else { (3, "#EXT-X-ENDLIST\n") };
+                let segments: String = (first..first + 5).map(|seq| format!("#EXTINF:1,\nseg{seq}.aac\n")).collect();
+                let playlist = format!("#EXTM3U\n#EXT-X-TARGETDURATION:1\n#EXT-X-MEDIA-SEQUENCE:{first}\n{segments}{end}");
+                return reply(stream, 200, "application/vnd.apple.mpegurl", playlist.as_bytes());
+            }
+            match path.strip_prefix("seg").and_then(|s| s.strip_suffix(".aac")).and_then(|s| s.parse::<u8>().ok()) {
+                Some(seq) if seq != 5 => reply(stream, 200, "audio/aac", &adts(seq)),
+                _ => reply(stream, 404, "text/plain", b""),
+            }
+        });
+        let (stream, _, _) = open_hls(&format!("{url}/live.m3u8")).unwrap();
+        // Playback joins three segments behind the live edge
+        assert_eq!(read_all(stream), [2, 3, 4, 6, 7].map(adts).concat());
+    }
+
+    #[test]
+    fn unreachable_playlists_are_errors() {
+        let url = serve_files(vec![("/text.m3u8", b"<html>".to_vec()), ("/empty.m3u8", b"#EXTM3U\n#EXT-X-ENDLIST\n".to_vec())]);
+        assert!(open_hls(&format!("{url}/missing.m3u8")).err().unwrap().contains("404"));
+        assert_eq!(open_hls(&format!("{url}/text.m3u8")).err().unwrap(), format!("{url}/text.m3u8: not an HLS playlist"));
+        assert_eq!(open_hls(&format!("{url}/empty.m3u8")).err().unwrap(), format!("{url}/empty.m3u8: playlist has no segments"));
+    }
+}
@@ -0,0 +1,280 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::tag_edit::{rewrite, Part};
+
+// Minimal ID3v2 (2.2/2.3/2.4) reader and writer: splits a tag into its frames and decodes text frames. Used for
+// containers Symphonia doesn't read tags from (DSF/DFF), for lyrics frames, to tag radio recordings, and by the tag editor for MP3s.
+
+/// The frames of an ID3v2 tag. Version 2.2 frame ids are mapped to their 2.3 equivalents where one exists.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Id3Tag {
+    pub frames: Vec<(String, Vec<u8>)>,
+    /// 3 for a tag read as 2.2 or 2.3, which is written back as 2.3; anything else is written as 2.4.
+    pub version: u8,
+    /// Compressed, encrypted or grouped frames, which can't be decoded (or would lose their group): id, flags and
+    /// content as read, written back unchanged unless a frame with the same id replaces them.
+    opaque: Vec<(String, [u8; 2], Vec<u8>)>,
+}
+
+impl Id3Tag {
+    /// Parse a tag starting at the `ID3` header. Returns `None` if `data` doesn't start with a tag.
+    pub(crate) fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < 10 || &data[..3] != b"ID3" { return None; }
+        let (version, flags) = (data[3], data[5]);
+        let size = syncsafe(&data[6..10]) as usize;
+        let mut body = data.get(10..10 + size).unwrap_or(&data[10..]).to_vec();
+        // Tag-wide unsynchronisation (2.4 flags it per frame instead)
+        if flags & 0x80 != 0 && version < 4 { body = resync(&body); }
+        let mut p = 0;
+        if flags & 0x40 != 0 && version >= 3 && body.len() >= 4 {
+            // Extended header: 2.3 gives the size without itself, 2.4 with it
+            p = if version == 3 { 4 + be(&body[..4]) as usize } else { syncsafe(&body[..4]) as usize };
+        }
+        let (id_len, header_len) = if version == 2 { (3, 6) } else { (4, 10) };
+        let (mut frames, mut opaque) = (Vec::new(), Vec::new());
+        while p + header_len <= body.len() && body[p] != 0 {
+            let id = String::from_utf8_lossy(&body[p..p + id_len]).into_owned();
+            let len = match version {
+                2 => be(&body[p + 3..p + 6]) as usize,
+                3 => be(&body[p + 4..p + 8]) as usize,
+                _ => syncsafe(&body[p + 4..p + 8]) as usize,
+            };
+            let flags = if version == 2 { [0, 0] } else { [body[p + 8], body[p + 9]] };
+            p += header_len;
+            let Some(content) = body.get(p..p + len) else { break };
+            p += len;
+            // Compression, encryption or grouping (bits 7-5 in 2.3; 6, 3 and 2 in 2.4)
+            if flags[1] & if version == 3 { 0xe0 } else { 0x4c } != 0 {
+                opaque.push((id, flags, content.to_vec()));
+                continue;
+            }
+            let frame_flags = if version == 4 { flags[1] } else { 0 };
+            let mut content = content.to_vec();
+            if frame_flags & 0x02 != 0 { content = resync(&content); }
+            // 2.4 data length indicator
+            if frame_flags & 0x01 != 0 && content.len() >= 4 { content.drain(..4); }
+            let id = if version == 2 { v22_id(&id).map_or(id, str::to_string) } else { id };
+            frames.push((id, content));
+        }
+        Some(Self { frames, version: if version == 4 { 4 } else { 3 }, opaque })
+    }
+
+    /// The first frame with `id`.
+    pub(crate) fn frame(&self, id: &str) -> Option<&[u8]> {
+        self.frames.iter().find(|(f, _)| f == id).map(|(_, data)| data.as_slice())
+    }
+
+    /// A text frame (`TIT2`, `TPE1`, ...). Multiple values are joined with ", ".
+    pub(crate) fn text(&self, id: &str) -> Option<String> {
+        let data = self.frame(id)?;
+        let (&encoding, text) = data.split_first()?;
+        let text = decode_text(encoding, text);
+        let values: Vec<&str> = text.split('\0').map(str::trim).filter(|s| !s.is_empty()).collect();
+        (!values.is_empty()).then(|| values.join(", "))
+    }
+
+    /// The picture data of the front cover (APIC type 3), or of the first picture when none is marked as one.
+    pub(crate) fn picture(&self) -> Option<&[u8]> {
+        // Encoding, MIME type, picture type, description, data
+        let pictures: Vec<(u8, &[u8])> = self.frames.iter().filter(|(id, _)| id == "APIC").filter_map(|(_, data)| apic_picture(data))
+            .filter(|(_, picture)| !picture.is_empty()).collect();
+        pictures.iter().find(|(kind, _)| *kind == 3).or(pictures.first()).map(|(_, picture)| *picture)
+    }
+
+    /// Make `data` (of MIME type `mime`) the front cover, keeping pictures of other types.
+    pub(crate) fn set_picture(&mut self, mime: &str, data: &[u8]) {
+        self.frames.retain(|(id, frame)| id != "APIC" || apic_picture(frame).is_some_and(|(kind, _)| kind != 3));
+        // Latin-1, MIME type, front cover, no description
+        self.frames.push(("APIC".into(), [&[0][..], mime.as_bytes(), &[0, 3, 0], data].concat()));
+    }
+
+    /// Replace the text frame `id` (UTF-8), or remove it when `text` is empty.
+    pub(crate) fn set_text(&mut self, id: &str, text: &str) {
+        self.set_frame(id, (!text.is_empty()).then(|| [&[3u8][..], text.as_bytes()].concat()));
+    }
+
+    /// Replace every frame `id` with `data`, or remove them for `None`.
+    pub(crate) fn set_frame(&mut self, id: &str, data: Option<Vec<u8>>) {
+        self.frames.retain(|(f, _)| f != id);
+        self.opaque.retain(|(f, _, _)| f != id);
+        if let Some(data) = data { self.frames.push((id.to_string(), data)); }
+    }
+
+    /// The tag as ID3v2.3 or 2.4 bytes, by `version`.
+    pub(crate) fn render(&self) -> Vec<u8> {
+        let v23 = self.version == 3;
+        let mut body = Vec::new();
+        // 2.3 has plain frame sizes and no UTF-8
+        let decoded = self.frames.iter().map(|(id, data)| (id, [0, 0], if v23 { to_utf16(id, data) } else { Cow::Borrowed(data.as_slice()) }));
+        let opaque = self.opaque.iter().map(|(id, flags, data)| (id, *flags, Cow::Borrowed(data.as_slice())));
+        for (id, flags, data) in decoded.chain(opaque).filter(|(id, _, _)| id.len() == 4) {
+            body.extend(id.as_bytes());
+            body.extend(if v23 { (data.len() as u32).to_be_bytes() } else { to_syncsafe(data.len() as u32) });
+            body.extend(flags);
+            body.extend(data.iter());
+        }
+        let mut out = if v23 { b"ID3\x03\x00\x00".to_vec() } else { b"ID3\x04\x00\x00".to_vec() };
+        out.extend(to_syncsafe(body.len() as u32));
+        out.extend(body);
+        out
+    }
+}
+
+/// The ID3v2 tag at the start of a file (MP3), read without the audio behind it.
+pub(crate) fn read_id3(path: &Path) -> Option<Id3Tag> {
+    let mut f = File::open(path).ok()?;
+    let mut header = [0u8; 10];
+    f.read_exact(&mut header).ok()?;
+    if &header[..3] != b"ID3" { return None; }
+    let mut data = header.to_vec();
+    f.take(syncsafe(&header[6..10]) as u64).read_to_end(&mut data).ok()?;
+    Id3Tag::parse(&data)
+}
+
+/// Put `tag` at the start of `path` in place of the tag that is there (if any), keeping the audio.
+pub(crate) fn write_id3(path: &Path, tag: &Id3Tag) -> Result<(), String> {
+    let err = |e: std::io::Error| format!("Failed to read {}: {e}", path.display());
+    let mut f = File::open(path).map_err(err)?;
+    let len = f.metadata().map_err(err)?.len();
+    let mut header = [0u8; 10];
+    let old_len = if f.read_exact(&mut header).is_ok() && &header[..3] == b"ID3" { tag_len(&header).min(len) } else { 0 };
+    rewrite(path, &[Part::New(&tag.render()), Part::Old(old_len..len)])
+}
+
+/// Length of the tag whose 10-byte `header` this is, header (and footer, if any) included.
+pub(crate) fn tag_len(header: &[u8; 10]) -> u64 {
+    // A footer repeats the header after the tag
+    10 + syncsafe(&header[6..10]) as u64 + if header[5] & 0x10 != 0 { 10 } else { 0 }
+}
+
+// Picture type and data of an APIC frame: encoding, MIME type, picture type, description, data
+fn apic_picture(data: &[u8]) -> Option<(u8, &[u8])> {
+    let (&encoding, rest) = data.split_first()?;
+    let (_, rest) = split_terminated(0, rest);
+    let (&kind, rest) = rest.split_first()?;
+    Some((kind, split_terminated(encoding, rest).1))
+}
+
+// A UTF-8 text, comment, lyrics or picture frame re-encoded as UTF-16 with BOM for a 2.3 tag
+fn to_utf16<'a>(id: &str, data: &'a [u8]) -> Cow<'a, [u8]> {
+    let Some((&3, rest)) = data.split_first() else { return Cow::Borrowed(data) };
+    let utf16 = |text: &[u8], terminated: bool| -> Vec<u8> {
+        let units = String::from_utf8_lossy(text).encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<_>>();
+        [&[0xff, 0xfe][..], &units, if terminated { &[0, 0] } else { &[] }].concat()
+    };
+    let mut out = vec![1];
+    match id {
+        // Zero-separated values (and TXXX's description)
+        _ if id.starts_with('T') => {
+            let values: Vec<&[u8]> = rest.strip_suffix(&[0]).unwrap_or(rest).split(|&b| b == 0).collect();
+            for (i, value) in values.iter().enumerate() { out.extend(utf16(value, i + 1 < values.len())); }
+        }
+        "COMM" | "USLT" if rest.len() >= 3 => {
+            let (description, text) = split_terminated(3, &rest[3..]);
+            out.extend(&rest[..3]);
+            out.extend(utf16(description, true));
+            out.extend(utf16(text, false));
+        }
+        "APIC" => {
+            let (mime, rest) = split_terminated(0, rest);
+            let Some((&kind, rest)) = rest.split_first() else { return Cow::Borrowed(data) };
+            let (description, picture) = split_terminated(3, rest);
+            out.extend([mime, &[0, kind]].concat());
+            out.extend(utf16(description, true));
+            out.extend(picture);
+        }
+        _ => return Cow::Borrowed(data),
+    }
+    Cow::Owned(out)
+}
+
+fn be(b: &[u8]) -> u32 { b.iter().fold(0, |acc, &x| acc << 8 | x as u32) }
+fn syncsafe(b: &[u8]) -> u32 { b.iter().fold(0, |acc, &x| acc << 7 | (x & 0x7f) as u32) }
+fn to_syncsafe(n: u32) -> [u8; 4] { [(n >> 21) as u8 & 0x7f, (n >> 14) as u8 & 0x7f, (n >> 7) as u8 & 0x7f, n as u8 & 0x7f] }
+
+// Undo unsynchronisation: every 0xFF 0x00 was written for a plain 0xFF.
+fn resync(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for (i, &b) in data.iter().enumerate() {
+        if b == 0 && i > 0 && data[i - 1] == 0xff { continue; }
+        out.push(b);
+    }
+    out
+}
+
+/// Decode ID3 text in the given encoding (0 Latin-1, 1 UTF-16 with BOM, 2 UTF-16BE, 3 UTF-8).
+pub(crate) fn decode_text(encoding: u8, data: &[u8]) -> String {
+    match encoding {
+        1 | 2 => {
+            let (big_endian, data) = match data {
+                [0xfe, 0xff, rest @ ..] => (true, rest),
+                [0xff, 0xfe, rest @ ..] => (false, rest),
+                _ => (encoding == 2, data),
+            };
+            let units = data.chunks_exact(2).map(|c| if big_endian { u16::from_be_bytes([c[0], c[1]]) } else { u16::from_le_bytes([c[0], c[1]]) });
+            char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect()
+        }
+        3 => String::from_utf8_lossy(data).into_owned(),
+        _ => data.iter().map(|&b| b as char).collect(),
+    }
+}
+
+/// Split off a string ended by the encoding's terminator (two zero bytes on a 16-bit boundary for UTF-16).
+pub(crate) fn split_terminated(encoding: u8, data: &[u8]) -> (&[u8], &[u8]) {
+    let end = if matches!(encoding, 1 | 2) {
+        (0..data.len() / 2).map(|i| i * 2).find(|&i| data[i] == 0 && data[i + 1] == 0).map(|i| (i, i + 2))
+    } else {
+        data.iter().position(|&b| b == 0).map(|i| (i, i + 1))
+    };
+    match end { Some((text_end, next)) => (&data[..text_end], &data[next..]), None => (data, &[]) }
+}
+
+fn v22_id(id: &str) -> Option<&'static str> {
+    Some(match id {
+        "TT2" => "TIT2", "TP1" => "TPE1", "TP2" => "TPE2", "TAL" => "TALB", "TRK" => "TRCK", "TPA" => "TPOS",
+        "TYE" => "TYER", "TCO" => "TCON", "COM" => "COMM", "ULT" => "USLT", "SLT" => "SYLT",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(version: u8, frames: &[(&str, [u8; 2], &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (id, flags, data) in frames {
+            body.extend(id.as_bytes());
+            body.extend(if version == 3 { (data.len() as u32).to_be_bytes() } else { to_syncsafe(data.len() as u32) });
+            body.extend(flags);
+            body.extend(*data);
+        }
+        [&[b'I', b'D', b'3', version, 0, 0][..], &to_syncsafe(body.len() as u32), &body].concat()
+    }
+
+    #[test]
+    fn frames_that_cant_be_decoded_are_written_back_unchanged() {
+        // A zlib-compressed 2.3 comment (decompressed size first) and a grouped 2.4 title
+        let compressed: &[u8] = &[0, 0, 0, 9, 0x78, 0x9c, 0x01, 0x02, 0x03];
+        for (version, id, flags, data) in [(3, "COMM", [0, 0x80], compressed), (4, "TIT3", [0, 0x40], &[7, 3, b'x'][..])] {
+            let mut tag = Id3Tag::parse(&tag(version, &[("TIT2", [0, 0], b"\x03Title"), (id, flags, data)])).unwrap();
+            assert_eq!((tag.text("TIT2").as_deref(), tag.frame(id)), (Some("Title"), None));
+            tag.set_text("TPE1", "Artist");
+            let written = Id3Tag::parse(&tag.render()).unwrap();
+            assert_eq!(written.opaque, vec![(id.to_string(), flags, data.to_vec())]);
+            assert_eq!(written.text("TPE1").as_deref(), Some("Artist"));
+            // Unless the frame is replaced
+            tag.set_text(id, "New");
+            assert!(Id3Tag::parse(&tag.render()).unwrap().opaque.is_empty());
+        }
+    }
+
+    #[test]
+    fn unsynchronised_frames_with_a_length_indicator_are_decoded() {
+        let tag = Id3Tag::parse(&tag(4, &[("TIT2", [0, 0x03], &[0, 0, 0, 4, 3, 0xff, 0, b'a'])])).unwrap();
+        assert_eq!(tag.frame("TIT2"), Some(&[3, 0xff, b'a'][..]));
+    }
+}
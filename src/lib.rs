@@ -1,3 +1,41 @@
+mod artwork;
+mod chapters;
+mod cue;
+mod decode;
+mod dsd;
+mod dsp;
+mod export;
+mod hls;
+mod id3;
+mod lyrics;
+mod midi;
+mod net;
+mod output;
+mod playlist;
+mod podcast;
+mod radio;
+mod record;
+mod scrobble;
+mod settings;
+mod silence;
 mod slint_app;
+mod subsonic;
+mod tag_edit;
+mod tags;
+#[cfg(test)]
+mod test_server;
+mod upnp;
 
+pub use artwork::{find_artwork, make_thumbnail, thumbnail, ArtSource, THUMB_SIZE};
+pub use dsp::{DspSettings, ResampleQuality};
+pub use export::{export_wav, ExportOptions, WavFormat};
+pub use lyrics::{embed_lyrics, parse_lrc, read_lyrics, save_sidecar, LrcEditor, LyricLine, Lyrics};
+pub use podcast::{download_episode, fetch_feed, Episode, Feed};
+pub use record::record_stream;
+pub use scrobble::{lastfm_sign_in, listenbrainz_sign_in, send_now_playing, submit_scrobbles, Listen, SubmitError, LASTFM_URL, LISTENBRAINZ_URL};
+pub use settings::{ScrobbleAccount, ScrobbleApi};
 pub use slint_app::run as run_app;
+pub use subsonic::SubsonicClient;
+pub use tag_edit::{write_tags, CoverEdit, TagEdit};
+pub use tags::{read_track_info, TagField, TrackInfo};
+pub use upnp::{discover, DidlEntry, MediaServer, DISCOVERY_TIME, ROOT_ID};
@@ -0,0 +1,522 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::dsd::{is_dsd, read_dsd_tag};
+use crate::id3::{decode_text, read_id3, split_terminated, write_id3, Id3Tag};
+
+// Lyrics of the playing file: a sidecar `.lrc` next to the audio, else lyrics embedded in its tags (ID3 SYLT/USLT,
+// Vorbis `LYRICS`/`UNSYNCEDLYRICS`, MP4 `©lyr`). Text lyrics are read as LRC wherever they come from, so embedded
+// LRC is synced too. Enhanced LRC (`<mm:ss.xx>` before each word) gives word timing for karaoke-style highlighting.
+// Lyrics synced by hand in the editor are saved as a sidecar `.lrc` or embedded in an MP3's ID3 tag.
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LyricLine {
+    /// Start in file time; `None` for unsynced lyrics.
+    pub start: Option<Duration>,
+    pub text: String,
+    /// Word timing from enhanced LRC: (start, byte offset of the word in `text`).
+    pub words: Vec<(Duration, usize)>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Lyrics {
+    pub lines: Vec<LyricLine>,
+    /// LRC `[offset:]`: milliseconds the lyrics are shown early (negative: late).
+    pub offset_ms: i64,
+}
+
+impl Lyrics {
+    pub fn is_synced(&self) -> bool { self.lines.iter().any(|l| l.start.is_some()) }
+
+    // Lyrics time at a file position: both the file's own offset and the user's shift it
+    fn time_at(&self, position: Duration, offset_ms: i64) -> i64 { position.as_millis() as i64 + self.offset_ms + offset_ms }
+
+    /// The line being sung at `position` (file time) and how many bytes of it have been sung: up to the end of
+    /// the current word with word timing, otherwise the whole line.
+    pub fn current(&self, position: Duration, offset_ms: i64) -> Option<(usize, usize)> {
+        let now = self.time_at(position, offset_ms);
+        let index = self.lines.iter().rposition(|l| l.start.is_some_and(|s| s.as_millis() as i64 <= now))?;
+        let line = &self.lines[index];
+        let sung = match line.words.iter().rposition(|(t, _)| t.as_millis() as i64 <= now) {
+            Some(w) => line.words.get(w + 1).map_or(line.text.len(), |&(_, at)| at),
+            None if line.words.is_empty() => line.text.len(),
+            None => line.words[0].1,
+        };
+        Some((index, sung))
+    }
+
+    /// File position at which line `index` starts, with the offsets applied.
+    pub fn line_position(&self, index: usize, offset_ms: i64) -> Option<Duration> {
+        let start = self.lines.get(index)?.start?.as_millis() as i64 - self.offset_ms - offset_ms;
+        Some(Duration::from_millis(start.max(0) as u64))
+    }
+
+    /// The lyrics as LRC, with enhanced LRC word times where lines have them. Unsynced lyrics give plain text.
+    pub fn to_lrc(&self) -> String {
+        let mut out = String::new();
+        if self.offset_ms != 0 { out.push_str(&format!("[offset:{:+}]\n", self.offset_ms)); }
+        for line in &self.lines {
+            if let Some(start) = line.start { out.push_str(&format!("[{}]", lrc_time(start))); }
+            let mut at = 0;
+            for &(time, word) in &line.words {
+                out.push_str(&line.text[at..word]);
+                out.push_str(&format!("<{}>", lrc_time(time)));
+                at = word;
+            }
+            out.push_str(&line.text[at..]);
+            out.push('\n');
+        }
+        out
+    }
+}
+
+fn lrc_time(t: Duration) -> String { format!("{:02}:{:02}.{:02}", t.as_secs() / 60, t.as_secs() % 60, t.subsec_millis() / 10) }
+
+/// Lyrics for `path`: the sidecar `.lrc` first, then embedded lyrics. `None` when there are none.
+pub fn read_lyrics(path: &Path) -> Option<Lyrics> {
+    let lyrics = sidecar(path).and_then(|lrc| std::fs::read(lrc).ok()).map(|data| parse_lrc(&decode_file(&data)))
+        .or_else(|| embedded(path))?;
+    (!lyrics.lines.is_empty()).then_some(lyrics)
+}
+
+/// `song.lrc` (or `song.flac.lrc`) next to `song.flac`, in any letter case.
+pub(crate) fn sidecar(path: &Path) -> Option<PathBuf> {
+    let (dir, stem, name) = (path.parent()?, path.file_stem()?.to_str()?, path.file_name()?.to_str()?);
+    let wanted = [format!("{stem}.lrc"), format!("{name}.lrc")];
+    std::fs::read_dir(dir).ok()?.flatten().map(|e| e.path())
+        .find(|p| p.file_name().and_then(|n| n.to_str()).is_some_and(|n| wanted.iter().any(|w| w.eq_ignore_ascii_case(n))))
+}
+
+// LRC files are mostly UTF-8, but UTF-16 (with BOM) and legacy 8-bit encodings turn up too
+fn decode_file(data: &[u8]) -> String {
+    match data {
+        [0xef, 0xbb, 0xbf, rest @ ..] => String::from_utf8_lossy(rest).into_owned(),
+        [0xff, 0xfe, ..] | [0xfe, 0xff, ..] => decode_text(1, data),
+        _ => String::from_utf8(data.to_vec()).unwrap_or_else(|_| decode_text(0, data)),
+    }
+}
+
+/// Save `lyrics` as the `.lrc` next to `audio`, over the one that was found there if any.
+pub fn save_sidecar(audio: &Path, lyrics: &Lyrics) -> Result<PathBuf, String> {
+    let lrc = sidecar(audio).unwrap_or_else(|| audio.with_extension("lrc"));
+    std::fs::write(&lrc, lyrics.to_lrc()).map_err(|e| format!("Failed to save {}: {e}", lrc.display()))?;
+    Ok(lrc)
+}
+
+/// Embed `lyrics` in the ID3 tag of an MP3: the timing as SYLT, and the words as USLT for players without SYLT.
+pub fn embed_lyrics(audio: &Path, lyrics: &Lyrics) -> Result<(), String> {
+    if !audio.extension().is_some_and(|e| e.eq_ignore_ascii_case("mp3")) {
+        return Err("Lyrics can only be embedded in MP3 files; save them as .lrc instead".into());
+    }
+    let mut tag = read_id3(audio).unwrap_or_default();
+    let text = lyrics.lines.iter().map(|l| l.text.as_str()).collect::<Vec<_>>().join("\n");
+    // UTF-8, no language, no descriptor
+    tag.set_frame("USLT", Some([&b"\x03XXX\0"[..], text.as_bytes()].concat()));
+    tag.set_frame("SYLT", lyrics.is_synced().then(|| render_sylt(lyrics)));
+    write_id3(audio, &tag)
+}
+
+// ===== LRC =====
+/// `mm:ss.xx` (also `mm:ss`, `mm:ss:xx` and `hh:mm:ss.xx`).
+fn parse_time(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let (clock, frac) = match s.rsplit_once('.') {
+        Some((clock, frac)) => (clock, Some(frac)),
+        // `mm:ss:xx`: a third two-digit field after minutes and seconds is hundredths
+        None if s.matches(':').count() == 2 && s.len() <= 8 => s.rsplit_once(':').map(|(c, f)| (c, Some(f)))?,
+        None => (s, None),
+    };
+    let mut secs = 0u64;
+    let mut fields = 0;
+    for part in clock.split(':') {
+        if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) { return None; }
+        secs = secs * 60 + part.parse::<u64>().ok()?;
+        fields += 1;
+    }
+    if !(2..=3).contains(&fields) { return None; }
+    let millis = match frac {
+        Some(f) if !f.is_empty() && f.len() <= 3 && f.bytes().all(|b| b.is_ascii_digit()) => f.parse::<u64>().ok()? * 10u64.pow(3 - f.len() as u32),
+        Some(_) => return None,
+        None => 0,
+    };
+    Some(Duration::from_millis(secs * 1000 + millis))
+}
+
+/// Parse LRC text. Lines without timestamps make unsynced lyrics when no line has one.
+pub fn parse_lrc(text: &str) -> Lyrics {
+    let mut lyrics = Lyrics::default();
+    let mut plain = Vec::new();
+    for raw in text.lines() {
+        let mut rest = raw.trim();
+        let mut times = Vec::new();
+        let mut tag_line = false;
+        // Leading `[..]` groups: timestamps (a repeated line lists several) or ID tags such as `[ar:Artist]`
+        while let Some(inner) = rest.strip_prefix('[') && let Some(end) = inner.find(']') {
+            let field = &inner[..end];
+            match parse_time(field) {
+                Some(t) => times.push(t),
+                None if times.is_empty() => {
+                    if let Some((key, value)) = field.split_once(':') && key.trim().eq_ignore_ascii_case("offset") {
+                        lyrics.offset_ms = value.trim().trim_start_matches('+').parse().unwrap_or(0);
+                    }
+                    tag_line = true;
+                }
+                None => break,
+            }
+            rest = inner[end + 1..].trim_start();
+        }
+        if times.is_empty() {
+            if !tag_line { plain.push(rest.to_string()); }
+            continue;
+        }
+        let (text, words) = parse_words(rest);
+        lyrics.lines.extend(times.into_iter().map(|t| LyricLine { start: Some(t), text: text.clone(), words: words.clone() }));
+    }
+    if lyrics.lines.is_empty() {
+        // Unsynced: keep the blank lines between verses, but not around the text
+        let first = plain.iter().position(|l| !l.is_empty()).unwrap_or(plain.len());
+        let last = plain.iter().rposition(|l| !l.is_empty()).map_or(first, |i| i + 1);
+        lyrics.lines = plain[first..last].iter().map(|text| LyricLine { start: None, text: text.clone(), words: Vec::new() }).collect();
+    } else {
+        lyrics.lines.sort_by_key(|l| l.start);
+    }
+    lyrics
+}
+
+// Enhanced LRC: `<mm:ss.xx>` in front of each word. Returns the plain text and where each timed word starts.
+fn parse_words(line: &str) -> (String, Vec<(Duration, usize)>) {
+    let (mut text, mut words) = (String::new(), Vec::new());
+    let mut rest = line;
+    while let Some(open) = rest.find('<') {
+        let Some(close) = rest[open..].find('>').map(|c| open + c) else { break };
+        text.push_str(&rest[..open]);
+        match parse_time(&rest[open + 1..close]) {
+            Some(t) => words.push((t, text.len())),
+            None => text.push_str(&rest[open..=close]),
+        }
+        rest = &rest[close + 1..];
+    }
+    text.push_str(rest);
+    let trimmed = text.trim_end().len();
+    text.truncate(trimmed);
+    // A trailing timestamp only marks where the last word ends; so do ones among the trailing spaces just cut
+    words.retain(|&(_, at)| at < text.len());
+    (text, words)
+}
+
+// ===== Embedded =====
+fn embedded(path: &Path) -> Option<Lyrics> {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+    let tag = if is_dsd(path) { read_dsd_tag(path) } else if ext == "mp3" { read_id3(path) } else { None };
+    match tag {
+        Some(tag) => id3_lyrics(&tag),
+        None => tag_lyrics(path).map(|text| parse_lrc(&text)),
+    }
+}
+
+// Synced SYLT beats USLT; USLT often holds LRC text anyway
+fn id3_lyrics(tag: &Id3Tag) -> Option<Lyrics> {
+    tag.frame("SYLT").and_then(parse_sylt).or_else(|| tag.frame("USLT").and_then(parse_uslt).map(|text| parse_lrc(&text)))
+}
+
+// Encoding, language (3), descriptor, text.
+fn parse_uslt(data: &[u8]) -> Option<String> {
+    let (&encoding, rest) = data.split_first()?;
+    let (_, text) = split_terminated(encoding, rest.get(3..)?);
+    let text = decode_text(encoding, text);
+    (!text.trim().is_empty()).then_some(text)
+}
+
+// Encoding, language (3), time format, content type, descriptor, then (text, 32-bit time) pairs. Only millisecond
+// times are supported; MPEG frame counts would need the bitrate. Taggers write either one entry per line, or one per
+// syllable with a newline in front of each entry that starts a line.
+fn parse_sylt(data: &[u8]) -> Option<Lyrics> {
+    let (&encoding, rest) = data.split_first()?;
+    if *rest.get(3)? != 2 { return None; }
+    let (_, mut rest) = split_terminated(encoding, rest.get(5..)?);
+    let mut entries = Vec::new();
+    while !rest.is_empty() {
+        let (text, after) = split_terminated(encoding, rest);
+        let Some(time) = after.get(..4) else { break };
+        entries.push((Duration::from_millis(u32::from_be_bytes(time.try_into().ok()?) as u64), decode_text(encoding, text)));
+        rest = &after[4..];
+    }
+    let syllables = entries.iter().skip(1).any(|(_, t)| t.starts_with(['\n', '\r']));
+    let mut lines: Vec<LyricLine> = Vec::new();
+    for (start, text) in entries {
+        let new_line = !syllables || text.starts_with(['\n', '\r']) || lines.is_empty();
+        let text = text.trim_start_matches(['\n', '\r']);
+        match lines.last_mut() {
+            Some(line) if !new_line => {
+                line.words.push((start, line.text.len()));
+                line.text.push_str(text);
+            }
+            _ => lines.push(LyricLine { start: Some(start), text: text.to_string(), words: if syllables { vec![(start, 0)] } else { Vec::new() } }),
+        }
+    }
+    for line in &mut lines {
+        let trimmed = line.text.trim_end().len();
+        line.text.truncate(trimmed);
+        line.words.retain(|&(_, at)| at < line.text.len());
+    }
+    lines.sort_by_key(|l| l.start);
+    (!lines.is_empty()).then_some(Lyrics { lines, offset_ms: 0 })
+}
+
+// SYLT in UTF-8 with millisecond times: one entry per line, or per word (each line's first entry starting with a
+// newline) when there is word timing. The LRC offset is applied, since SYLT has none.
+fn render_sylt(lyrics: &Lyrics) -> Vec<u8> {
+    let syllables = lyrics.lines.iter().any(|l| !l.words.is_empty());
+    let shift = |t: Duration| (t.as_millis() as i64 - lyrics.offset_ms).clamp(0, u32::MAX as i64) as u32;
+    let mut out = b"\x03XXX\x02\x01\0".to_vec();
+    for (i, line) in lyrics.lines.iter().enumerate() {
+        let Some(start) = line.start else { continue };
+        let mut entries = vec![(start, 0)];
+        entries.extend(line.words.iter().copied().filter(|&(_, at)| at > 0));
+        for (n, &(time, at)) in entries.iter().enumerate() {
+            let end = entries.get(n + 1).map_or(line.text.len(), |e| e.1);
+            if syllables && n == 0 && i > 0 { out.push(b'\n'); }
+            out.extend(&line.text.as_bytes()[at..end]);
+            out.push(0);
+            out.extend(shift(time).to_be_bytes());
+        }
+    }
+    out
+}
+
+// Lyrics among the tags Symphonia reads: Vorbis comments, MP4 atoms, and ID3 in other containers.
+fn tag_lyrics(path: &Path) -> Option<String> {
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::{MetadataOptions, StandardTagKey};
+    use symphonia::core::probe::Hint;
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) { hint.with_extension(ext); }
+    let mss = MediaSourceStream::new(Box::new(File::open(path).ok()?), Default::default());
+    let mut probed = symphonia::default::get_probe().format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default()).ok()?;
+    let mut tags = Vec::new();
+    if let Some(rev) = probed.metadata.get().as_ref().and_then(|m| m.current().cloned()) { tags.extend(rev.tags().to_vec()); }
+    if let Some(rev) = probed.format.metadata().current() { tags.extend(rev.tags().to_vec()); }
+    let is_lyrics = |t: &symphonia::core::meta::Tag| {
+        t.std_key == Some(StandardTagKey::Lyrics) || ["LYRICS", "UNSYNCEDLYRICS", "UNSYNCED LYRICS"].iter().any(|k| t.key.eq_ignore_ascii_case(k))
+    };
+    // Synced text is preferred when a file carries both kinds
+    let texts: Vec<String> = tags.iter().filter(|t| is_lyrics(t)).map(|t| t.value.to_string()).filter(|t| !t.trim().is_empty()).collect();
+    texts.iter().find(|t| parse_lrc(t).is_synced()).or(texts.first()).cloned()
+}
+
+// ===== Editor =====
+/// Lyrics being synced by hand: each tap stamps the current line with the playback position and moves on.
+/// Stamps are in file time and can be nudged afterwards.
+pub struct LrcEditor {
+    /// File the lyrics belong to.
+    pub path: PathBuf,
+    pub lines: Vec<LyricLine>,
+    /// Line the next stamp goes to.
+    pub current: usize,
+}
+
+impl LrcEditor {
+    /// Start from `lyrics` (plain or already synced). Existing stamps keep their place, with the file's offset
+    /// folded in.
+    pub fn new(path: &Path, lyrics: &Lyrics) -> Self {
+        let mut lines = lyrics.lines.clone();
+        for line in &mut lines {
+            if let Some(start) = line.start { shift_line(line, shifted(start, -lyrics.offset_ms)); }
+        }
+        let current = lines.iter().position(|l| l.start.is_none() && !is_blank(l)).unwrap_or(0);
+        Self { path: path.to_path_buf(), lines, current }
+    }
+
+    /// Stamp the current line with `position` and move to the next. Blank lines between verses are skipped.
+    pub fn stamp(&mut self, position: Duration) {
+        let Some(line) = self.lines.get_mut(self.current) else { return };
+        shift_line(line, position);
+        self.current = (self.current + 1..self.lines.len()).find(|&i| !is_blank(&self.lines[i])).unwrap_or(self.lines.len());
+    }
+
+    /// Go back to the previous line and clear its stamp.
+    pub fn undo(&mut self) {
+        let Some(index) = (0..self.current).rev().find(|&i| !is_blank(&self.lines[i])) else { return };
+        self.current = index;
+        let line = &mut self.lines[index];
+        line.start = None;
+        line.words.clear();
+    }
+
+    pub fn select(&mut self, index: usize) { self.current = index.min(self.lines.len()); }
+
+    /// The line a nudge moves: the current one once stamped, otherwise the one just stamped.
+    pub fn nudge_target(&self) -> Option<usize> {
+        if self.lines.get(self.current).is_some_and(|l| l.start.is_some()) { return Some(self.current); }
+        (0..self.current).rev().find(|&i| !is_blank(&self.lines[i])).filter(|&i| self.lines[i].start.is_some())
+    }
+
+    pub fn nudge(&mut self, delta_ms: i64) {
+        let Some(index) = self.nudge_target() else { return };
+        let line = &mut self.lines[index];
+        if let Some(start) = line.start { shift_line(line, shifted(start, delta_ms)); }
+    }
+
+    pub fn stamped(&self) -> usize { self.lines.iter().filter(|l| l.start.is_some()).count() }
+
+    /// The stamped lines, for previewing before every line has a time.
+    pub fn preview(&self) -> Lyrics {
+        let mut lines: Vec<LyricLine> = self.lines.iter().filter(|l| l.start.is_some()).cloned().collect();
+        lines.sort_by_key(|l| l.start);
+        Lyrics { lines, offset_ms: 0 }
+    }
+
+    /// The synced lyrics, once every line with words has a time. Unstamped blank lines are left out.
+    pub fn finish(&self) -> Result<Lyrics, String> {
+        let missing = self.lines.iter().filter(|l| l.start.is_none() && !is_blank(l)).count();
+        if missing > 0 { return Err(format!("{missing} line(s) still need a time")); }
+        Ok(self.preview())
+    }
+}
+
+fn is_blank(line: &LyricLine) -> bool { line.text.trim().is_empty() }
+
+fn shifted(t: Duration, delta_ms: i64) -> Duration { Duration::from_millis((t.as_millis() as i64 + delta_ms).max(0) as u64) }
+
+// Move a line to `start`, taking its word times along
+fn shift_line(line: &mut LyricLine, start: Duration) {
+    match line.start {
+        Some(old) => {
+            let delta = start.as_millis() as i64 - old.as_millis() as i64;
+            for (t, _) in &mut line.words { *t = shifted(*t, delta); }
+        }
+        None => line.words.clear(),
+    }
+    line.start = Some(start);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_times_among_trailing_spaces_are_dropped() {
+        let lyrics = parse_lrc("[00:01.00]<00:01.00>hello <00:02.00> <00:03.00>");
+        let line = &lyrics.lines[0];
+        assert_eq!((line.text.as_str(), line.words.as_slice()), ("hello", &[(Duration::from_secs(1), 0)][..]));
+        assert_eq!(lyrics.to_lrc(), "[00:01.00]<00:01.00>hello\n");
+    }
+
+    #[test]
+    fn enhanced_lrc_round_trips() {
+        let text = "[offset:+250]\n[00:01.50]<00:01.50>One <00:02.00>two\n[00:03.00][00:09.00]Chorus <not a time>\n";
+        let lyrics = parse_lrc(text);
+        assert_eq!(lyrics.offset_ms, 250);
+        assert_eq!(lyrics.lines.iter().map(|l| l.text.as_str()).collect::<Vec<_>>(), ["One two", "Chorus <not a time>", "Chorus <not a time>"]);
+        assert_eq!(lyrics.lines[0].words, [(Duration::from_millis(1500), 0), (Duration::from_secs(2), 4)]);
+        assert_eq!(lyrics.to_lrc(), "[offset:+250]\n[00:01.50]<00:01.50>One <00:02.00>two\n[00:03.00]Chorus <not a time>\n[00:09.00]Chorus <not a time>\n");
+    }
+
+    fn starts(editor: &LrcEditor) -> Vec<Option<u64>> { editor.lines.iter().map(|l| l.start.map(|s| s.as_millis() as u64)).collect() }
+
+    #[test]
+    fn stamps_skip_blank_lines_and_undo_clears_them() {
+        let mut editor = LrcEditor::new(Path::new("song.mp3"), &parse_lrc("one\ntwo\n\nthree"));
+        assert_eq!(editor.current, 0);
+        editor.stamp(Duration::from_secs(1));
+        editor.stamp(Duration::from_secs(2));
+        // The verse break is passed over
+        assert_eq!(editor.current, 3);
+        editor.stamp(Duration::from_secs(4));
+        assert_eq!(editor.current, 4);
+        assert_eq!(starts(&editor), [Some(1000), Some(2000), None, Some(4000)]);
+        // Stamping past the end does nothing
+        editor.stamp(Duration::from_secs(5));
+        assert_eq!(editor.stamped(), 3);
+        editor.undo();
+        editor.undo();
+        assert_eq!(editor.current, 1);
+        assert_eq!(starts(&editor), [Some(1000), None, None, None]);
+        editor.undo();
+        editor.undo();
+        assert_eq!((editor.current, editor.stamped()), (0, 0));
+    }
+
+    #[test]
+    fn nudge_moves_the_line_just_stamped_with_its_words() {
+        // Half-synced, as when an editing session is resumed
+        let mut lyrics = parse_lrc("[00:01.00]<00:01.00>one <00:01.50>two");
+        lyrics.lines.push(LyricLine { text: "three".into(), ..Default::default() });
+        let mut editor = LrcEditor::new(Path::new("song.mp3"), &lyrics);
+        // Existing stamps stay; the first unstamped line is next
+        assert_eq!(editor.current, 1);
+        assert_eq!(editor.nudge_target(), Some(0));
+        editor.nudge(-200);
+        assert_eq!(editor.lines[0].start, Some(Duration::from_millis(800)));
+        assert_eq!(editor.lines[0].words, [(Duration::from_millis(800), 0), (Duration::from_millis(1300), 4)]);
+        // A nudge never goes before the start of the file
+        editor.nudge(-5000);
+        assert_eq!(editor.lines[0].words, [(Duration::ZERO, 0), (Duration::from_millis(500), 4)]);
+        // Restamping a line moves its words along; stamping an unsynced one has none to move
+        editor.select(0);
+        assert_eq!(editor.nudge_target(), Some(0));
+        editor.stamp(Duration::from_secs(2));
+        assert_eq!(editor.lines[0].words, [(Duration::from_secs(2), 0), (Duration::from_millis(2500), 4)]);
+        editor.stamp(Duration::from_secs(3));
+        assert!(editor.lines[1].words.is_empty());
+        assert_eq!(editor.nudge_target(), Some(1));
+    }
+
+    #[test]
+    fn file_offset_is_folded_into_the_stamps() {
+        let lyrics = parse_lrc("[offset:+300]\n[00:02.00]two\n[00:01.00]one");
+        let editor = LrcEditor::new(Path::new("song.mp3"), &lyrics);
+        assert_eq!(starts(&editor), [Some(700), Some(1700)]);
+        // Finished lyrics are in file time, in order, and show at the same moments as before
+        let done = editor.finish().unwrap();
+        assert_eq!(done.offset_ms, 0);
+        assert_eq!(done.to_lrc(), "[00:00.70]one\n[00:01.70]two\n");
+        for position in [500, 800, 1800] {
+            let position = Duration::from_millis(position);
+            let text = |l: &Lyrics| l.current(position, 0).map(|(i, _)| l.lines[i].text.clone());
+            assert_eq!(text(&done), text(&lyrics));
+        }
+    }
+
+    #[test]
+    fn finish_needs_every_line_with_words() {
+        let mut editor = LrcEditor::new(Path::new("song.mp3"), &parse_lrc("one\n\ntwo"));
+        editor.stamp(Duration::from_secs(1));
+        assert_eq!(editor.finish().unwrap_err(), "1 line(s) still need a time");
+        assert_eq!(editor.preview().lines.len(), 1);
+        editor.stamp(Duration::from_secs(2));
+        // The unstamped blank line is left out
+        assert_eq!(editor.finish().unwrap().to_lrc(), "[00:01.00]one\n[00:02.00]two\n");
+    }
+
+    #[test]
+    fn finished_lyrics_read_back_from_sidecar_and_id3() {
+        let dir = std::env::temp_dir().join(format!("lyrics-{}-save", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // Two silent MPEG frames are enough of an MP3 to carry a tag
+        let frame = [&[0xff, 0xfb, 0x90, 0x64][..], &[0; 413]].concat();
+        let (song, other) = (dir.join("Song.mp3"), dir.join("other.mp3"));
+        for path in [&song, &other] { std::fs::write(path, frame.repeat(2)).unwrap(); }
+        let mut editor = LrcEditor::new(&song, &parse_lrc("[00:01.00]<00:01.00>one <00:01.50>two"));
+        editor.lines.push(LyricLine { text: "three".into(), ..Default::default() });
+        editor.select(1);
+        editor.stamp(Duration::from_secs(2));
+        let done = editor.finish().unwrap();
+        // An existing sidecar is overwritten whatever its letter case
+        std::fs::write(dir.join("song.LRC"), "old").unwrap();
+        assert_eq!(save_sidecar(&song, &done).unwrap(), dir.join("song.LRC"));
+        assert_eq!(read_lyrics(&song), Some(done.clone()));
+        embed_lyrics(&other, &done).unwrap();
+        // SYLT only has syllables, so a line without word times comes back as a single word
+        let embedded = read_lyrics(&other).unwrap();
+        let lines = |l: &Lyrics| l.lines.iter().map(|l| (l.start, l.text.clone())).collect::<Vec<_>>();
+        assert_eq!(lines(&embedded), lines(&done));
+        assert_eq!(embedded.lines[0].words, done.lines[0].words);
+        assert!(embed_lyrics(&dir.join("song.flac"), &done).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
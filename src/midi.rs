@@ -0,0 +1,642 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// MIDI playback: Standard MIDI Files are rendered by a small built-in SF2 synthesizer.
+// Supported: sample playback with loops, key/velocity zones, tuning, pan, attenuation, the volume envelope and
+// exclusive classes; channel volume/expression/pan, sustain pedal, pitch bend and program/bank changes.
+// Modulation envelopes, LFOs, filters and effects are ignored.
+
+const OUTPUT_RATE: u32 = 44_100;
+// Frames rendered per block; events and envelopes are updated at block boundaries
+const BLOCK: usize = 64;
+const MAX_VOICES: usize = 96;
+// Headroom so dense arrangements don't clip
+const MASTER_GAIN: f32 = 0.35;
+// Time appended after the last event so releases can ring out
+const TAIL: Duration = Duration::from_secs(1);
+
+pub(crate) fn is_midi(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("mid") || e.eq_ignore_ascii_case("midi"))
+}
+
+fn u16_le(b: &[u8], at: usize) -> u16 { u16::from_le_bytes([b[at], b[at + 1]]) }
+fn u32_le(b: &[u8], at: usize) -> u32 { u32::from_le_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]]) }
+
+// ===== Standard MIDI File =====
+/// Channel messages on a time line (seconds), with tempo changes already applied.
+pub(crate) struct MidiSong {
+    events: Vec<(f64, [u8; 3])>,
+    length: Duration,
+}
+
+fn read_vlq(data: &[u8], pos: &mut usize) -> Option<u32> {
+    let mut value = 0u32;
+    for _ in 0..4 {
+        let b = *data.get(*pos)?;
+        *pos += 1;
+        value = (value << 7) | (b & 0x7f) as u32;
+        if b & 0x80 == 0 { return Some(value); }
+    }
+    None
+}
+
+pub(crate) fn parse_smf(data: &[u8]) -> Result<MidiSong, String> {
+    let bad = || "Not a valid MIDI file".to_string();
+    if data.len() < 14 || &data[..4] != b"MThd" { return Err(bad()); }
+    let tracks = u16::from_be_bytes([data[10], data[11]]);
+    let division = u16::from_be_bytes([data[12], data[13]]);
+    let header_len = u32::from_be_bytes([data[4], data[5], data[6], data[7]]) as usize;
+
+    // (tick, sequence, event): tempo events use status 0xFF with the tempo in the data bytes
+    enum Ev { Channel([u8; 3]), Tempo(u32) }
+    let mut all: Vec<(u64, usize, Ev)> = Vec::new();
+    let mut pos = 8 + header_len;
+    for _ in 0..tracks {
+        if pos + 8 > data.len() { break; }
+        let len = u32::from_be_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]]) as usize;
+        let is_track = &data[pos..pos + 4] == b"MTrk";
+        let body = &data[(pos + 8).min(data.len())..(pos + 8 + len).min(data.len())];
+        pos += 8 + len;
+        if !is_track { continue; }
+        let (mut p, mut tick, mut running) = (0usize, 0u64, 0u8);
+        while p < body.len() {
+            let Some(delta) = read_vlq(body, &mut p) else { break };
+            tick += delta as u64;
+            let Some(&first) = body.get(p) else { break };
+            let status = if first & 0x80 != 0 { p += 1; first } else { running };
+            match status {
+                0xFF => {
+                    let Some(&kind) = body.get(p) else { break };
+                    p += 1;
+                    let Some(len) = read_vlq(body, &mut p) else { break };
+                    let meta = body.get(p..p + len as usize).unwrap_or_default();
+                    p += len as usize;
+                    if kind == 0x51 && meta.len() == 3 {
+                        all.push((tick, all.len(), Ev::Tempo(u32::from_be_bytes([0, meta[0], meta[1], meta[2]]))));
+                    }
+                    if kind == 0x2F { break; }
+                }
+                0xF0 | 0xF7 => {
+                    let Some(len) = read_vlq(body, &mut p) else { break };
+                    p += len as usize;
+                }
+                0x80..=0xEF => {
+                    running = status;
+                    let n = if matches!(status & 0xF0, 0xC0 | 0xD0) { 1 } else { 2 };
+                    let Some(d) = body.get(p..p + n) else { break };
+                    p += n;
+                    all.push((tick, all.len(), Ev::Channel([status, d[0], *d.get(1).unwrap_or(&0)])));
+                }
+                _ => break,
+            }
+        }
+    }
+    all.sort_by_key(|(tick, seq, _)| (*tick, *seq));
+
+    // Ticks to seconds. SMPTE divisions give a fixed tick length; otherwise it follows the tempo map
+    let smpte = division & 0x8000 != 0;
+    let fixed_tick = if smpte {
+        let fps = -((division >> 8) as i8) as f64;
+        Some(1.0 / (fps * (division & 0xff) as f64).max(1.0))
+    } else { None };
+    let tpq = (division.max(1)) as f64;
+    let mut tempo = 500_000.0f64;
+    let (mut last_tick, mut time) = (0u64, 0f64);
+    let mut events = Vec::new();
+    for (tick, _, ev) in all {
+        let tick_len = fixed_tick.unwrap_or(tempo / 1_000_000.0 / tpq);
+        time += (tick - last_tick) as f64 * tick_len;
+        last_tick = tick;
+        match ev {
+            Ev::Tempo(t) => tempo = t.max(1) as f64,
+            Ev::Channel(msg) => events.push((time, msg)),
+        }
+    }
+    if events.is_empty() { return Err("MIDI file has no notes".to_string()); }
+    Ok(MidiSong { events, length: Duration::from_secs_f64(time) + TAIL })
+}
+
+// ===== SoundFont 2 =====
+// Generator numbers used by the synth
+const GEN_START_OFS: usize = 0;
+const GEN_END_OFS: usize = 1;
+const GEN_LOOP_START_OFS: usize = 2;
+const GEN_LOOP_END_OFS: usize = 3;
+const GEN_START_COARSE: usize = 4;
+const GEN_END_COARSE: usize = 12;
+const GEN_PAN: usize = 17;
+const GEN_DELAY_VOL: usize = 33;
+const GEN_ATTACK_VOL: usize = 34;
+const GEN_HOLD_VOL: usize = 35;
+const GEN_DECAY_VOL: usize = 36;
+const GEN_SUSTAIN_VOL: usize = 37;
+const GEN_RELEASE_VOL: usize = 38;
+const GEN_INSTRUMENT: usize = 41;
+const GEN_KEY_RANGE: usize = 43;
+const GEN_VEL_RANGE: usize = 44;
+const GEN_LOOP_START_COARSE: usize = 45;
+const GEN_ATTENUATION: usize = 48;
+const GEN_LOOP_END_COARSE: usize = 50;
+const GEN_COARSE_TUNE: usize = 51;
+const GEN_FINE_TUNE: usize = 52;
+const GEN_SAMPLE_ID: usize = 53;
+const GEN_SAMPLE_MODES: usize = 54;
+const GEN_SCALE_TUNING: usize = 56;
+const GEN_EXCLUSIVE_CLASS: usize = 57;
+const GEN_ROOT_KEY: usize = 58;
+const GEN_COUNT: usize = 61;
+
+fn gen_default(id: usize) -> i32 {
+    match id {
+        GEN_DELAY_VOL | GEN_ATTACK_VOL | GEN_HOLD_VOL | GEN_DECAY_VOL | GEN_RELEASE_VOL => -12_000,
+        GEN_SCALE_TUNING => 100,
+        GEN_ROOT_KEY => -1,
+        _ => 0,
+    }
+}
+
+// Generators a preset zone may not set; everything else is added to the instrument's value
+fn instrument_only(id: usize) -> bool {
+    matches!(id, 0..=4 | 12 | 45..=47 | 50 | 53 | 54 | 57 | 58)
+}
+
+#[derive(Clone)]
+struct Zone {
+    gens: [Option<i16>; GEN_COUNT],
+    keys: (u8, u8),
+    vels: (u8, u8),
+}
+
+impl Zone {
+    fn matches(&self, key: u8, vel: u8) -> bool { (self.keys.0..=self.keys.1).contains(&key) && (self.vels.0..=self.vels.1).contains(&vel) }
+}
+
+struct Instrument { global: Option<Zone>, zones: Vec<Zone> }
+struct Preset { bank: u16, program: u16, global: Option<Zone>, zones: Vec<Zone> }
+
+#[derive(Clone, Copy)]
+struct SampleHeader { start: u32, end: u32, loop_start: u32, loop_end: u32, rate: u32, pitch: u8, correction: i8 }
+
+pub(crate) struct SoundFont {
+    presets: Vec<Preset>,
+    instruments: Vec<Instrument>,
+    samples: Vec<SampleHeader>,
+    data: Arc<[f32]>,
+}
+
+type Chunk<'a> = ([u8; 4], &'a [u8]);
+
+// RIFF sub-chunks of `body` as (id, data).
+fn riff_chunks(body: &[u8]) -> Vec<Chunk<'_>> {
+    let mut out = Vec::new();
+    let mut pos = 0usize;
+    while pos.checked_add(8).is_some_and(|end| end <= body.len()) {
+        let id: [u8; 4] = body[pos..pos + 4].try_into().unwrap();
+        let len = u32_le(body, pos + 4) as usize;
+        // Lengths come from the file; on 32-bit targets they can overflow the position
+        let end = (pos + 8).saturating_add(len);
+        out.push((id, &body[pos + 8..end.min(body.len())]));
+        pos = end.saturating_add(len & 1);
+    }
+    out
+}
+
+// Split bag/generator tables into zones. `bags` holds (first generator) per bag, with a terminal entry.
+fn build_zones(first_bag: usize, end_bag: usize, bags: &[u16], gens: &[u8], terminal: usize) -> (Option<Zone>, Vec<Zone>) {
+    let mut global = None;
+    let mut zones = Vec::new();
+    for b in first_bag..end_bag {
+        let (Some(&g0), Some(&g1)) = (bags.get(b), bags.get(b + 1)) else { break };
+        let mut zone = Zone { gens: [None; GEN_COUNT], keys: (0, 127), vels: (0, 127) };
+        for g in g0 as usize..g1 as usize {
+            let at = g * 4;
+            if at + 4 > gens.len() { break; }
+            let (oper, amount) = (u16_le(gens, at) as usize, u16_le(gens, at + 2));
+            match oper {
+                GEN_KEY_RANGE => zone.keys = (amount as u8, (amount >> 8) as u8),
+                GEN_VEL_RANGE => zone.vels = (amount as u8, (amount >> 8) as u8),
+                o if o < GEN_COUNT => zone.gens[o] = Some(amount as i16),
+                _ => {}
+            }
+        }
+        // A zone without the terminal generator (instrument / sampleID) is the global zone if it comes first
+        if zone.gens[terminal].is_some() { zones.push(zone); } else if b == first_bag { global = Some(zone); }
+    }
+    (global, zones)
+}
+
+impl SoundFont {
+    pub(crate) fn load(path: &Path) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|e| format!("Failed to read SoundFont {}: {e}", path.display()))?;
+        if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"sfbk" {
+            return Err(format!("{} is not an SF2 SoundFont", path.display()));
+        }
+        let (mut smpl, mut pdta): (&[u8], Vec<Chunk>) = (&[], Vec::new());
+        for (id, data) in riff_chunks(&bytes[12..]) {
+            if &id != b"LIST" || data.len() < 4 { continue; }
+            match &data[..4] {
+                b"sdta" => if let Some((_, s)) = riff_chunks(&data[4..]).into_iter().find(|(id, _)| id == b"smpl") { smpl = s; },
+                b"pdta" => pdta = riff_chunks(&data[4..]),
+                _ => {}
+            }
+        }
+        let chunk = |name: &[u8; 4]| pdta.iter().find(|(id, _)| id == name).map(|(_, d)| *d).unwrap_or_default();
+        let (phdr, pbag, pgen, inst, ibag, igen, shdr) =
+            (chunk(b"phdr"), chunk(b"pbag"), chunk(b"pgen"), chunk(b"inst"), chunk(b"ibag"), chunk(b"igen"), chunk(b"shdr"));
+        if phdr.len() < 76 || inst.len() < 44 || shdr.len() < 92 || smpl.is_empty() {
+            return Err(format!("{} has no presets or samples", path.display()));
+        }
+        let pbags: Vec<u16> = pbag.chunks_exact(4).map(|c| u16_le(c, 0)).collect();
+        let ibags: Vec<u16> = ibag.chunks_exact(4).map(|c| u16_le(c, 0)).collect();
+
+        let inst_recs: Vec<&[u8]> = inst.chunks_exact(22).collect();
+        let instruments = inst_recs.windows(2).map(|w| {
+            let (global, zones) = build_zones(u16_le(w[0], 20) as usize, u16_le(w[1], 20) as usize, &ibags, igen, GEN_SAMPLE_ID);
+            Instrument { global, zones }
+        }).collect();
+        let preset_recs: Vec<&[u8]> = phdr.chunks_exact(38).collect();
+        let presets = preset_recs.windows(2).map(|w| {
+            let (global, zones) = build_zones(u16_le(w[0], 24) as usize, u16_le(w[1], 24) as usize, &pbags, pgen, GEN_INSTRUMENT);
+            Preset { program: u16_le(w[0], 20), bank: u16_le(w[0], 22), global, zones }
+        }).collect();
+        let samples = shdr.chunks_exact(46).map(|c| SampleHeader {
+            start: u32_le(c, 20), end: u32_le(c, 24), loop_start: u32_le(c, 28), loop_end: u32_le(c, 32),
+            rate: u32_le(c, 36).max(1), pitch: c[40], correction: c[41] as i8,
+        }).collect();
+        let data: Arc<[f32]> = smpl.chunks_exact(2).map(|c| i16::from_le_bytes([c[0], c[1]]) as f32 / 32768.0).collect();
+        Ok(Self { presets, instruments, samples, data })
+    }
+
+    fn find_preset(&self, bank: u16, program: u16) -> Option<&Preset> {
+        self.presets.iter().find(|p| p.bank == bank && p.program == program)
+            // Fall back to the GM sound of the same program, then the standard drum kit, then anything
+            .or_else(|| self.presets.iter().find(|p| p.bank == if bank == 128 { 128 } else { 0 } && p.program == if bank == 128 { 0 } else { program }))
+            .or_else(|| self.presets.first())
+    }
+}
+
+/// SoundFonts are large; keep the last one loaded so seeking doesn't reparse it.
+fn cached_soundfont(path: &Path) -> Result<Arc<SoundFont>, String> {
+    static CACHE: Mutex<Option<(PathBuf, Arc<SoundFont>)>> = Mutex::new(None);
+    let mut cache = CACHE.lock().unwrap();
+    if let Some((p, sf)) = cache.as_ref() && p == path { return Ok(sf.clone()); }
+    let sf = Arc::new(SoundFont::load(path)?);
+    *cache = Some((path.to_path_buf(), sf.clone()));
+    Ok(sf)
+}
+
+/// `.sf2` files in the music folder and the usual system locations, for the SoundFont picker.
+pub(crate) fn find_soundfonts(music_dir: Option<&Path>) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = music_dir.map(Path::to_path_buf).into_iter().collect();
+    dirs.extend(["/usr/share/sounds/sf2", "/usr/share/soundfonts", "/usr/local/share/soundfonts"].map(PathBuf::from));
+    let mut found: Vec<PathBuf> = dirs.iter().filter_map(|d| std::fs::read_dir(d).ok())
+        .flat_map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()))
+        .filter(|p| p.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("sf2")))
+        .collect();
+    found.sort();
+    found.dedup();
+    found
+}
+
+// ===== Synth =====
+fn timecents(tc: i32) -> f32 { 2f32.powf(tc.clamp(-12_000, 8_000) as f32 / 1200.0) }
+
+#[derive(Clone, Copy, PartialEq)]
+enum Stage { Delay, Attack, Hold, Decay, Sustain, Release, Done }
+
+struct Voice {
+    channel: u8,
+    key: u8,
+    data: Arc<[f32]>,
+    pos: f64,
+    // Playback increment before pitch bend
+    step: f64,
+    end: f64,
+    loop_start: f64,
+    loop_end: f64,
+    // 1 = loop continuously, 3 = loop until released
+    loop_mode: i32,
+    gain: f32,
+    pan: f32,
+    exclusive: i32,
+    stage: Stage,
+    stage_time: f32,
+    delay: f32, attack: f32, hold: f32, decay: f32, release: f32,
+    // Sustain level and current level in dB for the decay/release slopes
+    sustain_db: f32,
+    level_db: f32,
+    amp: f32,
+    held_by_pedal: bool,
+}
+
+impl Voice {
+    // Envelope amplitude after `dt` seconds
+    fn advance_envelope(&mut self, dt: f32) -> f32 {
+        self.stage_time += dt;
+        loop {
+            let (stage, length) = match self.stage {
+                Stage::Delay => (Stage::Attack, self.delay),
+                Stage::Attack => (Stage::Hold, self.attack),
+                Stage::Hold => (Stage::Decay, self.hold),
+                _ => break,
+            };
+            if self.stage_time < length { break; }
+            self.stage_time -= length;
+            self.stage = stage;
+        }
+        self.amp = match self.stage {
+            Stage::Delay => 0.0,
+            Stage::Attack => self.stage_time / self.attack.max(1e-4),
+            Stage::Hold => 1.0,
+            Stage::Decay | Stage::Sustain => {
+                // 100 dB per decay time, down to the sustain level
+                self.level_db = (-100.0 * self.stage_time / self.decay.max(1e-4)).max(self.sustain_db);
+                if self.level_db <= self.sustain_db { self.stage = Stage::Sustain; }
+                10f32.powf(self.level_db / 20.0)
+            }
+            Stage::Release => {
+                self.level_db -= 100.0 * dt / self.release.max(1e-4);
+                if self.level_db < -100.0 { self.stage = Stage::Done; }
+                10f32.powf(self.level_db / 20.0)
+            }
+            Stage::Done => 0.0,
+        };
+        self.amp
+    }
+
+    fn note_off(&mut self) {
+        if self.stage == Stage::Done || self.stage == Stage::Release { return; }
+        self.level_db = 20.0 * self.amp.max(1e-5).log10();
+        self.stage = Stage::Release;
+        self.stage_time = 0.0;
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Channel { program: u16, bank: u16, volume: f32, expression: f32, pan: f32, sustain: bool, bend: f32, bend_range: f32, rpn: (u8, u8) }
+
+impl Default for Channel {
+    fn default() -> Self { Self { program: 0, bank: 0, volume: 100.0 / 127.0, expression: 1.0, pan: 0.0, sustain: false, bend: 0.0, bend_range: 2.0, rpn: (127, 127) } }
+}
+
+struct Synth {
+    font: Arc<SoundFont>,
+    channels: [Channel; 16],
+    voices: Vec<Voice>,
+}
+
+impl Synth {
+    fn new(font: Arc<SoundFont>) -> Self {
+        let mut channels = [Channel::default(); 16];
+        channels[9].bank = 128;
+        Self { font, channels, voices: Vec::new() }
+    }
+
+    /// Apply a channel message. With `silent` notes are not started (used when fast-forwarding to a seek point).
+    fn handle(&mut self, msg: [u8; 3], silent: bool) {
+        let ch = (msg[0] & 0x0f) as usize;
+        match msg[0] & 0xf0 {
+            0x90 if msg[2] > 0 && !silent => self.note_on(ch as u8, msg[1], msg[2]),
+            0x80 | 0x90 => {
+                let sustain = self.channels[ch].sustain;
+                for v in self.voices.iter_mut().filter(|v| v.channel as usize == ch && v.key == msg[1]) {
+                    if sustain { v.held_by_pedal = true; } else { v.note_off(); }
+                }
+            }
+            0xB0 => {
+                let c = &mut self.channels[ch];
+                let value = msg[2];
+                match msg[1] {
+                    0 if ch != 9 => c.bank = value as u16,
+                    7 => c.volume = value as f32 / 127.0,
+                    10 => c.pan = (value as f32 - 64.0) / 64.0,
+                    11 => c.expression = value as f32 / 127.0,
+                    64 => {
+                        c.sustain = value >= 64;
+                        if !c.sustain {
+                            for v in self.voices.iter_mut().filter(|v| v.channel as usize == ch && v.held_by_pedal) { v.held_by_pedal = false; v.note_off(); }
+                        }
+                    }
+                    100 => c.rpn.0 = value,
+                    101 => c.rpn.1 = value,
+                    6 if c.rpn == (0, 0) => c.bend_range = value as f32,
+                    120 | 123 => for v in self.voices.iter_mut().filter(|v| v.channel as usize == ch) {
+                        if msg[1] == 120 { v.stage = Stage::Done; } else { v.note_off(); }
+                    },
+                    121 => { let bank = c.bank; *c = Channel { bank, ..Channel::default() }; }
+                    _ => {}
+                }
+            }
+            0xC0 => self.channels[ch].program = msg[1] as u16,
+            0xE0 => self.channels[ch].bend = ((msg[2] as i32) << 7 | msg[1] as i32) as f32 / 8192.0 - 1.0,
+            _ => {}
+        }
+    }
+
+    fn note_on(&mut self, channel: u8, key: u8, vel: u8) {
+        let font = self.font.clone();
+        let c = self.channels[channel as usize];
+        let Some(preset) = font.find_preset(c.bank, c.program) else { return };
+        for pz in preset.zones.iter().filter(|z| z.matches(key, vel)) {
+            let Some(inst) = pz.gens[GEN_INSTRUMENT].and_then(|i| font.instruments.get(i as u16 as usize)) else { continue };
+            for iz in inst.zones.iter().filter(|z| z.matches(key, vel)) {
+                let param = |id: usize| {
+                    let base = iz.gens[id].or(inst.global.as_ref().and_then(|g| g.gens[id])).map_or(gen_default(id), i32::from);
+                    if instrument_only(id) { return base; }
+                    base + pz.gens[id].or(preset.global.as_ref().and_then(|g| g.gens[id])).map_or(0, i32::from)
+                };
+                let Some(sample) = font.samples.get(param(GEN_SAMPLE_ID) as u16 as usize).copied() else { continue };
+                let exclusive = param(GEN_EXCLUSIVE_CLASS);
+                if exclusive != 0 {
+                    for v in self.voices.iter_mut().filter(|v| v.channel == channel && v.exclusive == exclusive) { v.stage = Stage::Done; }
+                }
+                let ofs = |fine: usize, coarse: usize| param(fine) as i64 + param(coarse) as i64 * 32768;
+                let len = font.data.len() as i64;
+                if len < 2 { continue; }
+                let start = (sample.start as i64 + ofs(GEN_START_OFS, GEN_START_COARSE)).clamp(0, len - 1);
+                let end = (sample.end as i64 + ofs(GEN_END_OFS, GEN_END_COARSE)).clamp(start, len);
+                let loop_start = (sample.loop_start as i64 + ofs(GEN_LOOP_START_OFS, GEN_LOOP_START_COARSE)).clamp(start, end);
+                let loop_end = (sample.loop_end as i64 + ofs(GEN_LOOP_END_OFS, GEN_LOOP_END_COARSE)).clamp(loop_start, end);
+                let root = match param(GEN_ROOT_KEY) { r if (0..=127).contains(&r) => r, _ => sample.pitch as i32 };
+                let semitones = (key as i32 - root) as f32 * param(GEN_SCALE_TUNING) as f32 / 100.0
+                    + param(GEN_COARSE_TUNE) as f32 + (param(GEN_FINE_TUNE) + sample.correction as i32) as f32 / 100.0;
+                // The EMU 0.4 factor on initial attenuation matches how most SoundFonts are voiced
+                let attenuation = 10f32.powf(-(param(GEN_ATTENUATION).max(0) as f32 * 0.4) / 200.0);
+                let velocity = (vel as f32 / 127.0).powi(2);
+                let voice = Voice {
+                    channel, key,
+                    data: font.data.clone(),
+                    pos: start as f64,
+                    step: 2f64.powf(semitones as f64 / 12.0) * sample.rate as f64 / OUTPUT_RATE as f64,
+                    end: end as f64,
+                    loop_start: loop_start as f64,
+                    loop_end: loop_end as f64,
+                    loop_mode: if loop_end > loop_start + 1 { param(GEN_SAMPLE_MODES) & 3 } else { 0 },
+                    gain: attenuation * velocity,
+                    pan: param(GEN_PAN).clamp(-500, 500) as f32 / 500.0,
+                    exclusive,
+                    stage: Stage::Delay,
+                    stage_time: 0.0,
+                    delay: timecents(param(GEN_DELAY_VOL)),
+                    attack: timecents(param(GEN_ATTACK_VOL)),
+                    hold: timecents(param(GEN_HOLD_VOL)),
+                    decay: timecents(param(GEN_DECAY_VOL)),
+                    release: timecents(param(GEN_RELEASE_VOL)).max(0.01),
+                    sustain_db: -(param(GEN_SUSTAIN_VOL).clamp(0, 1440) as f32) / 10.0,
+                    level_db: 0.0,
+                    amp: 0.0,
+                    held_by_pedal: false,
+                };
+                if self.voices.len() >= MAX_VOICES {
+                    // Steal the quietest voice, preferring ones already released
+                    if let Some(i) = (0..self.voices.len()).min_by(|&a, &b| {
+                        let score = |v: &Voice| v.amp * if v.stage == Stage::Release { 0.1 } else { 1.0 };
+                        score(&self.voices[a]).total_cmp(&score(&self.voices[b]))
+                    }) { self.voices.swap_remove(i); }
+                }
+                self.voices.push(voice);
+            }
+        }
+    }
+
+    /// Mix `frames` stereo frames into `out` (interleaved, overwritten).
+    fn render(&mut self, out: &mut [f32], frames: usize) {
+        out[..frames * 2].fill(0.0);
+        let dt = frames as f32 / OUTPUT_RATE as f32;
+        for v in self.voices.iter_mut() {
+            let c = &self.channels[v.channel as usize];
+            let amp0 = v.amp;
+            let amp1 = v.advance_envelope(dt);
+            let level = v.gain * c.volume * c.volume * c.expression * c.expression * MASTER_GAIN;
+            let pan = (v.pan + c.pan).clamp(-1.0, 1.0);
+            let angle = (pan + 1.0) * std::f32::consts::FRAC_PI_4;
+            let (gl, gr) = (angle.cos() * level, angle.sin() * level);
+            let step = v.step * 2f64.powf((c.bend * c.bend_range) as f64 / 12.0);
+            for f in 0..frames {
+                let looping = v.loop_mode == 1 || (v.loop_mode == 3 && v.stage != Stage::Release);
+                // A step can be longer than the whole loop (high notes, short loops, pitch bend)
+                if looping && v.pos >= v.loop_end { v.pos = v.loop_start + (v.pos - v.loop_start) % (v.loop_end - v.loop_start); }
+                if !looping && v.pos >= v.end - 1.0 { v.stage = Stage::Done; break; }
+                let i = v.pos as usize;
+                let frac = (v.pos - i as f64) as f32;
+                let next = if looping && i + 1 >= v.loop_end as usize { v.loop_start as usize } else { i + 1 };
+                // Never read past the sample into its neighbour or off the end of the data
+                if i >= v.end as usize || next >= v.end as usize { v.stage = Stage::Done; break; }
+                let (Some(&a), Some(&b)) = (v.data.get(i), v.data.get(next)) else { v.stage = Stage::Done; break };
+                let x = a + (b - a) * frac;
+                let env = amp0 + (amp1 - amp0) * f as f32 / frames as f32;
+                out[f * 2] += x * env * gl;
+                out[f * 2 + 1] += x * env * gr;
+                v.pos += step;
+            }
+        }
+        self.voices.retain(|v| v.stage != Stage::Done);
+    }
+}
+
+/// A MIDI file rendered with a SoundFont, as a 44.1 kHz stereo source.
+pub(crate) struct MidiSource {
+    synth: Synth,
+    song: Arc<MidiSong>,
+    next_event: usize,
+    frame: u64,
+    total_frames: u64,
+    buf: Vec<f32>,
+    buf_pos: usize,
+}
+
+impl MidiSource {
+    /// Load `path` and position it at `start`. Controller and program changes before `start` are applied so the
+    /// instruments are right; notes that started before it are not replayed.
+    pub(crate) fn open(path: &Path, soundfont: &Path, start: Duration) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|e| format!("Failed to open file: {e}"))?;
+        let song = Arc::new(parse_smf(&data)?);
+        let mut synth = Synth::new(cached_soundfont(soundfont)?);
+        let start_secs = start.as_secs_f64();
+        let mut next_event = 0;
+        while let Some(&(t, msg)) = song.events.get(next_event) && t < start_secs {
+            synth.handle(msg, true);
+            next_event += 1;
+        }
+        let to_frames = |d: Duration| (d.as_secs_f64() * OUTPUT_RATE as f64) as u64;
+        Ok(Self { synth, next_event, frame: to_frames(start), total_frames: to_frames(song.length), song, buf: vec![0.0; BLOCK * 2], buf_pos: BLOCK * 2 })
+    }
+
+    fn render_block(&mut self) {
+        let block_end = (self.frame + BLOCK as u64) as f64 / OUTPUT_RATE as f64;
+        while let Some(&(t, msg)) = self.song.events.get(self.next_event) && t < block_end {
+            self.synth.handle(msg, false);
+            self.next_event += 1;
+        }
+        self.synth.render(&mut self.buf, BLOCK);
+        self.frame += BLOCK as u64;
+        self.buf_pos = 0;
+    }
+}
+
+impl Iterator for MidiSource {
+    type Item = f32;
+    fn next(&mut self) -> Option<f32> {
+        if self.buf_pos >= self.buf.len() {
+            if self.frame >= self.total_frames { return None; }
+            self.render_block();
+        }
+        let x = self.buf[self.buf_pos];
+        self.buf_pos += 1;
+        Some(x.clamp(-1.0, 1.0))
+    }
+}
+
+impl rodio::Source for MidiSource {
+    fn channels(&self) -> u16 { 2 }
+    fn sample_rate(&self) -> u32 { OUTPUT_RATE }
+    fn current_span_len(&self) -> Option<usize> { None }
+    fn total_duration(&self) -> Option<Duration> { Some(self.song.length) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One looped sample with a 3-sample loop, followed by a neighbour that must never be heard
+    fn tiny_font(coarse_tune: i16) -> Arc<SoundFont> {
+        let mut preset_zone = Zone { gens: [None; GEN_COUNT], keys: (0, 127), vels: (0, 127) };
+        let mut inst_zone = preset_zone.clone();
+        preset_zone.gens[GEN_INSTRUMENT] = Some(0);
+        inst_zone.gens[GEN_SAMPLE_ID] = Some(0);
+        inst_zone.gens[GEN_SAMPLE_MODES] = Some(1);
+        inst_zone.gens[GEN_COARSE_TUNE] = Some(coarse_tune);
+        let data: Arc<[f32]> = (0..64).map(|i| if i < 32 { 0.5 } else { 100.0 }).collect();
+        Arc::new(SoundFont {
+            presets: vec![Preset { bank: 0, program: 0, global: None, zones: vec![preset_zone] }],
+            instruments: vec![Instrument { global: None, zones: vec![inst_zone] }],
+            samples: vec![SampleHeader { start: 0, end: 32, loop_start: 10, loop_end: 13, rate: 44_100, pitch: 60, correction: 0 }],
+            data,
+        })
+    }
+
+    #[test]
+    fn steps_longer_than_the_loop_stay_inside_the_sample() {
+        let mut synth = Synth::new(tiny_font(120));
+        synth.handle([0x90, 127, 127], false);
+        synth.handle([0xE0, 0x7f, 0x7f], false);
+        let mut out = vec![0.0; BLOCK * 2];
+        for _ in 0..100 {
+            synth.render(&mut out, BLOCK);
+            // Only the 0.5 of the sample itself, never the neighbour's 100
+            assert!(out.iter().all(|x| x.abs() < 1.0), "{out:?}");
+        }
+        // Still looping
+        assert_eq!(synth.voices.len(), 1);
+    }
+
+    #[test]
+    fn riff_lengths_past_the_end_are_cut_short() {
+        let mut body = b"LIST".to_vec();
+        body.extend(u32::MAX.to_le_bytes());
+        body.extend(b"data");
+        assert_eq!(riff_chunks(&body), vec![(*b"LIST", &b"data"[..])]);
+        assert!(riff_chunks(&body[..7]).is_empty());
+    }
+}
@@ -0,0 +1,460 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rodio::Source;
+
+use crate::export::{WavFormat, WavWriter};
+
+// ===== Output backends =====
+// `AudioEngine` talks to the outside world only through `OutputBackend`. The cpal/rodio backend drives a real
+// device; the null and WAV-capture backends pull samples themselves so the engine runs without a sound card.
+
+pub(crate) type BoxedSource = Box<dyn Source<Item = f32> + Send>;
+
+pub(crate) trait OutputBackend: Send {
+    /// Open the output if it isn't open yet, trying the `preferred` device first.
+    fn ensure_open(&mut self, preferred: Option<&str>) -> Result<(), String>;
+    fn is_open(&self) -> bool;
+    /// Drop the stream (and any playing source).
+    fn close(&mut self);
+    /// Name of the device the output is currently open on.
+    fn device_name(&self) -> Option<String>;
+    fn list_devices(&self) -> Vec<String>;
+    /// Replace whatever is playing with `source`, optionally starting paused.
+    fn play(&mut self, source: BoxedSource, paused: bool) -> Result<(), String>;
+    fn stop(&mut self);
+    fn pause(&mut self);
+    fn resume(&mut self);
+    fn is_paused(&self) -> bool;
+    /// A source was handed to `play` and hasn't been stopped since.
+    fn has_source(&self) -> bool;
+    /// The current source has played to its end.
+    fn is_finished(&self) -> bool;
+    /// Playback time of the current source, as consumed by the output.
+    fn elapsed(&self) -> Duration;
+    /// A fatal stream error reported since the last call, if any.
+    fn take_error(&mut self) -> Option<String>;
+    /// Rate the output consumes samples at; `None` accepts any rate as-is.
+    fn sample_rate(&self) -> Option<u32>;
+    /// Reopen the output at `rate` if the device supports it. Returns whether the output now runs at `rate`.
+    fn request_sample_rate(&mut self, _rate: u32) -> bool { false }
+}
+
+// ----- cpal / rodio -----
+#[derive(Default)]
+pub(crate) struct RodioOutput {
+    // Lazily opened to avoid failing UI startup on platforms where audio output isn't immediately available (e.g., Android).
+    stream: Option<rodio::OutputStream>,
+    sink: Option<rodio::Sink>,
+    device: Option<String>,
+    // Fatal stream errors reported from the cpal callback thread
+    stream_error: Arc<Mutex<Option<String>>>,
+}
+
+impl RodioOutput {
+    fn find_device(name: &str) -> Option<rodio::cpal::Device> {
+        use rodio::cpal::traits::{DeviceTrait, HostTrait};
+        rodio::cpal::default_host().output_devices().ok()?.find(|d| d.name().is_ok_and(|n| n == name))
+    }
+
+    /// Open a stream on `device`, routing stream errors into `stream_error` so device loss can be detected.
+    /// With `config` the stream is opened exactly as given, otherwise the device default (or any working fallback) is used.
+    fn open_device(&self, device: rodio::cpal::Device, config: Option<&rodio::SupportedStreamConfig>) -> Result<rodio::OutputStream, String> {
+        let errors = self.stream_error.clone();
+        let mut recent: Vec<Instant> = Vec::new();
+        let on_error = move |err: rodio::cpal::StreamError| {
+            log::error!("Audio stream error: {err}");
+            // A vanished device is fatal right away; backend errors (xruns, server hiccups) only when they keep coming
+            let fatal = match err {
+                rodio::cpal::StreamError::DeviceNotAvailable => true,
+                rodio::cpal::StreamError::BackendSpecific { .. } => {
+                    recent.retain(|t| t.elapsed() < Duration::from_secs(2));
+                    recent.push(Instant::now());
+                    recent.len() >= 3
+                }
+            };
+            if fatal && let Ok(mut slot) = errors.lock() { slot.get_or_insert_with(|| err.to_string()); }
+        };
+        let builder = rodio::OutputStreamBuilder::from_device(device)
+            .map_err(|e| format!("Audio output error: {e}"))?
+            .with_error_callback(on_error);
+        let mut stream = match config {
+            Some(config) => builder.with_supported_config(config).open_stream(),
+            None => builder.open_stream_or_fallback(),
+        }.map_err(|e| format!("Audio output error: {e}"))?;
+        stream.log_on_drop(false);
+        Ok(stream)
+    }
+}
+
+impl OutputBackend for RodioOutput {
+    fn ensure_open(&mut self, preferred: Option<&str>) -> Result<(), String> {
+        use rodio::cpal::traits::{DeviceTrait, HostTrait};
+        if self.stream.is_some() { return Ok(()); }
+        // Prefer the remembered device, then the system default, then anything else that opens.
+        let host = rodio::cpal::default_host();
+        let mut candidates: Vec<rodio::cpal::Device> = Vec::new();
+        if let Some(dev) = preferred.and_then(Self::find_device) { candidates.push(dev); }
+        if let Some(dev) = host.default_output_device() { candidates.push(dev); }
+        if let Ok(devs) = host.output_devices() { candidates.extend(devs); }
+        if let Ok(mut slot) = self.stream_error.lock() { *slot = None; }
+        let mut last_err = "Audio output error: no output device available".to_string();
+        for dev in candidates {
+            let name = dev.name().unwrap_or_else(|_| "default".to_string());
+            match self.open_device(dev, None) {
+                Ok(stream) => {
+                    self.device = Some(name);
+                    self.stream = Some(stream);
+                    return Ok(());
+                }
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+
+    fn is_open(&self) -> bool { self.stream.is_some() }
+
+    fn close(&mut self) {
+        self.stop();
+        self.stream = None;
+        self.device = None;
+    }
+
+    fn device_name(&self) -> Option<String> { self.device.clone() }
+
+    fn list_devices(&self) -> Vec<String> {
+        use rodio::cpal::traits::{DeviceTrait, HostTrait};
+        rodio::cpal::default_host().output_devices()
+            .map(|devs| devs.filter_map(|d| d.name().ok()).collect())
+            .unwrap_or_default()
+    }
+
+    fn play(&mut self, source: BoxedSource, paused: bool) -> Result<(), String> {
+        self.stop();
+        let stream = self.stream.as_ref().ok_or("Audio stream not initialized")?;
+        let sink = rodio::Sink::connect_new(stream.mixer());
+        if paused { sink.pause(); }
+        sink.append(source);
+        self.sink = Some(sink);
+        Ok(())
+    }
+
+    fn stop(&mut self) { if let Some(sink) = self.sink.take() { sink.stop(); } }
+    fn pause(&mut self) { if let Some(s) = &self.sink { s.pause(); } }
+    fn resume(&mut self) { if let Some(s) = &self.sink { s.play(); } }
+    fn is_paused(&self) -> bool { self.sink.as_ref().is_some_and(|s| s.is_paused()) }
+    fn has_source(&self) -> bool { self.sink.is_some() }
+    fn is_finished(&self) -> bool { self.sink.as_ref().is_some_and(|s| s.empty()) }
+    fn elapsed(&self) -> Duration { self.sink.as_ref().map(|s| s.get_pos()).unwrap_or(Duration::ZERO) }
+    fn take_error(&mut self) -> Option<String> { self.stream_error.lock().ok()?.take() }
+    fn sample_rate(&self) -> Option<u32> { self.stream.as_ref().map(|s| s.config().sample_rate()) }
+
+    fn request_sample_rate(&mut self, rate: u32) -> bool {
+        use rodio::cpal::traits::{DeviceTrait, HostTrait};
+        if self.sample_rate() == Some(rate) { return true; }
+        let device = match self.device.as_deref() {
+            Some(name) => Self::find_device(name),
+            None => rodio::cpal::default_host().default_output_device(),
+        };
+        let Some(device) = device else { return false };
+        let Ok(configs) = device.supported_output_configs() else { return false };
+        let Some(config) = configs.filter(|c| c.min_sample_rate().0 <= rate && rate <= c.max_sample_rate().0)
+            .max_by_key(|c| c.channels().min(2))
+            .map(|c| c.with_sample_rate(rodio::cpal::SampleRate(rate))) else { return false };
+        // The old stream has to go first; some backends refuse a second stream on the same device
+        self.stop();
+        self.stream = None;
+        match self.open_device(device.clone(), Some(&config)) {
+            Ok(stream) => { self.stream = Some(stream); true }
+            Err(e) => {
+                log::error!("Could not reopen output at {rate} Hz: {e}");
+                // Stay on the same device at its default rate; only if that fails too is the device given up
+                match self.open_device(device, None) {
+                    Ok(stream) => self.stream = Some(stream),
+                    Err(e) => { log::error!("Could not reopen output: {e}"); self.device = None; }
+                }
+                false
+            }
+        }
+    }
+}
+
+// ----- Null / WAV capture -----
+/// How a device-less backend consumes samples.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Pace {
+    /// Consume at the source's sample rate on a background thread, like a real device.
+    RealTime,
+    /// Consume as fast as possible on a background thread.
+    MaxSpeed,
+    /// Only consume when `ThreadOutput::advance` is called; fully deterministic, for tests.
+    #[cfg(test)]
+    Manual,
+}
+
+/// Destination for samples pulled by a device-less backend.
+pub(crate) trait SampleConsumer: Send {
+    fn consume(&mut self, samples: &[f32], channels: u16, sample_rate: u32);
+    fn finish(&mut self) {}
+}
+
+/// Throws samples away.
+pub(crate) struct Discard;
+impl SampleConsumer for Discard { fn consume(&mut self, _: &[f32], _: u16, _: u32) {} }
+
+/// Writes everything that would have been played to a WAV file. The format is taken from the first samples.
+pub(crate) struct WavCapture {
+    path: PathBuf,
+    format: WavFormat,
+    writer: Option<WavWriter<std::io::BufWriter<std::fs::File>>>,
+}
+
+impl WavCapture {
+    pub(crate) fn new(path: PathBuf, format: WavFormat) -> Self { Self { path, format, writer: None } }
+}
+
+impl SampleConsumer for WavCapture {
+    fn consume(&mut self, samples: &[f32], channels: u16, sample_rate: u32) {
+        if self.writer.is_none() {
+            match std::fs::File::create(&self.path).and_then(|f| WavWriter::new(std::io::BufWriter::new(f), self.format, channels, sample_rate)) {
+                Ok(w) => self.writer = Some(w),
+                Err(e) => { log::error!("WAV capture {}: {e}", self.path.display()); return; }
+            }
+        }
+        if let Some(w) = self.writer.as_mut() {
+            for &x in samples { if w.write_sample(x).is_err() { break; } }
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(w) = self.writer.take() && let Err(e) = w.finish() { log::error!("WAV capture {}: {e}", self.path.display()); }
+    }
+}
+
+impl Drop for WavCapture { fn drop(&mut self) { self.finish(); } }
+
+struct Pump {
+    sink: Option<rodio::Sink>,
+    queue: Option<rodio::queue::SourcesQueueOutput>,
+    consumer: Box<dyn SampleConsumer>,
+    buf: Vec<f32>,
+}
+
+impl Pump {
+    fn paused(&self) -> bool { self.sink.as_ref().is_some_and(|s| s.is_paused()) }
+
+    /// Pull up to `budget` worth of audio from the queue into the consumer. Returns the audio time pulled.
+    fn run(&mut self, budget: Duration) -> Duration {
+        // Once the track has played out there is nothing worth pulling (the queue would only produce filler silence)
+        if self.sink.as_ref().is_none_or(|s| s.empty()) { return Duration::ZERO; }
+        let Some(queue) = self.queue.as_mut() else { return Duration::ZERO };
+        // The queue only switches to a newly appended source (and its format) once a sample is pulled,
+        // so the format is read after every sample and the buffer flushed whenever it changes.
+        let mut format = (queue.channels().max(1), queue.sample_rate());
+        let (mut pulled, mut samples) = (0f64, 0usize);
+        let budget = budget.as_secs_f64();
+        self.buf.clear();
+        while pulled < budget {
+            let Some(x) = queue.next() else { self.queue = None; break };
+            // The track ended with this pull; what follows is filler, often at another rate
+            if self.sink.as_ref().is_none_or(|s| s.empty()) { break; }
+            let now = (queue.channels().max(1), queue.sample_rate());
+            if now != format {
+                if !self.buf.is_empty() { self.consumer.consume(&self.buf, format.0, format.1); self.buf.clear(); }
+                format = now;
+                samples = 0;
+            }
+            self.buf.push(x);
+            samples += 1;
+            if samples % format.0 as usize == 0 { pulled += 1.0 / format.1 as f64; }
+        }
+        if !self.buf.is_empty() { self.consumer.consume(&self.buf, format.0, format.1); }
+        Duration::from_secs_f64(pulled)
+    }
+}
+
+/// Output without a device: samples are pulled through a `rodio::Sink` and handed to a `SampleConsumer`.
+pub(crate) struct ThreadOutput {
+    pace: Pace,
+    pump: Arc<Mutex<Pump>>,
+    open: bool,
+    name: String,
+    worker: Option<std::thread::JoinHandle<()>>,
+    running: Arc<std::sync::atomic::AtomicBool>,
+}
+
+impl ThreadOutput {
+    pub(crate) fn new(name: &str, consumer: Box<dyn SampleConsumer>, pace: Pace) -> Self {
+        Self {
+            pace,
+            pump: Arc::new(Mutex::new(Pump { sink: None, queue: None, consumer, buf: Vec::new() })),
+            open: false,
+            name: name.to_string(),
+            worker: None,
+            running: Arc::new(std::sync::atomic::AtomicBool::new(false)),
+        }
+    }
+
+    /// A null sink that discards samples.
+    pub(crate) fn null(pace: Pace) -> Self { Self::new("null", Box::new(Discard), pace) }
+
+    /// A sink that records the output into a WAV file.
+    pub(crate) fn wav(path: PathBuf, format: WavFormat, pace: Pace) -> Self {
+        let name = format!("wav:{}", path.display());
+        Self::new(&name, Box::new(WavCapture::new(path, format)), pace)
+    }
+
+    /// Consume `duration` worth of audio right now (for `Pace::Manual`). Returns the audio time consumed.
+    #[cfg(test)]
+    pub(crate) fn advance(&self, duration: Duration) -> Duration { self.pump.lock().unwrap().run(duration) }
+
+    fn spawn_worker(&mut self) {
+        use std::sync::atomic::Ordering;
+        #[cfg(test)]
+        if self.pace == Pace::Manual { return; }
+        if self.worker.is_some() { return; }
+        self.running.store(true, Ordering::SeqCst);
+        let (pump, running, pace) = (self.pump.clone(), self.running.clone(), self.pace);
+        let chunk = Duration::from_millis(10);
+        self.worker = Some(std::thread::spawn(move || {
+            let mut next_tick = Instant::now();
+            while running.load(Ordering::SeqCst) {
+                let pulled = {
+                    let mut pump = pump.lock().unwrap();
+                    // At max speed there is no clock to keep, so don't churn out silence while paused or done
+                    if pace == Pace::MaxSpeed && pump.paused() { Duration::ZERO } else { pump.run(chunk) }
+                };
+                if pace == Pace::RealTime || pulled.is_zero() {
+                    // Keep wall-clock pace (and idle cheaply when there is nothing to pull)
+                    next_tick += chunk;
+                    let now = Instant::now();
+                    if next_tick > now { std::thread::sleep(next_tick - now); } else { next_tick = now; }
+                }
+            }
+        }));
+    }
+}
+
+impl OutputBackend for ThreadOutput {
+    fn ensure_open(&mut self, _preferred: Option<&str>) -> Result<(), String> {
+        self.open = true;
+        self.spawn_worker();
+        Ok(())
+    }
+    fn is_open(&self) -> bool { self.open }
+    fn close(&mut self) {
+        self.stop();
+        self.open = false;
+        self.running.store(false, std::sync::atomic::Ordering::SeqCst);
+        if let Some(worker) = self.worker.take() { let _ = worker.join(); }
+        if let Ok(mut pump) = self.pump.lock() { pump.consumer.finish(); }
+    }
+    fn device_name(&self) -> Option<String> { self.open.then(|| self.name.clone()) }
+    fn list_devices(&self) -> Vec<String> { vec![self.name.clone()] }
+
+    fn play(&mut self, source: BoxedSource, paused: bool) -> Result<(), String> {
+        self.stop();
+        let (sink, queue) = rodio::Sink::new();
+        if paused { sink.pause(); }
+        sink.append(source);
+        let mut pump = self.pump.lock().unwrap();
+        pump.queue = Some(queue);
+        pump.sink = Some(sink);
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        if let Ok(mut pump) = self.pump.lock() {
+            if let Some(sink) = pump.sink.take() { sink.stop(); }
+            pump.queue = None;
+        }
+    }
+    fn pause(&mut self) { if let Some(s) = &self.pump.lock().unwrap().sink { s.pause(); } }
+    fn resume(&mut self) { if let Some(s) = &self.pump.lock().unwrap().sink { s.play(); } }
+    fn is_paused(&self) -> bool { self.pump.lock().unwrap().sink.as_ref().is_some_and(|s| s.is_paused()) }
+    fn has_source(&self) -> bool { self.pump.lock().unwrap().sink.is_some() }
+    fn is_finished(&self) -> bool { self.pump.lock().unwrap().sink.as_ref().is_some_and(|s| s.empty()) }
+    fn elapsed(&self) -> Duration { self.pump.lock().unwrap().sink.as_ref().map(|s| s.get_pos()).unwrap_or(Duration::ZERO) }
+    fn take_error(&mut self) -> Option<String> { None }
+    fn sample_rate(&self) -> Option<u32> { None }
+}
+
+impl Drop for ThreadOutput { fn drop(&mut self) { self.close(); } }
+
+/// Pick the output backend from `AUDIO_PLAYER_OUTPUT`: `null`, `null-fast`, `wav:<path>`; anything else uses the device.
+pub(crate) fn backend_from_env() -> Box<dyn OutputBackend> {
+    match std::env::var("AUDIO_PLAYER_OUTPUT") {
+        Ok(v) if v == "null" => Box::new(ThreadOutput::null(Pace::RealTime)),
+        Ok(v) if v == "null-fast" => Box::new(ThreadOutput::null(Pace::MaxSpeed)),
+        Ok(v) if v.starts_with("wav:") => Box::new(ThreadOutput::wav(PathBuf::from(&v[4..]), WavFormat::Float32, Pace::RealTime)),
+        _ => Box::new(RodioOutput::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Half a second of a ramp at 8 kHz mono
+    fn ramp() -> BoxedSource { Box::new(rodio::buffer::SamplesBuffer::new(1, 8000, (0..4000).map(|i| (i % 100) as f32 / 100.0).collect::<Vec<f32>>())) }
+
+    fn assert_near(actual: Duration, expected_ms: u64) {
+        // The sink updates its position every 5 ms of audio
+        let diff = actual.as_secs_f64() * 1000.0 - expected_ms as f64;
+        assert!(diff.abs() <= 10.0, "expected about {expected_ms} ms, got {actual:?}");
+    }
+
+    #[test]
+    fn manual_pace_consumes_only_when_advanced() {
+        let mut out = ThreadOutput::null(Pace::Manual);
+        out.ensure_open(None).unwrap();
+        out.play(ramp(), false).unwrap();
+        assert_near(out.elapsed(), 0);
+        assert_near(out.advance(Duration::from_millis(200)), 200);
+        assert_near(out.elapsed(), 200);
+        out.pause();
+        out.advance(Duration::from_millis(200));
+        assert!(out.is_paused());
+        assert_near(out.elapsed(), 200);
+        out.resume();
+        out.advance(Duration::from_millis(100));
+        assert_near(out.elapsed(), 300);
+        assert!(!out.is_finished());
+        out.advance(Duration::from_millis(500));
+        assert!(out.is_finished());
+        assert_eq!(out.advance(Duration::from_millis(100)), Duration::ZERO);
+    }
+
+    #[test]
+    fn paused_start_waits_for_resume() {
+        let mut out = ThreadOutput::null(Pace::Manual);
+        out.ensure_open(None).unwrap();
+        out.play(ramp(), true).unwrap();
+        out.advance(Duration::from_millis(100));
+        assert!(out.is_paused() && out.has_source());
+        assert_near(out.elapsed(), 0);
+        out.stop();
+        assert!(!out.has_source());
+    }
+
+    #[test]
+    fn wav_capture_writes_what_was_played() {
+        let path = std::env::temp_dir().join(format!("output-capture-{}.wav", std::process::id()));
+        let mut out = ThreadOutput::wav(path.clone(), WavFormat::Pcm16, Pace::Manual);
+        out.ensure_open(None).unwrap();
+        assert_eq!(out.device_name(), Some(format!("wav:{}", path.display())));
+        out.play(ramp(), false).unwrap();
+        out.advance(Duration::from_secs(1));
+        out.close();
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&data[..4], b"RIFF");
+        assert_eq!(u16::from_le_bytes([data[22], data[23]]), 1);
+        assert_eq!(u32::from_le_bytes(data[24..28].try_into().unwrap()), 8000);
+        // The whole ramp and nothing after it: 4000 16-bit samples
+        assert_eq!(u32::from_le_bytes(data[40..44].try_into().unwrap()), 8000);
+        assert_eq!(data.len(), 44 + 8000);
+    }
+}
@@ -0,0 +1,367 @@
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use crate::net::is_url;
+
+// Playlist files: M3U/M3U8 (with #EXTINF), PLS and XSPF are read; M3U8 and XSPF are written. Entries can be
+// relative paths, absolute paths, `file://` URIs or stream URLs. Paths are resolved against the playlist's folder
+// on reading and written relative to it where possible.
+
+pub(crate) const PLAYLIST_EXTS: &[&str] = &["m3u", "m3u8", "pls", "xspf"];
+
+#[derive(Clone, Debug)]
+pub(crate) struct PlaylistEntry {
+    /// Resolved file path, or a stream URL.
+    pub path: PathBuf,
+    /// Display title from the playlist (`#EXTINF`, `TitleN`, XSPF creator/title).
+    pub title: Option<String>,
+    pub duration: Option<Duration>,
+    /// The file didn't exist when the playlist was loaded.
+    pub missing: bool,
+}
+
+impl PlaylistEntry {
+    pub(crate) fn new(path: PathBuf, title: Option<String>, duration: Option<Duration>) -> Self {
+        let missing = !is_url(&path) && !path.is_file();
+        Self { path, title, duration, missing }
+    }
+
+    pub(crate) fn label(&self) -> String {
+        self.title.clone().unwrap_or_else(|| self.path.file_name().map_or_else(|| self.path.display().to_string(), |n| n.to_string_lossy().into_owned()))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Playlist {
+    pub name: String,
+    /// File the playlist was loaded from or last exported to; `None` for one made in the app and not yet saved.
+    pub file: Option<PathBuf>,
+    pub entries: Vec<PlaylistEntry>,
+}
+
+impl Playlist {
+    pub(crate) fn missing(&self) -> usize { self.entries.iter().filter(|e| e.missing).count() }
+}
+
+pub(crate) fn is_playlist(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()).is_some_and(|ext| PLAYLIST_EXTS.iter().any(|x| x.eq_ignore_ascii_case(ext)))
+}
+
+/// Read the playlist at `path`, in the format its extension names.
+pub(crate) fn read_playlist(path: &Path) -> Result<Playlist, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+    // Plain .m3u files from older players are usually Latin-1/Windows-1252 rather than UTF-8
+    let text = match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => e.into_bytes().iter().map(|&b| b as char).collect(),
+    };
+    let text = text.trim_start_matches('\u{feff}');
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+    let (name, entries) = match ext.as_str() {
+        "pls" => (None, parse_pls(text, dir)),
+        "xspf" => parse_xspf(text, dir).map_err(|e| format!("{}: {e}", path.display()))?,
+        _ => (None, parse_m3u(text, dir)),
+    };
+    let name = name.unwrap_or_else(|| path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default());
+    Ok(Playlist { name, file: Some(path.to_path_buf()), entries })
+}
+
+/// The playlists in `dir`, sorted by name. Ones that can't be read are logged and left out.
+pub(crate) fn scan_playlists(dir: &Path) -> Vec<Playlist> {
+    let Ok(entries) = std::fs::read_dir(dir) else { return Vec::new() };
+    let mut playlists: Vec<Playlist> = entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.is_file() && is_playlist(p))
+        .filter_map(|p| read_playlist(&p).inspect_err(|e| log::warn!("{e}")).ok()).collect();
+    for playlist in &playlists {
+        for entry in playlist.entries.iter().filter(|e| e.missing) { log::warn!("{}: missing {}", playlist.name, entry.path.display()); }
+    }
+    playlists.sort_by_key(|p| p.name.to_lowercase());
+    playlists
+}
+
+fn parse_m3u(text: &str, dir: &Path) -> Vec<PlaylistEntry> {
+    let mut entries = Vec::new();
+    let mut info: Option<(Option<Duration>, Option<String>)> = None;
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if let Some(rest) = line.strip_prefix("#EXTINF:") {
+            // `#EXTINF:<seconds>[ key="value"...],<title>`; -1 means unknown. Attribute values may contain commas,
+            // titles too, so the title starts after the first comma outside quotes
+            let mut quoted = false;
+            let split = rest.char_indices().find(|&(_, c)| { if c == '"' { quoted = !quoted; } c == ',' && !quoted }).map(|(i, _)| i);
+            let (head, title) = split.map_or((rest, ""), |i| (&rest[..i], &rest[i + 1..]));
+            let secs = head.split_whitespace().next().and_then(|s| s.parse::<f64>().ok()).filter(|s| *s > 0.0);
+            info = Some((secs.and_then(|s| Duration::try_from_secs_f64(s).ok()), Some(title.trim().to_string()).filter(|t| !t.is_empty())));
+        } else if !line.starts_with('#') {
+            let (duration, title) = info.take().unwrap_or_default();
+            entries.push(PlaylistEntry::new(resolve(dir, line, false), title, duration));
+        }
+    }
+    entries
+}
+
+fn parse_pls(text: &str, dir: &Path) -> Vec<PlaylistEntry> {
+    // `FileN=`, `TitleN=` and `LengthN=` can come in any order; N gives the playlist order
+    #[derive(Default)]
+    struct Slot { file: Option<String>, title: Option<String>, length: Option<Duration> }
+    let mut slots: BTreeMap<u32, Slot> = BTreeMap::new();
+    for line in text.lines() {
+        let Some((key, value)) = line.split_once('=') else { continue };
+        let key = key.trim().to_ascii_lowercase();
+        let Some((field, n)) = ["file", "title", "length"].iter().find_map(|f| key.strip_prefix(f).and_then(|n| n.parse::<u32>().ok()).map(|n| (*f, n))) else { continue };
+        let slot = slots.entry(n).or_default();
+        let value = value.trim().to_string();
+        match field {
+            "file" => slot.file = Some(value),
+            "title" => slot.title = Some(value).filter(|v| !v.is_empty()),
+            _ => slot.length = value.parse::<u64>().ok().filter(|s| *s > 0).map(Duration::from_secs),
+        }
+    }
+    slots.into_values().filter_map(|slot| Some(PlaylistEntry::new(resolve(dir, &slot.file?, false), slot.title, slot.length))).collect()
+}
+
+fn parse_xspf(text: &str, dir: &Path) -> Result<(Option<String>, Vec<PlaylistEntry>), String> {
+    let doc = roxmltree::Document::parse(text).map_err(|e| format!("invalid XSPF: {e}"))?;
+    let child_text = |node: roxmltree::Node, name: &str| node.children().find(|c| c.has_tag_name(name)).and_then(|c| c.text()).map(|t| t.trim().to_string()).filter(|t| !t.is_empty());
+    let root = doc.root_element();
+    let mut entries = Vec::new();
+    for track in root.descendants().filter(|n| n.has_tag_name("track")) {
+        let Some(location) = child_text(track, "location") else { continue };
+        let title = match (child_text(track, "creator"), child_text(track, "title")) {
+            (Some(creator), Some(title)) => Some(format!("{creator} - {title}")),
+            (_, title) => title,
+        };
+        let duration = child_text(track, "duration").and_then(|d| d.parse().ok()).filter(|ms| *ms > 0).map(Duration::from_millis);
+        entries.push(PlaylistEntry::new(resolve(dir, &location, true), title, duration));
+    }
+    Ok((child_text(root, "title"), entries))
+}
+
+/// Resolve a playlist location against the playlist's folder. XSPF locations are URIs, so they are percent-decoded.
+fn resolve(dir: &Path, location: &str, uri: bool) -> PathBuf {
+    let location = location.trim();
+    if is_url(Path::new(location)) { return PathBuf::from(location); }
+    let path = match location.strip_prefix("file://") {
+        // `file:///C:/Music/a.mp3` on Windows, `file:///home/me/a.mp3` elsewhere; `localhost` is the same machine
+        Some(rest) => {
+            let rest = percent_decode(rest.strip_prefix("localhost").unwrap_or(rest));
+            let rest = if cfg!(windows) && rest.as_bytes().get(2) == Some(&b':') && rest.starts_with('/') { rest[1..].to_string() } else { rest };
+            PathBuf::from(native_separators(&rest))
+        }
+        None => PathBuf::from(native_separators(&if uri { percent_decode(location) } else { location.to_string() })),
+    };
+    normalize(&dir.join(path))
+}
+
+/// Playlists made on Windows use `\`, which is an ordinary character elsewhere.
+fn native_separators(path: &str) -> String {
+    if cfg!(windows) { path.to_string() } else { path.replace('\\', "/") }
+}
+
+/// Remove `.` and `..` components without touching the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(out.components().next_back(), Some(Component::Normal(_))) => { out.pop(); }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// `path` relative to `dir`, if both are on the same root (drive).
+fn relative_to(path: &Path, dir: &Path) -> Option<PathBuf> {
+    let (path, dir) = (normalize(path), normalize(dir));
+    let (mut p, mut d) = (path.components().peekable(), dir.components().peekable());
+    // The first component is the root (and drive prefix on Windows); paths on different roots can't be related
+    if p.peek() != d.peek() || p.peek().is_none() { return None; }
+    while let (Some(a), Some(b)) = (p.peek(), d.peek()) && a == b { p.next(); d.next(); }
+    let mut out: PathBuf = d.map(|_| Component::ParentDir).collect();
+    out.extend(p);
+    Some(out)
+}
+
+/// How `entry` is written into a playlist saved in `dir`: a URL as is, a file relative to `dir` when possible.
+fn location(entry: &PlaylistEntry, dir: &Path) -> (PathBuf, bool) {
+    if is_url(&entry.path) { return (entry.path.clone(), false); }
+    let dir = std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+    let path = std::path::absolute(&entry.path).unwrap_or_else(|_| entry.path.clone());
+    match relative_to(&path, &dir) {
+        Some(relative) => (relative, true),
+        None => (path, false),
+    }
+}
+
+/// Write `playlist` as an extended M3U (UTF-8) file.
+pub(crate) fn write_m3u8(playlist: &Playlist, path: &Path) -> Result<(), String> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut out = String::from("#EXTM3U\n");
+    for entry in &playlist.entries {
+        let secs = entry.duration.map_or(-1, |d| d.as_secs_f64().round() as i64);
+        out.push_str(&format!("#EXTINF:{secs},{}\n{}\n", entry.label(), location(entry, dir).0.display()));
+    }
+    std::fs::write(path, out).map_err(|e| format!("Failed to write {}: {e}", path.display()))
+}
+
+/// Write `playlist` as XSPF. "Artist - Title" labels are split into `creator` and `title`.
+pub(crate) fn write_xspf(playlist: &Playlist, path: &Path) -> Result<(), String> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n");
+    out.push_str(&format!("  <title>{}</title>\n  <trackList>\n", xml_escape(&playlist.name)));
+    for entry in &playlist.entries {
+        let uri = match location(entry, dir) {
+            (url, false) if is_url(&url) => url.to_string_lossy().into_owned(),
+            (relative, true) => percent_encode(&path_with_slashes(&relative)),
+            (absolute, _) => {
+                let path = path_with_slashes(&absolute);
+                format!("file://{}{}", if path.starts_with('/') { "" } else { "/" }, percent_encode(&path))
+            }
+        };
+        out.push_str(&format!("    <track>\n      <location>{}</location>\n", xml_escape(&uri)));
+        if let Some(title) = &entry.title {
+            match title.split_once(" - ") {
+                Some((creator, title)) => out.push_str(&format!("      <creator>{}</creator>\n      <title>{}</title>\n", xml_escape(creator), xml_escape(title))),
+                None => out.push_str(&format!("      <title>{}</title>\n", xml_escape(title))),
+            }
+        }
+        if let Some(duration) = entry.duration { out.push_str(&format!("      <duration>{}</duration>\n", duration.as_millis())); }
+        out.push_str("    </track>\n");
+    }
+    out.push_str("  </trackList>\n</playlist>\n");
+    std::fs::write(path, out).map_err(|e| format!("Failed to write {}: {e}", path.display()))
+}
+
+fn path_with_slashes(path: &Path) -> String {
+    let text = path.to_string_lossy();
+    if cfg!(windows) { text.replace('\\', "/") } else { text.into_owned() }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Percent-encode everything in a path except unreserved characters and separators.
+fn percent_encode(path: &str) -> String {
+    path.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' | b':' => (b as char).to_string(),
+        _ => format!("%{b:02X}"),
+    }).collect()
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = (bytes[i] == b'%').then(|| text.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(h, 16).ok())).flatten();
+        match hex {
+            Some(b) => { out.push(b); i += 3; }
+            None => { out.push(bytes[i]); i += 1; }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh folder with `files` (relative paths) created empty.
+    fn folder(name: &str, files: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("playlist-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for file in files {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"").unwrap();
+        }
+        dir
+    }
+
+    fn summary(playlist: &Playlist) -> Vec<(PathBuf, Option<String>, Option<Duration>, bool)> {
+        playlist.entries.iter().map(|e| (e.path.clone(), e.title.clone(), e.duration, e.missing)).collect()
+    }
+
+    fn sample(dir: &Path) -> Playlist {
+        let entries = vec![
+            PlaylistEntry::new(dir.join("lists/../Album/01 Song #1.mp3"), Some("Artist - First, Part 1".to_string()), Some(Duration::from_secs(200))),
+            PlaylistEntry::new(dir.join("Other/Café & Bar.flac"), None, None),
+            PlaylistEntry::new(PathBuf::from("http://radio.example/stream?id=1&fmt=mp3"), Some("Radio".to_string()), None),
+            PlaylistEntry::new(dir.join("Album/gone.mp3"), Some("Gone".to_string()), Some(Duration::from_secs(61))),
+        ];
+        Playlist { name: "Mix <1>".to_string(), file: None, entries }
+    }
+
+    #[test]
+    fn m3u8_round_trip() {
+        let dir = folder("m3u8", &["Album/01 Song #1.mp3", "Other/Café & Bar.flac"]);
+        std::fs::create_dir_all(dir.join("lists")).unwrap();
+        let playlist = sample(&dir);
+        let file = dir.join("lists/mix.m3u8");
+        write_m3u8(&playlist, &file).unwrap();
+        // Paths are written relative to the playlist
+        let text = std::fs::read_to_string(&file).unwrap();
+        assert!(text.contains("#EXTINF:200,Artist - First, Part 1\n../Album/01 Song #1.mp3\n"), "{text}");
+        let read = read_playlist(&file).unwrap();
+        assert_eq!(read.name, "mix");
+        let mut expected = summary(&playlist);
+        expected[0].0 = dir.join("Album/01 Song #1.mp3");
+        // M3U has no separate title field, so the file name stands in
+        expected[1].1 = Some("Café & Bar.flac".to_string());
+        assert_eq!(summary(&read), expected);
+        assert_eq!(read.missing(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn xspf_round_trip() {
+        let dir = folder("xspf", &["Album/01 Song #1.mp3", "Other/Café & Bar.flac"]);
+        std::fs::create_dir_all(dir.join("lists")).unwrap();
+        let playlist = sample(&dir);
+        let file = dir.join("mix.xspf");
+        write_xspf(&playlist, &file).unwrap();
+        let text = std::fs::read_to_string(&file).unwrap();
+        assert!(text.contains("<location>Other/Caf%C3%A9%20%26%20Bar.flac</location>"), "{text}");
+        assert!(text.contains("<creator>Artist</creator>\n      <title>First, Part 1</title>"), "{text}");
+        let read = read_playlist(&file).unwrap();
+        assert_eq!(read.name, "Mix <1>");
+        let mut expected = summary(&playlist);
+        expected[0].0 = dir.join("Album/01 Song #1.mp3");
+        assert_eq!(summary(&read), expected);
+        assert_eq!(read.missing(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn file_uris_and_relative_entries_are_resolved() {
+        let dir = folder("uris", &["music/a b.mp3", "music/ü.ogg", "elsewhere/c.wav"]);
+        let root = dir.to_string_lossy().replace(' ', "%20");
+        let m3u = format!("#EXTM3U\nfile://{root}/music/a%20b.mp3\nfile://localhost{root}/music/%C3%BC.ogg\n../elsewhere/c.wav\n./../music/missing.mp3\nhttps://example.com/live\n");
+        std::fs::write(dir.join("music/list.m3u"), m3u).unwrap();
+        let read = read_playlist(&dir.join("music/list.m3u")).unwrap();
+        let paths: Vec<PathBuf> = read.entries.iter().map(|e| e.path.clone()).collect();
+        assert_eq!(paths, [dir.join("music/a b.mp3"), dir.join("music/ü.ogg"), dir.join("elsewhere/c.wav"), dir.join("music/missing.mp3"), PathBuf::from("https://example.com/live")]);
+        assert_eq!(read.entries.iter().map(|e| e.missing).collect::<Vec<_>>(), [false, false, false, true, false]);
+        // Plain M3U paths aren't URIs, so a literal % stays
+        std::fs::write(dir.join("music/literal.m3u"), "100%25.mp3\n").unwrap();
+        assert_eq!(read_playlist(&dir.join("music/literal.m3u")).unwrap().entries[0].path, dir.join("music/100%25.mp3"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn extinf_attributes_may_contain_commas() {
+        let entries = parse_m3u("#EXTM3U\n#EXTINF:-1 tvg-name=\"a,b\" group-title=\"News, Live\",Channel, HD\nhttp://tv.example/1\n#EXTINF:12.5,\nsong.mp3\n#EXTINF:30\nother.mp3\n", Path::new("/m"));
+        assert_eq!((entries[0].title.as_deref(), entries[0].duration), (Some("Channel, HD"), None));
+        assert_eq!((entries[1].title.as_deref(), entries[1].duration), (None, Some(Duration::from_millis(12_500))));
+        assert_eq!((entries[2].title.as_deref(), entries[2].duration), (None, Some(Duration::from_secs(30))));
+    }
+
+    #[test]
+    fn pls_keeps_its_numbering() {
+        let entries = parse_pls("[playlist]\nFile2=b.mp3\nTitle1=First\nFile1=a.mp3\nLength1=90\nLength2=-1\nNumberOfEntries=2\n", Path::new("/m"));
+        assert_eq!(entries.iter().map(|e| (e.path.clone(), e.title.clone(), e.duration)).collect::<Vec<_>>(),
+            [(PathBuf::from("/m/a.mp3"), Some("First".to_string()), Some(Duration::from_secs(90))), (PathBuf::from("/m/b.mp3"), None, None)]);
+    }
+}
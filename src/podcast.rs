@@ -0,0 +1,734 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::net::{agent, fetch, hex, md5, resolve_url};
+use crate::record::{days_from_civil, sanitize};
+
+// Podcast subscriptions. Feeds (RSS 2.0 with the iTunes extensions, or Atom) are fetched on a background thread
+// and cached next to the downloads, so episode lists are available offline. Episodes are downloaded into the
+// music folder through a `.part` file that is resumed with a Range request. Played state and resume positions
+// are kept in `podcasts.conf` in the config folder.
+
+/// Folder (inside the music folder) that holds one folder per show.
+pub(crate) const PODCASTS_DIR: &str = "Podcasts";
+const STATE_FILE: &str = "podcasts.conf";
+const FEED_CACHE: &str = "feed.xml";
+
+const ITUNES_NS: &str = "http://www.itunes.com/dtds/podcast-1.0.dtd";
+const CONTENT_NS: &str = "http://purl.org/rss/1.0/modules/content/";
+const ATOM_NS: &str = "http://www.w3.org/2005/Atom";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Episode {
+    /// Stable id (`guid`/`id`, or the enclosure URL when the feed has none).
+    pub guid: String,
+    pub title: String,
+    /// Publication time in seconds since 1970 (UTC).
+    pub published: Option<i64>,
+    pub duration: Option<Duration>,
+    /// Show notes as plain text.
+    pub notes: String,
+    /// Enclosure (audio file) URL.
+    pub url: String,
+    /// Enclosure size in bytes, as the feed states it.
+    pub length: Option<u64>,
+}
+
+impl Episode {
+    /// Publication date as `YYYY-MM-DD`.
+    pub fn date_text(&self) -> String {
+        self.published.map(|secs| {
+            let (y, m, d) = civil_from_days(secs.div_euclid(86_400));
+            format!("{y:04}-{m:02}-{d:02}")
+        }).unwrap_or_default()
+    }
+
+    /// File name the episode is downloaded to. A hash of the guid keeps episodes with the same date and title apart.
+    fn file_name(&self) -> String {
+        let ext = self.url.split(['?', '#']).next().and_then(|u| u.rsplit('/').next()).and_then(|name| name.rsplit_once('.'))
+            .map(|(_, ext)| ext.to_ascii_lowercase()).filter(|e| e.len() <= 4 && e.chars().all(|c| c.is_ascii_alphanumeric())).unwrap_or_else(|| "mp3".into());
+        let date = self.date_text();
+        let name = sanitize(&if date.is_empty() { self.title.clone() } else { format!("{date} {}", self.title) });
+        format!("{name} {}.{ext}", &hex(&md5(self.guid.as_bytes()))[..8])
+    }
+}
+
+/// A parsed feed. Episodes are newest first.
+#[derive(Clone, Debug, Default)]
+pub struct Feed {
+    pub title: String,
+    pub episodes: Vec<Episode>,
+}
+
+/// Download and parse the feed at `url`.
+pub fn fetch_feed(url: &str) -> Result<Feed, String> {
+    let data = fetch(url, None)?;
+    parse_feed(&String::from_utf8_lossy(&data), url).map_err(|e| format!("{url}: {e}"))
+}
+
+/// Parse an RSS 2.0 or Atom document. Relative links are resolved against `url`.
+pub(crate) fn parse_feed(text: &str, url: &str) -> Result<Feed, String> {
+    let doc = roxmltree::Document::parse(text).map_err(|e| format!("invalid feed: {e}"))?;
+    let root = doc.root_element();
+    let mut feed = if root.has_tag_name((ATOM_NS, "feed")) {
+        parse_atom(root, url)
+    } else {
+        let channel = root.descendants().find(|n| n.has_tag_name("channel")).ok_or("not an RSS or Atom feed")?;
+        parse_rss(channel, url)
+    };
+    // Newest first; undated episodes keep their feed order at the end
+    feed.episodes.sort_by_key(|e| std::cmp::Reverse(e.published.unwrap_or(i64::MIN)));
+    Ok(feed)
+}
+
+/// Text of the first child element called `name` in namespace `ns` (`None` for no namespace).
+fn child_text(node: roxmltree::Node, ns: Option<&str>, name: &str) -> Option<String> {
+    node.children().find(|c| c.is_element() && c.tag_name().name() == name && c.tag_name().namespace() == ns)
+        .map(|c| c.text().unwrap_or_default().trim().to_string()).filter(|t| !t.is_empty())
+}
+
+fn parse_rss(channel: roxmltree::Node, url: &str) -> Feed {
+    let title = child_text(channel, None, "title").unwrap_or_default();
+    let episodes = channel.children().filter(|n| n.has_tag_name("item")).filter_map(|item| {
+        // Items without audio (blog posts, announcements) aren't episodes
+        let enclosure = item.children().find(|c| c.has_tag_name("enclosure"))?;
+        let media_url = resolve_url(url, enclosure.attribute("url")?);
+        let notes = child_text(item, Some(CONTENT_NS), "encoded").or_else(|| child_text(item, None, "description"))
+            .or_else(|| child_text(item, Some(ITUNES_NS), "summary")).unwrap_or_default();
+        Some(Episode {
+            guid: child_text(item, None, "guid").unwrap_or_else(|| media_url.clone()),
+            title: child_text(item, None, "title").or_else(|| child_text(item, Some(ITUNES_NS), "title")).unwrap_or_else(|| "Untitled".into()),
+            published: child_text(item, None, "pubDate").and_then(|d| parse_rfc822(&d)),
+            duration: child_text(item, Some(ITUNES_NS), "duration").and_then(|d| parse_duration(&d)),
+            notes: html_to_text(&notes),
+            length: enclosure.attribute("length").and_then(|l| l.trim().parse().ok()).filter(|l| *l > 0),
+            url: media_url,
+        })
+    }).collect();
+    Feed { title, episodes }
+}
+
+fn parse_atom(feed: roxmltree::Node, url: &str) -> Feed {
+    let ns = Some(ATOM_NS);
+    let title = child_text(feed, ns, "title").unwrap_or_default();
+    let episodes = feed.children().filter(|n| n.has_tag_name((ATOM_NS, "entry"))).filter_map(|entry| {
+        let enclosure = entry.children().find(|c| c.has_tag_name((ATOM_NS, "link")) && c.attribute("rel") == Some("enclosure"))?;
+        let media_url = resolve_url(url, enclosure.attribute("href")?);
+        let notes = child_text(entry, ns, "content").or_else(|| child_text(entry, ns, "summary")).unwrap_or_default();
+        Some(Episode {
+            guid: child_text(entry, ns, "id").unwrap_or_else(|| media_url.clone()),
+            title: child_text(entry, ns, "title").unwrap_or_else(|| "Untitled".into()),
+            published: child_text(entry, ns, "published").or_else(|| child_text(entry, ns, "updated")).and_then(|d| parse_rfc3339(&d)),
+            duration: child_text(entry, Some(ITUNES_NS), "duration").and_then(|d| parse_duration(&d)),
+            notes: html_to_text(&notes),
+            length: enclosure.attribute("length").and_then(|l| l.trim().parse().ok()).filter(|l| *l > 0),
+            url: media_url,
+        })
+    }).collect();
+    Feed { title, episodes }
+}
+
+/// `itunes:duration`: plain seconds, `MM:SS` or `HH:MM:SS`.
+fn parse_duration(text: &str) -> Option<Duration> {
+    let mut secs = 0.0;
+    for part in text.trim().split(':') { secs = secs * 60.0 + part.trim().parse::<f64>().ok()?; }
+    Duration::try_from_secs_f64(secs).ok().filter(|d| !d.is_zero())
+}
+
+/// RSS dates: `Tue, 10 Jun 2003 04:00:00 GMT` (RFC 822, with the usual variations).
+fn parse_rfc822(text: &str) -> Option<i64> {
+    let text = text.split_once(',').map_or(text, |(_, rest)| rest);
+    let mut parts = text.split_whitespace();
+    let day: u32 = parts.next()?.parse().ok()?;
+    let month = month_number(parts.next()?)?;
+    let year: i32 = parts.next()?.parse().ok()?;
+    let year = if year < 100 { year + if year < 70 { 2000 } else { 1900 } } else { year };
+    let time = parts.next().unwrap_or("00:00");
+    let mut hms = time.split(':').map(|p| p.parse::<i64>().ok());
+    let (h, m, s) = (hms.next()??, hms.next()??, hms.next().flatten().unwrap_or(0));
+    let offset = match parts.next().unwrap_or("GMT") {
+        "GMT" | "UT" | "UTC" | "Z" => 0,
+        "EST" => -5 * 60, "EDT" => -4 * 60, "CST" => -6 * 60, "CDT" => -5 * 60,
+        "MST" => -7 * 60, "MDT" => -6 * 60, "PST" => -8 * 60, "PDT" => -7 * 60,
+        zone => zone_offset(zone)?,
+    };
+    Some(days_from_civil(year, month, day) * 86_400 + h * 3600 + m * 60 + s - offset * 60)
+}
+
+/// Atom dates: `2003-12-13T18:30:02Z`, `2003-12-13T18:30:02.25+01:00`.
+fn parse_rfc3339(text: &str) -> Option<i64> {
+    let text = text.trim();
+    let (date, time) = text.split_once(['T', 't', ' ']).unwrap_or((text, "00:00:00Z"));
+    let mut ymd = date.split('-').map(|p| p.parse::<u32>().ok());
+    let (y, mo, d) = (ymd.next()?? as i32, ymd.next()??, ymd.next()??);
+    let zone_at = time.find(['Z', 'z', '+', '-']).unwrap_or(time.len());
+    let (clock, zone) = time.split_at(zone_at);
+    let mut hms = clock.split(':').map(|p| p.split('.').next().and_then(|p| p.parse::<i64>().ok()));
+    let (h, m, s) = (hms.next()??, hms.next()??, hms.next().flatten().unwrap_or(0));
+    let offset = if zone.is_empty() || zone.eq_ignore_ascii_case("z") { 0 } else { zone_offset(&zone.replace(':', ""))? };
+    Some(days_from_civil(y, mo, d) * 86_400 + h * 3600 + m * 60 + s - offset * 60)
+}
+
+/// `+hhmm`/`-hhmm` in minutes.
+fn zone_offset(zone: &str) -> Option<i64> {
+    let (sign, digits) = match zone.as_bytes().first()? { b'+' => (1, &zone[1..]), b'-' => (-1, &zone[1..]), _ => return None };
+    let value: i64 = digits.get(..4)?.parse().ok()?;
+    Some(sign * (value / 100 * 60 + value % 100))
+}
+
+fn month_number(name: &str) -> Option<u32> {
+    const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+    let name = name.get(..3)?.to_ascii_lowercase();
+    MONTHS.iter().position(|m| *m == name).map(|i| i as u32 + 1)
+}
+
+/// Calendar date of a day number (days since 1970-01-01).
+fn civil_from_days(days: i64) -> (i32, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    ((yoe + era * 400 + i64::from(m <= 2)) as i32, m, d)
+}
+
+/// Show notes are usually HTML: keep the text and paragraph breaks.
+fn html_to_text(html: &str) -> String {
+    let mut out = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        out.push_str(&rest[..start]);
+        let end = rest[start..].find('>').map_or(rest.len(), |e| start + e + 1);
+        let tag = rest[start + 1..end.saturating_sub(1).max(start + 1)].trim_start_matches('/').to_ascii_lowercase();
+        if ["br", "p", "div", "li", "h1", "h2", "h3", "h4"].iter().any(|t| tag.split([' ', '/']).next() == Some(t)) { out.push('\n'); }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    let text = decode_entities(&out);
+    // Collapse runs of whitespace, keeping at most one blank line
+    let lines: Vec<String> = text.lines().map(|l| l.split_whitespace().collect::<Vec<_>>().join(" ")).collect();
+    let mut result = String::new();
+    for line in lines.iter().skip_while(|l| l.is_empty()) {
+        if line.is_empty() && (result.is_empty() || result.ends_with("\n\n")) { continue; }
+        result.push_str(line);
+        result.push('\n');
+    }
+    result.trim_end().to_string()
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let entity = rest.find(';').filter(|&e| e <= 10).map(|e| &rest[1..e]);
+        let decoded = entity.and_then(|name| match name {
+            "amp" => Some('&'), "lt" => Some('<'), "gt" => Some('>'), "quot" => Some('"'), "apos" => Some('\''), "nbsp" => Some(' '),
+            _ => name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")).map_or_else(|| name.strip_prefix('#')?.parse().ok(), |hex| u32::from_str_radix(hex, 16).ok())
+                .and_then(char::from_u32),
+        });
+        match (decoded, entity) {
+            (Some(c), Some(name)) => { out.push(c); rest = &rest[name.len() + 2..]; }
+            _ => { out.push('&'); rest = &rest[1..]; }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Download `episode` into `dir`. An interrupted download (the `.part` file) is resumed. `progress` gets the bytes
+/// received so far and the total, when known; returning `false` cancels the download.
+pub fn download_episode(episode: &Episode, dir: &Path, progress: &dyn Fn(u64, Option<u64>) -> bool) -> Result<PathBuf, String> {
+    std::fs::create_dir_all(dir).map_err(|e| format!("Can't create {}: {e}", dir.display()))?;
+    let path = dir.join(episode.file_name());
+    let part = path.with_file_name(format!("{}.part", episode.file_name()));
+    let have = std::fs::metadata(&part).map_or(0, |m| m.len());
+    let mut request = agent().get(&episode.url);
+    if have > 0 { request = request.header("Range", format!("bytes={have}-")); }
+    let response = match request.call() {
+        Ok(response) => response,
+        // The part file already holds the whole episode
+        Err(ureq::Error::StatusCode(416)) if have > 0 && episode.length.is_none_or(|l| l == have) => return finish_download(&part, &path),
+        Err(e) => return Err(format!("{}: {e}", episode.url)),
+    };
+    let resumed = have > 0 && response.status().as_u16() == 206;
+    let length = response.headers().get("content-length").and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<u64>().ok());
+    let total = length.map(|l| if resumed { l + have } else { l }).or(episode.length);
+    // A server that doesn't do ranges sends the whole file again
+    let mut file = if resumed { OpenOptions::new().append(true).open(&part) } else { File::create(&part) }
+        .map_err(|e| format!("Can't write {}: {e}", part.display()))?;
+    let mut received = if resumed { have } else { 0 };
+    let mut body = response.into_body().into_reader();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        if !progress(received, total) { return Err("Download cancelled".into()); }
+        let n = body.read(&mut buf).map_err(|e| format!("{}: {e}", episode.url))?;
+        if n == 0 { break; }
+        file.write_all(&buf[..n]).map_err(|e| format!("Can't write {}: {e}", part.display()))?;
+        received += n as u64;
+    }
+    drop(file);
+    if let Some(total) = total.filter(|t| received < *t) { return Err(format!("{}: connection closed after {received} of {total} bytes", episode.url)); }
+    progress(received, total);
+    finish_download(&part, &path)
+}
+
+fn finish_download(part: &Path, path: &Path) -> Result<PathBuf, String> {
+    std::fs::rename(part, path).map_err(|e| format!("Can't save {}: {e}", path.display()))?;
+    Ok(path.to_path_buf())
+}
+
+/// A subscribed feed and its rules.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Subscription {
+    pub url: String,
+    pub title: String,
+    /// Download new episodes when they appear.
+    pub auto_download: bool,
+    /// Delete an episode's download once it has been played.
+    pub delete_played: bool,
+    /// Most downloads to keep; older ones are deleted. 0 means no limit.
+    pub keep: u32,
+    pub episodes: Vec<Episode>,
+    /// Why the last refresh failed.
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct EpisodeState {
+    pub played: bool,
+    /// Where to resume.
+    pub position: Duration,
+}
+
+/// A download in progress.
+#[derive(Default)]
+struct Transfer { received: AtomicU64, total: AtomicU64, cancel: AtomicBool }
+
+/// Work finished on a background thread, applied in `Podcasts::poll`.
+enum Done {
+    Refreshed { url: String, result: Result<(Feed, Vec<u8>), String> },
+    Downloaded { key: (String, String), result: Result<PathBuf, String> },
+}
+
+/// Subscriptions, episode state and downloads.
+pub(crate) struct Podcasts {
+    /// Where shows are downloaded (a folder per show).
+    dir: PathBuf,
+    pub subscriptions: Vec<Subscription>,
+    /// Keyed by (feed URL, episode guid).
+    state: HashMap<(String, String), EpisodeState>,
+    transfers: HashMap<(String, String), Arc<Transfer>>,
+    refreshing: Vec<String>,
+    done: Arc<Mutex<Vec<Done>>>,
+    /// The state file as last loaded or saved, so saving is skipped when nothing changed.
+    saved: String,
+}
+
+impl Podcasts {
+    /// Load the subscriptions and cached feeds. Downloads go to `dir`.
+    pub(crate) fn load(dir: &Path) -> Self {
+        let mut podcasts = Self { dir: dir.to_path_buf(), subscriptions: Vec::new(), state: HashMap::new(), transfers: HashMap::new(), refreshing: Vec::new(), done: Arc::default(), saved: String::new() };
+        let Some(text) = state_path().and_then(|p| std::fs::read_to_string(p).ok()) else { return podcasts };
+        for line in text.lines() {
+            let Some((key, value)) = line.split_once('=') else { continue };
+            match key.trim() {
+                // `feed=AUTO|DELETE|KEEP|Title|URL`
+                "feed" => {
+                    let mut fields = value.trim().splitn(4, '|');
+                    let (Some(auto), Some(delete), Some(keep), Some(rest)) = (fields.next(), fields.next(), fields.next(), fields.next()) else { continue };
+                    let Some((title, url)) = rest.rsplit_once('|') else { continue };
+                    let mut sub = Subscription { url: url.to_string(), title: title.to_string(), auto_download: auto == "1", delete_played: delete == "1", keep: keep.parse().unwrap_or(0), ..Default::default() };
+                    if let Ok(cached) = std::fs::read_to_string(podcasts.show_dir(&sub).join(FEED_CACHE)) && let Ok(feed) = parse_feed(&cached, url) {
+                        sub.episodes = feed.episodes;
+                    }
+                    podcasts.subscriptions.push(sub);
+                }
+                // `episode=PLAYED|POSITION_MS|GUID`, for the feed above
+                "episode" => {
+                    let mut fields = value.trim().splitn(3, '|');
+                    let (Some(played), Some(position), Some(guid)) = (fields.next(), fields.next(), fields.next()) else { continue };
+                    let Some(sub) = podcasts.subscriptions.last() else { continue };
+                    let state = EpisodeState { played: played == "1", position: Duration::from_millis(position.parse().unwrap_or(0)) };
+                    podcasts.state.insert((sub.url.clone(), guid.to_string()), state);
+                }
+                _ => {}
+            }
+        }
+        podcasts.saved = podcasts.render();
+        podcasts
+    }
+
+    /// Write the subscriptions and episode state, if they changed since the last save.
+    pub(crate) fn save(&mut self) -> Result<(), String> {
+        let out = self.render();
+        if out == self.saved { return Ok(()); }
+        let path = state_path().ok_or("No settings directory available")?;
+        std::fs::write(&path, &out).map_err(|e| format!("Failed to save podcasts: {e}"))?;
+        self.saved = out;
+        Ok(())
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        for sub in &self.subscriptions {
+            let flag = |b: bool| if b { "1" } else { "0" };
+            out.push_str(&format!("feed={}|{}|{}|{}|{}\n", flag(sub.auto_download), flag(sub.delete_played), sub.keep, sub.title.replace(['\r', '\n'], " "), sub.url));
+            for episode in &sub.episodes {
+                let Some(state) = self.state.get(&(sub.url.clone(), episode.guid.clone())).filter(|s| **s != EpisodeState::default()) else { continue };
+                out.push_str(&format!("episode={}|{}|{}\n", flag(state.played), state.position.as_millis(), episode.guid.replace(['\r', '\n'], " ")));
+            }
+        }
+        out
+    }
+
+    /// A show's folder is named after its feed URL, which (unlike its title) doesn't change or clash with others.
+    fn show_dir(&self, sub: &Subscription) -> PathBuf { self.dir.join(&hex(&md5(sub.url.as_bytes()))[..16]) }
+
+    /// Subscribe to the feed at `url` and fetch it.
+    pub(crate) fn subscribe(&mut self, url: &str) -> Result<usize, String> {
+        if self.subscriptions.iter().any(|s| s.url == url) { return Err("Already subscribed".into()); }
+        self.subscriptions.push(Subscription { url: url.to_string(), ..Default::default() });
+        self.refresh(self.subscriptions.len() - 1);
+        Ok(self.subscriptions.len() - 1)
+    }
+
+    /// Forget a subscription. Downloaded files are kept.
+    pub(crate) fn unsubscribe(&mut self, index: usize) {
+        if index >= self.subscriptions.len() { return; }
+        let sub = self.subscriptions.remove(index);
+        for (key, transfer) in &self.transfers { if key.0 == sub.url { transfer.cancel.store(true, Ordering::Relaxed); } }
+        self.state.retain(|key, _| key.0 != sub.url);
+    }
+
+    /// Fetch a feed again in the background.
+    pub(crate) fn refresh(&mut self, index: usize) {
+        let Some(sub) = self.subscriptions.get(index) else { return };
+        if self.refreshing.contains(&sub.url) { return; }
+        let (url, done) = (sub.url.clone(), self.done.clone());
+        let spawned = std::thread::Builder::new().name("podcast-feed".into()).spawn(move || {
+            let result = fetch(&url, None).and_then(|data| {
+                let feed = parse_feed(&String::from_utf8_lossy(&data), &url).map_err(|e| format!("{url}: {e}"))?;
+                Ok((feed, data))
+            });
+            done.lock().unwrap().push(Done::Refreshed { url, result });
+        });
+        match spawned {
+            Ok(_) => self.refreshing.push(sub.url.clone()),
+            Err(e) => log::warn!("Can't refresh {}: {e}", sub.url),
+        }
+    }
+
+    pub(crate) fn refresh_all(&mut self) { for i in 0..self.subscriptions.len() { self.refresh(i); } }
+
+    pub(crate) fn is_refreshing(&self, index: usize) -> bool { self.subscriptions.get(index).is_some_and(|s| self.refreshing.contains(&s.url)) }
+
+    pub(crate) fn is_downloading(&self) -> bool { !self.transfers.is_empty() }
+
+    fn key(&self, index: usize, episode: usize) -> Option<((String, String), &Episode)> {
+        let sub = self.subscriptions.get(index)?;
+        let episode = sub.episodes.get(episode)?;
+        Some(((sub.url.clone(), episode.guid.clone()), episode))
+    }
+
+    pub(crate) fn state(&self, index: usize, episode: usize) -> EpisodeState {
+        self.key(index, episode).and_then(|(key, _)| self.state.get(&key).cloned()).unwrap_or_default()
+    }
+
+    /// The downloaded file of an episode, if there is one.
+    pub(crate) fn file(&self, index: usize, episode: usize) -> Option<PathBuf> {
+        let sub = self.subscriptions.get(index)?;
+        let path = self.show_dir(sub).join(sub.episodes.get(episode)?.file_name());
+        path.is_file().then_some(path)
+    }
+
+    /// Download progress (0..1, or `None` when the size is unknown) of an episode being downloaded.
+    pub(crate) fn download_progress(&self, index: usize, episode: usize) -> Option<Option<f32>> {
+        let transfer = self.transfers.get(&self.key(index, episode)?.0)?;
+        let total = transfer.total.load(Ordering::Relaxed);
+        Some((total > 0).then(|| transfer.received.load(Ordering::Relaxed) as f32 / total as f32))
+    }
+
+    /// Start downloading an episode in the background.
+    pub(crate) fn download(&mut self, index: usize, episode: usize) {
+        let Some((key, ep)) = self.key(index, episode) else { return };
+        if self.transfers.contains_key(&key) || self.file(index, episode).is_some() { return; }
+        let (ep, dir, done) = (ep.clone(), self.show_dir(&self.subscriptions[index]), self.done.clone());
+        let (transfer, thread_key) = (Arc::new(Transfer::default()), key.clone());
+        let thread_transfer = transfer.clone();
+        let spawned = std::thread::Builder::new().name("podcast-download".into()).spawn(move || {
+            let result = download_episode(&ep, &dir, &|received, total| {
+                thread_transfer.received.store(received, Ordering::Relaxed);
+                thread_transfer.total.store(total.unwrap_or(0), Ordering::Relaxed);
+                !thread_transfer.cancel.load(Ordering::Relaxed)
+            });
+            done.lock().unwrap().push(Done::Downloaded { key: thread_key, result });
+        });
+        match spawned {
+            Ok(_) => { self.transfers.insert(key, transfer); }
+            Err(e) => log::warn!("Can't download {}: {e}", self.subscriptions[index].episodes[episode].title),
+        }
+    }
+
+    /// Cancel a download in progress, or delete the downloaded file.
+    pub(crate) fn delete_download(&mut self, index: usize, episode: usize) -> Result<(), String> {
+        let Some((key, _)) = self.key(index, episode) else { return Ok(()) };
+        if let Some(transfer) = self.transfers.get(&key) { transfer.cancel.store(true, Ordering::Relaxed); }
+        match self.file(index, episode) {
+            Some(path) => std::fs::remove_file(&path).map_err(|e| format!("Can't delete {}: {e}", path.display())),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn set_played(&mut self, index: usize, episode: usize, played: bool) {
+        let Some((key, _)) = self.key(index, episode) else { return };
+        self.state.insert(key, EpisodeState { played, position: Duration::ZERO });
+        if played && self.subscriptions[index].delete_played && let Err(e) = self.delete_download(index, episode) { log::warn!("{e}"); }
+    }
+
+    pub(crate) fn set_position(&mut self, index: usize, episode: usize, position: Duration) {
+        let Some((key, _)) = self.key(index, episode) else { return };
+        self.state.entry(key).or_default().position = position;
+    }
+
+    /// Find an episode by feed URL and guid (indices shift when feeds are refreshed or removed).
+    pub(crate) fn find(&self, url: &str, guid: &str) -> Option<(usize, usize)> {
+        let index = self.subscriptions.iter().position(|s| s.url == url)?;
+        Some((index, self.subscriptions[index].episodes.iter().position(|e| e.guid == guid)?))
+    }
+
+    pub(crate) fn set_rules(&mut self, index: usize, auto_download: bool, delete_played: bool, keep: u32) {
+        let Some(sub) = self.subscriptions.get_mut(index) else { return };
+        (sub.auto_download, sub.delete_played, sub.keep) = (auto_download, delete_played, keep);
+        self.apply_keep_limit(index);
+    }
+
+    /// Delete the oldest downloads of a feed beyond its `keep` limit.
+    fn apply_keep_limit(&mut self, index: usize) {
+        let keep = self.subscriptions[index].keep as usize;
+        if keep == 0 { return; }
+        let downloaded: Vec<usize> = (0..self.subscriptions[index].episodes.len()).filter(|&e| self.file(index, e).is_some()).collect();
+        // Episodes are newest first
+        for &episode in downloaded.iter().skip(keep) {
+            match self.delete_download(index, episode) {
+                Ok(()) => log::info!("Deleted old episode {}", self.subscriptions[index].episodes[episode].title),
+                Err(e) => log::warn!("{e}"),
+            }
+        }
+    }
+
+    /// Apply finished refreshes and downloads (not saved yet). Returns messages for the status line; empty if nothing
+    /// changed.
+    pub(crate) fn poll(&mut self) -> Vec<String> {
+        let done = std::mem::take(&mut *self.done.lock().unwrap());
+        let mut messages = Vec::new();
+        for item in done {
+            match item {
+                Done::Refreshed { url, result } => {
+                    self.refreshing.retain(|u| *u != url);
+                    let Some(index) = self.subscriptions.iter().position(|s| s.url == url) else { continue };
+                    match result {
+                        Ok((feed, data)) => messages.extend(self.apply_feed(index, feed, &data)),
+                        Err(e) => {
+                            messages.push(e.clone());
+                            self.subscriptions[index].error = Some(e);
+                        }
+                    }
+                }
+                Done::Downloaded { key, result } => {
+                    self.transfers.remove(&key);
+                    let title = self.find(&key.0, &key.1).map(|(i, e)| self.subscriptions[i].episodes[e].title.clone()).unwrap_or_default();
+                    match result {
+                        Ok(_) => {
+                            messages.push(format!("Downloaded {title}"));
+                            if let Some((index, _)) = self.find(&key.0, &key.1) { self.apply_keep_limit(index); }
+                        }
+                        Err(e) => messages.push(format!("Download of {title} failed: {e}")),
+                    }
+                }
+            }
+        }
+        messages
+    }
+
+    fn apply_feed(&mut self, index: usize, feed: Feed, data: &[u8]) -> Option<String> {
+        let sub = &mut self.subscriptions[index];
+        let first_fetch = sub.episodes.is_empty();
+        let new: Vec<String> = feed.episodes.iter().filter(|e| !sub.episodes.iter().any(|old| old.guid == e.guid)).map(|e| e.guid.clone()).collect();
+        if !feed.title.is_empty() { sub.title = feed.title; }
+        sub.episodes = feed.episodes;
+        sub.error = None;
+        let (title, auto) = (sub.title.clone(), sub.auto_download);
+        let dir = self.show_dir(&self.subscriptions[index]);
+        if let Err(e) = std::fs::create_dir_all(&dir).and_then(|()| std::fs::write(dir.join(FEED_CACHE), data)) { log::warn!("Can't cache feed {title}: {e}"); }
+        let new_count = new.len();
+        if auto {
+            // A new subscription only gets its latest episode rather than the whole back catalogue
+            let wanted = if first_fetch { new.into_iter().take(1).collect() } else { new.clone() };
+            for guid in wanted {
+                if let Some(episode) = self.subscriptions[index].episodes.iter().position(|e| e.guid == guid) { self.download(index, episode); }
+            }
+        }
+        Some(if first_fetch { format!("Subscribed to {title}") } else { format!("{title}: {new_count} new episode(s)") })
+    }
+
+    /// Episodes of a feed not played yet.
+    pub(crate) fn unplayed(&self, index: usize) -> usize {
+        (0..self.subscriptions.get(index).map_or(0, |s| s.episodes.len())).filter(|&e| !self.state(index, e).played).count()
+    }
+}
+
+impl Drop for Podcasts {
+    fn drop(&mut self) {
+        for transfer in self.transfers.values() { transfer.cancel.store(true, Ordering::Relaxed); }
+    }
+}
+
+fn state_path() -> Option<PathBuf> { crate::settings::config_dir().map(|dir| dir.join(STATE_FILE)) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{head, reply, serve};
+    use std::sync::atomic::AtomicUsize;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("podcast-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    const RSS: &str = r#"<?xml version="1.0"?>
+<rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd" xmlns:content="http://purl.org/rss/1.0/modules/content/">
+<channel>
+  <title>Test Show</title>
+  <item><title>Announcement</title><description>No audio here</description></item>
+  <item>
+    <title>First</title><guid>ep-1</guid>
+    <pubDate>Mon, 01 Jan 2024 10:00:00 +0100</pubDate>
+    <itunes:duration>1:02:03</itunes:duration>
+    <description>&lt;p&gt;Intro &amp;amp; notes&lt;/p&gt;&lt;p&gt;Second&amp;nbsp;paragraph&lt;/p&gt;</description>
+    <enclosure url="media/first.MP3?x=1" length="1234" type="audio/mpeg"/>
+  </item>
+  <item>
+    <title>Second</title>
+    <pubDate>Tue, 02 Jan 24 09:00 PST</pubDate>
+    <itunes:duration>95</itunes:duration>
+    <content:encoded><![CDATA[Line<br/>break]]></content:encoded>
+    <enclosure url="https://cdn.example/second" length="0"/>
+  </item>
+</channel>
+</rss>"#;
+
+    #[test]
+    fn rss_feeds_list_audio_items_newest_first() {
+        let feed = parse_feed(RSS, "http://host/shows/feed.xml").unwrap();
+        assert_eq!(feed.title, "Test Show");
+        let [second, first] = &feed.episodes[..] else { panic!("{:?}", feed.episodes) };
+        assert_eq!((first.guid.as_str(), first.url.as_str()), ("ep-1", "http://host/shows/media/first.MP3?x=1"));
+        assert_eq!((first.date_text(), first.published), ("2024-01-01".to_string(), Some(1_704_099_600)));
+        assert_eq!((first.duration, first.length), (Some(Duration::from_secs(3723)), Some(1234)));
+        assert_eq!(first.notes, "Intro & notes\n\nSecond paragraph");
+        assert_eq!(first.file_name(), format!("2024-01-01 First {}.mp3", &hex(&md5(b"ep-1"))[..8]));
+        // Without a guid the enclosure URL identifies the episode
+        assert_eq!((second.guid.as_str(), second.length), ("https://cdn.example/second", None));
+        assert_eq!(second.published, Some(1_704_214_800));
+        assert_eq!(second.notes, "Line\nbreak");
+        assert!(second.file_name().starts_with("2024-01-02 Second ") && second.file_name() != first.file_name());
+    }
+
+    #[test]
+    fn atom_feeds_use_enclosure_links() {
+        let atom = r#"<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Atom Show</title>
+  <entry><id>tag:1</id><title>Old</title><updated>2023-05-01T12:00:00.5-02:30</updated><link rel="enclosure" href="/a.ogg" length="10"/></entry>
+  <entry><id>tag:2</id><title>Text only</title><link rel="alternate" href="/post"/></entry>
+  <entry><id>tag:3</id><title>New</title><published>2023-06-01T00:00:00Z</published><summary>Hi</summary><link rel="enclosure" href="b.m4a"/></entry>
+</feed>"#;
+        let feed = parse_feed(atom, "http://host/feeds/feed.atom").unwrap();
+        assert_eq!(feed.title, "Atom Show");
+        let episodes: Vec<_> = feed.episodes.iter().map(|e| (e.guid.as_str(), e.url.as_str(), e.published)).collect();
+        assert_eq!(episodes, [("tag:3", "http://host/feeds/b.m4a", Some(1_685_577_600)), ("tag:1", "http://host/a.ogg", Some(1_682_951_400))]);
+        assert_eq!(parse_feed("<html/>", "http://host/").err().as_deref(), Some("not an RSS or Atom feed"));
+    }
+
+    #[test]
+    fn feeds_are_fetched_over_http() {
+        let url = serve(|request, stream| match request.target.as_str() {
+            "/feed.xml" => reply(stream, 200, "application/rss+xml", RSS.as_bytes()),
+            _ => reply(stream, 404, "text/plain", b""),
+        });
+        let feed = fetch_feed(&format!("{url}/feed.xml")).unwrap();
+        assert_eq!(feed.episodes[1].url, format!("{url}/media/first.MP3?x=1"));
+        assert!(fetch_feed(&format!("{url}/gone.xml")).err().unwrap().contains("404"));
+    }
+
+    /// Serve `data` at any path, cutting off the first response after `first` bytes.
+    fn episode_server(data: Vec<u8>, first: usize, ranges: bool) -> String {
+        let requests = AtomicUsize::new(0);
+        serve(move |request, stream| {
+            let from: usize = request.header("range").and_then(|r| r.strip_prefix("bytes=")?.strip_suffix('-')?.parse().ok()).unwrap_or(0);
+            let cut = if requests.fetch_add(1, Ordering::SeqCst) == 0 { first } else { data.len() };
+            if ranges && from >= data.len() { return reply(stream, 416, "text/plain", b""); }
+            let (status, from) = if ranges && from > 0 { (206, from) } else { (200, 0) };
+            head(stream, status, &[("Content-Length", &(data.len() - from).to_string())])?;
+            stream.write_all(&data[from..cut.max(from)])
+        })
+    }
+
+    fn episode(url: String, length: Option<u64>) -> Episode {
+        Episode { guid: "g".into(), title: "Episode".into(), url: format!("{url}/ep.mp3"), length, ..Default::default() }
+    }
+
+    #[test]
+    fn interrupted_downloads_resume() {
+        for ranges in [true, false] {
+            let data: Vec<u8> = (0..200_000).map(|i| (i % 253) as u8).collect();
+            let dir = temp_dir(&format!("resume-{ranges}"));
+            let ep = episode(episode_server(data.clone(), 70_000, ranges), None);
+            let path = dir.join(ep.file_name());
+            let error = download_episode(&ep, &dir, &|_, _| true).unwrap_err();
+            assert!(error.starts_with(&ep.url), "{error}");
+            assert_eq!(std::fs::metadata(path.with_extension("mp3.part")).unwrap().len(), 70_000);
+            let seen = Mutex::new(Vec::new());
+            assert_eq!(download_episode(&ep, &dir, &|received, total| { seen.lock().unwrap().push((received, total)); true }).unwrap(), path);
+            assert_eq!(std::fs::read(&path).unwrap(), data, "ranges: {ranges}");
+            let seen = seen.into_inner().unwrap();
+            // Progress counts what was already there when the server resumed, and always knows the total
+            assert_eq!(seen.first(), Some(&(if ranges { 70_000 } else { 0 }, Some(200_000))));
+            assert_eq!(seen.last(), Some(&(200_000, Some(200_000))));
+            assert!(!path.with_extension("mp3.part").exists());
+            let _ = std::fs::remove_dir_all(&dir);
+        }
+    }
+
+    #[test]
+    fn complete_part_files_are_finished_without_downloading_again() {
+        let data = vec![7u8; 1000];
+        let dir = temp_dir("complete");
+        std::fs::create_dir_all(&dir).unwrap();
+        let ep = episode(episode_server(data.clone(), 0, true), Some(1000));
+        std::fs::write(dir.join(format!("{}.part", ep.file_name())), &data).unwrap();
+        assert_eq!(std::fs::read(download_episode(&ep, &dir, &|_, _| true).unwrap()).unwrap(), data);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn cancelled_and_failed_downloads_are_errors() {
+        let dir = temp_dir("cancel");
+        let url = episode_server(vec![1; 1000], 1000, true);
+        assert_eq!(download_episode(&episode(url.clone(), None), &dir, &|_, _| false).unwrap_err(), "Download cancelled");
+        let missing = serve(|_, stream| reply(stream, 404, "text/plain", b""));
+        assert!(download_episode(&episode(missing, None), &dir, &|_, _| true).unwrap_err().contains("404"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,475 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use symphonia::core::io::MediaSource;
+
+use crate::net::{agent, redact_url};
+use crate::record::Recorder;
+
+// Internet radio and other progressive HTTP audio. A download thread fills a ring buffer that the decoder reads
+// from. Dropped or stalled connections are reopened with backoff (files resume with a Range request), and ICY
+// metadata (Shoutcast/Icecast) is stripped from the audio and published as the live title. The same buffer carries
+// HLS audio, fed by the segment fetcher in `hls`. What the decoder reads can also be saved to disk (see `record`).
+
+const BUFFER_BYTES: usize = 1 << 20;
+/// Time without data, while the decoder is waiting for it, after which the connection is reopened.
+const STALL_TIMEOUT: Duration = Duration::from_secs(15);
+/// Time the decoder waits for data before giving up on the stream.
+pub(crate) const READ_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// What the server says about the stream, updated live.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct StreamMeta {
+    /// Station name (`icy-name`).
+    pub station: Option<String>,
+    /// Current song (`StreamTitle`), usually "Artist - Title".
+    pub title: Option<String>,
+    pub content_type: Option<String>,
+    /// Set while the connection is being reopened.
+    pub status: Option<String>,
+    /// Format the decoder was told to expect (a file extension).
+    pub format: Option<String>,
+}
+
+/// Shared view of a stream's `StreamMeta`, and its recording if one is running.
+#[derive(Clone, Default)]
+pub(crate) struct StreamInfo {
+    meta: Arc<Mutex<StreamMeta>>,
+    recorder: Arc<Mutex<Option<Recorder>>>,
+}
+
+impl StreamInfo {
+    pub(crate) fn get(&self) -> StreamMeta { self.meta.lock().unwrap().clone() }
+    pub(crate) fn update(&self, f: impl FnOnce(&mut StreamMeta)) { f(&mut self.meta.lock().unwrap()); }
+
+    /// Start saving the stream's audio, as it is played, to files in `dir`.
+    pub(crate) fn start_recording(&self, dir: &Path) -> Result<(), String> {
+        let mut recorder = self.recorder.lock().unwrap();
+        if recorder.is_some() { return Ok(()); }
+        let meta = self.get();
+        if !matches!(meta.format.as_deref(), Some("mp3" | "aac")) { return Err("Only MP3 and AAC streams can be recorded".into()); }
+        *recorder = Some(Recorder::new(dir, meta.station, meta.title)?);
+        Ok(())
+    }
+
+    /// Stop recording. Returns the files written.
+    pub(crate) fn stop_recording(&self) -> Vec<PathBuf> { self.recorder.lock().unwrap().take().map(Recorder::finish).unwrap_or_default() }
+
+    pub(crate) fn is_recording(&self) -> bool { self.recorder.lock().unwrap().is_some() }
+
+    /// The file being recorded to, or why recording failed.
+    pub(crate) fn recording_status(&self) -> Result<Option<PathBuf>, String> {
+        match self.recorder.lock().unwrap().as_ref().map(Recorder::status) {
+            Some(Err(e)) => Err(e.to_string()),
+            Some(Ok(file)) => Ok(file.map(Path::to_path_buf)),
+            None => Ok(None),
+        }
+    }
+
+    fn record(&self, data: &[u8], then_title: Option<&str>) {
+        if let Some(recorder) = self.recorder.lock().unwrap().as_mut() {
+            recorder.write(data);
+            if let Some(title) = then_title { recorder.set_title(title); }
+        }
+    }
+}
+
+struct Ring {
+    buf: VecDeque<u8>,
+    /// The reader is gone; downloads stop.
+    closed: bool,
+    /// Everything has arrived (files only; live streams never end).
+    eof: bool,
+    /// Audio bytes received over all connections, used to resume files.
+    received: u64,
+    /// Audio bytes handed to the decoder.
+    consumed: u64,
+    /// Titles that take effect once the decoder reaches a stream offset (in `received` terms).
+    titles: VecDeque<(u64, String)>,
+    /// Current connection; older download threads stop once they notice they've been replaced.
+    generation: u64,
+    /// How the current connection ended: `Ok` at the end of the body.
+    ended: Option<Result<(), String>>,
+    last_data: Instant,
+    /// The feeder gave up; the decoder gets this once the buffer is drained.
+    error: Option<String>,
+}
+
+/// The buffer between a download thread and the decoder.
+pub(crate) struct Shared {
+    url: String,
+    ring: Mutex<Ring>,
+    cond: Condvar,
+    info: StreamInfo,
+}
+
+impl Shared {
+    /// Append audio, waiting while the buffer is full. Returns `false` once this connection should stop.
+    /// `generation` identifies the connection; feeders that never replace theirs pass 0.
+    pub(crate) fn push(&self, mut data: &[u8], generation: u64) -> bool {
+        let mut ring = self.ring.lock().unwrap();
+        while !data.is_empty() {
+            if ring.closed || ring.generation != generation { return false; }
+            let space = BUFFER_BYTES - ring.buf.len();
+            if space == 0 { ring = self.cond.wait(ring).unwrap(); continue; }
+            let n = space.min(data.len());
+            ring.buf.extend(&data[..n]);
+            ring.received += n as u64;
+            ring.last_data = Instant::now();
+            data = &data[n..];
+            self.cond.notify_all();
+        }
+        true
+    }
+
+    /// No more data will come: the stream ended, or failed with `error`.
+    pub(crate) fn finish(&self, error: Option<String>) {
+        let mut ring = self.ring.lock().unwrap();
+        ring.eof = true;
+        ring.error = error;
+        self.cond.notify_all();
+    }
+
+    /// Publish `title` when the decoder reaches the audio pushed next, rather than while it is still buffered.
+    pub(crate) fn title_from_here(&self, title: String) {
+        let mut ring = self.ring.lock().unwrap();
+        let at = ring.received;
+        ring.titles.retain(|(offset, _)| *offset < at);
+        if ring.titles.back().is_some_and(|(_, t)| *t == title) { return; }
+        ring.titles.push_back((at, title));
+    }
+
+    /// The decoder has gone away.
+    pub(crate) fn is_closed(&self) -> bool { self.ring.lock().unwrap().closed }
+
+    pub(crate) fn info(&self) -> &StreamInfo { &self.info }
+}
+
+/// An open HTTP response body, past the headers.
+struct Connection {
+    body: ureq::BodyReader<'static>,
+    /// Audio bytes between ICY metadata blocks.
+    metaint: Option<usize>,
+    /// Remaining length for files; `None` for live streams.
+    length: Option<u64>,
+    icy: bool,
+    station: Option<String>,
+    content_type: Option<String>,
+    /// Bytes to drop because the server ignored a Range request.
+    skip: u64,
+}
+
+fn connect(url: &str, from: u64) -> Result<Connection, String> {
+    let mut request = agent().get(url).header("Icy-MetaData", "1");
+    if from > 0 { request = request.header("Range", format!("bytes={from}-")); }
+    let response = request.call().map_err(|e| e.to_string())?;
+    let header = |name: &str| response.headers().get(name).and_then(|v| v.to_str().ok()).map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+    let metaint = header("icy-metaint").and_then(|v| v.parse().ok()).filter(|&n| n > 0);
+    let station = header("icy-name");
+    let icy = metaint.is_some() || station.is_some();
+    let (length, content_type) = (header("content-length").and_then(|v| v.parse().ok()), header("content-type"));
+    let skip = if from > 0 && response.status().as_u16() != 206 { from } else { 0 };
+    Ok(Connection { body: response.into_body().into_reader(), metaint, length, icy, station, content_type, skip })
+}
+
+/// Extract `StreamTitle` from an ICY metadata block (`StreamTitle='Artist - Title';StreamUrl='';`).
+fn stream_title(block: &[u8]) -> Option<String> {
+    let block = block.split(|&b| b == 0).next().unwrap_or_default();
+    let text = match std::str::from_utf8(block) {
+        Ok(text) => text.to_string(),
+        Err(_) => block.iter().map(|&b| b as char).collect(),
+    };
+    let rest = &text[text.find("StreamTitle='")? + "StreamTitle='".len()..];
+    // Titles may contain quotes, so the value runs to the `';` that ends the field
+    let title = rest.find("';").map_or(rest.trim_end_matches('\''), |end| &rest[..end]).trim();
+    (!title.is_empty()).then(|| title.to_string())
+}
+
+/// Copy a connection's audio into the ring buffer until it ends or is replaced.
+fn pump(shared: &Shared, conn: &mut Connection, generation: u64) -> Result<(), String> {
+    let mut buf = vec![0u8; 16 * 1024];
+    let mut until_meta = conn.metaint;
+    loop {
+        let want = until_meta.map_or(buf.len(), |m| m.min(buf.len()));
+        let n = conn.body.read(&mut buf[..want]).map_err(|e| e.to_string())?;
+        if n == 0 { return Ok(()); }
+        let dropped = conn.skip.min(n as u64) as usize;
+        conn.skip -= dropped as u64;
+        if !shared.push(&buf[dropped..n], generation) { return Ok(()); }
+        if let Some(left) = until_meta.as_mut() {
+            *left -= n;
+            if *left == 0 {
+                let mut len = [0u8; 1];
+                conn.body.read_exact(&mut len).map_err(|e| e.to_string())?;
+                let mut block = vec![0u8; len[0] as usize * 16];
+                conn.body.read_exact(&mut block).map_err(|e| e.to_string())?;
+                if let Some(title) = stream_title(&block) { shared.title_from_here(title); }
+                until_meta = conn.metaint;
+            }
+        }
+    }
+}
+
+enum Outcome { Closed, Ended(Result<(), String>), Stalled }
+
+/// Wait for the current connection to end, stall or be abandoned by the reader.
+fn watch(shared: &Shared) -> Outcome {
+    let mut ring = shared.ring.lock().unwrap();
+    loop {
+        if ring.closed { return Outcome::Closed; }
+        if let Some(result) = ring.ended.take() { return Outcome::Ended(result); }
+        if ring.buf.is_empty() && ring.last_data.elapsed() > STALL_TIMEOUT { return Outcome::Stalled; }
+        ring = shared.cond.wait_timeout(ring, Duration::from_secs(1)).unwrap().0;
+    }
+}
+
+/// Keep the ring buffer fed: run connections one after another until the reader goes away or a file is complete.
+fn supervise(shared: Arc<Shared>, first: Connection) {
+    let (icy, total) = (first.icy, first.length);
+    let mut next = Some(first);
+    let mut backoff = Duration::from_millis(500);
+    loop {
+        let (generation, from) = {
+            let mut ring = shared.ring.lock().unwrap();
+            if ring.closed { return; }
+            ring.generation += 1;
+            ring.ended = None;
+            ring.last_data = Instant::now();
+            (ring.generation, if total.is_some() { ring.received } else { 0 })
+        };
+        let connection = match next.take() { Some(c) => Ok(c), None => connect(&shared.url, from) };
+        let reason = match connection {
+            Ok(mut conn) => {
+                shared.info.update(|m| m.status = None);
+                let received_before = from;
+                let thread_shared = shared.clone();
+                let spawned = std::thread::Builder::new().name("radio-download".into()).spawn(move || {
+                    let result = pump(&thread_shared, &mut conn, generation);
+                    let mut ring = thread_shared.ring.lock().unwrap();
+                    if ring.generation == generation { ring.ended = Some(result); thread_shared.cond.notify_all(); }
+                });
+                if let Err(e) = spawned { log::warn!("{}: {e}", redact_url(&shared.url)); return; }
+                let outcome = watch(&shared);
+                let (received, complete) = {
+                    let ring = shared.ring.lock().unwrap();
+                    (ring.received, total.is_some_and(|t| ring.received >= t))
+                };
+                if received > received_before { backoff = Duration::from_millis(500); }
+                match outcome {
+                    Outcome::Closed => return,
+                    // A file (or a plain HTTP stream without ICY headers) that has finished
+                    Outcome::Ended(Ok(())) if complete || (!icy && total.is_none()) => return shared.finish(None),
+                    Outcome::Ended(Ok(())) => "the server closed the stream".to_string(),
+                    Outcome::Ended(Err(e)) => e,
+                    Outcome::Stalled => format!("no data for {}s", STALL_TIMEOUT.as_secs()),
+                }
+            }
+            Err(e) => e,
+        };
+        log::warn!("{}: {reason}; reconnecting in {:.1}s", redact_url(&shared.url), backoff.as_secs_f32());
+        shared.info.update(|m| m.status = Some(format!("Reconnecting ({reason})")));
+        let retry_at = Instant::now() + backoff;
+        while Instant::now() < retry_at {
+            if shared.ring.lock().unwrap().closed { return; }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// The audio of an HTTP stream as a (non-seekable) byte stream for the decoder.
+pub(crate) struct HttpStream {
+    shared: Arc<Shared>,
+}
+
+impl HttpStream {
+    /// Connect to `url`. Errors from the first connection are returned; later drops are retried in the background.
+    pub(crate) fn open(url: &str) -> Result<(Self, StreamInfo), String> {
+        let first = connect(url, 0).map_err(|e| format!("Failed to open {}: {e}", redact_url(url)))?;
+        let info = StreamInfo::default();
+        info.update(|m| { m.station = first.station.clone(); m.content_type = first.content_type.clone(); });
+        let stream = Self::fed_by(url, info.clone(), move |shared| supervise(shared, first))?;
+        Ok((stream, info))
+    }
+
+    /// A stream whose data comes from `feeder`, run on its own thread (see `Shared::push` and `Shared::finish`).
+    pub(crate) fn fed_by(url: &str, info: StreamInfo, feeder: impl FnOnce(Arc<Shared>) + Send + 'static) -> Result<Self, String> {
+        let ring = Ring { buf: VecDeque::new(), closed: false, eof: false, received: 0, consumed: 0, titles: VecDeque::new(), generation: 0, ended: None, last_data: Instant::now(), error: None };
+        let shared = Arc::new(Shared { url: url.to_string(), ring: Mutex::new(ring), cond: Condvar::new(), info });
+        let feeder_shared = shared.clone();
+        std::thread::Builder::new().name("radio".into()).spawn(move || feeder(feeder_shared))
+            .map_err(|e| format!("Failed to start download: {e}"))?;
+        Ok(Self { shared })
+    }
+}
+
+impl Read for HttpStream {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let started = Instant::now();
+        let mut ring = self.shared.ring.lock().unwrap();
+        loop {
+            if !ring.buf.is_empty() {
+                let n = out.len().min(ring.buf.len());
+                for (o, b) in out.iter_mut().zip(ring.buf.drain(..n)) { *o = b; }
+                let start = ring.consumed;
+                ring.consumed += n as u64;
+                // Recordings are cut where each title takes effect
+                let mut recorded = 0;
+                while let Some((at, title)) = ring.titles.front().filter(|(at, _)| *at <= ring.consumed).cloned() {
+                    ring.titles.pop_front();
+                    let upto = (at.saturating_sub(start) as usize).clamp(recorded, n);
+                    self.shared.info.record(&out[recorded..upto], Some(&title));
+                    recorded = upto;
+                    if self.shared.info.get().title.as_deref() != Some(&title) {
+                        log::info!("{}: now playing {title}", redact_url(&self.shared.url));
+                        self.shared.info.update(|m| m.title = Some(title));
+                    }
+                }
+                self.shared.info.record(&out[recorded..n], None);
+                self.shared.cond.notify_all();
+                return Ok(n);
+            }
+            if let Some(e) = &ring.error { return Err(io::Error::other(e.clone())); }
+            if ring.eof { return Ok(0); }
+            if started.elapsed() > READ_TIMEOUT {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, format!("no data from the server for {}s", READ_TIMEOUT.as_secs())));
+            }
+            ring = self.shared.cond.wait_timeout(ring, Duration::from_millis(500)).unwrap().0;
+        }
+    }
+}
+
+impl Seek for HttpStream {
+    fn seek(&mut self, _: SeekFrom) -> io::Result<u64> { Err(io::Error::new(io::ErrorKind::Unsupported, "network streams can't seek")) }
+}
+
+impl MediaSource for HttpStream {
+    fn is_seekable(&self) -> bool { false }
+    fn byte_len(&self) -> Option<u64> { None }
+}
+
+impl Drop for HttpStream {
+    fn drop(&mut self) {
+        self.shared.ring.lock().unwrap().closed = true;
+        self.shared.cond.notify_all();
+    }
+}
+
+/// File extension Symphonia should assume for a stream's `Content-Type`.
+pub(crate) fn content_type_extension(content_type: &str) -> Option<&'static str> {
+    let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    Some(match mime.as_str() {
+        "audio/mpeg" | "audio/mp3" | "audio/mpeg3" | "audio/x-mpeg" => "mp3",
+        "audio/aac" | "audio/aacp" | "audio/x-aac" => "aac",
+        "application/ogg" | "audio/ogg" | "audio/vorbis" | "audio/opus" => "ogg",
+        "audio/flac" | "audio/x-flac" => "flac",
+        "audio/wav" | "audio/x-wav" | "audio/wave" => "wav",
+        "audio/mp4" | "audio/x-m4a" => "m4a",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{head, reply, serve};
+    use std::io::Write;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn audio(len: usize) -> Vec<u8> { (0..len).map(|i| (i * 7 % 251) as u8).collect() }
+
+    /// ICY metadata block announcing `title`: a length byte in 16-byte units, then the zero-padded text.
+    fn meta_block(title: &str) -> Vec<u8> {
+        let mut text = format!("StreamTitle='{title}';StreamUrl='';").into_bytes();
+        text.resize(text.len().div_ceil(16) * 16, 0);
+        let mut block = vec![(text.len() / 16) as u8];
+        block.extend(text);
+        block
+    }
+
+    #[test]
+    fn icy_metadata_is_stripped_and_titles_follow_playback() {
+        let data = audio(48);
+        let body = data.clone();
+        let url = serve(move |request, stream| {
+            assert_eq!((request.method.as_str(), request.target.as_str()), ("GET", "/live"));
+            assert_eq!(request.header("icy-metadata"), Some("1"));
+            head(stream, 200, &[("Content-Type", "audio/mpeg"), ("icy-name", "Test FM"), ("icy-metaint", "16")])?;
+            for (chunk, title) in body.chunks(16).zip(["It's One", "Two", ""]) {
+                stream.write_all(chunk)?;
+                stream.write_all(&if title.is_empty() { vec![0] } else { meta_block(title) })?;
+            }
+            // A live stream stays open
+            std::thread::sleep(Duration::from_secs(5));
+            Ok(())
+        });
+        let (mut stream, info) = HttpStream::open(&format!("{url}/live")).unwrap();
+        assert_eq!(info.get().station.as_deref(), Some("Test FM"));
+        assert_eq!(info.get().content_type.as_deref(), Some("audio/mpeg"));
+        let mut read = vec![0u8; 48];
+        stream.read_exact(&mut read).unwrap();
+        assert_eq!(read, data);
+        // "Two" was announced before the last 16 bytes, and the quote inside the first title didn't end it
+        assert_eq!(info.get().title.as_deref(), Some("Two"));
+        assert_eq!(stream_title(&meta_block("It's One")[1..]).as_deref(), Some("It's One"));
+    }
+
+    /// Serve `data` as a file, dropping the first connection halfway. Returns the URL and the Range headers seen.
+    fn flaky_file(data: Vec<u8>, honour_range: bool) -> (String, Arc<Mutex<Vec<Option<String>>>>) {
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let seen = ranges.clone();
+        let requests = AtomicUsize::new(0);
+        let url = serve(move |request, stream| {
+            seen.lock().unwrap().push(request.header("range").map(str::to_string));
+            let from = request.header("range").and_then(|r| r.strip_prefix("bytes=")?.strip_suffix('-')?.parse().ok()).unwrap_or(0);
+            if requests.fetch_add(1, Ordering::SeqCst) == 0 {
+                head(stream, 200, &[("Content-Type", "audio/mpeg"), ("Content-Length", &data.len().to_string())])?;
+                return stream.write_all(&data[..data.len() / 2]);
+            }
+            if honour_range && from > 0 {
+                head(stream, 206, &[("Content-Length", &(data.len() - from).to_string())])?;
+                return stream.write_all(&data[from..]);
+            }
+            reply(stream, 200, "audio/mpeg", &data)
+        });
+        (url, ranges)
+    }
+
+    #[test]
+    fn dropped_files_resume_where_they_stopped() {
+        for honour_range in [true, false] {
+            let data = audio(40_000);
+            let (url, ranges) = flaky_file(data.clone(), honour_range);
+            let (mut stream, info) = HttpStream::open(&url).unwrap();
+            let mut read = Vec::new();
+            stream.read_to_end(&mut read).unwrap();
+            // A server that ignores the Range header resends everything, and what was already played is dropped
+            assert_eq!(read, data, "honour_range: {honour_range}");
+            assert_eq!(*ranges.lock().unwrap(), [None, Some("bytes=20000-".to_string())]);
+            assert_eq!(info.get().status, None);
+        }
+    }
+
+    #[test]
+    fn failed_first_connection_is_an_error() {
+        let url = serve(|_, stream| reply(stream, 404, "text/plain", b"no such station"));
+        let error = HttpStream::open(&format!("{url}/missing")).err().unwrap();
+        assert!(error.starts_with(&format!("Failed to open {url}/missing")), "{error}");
+    }
+
+    #[test]
+    fn feeder_errors_reach_the_decoder_after_the_buffered_audio() {
+        let mut stream = HttpStream::fed_by("test", StreamInfo::default(), |shared| {
+            shared.push(b"abc", 0);
+            shared.finish(Some("segment missing".into()));
+        }).unwrap();
+        let mut read = [0u8; 3];
+        stream.read_exact(&mut read).unwrap();
+        assert_eq!(&read, b"abc");
+        assert_eq!(stream.read(&mut read).unwrap_err().to_string(), "segment missing");
+    }
+}
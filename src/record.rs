@@ -0,0 +1,344 @@
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::decode::open_stream;
+use crate::id3::Id3Tag;
+use crate::settings::RecordSchedule;
+
+// Recording internet radio as it arrives, without re-encoding. MP3 and AAC (ADTS) streams are made of
+// self-contained frames, so the recording is cut at the first frame after each title change and every file gets
+// an ID3 tag. Recordings go to a folder inside the music folder so the library picks them up.
+
+/// Folder (inside the music folder) that recordings are saved to.
+pub(crate) const RECORDINGS_DIR: &str = "Recordings";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Codec { Mp3, Aac }
+
+impl Codec {
+    fn extension(self) -> &'static str { match self { Codec::Mp3 => "mp3", Codec::Aac => "aac" } }
+}
+
+/// Whether an MP3 or ADTS frame header starts at `data[0]`.
+fn frame_header(data: &[u8]) -> Option<Codec> {
+    let [0xff, b1, b2, ..] = *data else { return None };
+    if b1 & 0xf6 == 0xf0 && b2 >> 2 & 0x0f < 13 { return Some(Codec::Aac); }
+    // MPEG audio: sync, a layer, and a valid bitrate and sample rate
+    (b1 & 0xe0 == 0xe0 && b1 >> 1 & 3 != 0 && !matches!(b2 >> 4, 0 | 15) && b2 >> 2 & 3 != 3).then_some(Codec::Mp3)
+}
+
+/// Length of the frame whose header starts at `data[0]`, if it makes sense.
+fn frame_length(data: &[u8]) -> Option<usize> {
+    let [0xff, b1, b2, b3, b4, b5, ..] = *data else { return None };
+    if frame_header(data)? == Codec::Aac {
+        let length = (b3 as usize & 3) << 11 | (b4 as usize) << 3 | (b5 as usize) >> 5;
+        return (length > 7).then_some(length);
+    }
+    let (version, layer, padding) = (b1 >> 3 & 3, b1 >> 1 & 3, (b2 >> 1 & 1) as usize);
+    // Version 1 is reserved
+    if version == 1 { return None; }
+    const KBPS: [[u16; 14]; 5] = [
+        [32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448],
+        [32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384],
+        [32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320],
+        [32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256],
+        [8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+    ];
+    let row = match (version == 3, layer) { (true, 3) => 0, (true, 2) => 1, (true, _) => 2, (false, 3) => 3, (false, _) => 4 };
+    let bitrate = KBPS[row][(b2 >> 4) as usize - 1] as usize * 1000;
+    let rate = [44100, 48000, 32000][(b2 >> 2 & 3) as usize] >> (3 - version).min(2);
+    Some(match (layer, version) {
+        (3, _) => (12 * bitrate / rate + padding) * 4,
+        (1, 0 | 2) => 72 * bitrate / rate + padding,
+        _ => 144 * bitrate / rate + padding,
+    })
+}
+
+/// Offset of the first frame of `codec` in `data`. Audio can contain a frame sync by chance, so a header only counts
+/// when the next frame's header follows where it ends (or `data` ends first).
+fn frame_start(data: &[u8], codec: Codec) -> Option<usize> {
+    (0..data.len().saturating_sub(2)).find(|&i| {
+        frame_header(&data[i..]) == Some(codec)
+            && frame_length(&data[i..]).is_some_and(|n| data.len() < i + n + 3 || frame_header(&data[i + n..]) == Some(codec))
+    })
+}
+
+/// Writes one stream's audio to files, starting a new file whenever the title changes.
+pub(crate) struct Recorder {
+    dir: PathBuf,
+    station: Option<String>,
+    title: Option<String>,
+    codec: Option<Codec>,
+    file: Option<(PathBuf, BufWriter<File>)>,
+    /// The title changed; the next frame starts a new file.
+    split: bool,
+    files: Vec<PathBuf>,
+    error: Option<String>,
+}
+
+impl Recorder {
+    pub(crate) fn new(dir: &Path, station: Option<String>, title: Option<String>) -> Result<Self, String> {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Can't create {}: {e}", dir.display()))?;
+        Ok(Self { dir: dir.to_path_buf(), station, title, codec: None, file: None, split: false, files: Vec::new(), error: None })
+    }
+
+    /// Add received audio.
+    pub(crate) fn write(&mut self, mut data: &[u8]) {
+        if self.error.is_some() { return; }
+        let codec = match self.codec {
+            Some(codec) => codec,
+            None => {
+                // Find out what the stream carries from its first frame
+                let Some(start) = (0..data.len()).find(|&i| frame_header(&data[i..]).is_some()) else { return };
+                data = &data[start..];
+                *self.codec.insert(frame_header(data).unwrap_or(Codec::Mp3))
+            }
+        };
+        if self.split && let Some(start) = frame_start(data, codec) {
+            let (tail, rest) = data.split_at(start);
+            self.append(tail);
+            self.close();
+            self.split = false;
+            data = rest;
+        }
+        if self.file.is_none() && !data.is_empty() && let Err(e) = self.open(codec) {
+            log::warn!("{e}");
+            self.error = Some(e);
+            return;
+        }
+        self.append(data);
+    }
+
+    /// The stream's title changed to `title`.
+    pub(crate) fn set_title(&mut self, title: &str) {
+        if self.title.as_deref() == Some(title) { return; }
+        self.title = Some(title.to_string());
+        if self.file.is_some() { self.split = true; }
+    }
+
+    fn append(&mut self, data: &[u8]) {
+        let Some((path, file)) = self.file.as_mut() else { return };
+        if let Err(e) = file.write_all(data) {
+            let e = format!("Recording to {} failed: {e}", path.display());
+            log::warn!("{e}");
+            self.error = Some(e);
+            self.close();
+        }
+    }
+
+    fn open(&mut self, codec: Codec) -> Result<(), String> {
+        let now = LocalTime::now();
+        let (artist, song) = match self.title.as_deref().map(|t| t.split_once(" - ").map_or((None, t), |(a, s)| (Some(a), s))) {
+            Some((artist, song)) => (artist, song.to_string()),
+            None => (None, format!("{} {}", self.station.as_deref().unwrap_or("Radio"), now.stamp())),
+        };
+        let stem = sanitize(&match artist { Some(artist) => format!("{artist} - {song}"), None => song.clone() });
+        let mut path = self.dir.join(format!("{stem}.{}", codec.extension()));
+        for n in 2.. {
+            if !path.exists() { break; }
+            path = self.dir.join(format!("{stem} ({n}).{}", codec.extension()));
+        }
+        let mut tag = Id3Tag::default();
+        tag.set_text("TIT2", &song);
+        tag.set_text("TPE1", artist.unwrap_or_default());
+        tag.set_text("TRSN", self.station.as_deref().unwrap_or_default());
+        tag.set_text("TDRC", &now.date());
+        let mut file = BufWriter::new(File::create(&path).map_err(|e| format!("Can't create {}: {e}", path.display()))?);
+        file.write_all(&tag.render()).map_err(|e| format!("Recording to {} failed: {e}", path.display()))?;
+        log::info!("Recording to {}", path.display());
+        self.files.push(path.clone());
+        self.file = Some((path, file));
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        if let Some((path, mut file)) = self.file.take() && let Err(e) = file.flush() { log::warn!("Recording to {} failed: {e}", path.display()); }
+    }
+
+    /// The file being written, or why recording stopped.
+    pub(crate) fn status(&self) -> Result<Option<&Path>, &str> {
+        match &self.error {
+            Some(e) => Err(e),
+            None => Ok(self.file.as_ref().map(|(p, _)| p.as_path())),
+        }
+    }
+
+    /// Close the current file. Returns the files written.
+    pub(crate) fn finish(mut self) -> Vec<PathBuf> {
+        self.close();
+        self.files
+    }
+}
+
+/// `name` with characters that aren't allowed in file names replaced.
+pub(crate) fn sanitize(name: &str) -> String {
+    let cleaned: String = name.chars().map(|c| if matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control() { '_' } else { c }).collect();
+    let cleaned = cleaned.trim().trim_matches('.').to_string();
+    if cleaned.is_empty() { "Recording".to_string() } else { cleaned.chars().take(120).collect() }
+}
+
+/// Record `url` for `duration` without playing it, or until `stop` is set. Returns the files written.
+pub fn record_stream(url: &str, dir: &Path, duration: Duration, stop: &AtomicBool) -> Result<Vec<PathBuf>, String> {
+    let (mut stream, info, _) = open_stream(Path::new(url))?;
+    info.start_recording(dir)?;
+    let until = Instant::now() + duration;
+    // Nothing decodes the stream, so just keep its buffer drained
+    let mut buf = vec![0u8; 64 * 1024];
+    let result = loop {
+        if Instant::now() >= until || stop.load(Ordering::Relaxed) { break Ok(()); }
+        match stream.read(&mut buf) {
+            Ok(0) => break Ok(()),
+            Ok(_) => {}
+            Err(e) => break Err(e.to_string()),
+        }
+        if let Err(e) = info.recording_status() { break Err(e); }
+    };
+    let files = info.stop_recording();
+    result.map(|()| files)
+}
+
+/// A scheduled recording that is running.
+struct ScheduledRun {
+    schedule: RecordSchedule,
+    stop: Arc<AtomicBool>,
+    done: Arc<AtomicBool>,
+}
+
+/// Starts scheduled recordings when their time comes. Each runs on its own thread, independent of playback.
+#[derive(Default)]
+pub(crate) struct Scheduler {
+    running: Vec<ScheduledRun>,
+    /// Occurrences already started, so a finished one isn't started again the same day.
+    started: Vec<(RecordSchedule, i64)>,
+    /// Files of finished recordings, until `take_recorded`.
+    recorded: Arc<Mutex<Vec<PathBuf>>>,
+}
+
+impl Scheduler {
+    /// Start recordings that are due. Returns messages for the status line.
+    pub(crate) fn poll(&mut self, schedules: &[RecordSchedule], dir: &Path) -> Vec<String> {
+        let now = LocalTime::now();
+        let mut messages = Vec::new();
+        self.running.retain(|run| !run.done.load(Ordering::Relaxed));
+        for schedule in schedules {
+            // An occurrence that started yesterday may still be running past midnight
+            for day in [now.day, now.day - 1] {
+                let into = (now.day - day) * 24 * 60 + now.minute_of_day as i64 - schedule.start_minute as i64;
+                if !(0..schedule.minutes as i64).contains(&into) || self.started.contains(&(schedule.clone(), day)) { continue; }
+                self.started.push((schedule.clone(), day));
+                let remaining = Duration::from_secs((schedule.minutes as i64 - into) as u64 * 60 - now.second as u64);
+                let (stop, done) = (Arc::new(AtomicBool::new(false)), Arc::new(AtomicBool::new(false)));
+                let (url, dir, thread_stop, thread_done, recorded) = (schedule.url.clone(), dir.to_path_buf(), stop.clone(), done.clone(), self.recorded.clone());
+                let label = schedule.label().to_string();
+                let spawned = std::thread::Builder::new().name("record".into()).spawn(move || {
+                    match record_stream(&url, &dir, remaining, &thread_stop) {
+                        Ok(files) => {
+                            log::info!("Scheduled recording of {label} finished ({} file(s))", files.len());
+                            recorded.lock().unwrap().extend(files);
+                        }
+                        Err(e) => log::warn!("Scheduled recording of {label} failed: {e}"),
+                    }
+                    thread_done.store(true, Ordering::Relaxed);
+                });
+                match spawned {
+                    Ok(_) => {
+                        messages.push(format!("Recording {} for {} min", schedule.label(), remaining.as_secs().div_ceil(60)));
+                        self.running.push(ScheduledRun { schedule: schedule.clone(), stop, done });
+                    }
+                    Err(e) => messages.push(format!("Can't start recording {}: {e}", schedule.label())),
+                }
+            }
+        }
+        // Schedules removed while recording stop
+        for run in self.running.iter().filter(|run| !schedules.contains(&run.schedule)) { run.stop.store(true, Ordering::Relaxed); }
+        self.started.retain(|(_, day)| *day >= now.day - 1);
+        messages
+    }
+
+    /// The files of the recordings finished since the last call.
+    pub(crate) fn take_recorded(&mut self) -> Vec<PathBuf> { std::mem::take(&mut *self.recorded.lock().unwrap()) }
+
+    /// Labels of the schedules recording right now.
+    pub(crate) fn active(&self) -> Vec<String> {
+        self.running.iter().filter(|run| !run.done.load(Ordering::Relaxed)).map(|run| format!("{} (since {})", run.schedule.label(), run.schedule.start_text())).collect()
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        for run in &self.running { run.stop.store(true, Ordering::Relaxed); }
+    }
+}
+
+/// The local wall-clock time, for naming recordings and running schedules.
+struct LocalTime { year: i32, month: u32, day_of_month: u32, minute_of_day: u32, second: u32, day: i64 }
+
+impl LocalTime {
+    fn now() -> Self {
+        let secs = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs()) as libc::time_t;
+        // SAFETY: `tm` is plain data that localtime fills in; both pointers are valid for the call
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        #[cfg(unix)]
+        unsafe { libc::localtime_r(&secs, &mut tm); }
+        #[cfg(windows)]
+        unsafe { libc::localtime_s(&mut tm, &secs); }
+        let offset = days_from_civil(tm.tm_year + 1900, tm.tm_mon as u32 + 1, tm.tm_mday as u32);
+        Self {
+            year: tm.tm_year + 1900, month: tm.tm_mon as u32 + 1, day_of_month: tm.tm_mday as u32,
+            minute_of_day: tm.tm_hour as u32 * 60 + tm.tm_min as u32, second: tm.tm_sec as u32, day: offset,
+        }
+    }
+
+    fn date(&self) -> String { format!("{:04}-{:02}-{:02}", self.year, self.month, self.day_of_month) }
+    fn stamp(&self) -> String { format!("{} {:02}-{:02}", self.date(), self.minute_of_day / 60, self.minute_of_day % 60) }
+}
+
+/// Days since 1970-01-01 of a calendar date.
+pub(crate) fn days_from_civil(year: i32, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year } as i64;
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    era * 146_097 + yoe * 365 + yoe / 4 - yoe / 100 + doy - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 128 kbit/s, 44.1 kHz MP3 frame: 417 bytes
+    fn mp3_frame() -> Vec<u8> {
+        let mut frame = vec![0x55; 417];
+        frame[..4].copy_from_slice(&[0xff, 0xfb, 0x90, 0x64]);
+        frame
+    }
+
+    fn adts_frame(length: usize) -> Vec<u8> {
+        let mut frame = vec![0x55; length];
+        frame[..7].copy_from_slice(&[0xff, 0xf1, 0x50, 0x80 | (length >> 11) as u8 & 3, (length >> 3) as u8, (length as u8 & 7) << 5 | 0x1f, 0xfc]);
+        frame
+    }
+
+    #[test]
+    fn cuts_only_at_frames_followed_by_another() {
+        // Audio data that happens to contain a frame sync
+        let mut data = [vec![0; 10], vec![0xff, 0xfb, 0x90, 0x64], vec![0x11; 20]].concat();
+        data.extend(mp3_frame());
+        data.extend(mp3_frame());
+        assert_eq!(frame_length(&mp3_frame()), Some(417));
+        assert_eq!(frame_start(&data, Codec::Mp3), Some(34));
+        // The last frame in the data can't be checked, so it counts
+        assert_eq!(frame_start(&data[34 + 417..], Codec::Mp3), Some(0));
+
+        let mut data = [vec![0xff, 0xf1, 0x50, 0x80, 0x10, 0x1f], vec![0x22; 9]].concat();
+        data.extend(adts_frame(200));
+        data.extend(adts_frame(180));
+        assert_eq!(frame_start(&data, Codec::Aac), Some(15));
+        assert_eq!(frame_start(&data, Codec::Mp3), None);
+    }
+}
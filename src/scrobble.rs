@@ -0,0 +1,618 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::net::{agent, hex, md5};
+use crate::settings::{config_dir, ScrobbleAccount, ScrobbleApi};
+use crate::tags::TrackInfo;
+
+// Scrobbling to services that speak the Last.fm 2.0 web API (Last.fm, Libre.fm, Maloja…) or the ListenBrainz API.
+// A track is scrobbled once half of it, or four minutes, has actually been heard; seeking past parts doesn't count.
+// Scrobbles wait in `scrobbles.queue` in the config folder until a service accepts them, so listens made offline are
+// sent in batches later. "Now playing" updates are sent once and never queued.
+
+const QUEUE_FILE: &str = "scrobbles.queue";
+pub const LASTFM_URL: &str = "https://ws.audioscrobbler.com/2.0/";
+pub const LISTENBRAINZ_URL: &str = "https://api.listenbrainz.org";
+const CLIENT_NAME: &str = "RustAudioPlayer";
+/// Most scrobbles the Last.fm API takes per request; ListenBrainz is sent the same size of batch.
+const BATCH: usize = 50;
+/// Tracks shorter than this are never scrobbled.
+const MIN_LENGTH: Duration = Duration::from_secs(30);
+/// Listening time after which any track counts, however long it is.
+const MAX_THRESHOLD: Duration = Duration::from_secs(240);
+/// Largest position step between two looks that counts as listening; anything bigger was a seek.
+const MAX_STEP: Duration = Duration::from_secs(2);
+const RETRY_MIN: Duration = Duration::from_secs(30);
+const RETRY_MAX: Duration = Duration::from_secs(30 * 60);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// A track that was listened to.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Listen {
+    pub artist: String,
+    pub title: String,
+    pub album: String,
+    pub duration: Option<Duration>,
+    /// When playback started (Unix time).
+    pub started: i64,
+}
+
+impl Listen {
+    /// A listen for a library label such as "03. Artist - Title.mp3". `None` without an artist, which every
+    /// service requires.
+    pub(crate) fn from_label(label: &str, extension: Option<&str>, duration: Option<Duration>) -> Option<Self> {
+        let mut label = label.trim();
+        // File names still carry their extension
+        if let Some(ext) = extension && let Some(stem) = label.strip_suffix(ext).and_then(|s| s.strip_suffix('.')) { label = stem; }
+        // CUE tracks are numbered
+        if let Some((number, rest)) = label.split_once(". ") && !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()) { label = rest; }
+        let (artist, title) = label.split_once(" - ")?;
+        let (artist, title) = (artist.trim(), title.trim());
+        (!artist.is_empty() && !title.is_empty()).then(|| Self { artist: artist.into(), title: title.into(), duration, ..Default::default() })
+    }
+
+    /// A listen for a track's tags. `None` without an artist and title.
+    pub(crate) fn from_info(info: &TrackInfo, duration: Option<Duration>) -> Option<Self> {
+        let artist = if info.artist.is_empty() { &info.album_artist } else { &info.artist };
+        (!artist.is_empty() && !info.title.is_empty())
+            .then(|| Self { artist: artist.clone(), title: info.title.clone(), album: info.album.clone(), duration, ..Default::default() })
+    }
+
+    /// How long the track must be heard before it is scrobbled; `None` if it is too short to scrobble at all.
+    fn threshold(&self) -> Option<Duration> {
+        match self.duration {
+            Some(length) if length < MIN_LENGTH => None,
+            Some(length) => Some((length / 2).min(MAX_THRESHOLD)),
+            // Streams without a length only count after the full four minutes
+            None => Some(MAX_THRESHOLD),
+        }
+    }
+}
+
+fn unix_now() -> i64 { SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64) }
+
+// ===== Web APIs =====
+
+/// Why a submission failed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SubmitError {
+    /// Offline, a server problem or a sign-in that needs fixing: the listens are kept and sent again later.
+    Retry(String),
+    /// The service refused these listens; sending them again won't help.
+    Rejected(String),
+}
+
+impl std::fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self { SubmitError::Retry(e) | SubmitError::Rejected(e) => f.write_str(e) }
+    }
+}
+
+/// Tell `account` which track has started playing.
+pub fn send_now_playing(account: &ScrobbleAccount, listen: &Listen) -> Result<(), SubmitError> {
+    match account.api {
+        ScrobbleApi::LastFm => {
+            let mut params = vec![param("method", "track.updateNowPlaying"), param("artist", &listen.artist), param("track", &listen.title)];
+            if !listen.album.is_empty() { params.push(param("album", &listen.album)); }
+            if let Some(length) = listen.duration { params.push(param("duration", &length.as_secs().to_string())); }
+            lastfm_call(account, params).map(drop)
+        }
+        ScrobbleApi::ListenBrainz => listenbrainz_submit(account, "playing_now", std::slice::from_ref(listen)),
+    }
+}
+
+/// Scrobble `listens` (at most one batch) to `account`.
+pub fn submit_scrobbles(account: &ScrobbleAccount, listens: &[Listen]) -> Result<(), SubmitError> {
+    match account.api {
+        ScrobbleApi::LastFm => {
+            let mut params = vec![param("method", "track.scrobble")];
+            for (i, listen) in listens.iter().enumerate() {
+                params.push((format!("artist[{i}]"), listen.artist.clone()));
+                params.push((format!("track[{i}]"), listen.title.clone()));
+                params.push((format!("timestamp[{i}]"), listen.started.to_string()));
+                if !listen.album.is_empty() { params.push((format!("album[{i}]"), listen.album.clone())); }
+                if let Some(length) = listen.duration { params.push((format!("duration[{i}]"), length.as_secs().to_string())); }
+            }
+            lastfm_call(account, params).map(drop)
+        }
+        ScrobbleApi::ListenBrainz => listenbrainz_submit(account, if listens.len() == 1 { "single" } else { "import" }, listens),
+    }
+}
+
+/// Sign in to a Last.fm-compatible service with a user name and password, returning the account with its session
+/// key. The password is only sent, never kept.
+pub fn lastfm_sign_in(url: &str, api_key: &str, api_secret: &str, user: &str, password: &str) -> Result<ScrobbleAccount, String> {
+    let mut account = ScrobbleAccount { api: ScrobbleApi::LastFm, url: url.to_string(), user: user.to_string(), api_key: api_key.to_string(), api_secret: api_secret.to_string(), ..Default::default() };
+    let params = vec![param("method", "auth.getMobileSession"), param("username", user), param("password", password)];
+    let text = lastfm_call(&account, params).map_err(|e| e.to_string())?;
+    let doc = roxmltree::Document::parse(&text).map_err(|e| format!("{url}: invalid response: {e}"))?;
+    let field = |name: &str| doc.descendants().find(|n| n.has_tag_name(name)).and_then(|n| n.text()).map(str::trim).unwrap_or_default().to_string();
+    account.key = field("key");
+    if account.key.is_empty() { return Err(format!("{url}: no session key in the response")); }
+    if !field("name").is_empty() { account.user = field("name"); }
+    Ok(account)
+}
+
+/// Check a ListenBrainz user token, returning the account it belongs to.
+pub fn listenbrainz_sign_in(url: &str, token: &str) -> Result<ScrobbleAccount, String> {
+    let endpoint = format!("{}/1/validate-token", url.trim_end_matches('/'));
+    let mut response = agent().get(&endpoint).header("Authorization", format!("Token {token}"))
+        .config().http_status_as_error(false).timeout_global(Some(REQUEST_TIMEOUT)).build()
+        .call().map_err(|e| format!("{endpoint}: {e}"))?;
+    let text = response.body_mut().read_to_string().map_err(|e| format!("{endpoint}: {e}"))?;
+    if json_field(&text, "valid").as_deref() != Some("true") {
+        return Err(json_field(&text, "message").or_else(|| json_field(&text, "error")).unwrap_or_else(|| format!("{endpoint}: HTTP {}", response.status().as_u16())));
+    }
+    let user = json_field(&text, "user_name").unwrap_or_default();
+    Ok(ScrobbleAccount { api: ScrobbleApi::ListenBrainz, url: url.trim_end_matches('/').to_string(), user, key: token.to_string(), ..Default::default() })
+}
+
+fn param(name: &str, value: &str) -> (String, String) { (name.to_string(), value.to_string()) }
+
+/// Call a Last.fm API method, signing it with the account's secret. Returns the response XML.
+fn lastfm_call(account: &ScrobbleAccount, mut params: Vec<(String, String)>) -> Result<String, SubmitError> {
+    params.push(param("api_key", &account.api_key));
+    if !account.key.is_empty() { params.push(param("sk", &account.key)); }
+    // api_sig: md5 of every parameter name and value in name order, then the secret
+    params.sort();
+    let signed: String = params.iter().flat_map(|(k, v)| [k.as_str(), v.as_str()]).chain([account.api_secret.as_str()]).collect();
+    params.push(param("api_sig", &hex(&md5(signed.as_bytes()))));
+    let url = &account.url;
+    let mut response = agent().post(url)
+        // Errors come with a 4xx/5xx status and say what went wrong
+        .config().http_status_as_error(false).timeout_global(Some(REQUEST_TIMEOUT)).build()
+        .send_form(params).map_err(|e| SubmitError::Retry(format!("{url}: {e}")))?;
+    let status = response.status().as_u16();
+    let text = response.body_mut().read_to_string().map_err(|e| SubmitError::Retry(format!("{url}: {e}")))?;
+    let failure = match roxmltree::Document::parse(&text) {
+        Ok(doc) if doc.root_element().attribute("status") == Some("ok") => None,
+        Ok(doc) => Some(match doc.root_element().children().find(|n| n.has_tag_name("error")) {
+            Some(error) => {
+                let message = format!("{} (error {})", error.text().unwrap_or_default().trim(), error.attribute("code").unwrap_or("?"));
+                // 6 and 7 are bad parameters; the rest are sign-in, rate limit and service problems that may go away
+                if matches!(error.attribute("code"), Some("6" | "7")) { SubmitError::Rejected(message) } else { SubmitError::Retry(message) }
+            }
+            None => SubmitError::Retry(format!("{url}: HTTP {status}")),
+        }),
+        Err(_) => Some(SubmitError::Retry(format!("{url}: HTTP {status}"))),
+    };
+    match failure { None => Ok(text), Some(e) => Err(e) }
+}
+
+fn listenbrainz_submit(account: &ScrobbleAccount, listen_type: &str, listens: &[Listen]) -> Result<(), SubmitError> {
+    let payload: Vec<String> = listens.iter().map(|listen| {
+        let mut info = format!("\"submission_client\":{},\"submission_client_version\":{}", json_string(CLIENT_NAME), json_string(env!("CARGO_PKG_VERSION")));
+        if let Some(length) = listen.duration { info.push_str(&format!(",\"duration_ms\":{}", length.as_millis())); }
+        let album = if listen.album.is_empty() { String::new() } else { format!(",\"release_name\":{}", json_string(&listen.album)) };
+        let metadata = format!("{{\"artist_name\":{},\"track_name\":{}{album},\"additional_info\":{{{info}}}}}", json_string(&listen.artist), json_string(&listen.title));
+        // "Now playing" has no time
+        if listen_type == "playing_now" { format!("{{\"track_metadata\":{metadata}}}") } else { format!("{{\"listened_at\":{},\"track_metadata\":{metadata}}}", listen.started) }
+    }).collect();
+    let body = format!("{{\"listen_type\":\"{listen_type}\",\"payload\":[{}]}}", payload.join(","));
+    let url = format!("{}/1/submit-listens", account.url.trim_end_matches('/'));
+    let mut response = agent().post(&url)
+        .header("Authorization", format!("Token {}", account.key))
+        .header("Content-Type", "application/json")
+        .config().http_status_as_error(false).timeout_global(Some(REQUEST_TIMEOUT)).build()
+        .send(body.as_str()).map_err(|e| SubmitError::Retry(format!("{url}: {e}")))?;
+    let status = response.status().as_u16();
+    if status == 200 { return Ok(()); }
+    let text = response.body_mut().read_to_string().unwrap_or_default();
+    let message = format!("{url}: {}", json_field(&text, "error").unwrap_or_else(|| format!("HTTP {status}")));
+    // A 400 means the listens themselves are invalid; 401 is a token to fix, and 429/5xx pass
+    Err(if status == 400 { SubmitError::Rejected(message) } else { SubmitError::Retry(message) })
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The value of the first `"name":` in a flat JSON reply, as text. Enough for the few fields read from
+/// ListenBrainz answers.
+fn json_field(text: &str, name: &str) -> Option<String> {
+    let rest = text[text.find(&format!("\"{name}\""))? + name.len() + 2..].trim_start().strip_prefix(':')?.trim_start();
+    if let Some(quoted) = rest.strip_prefix('"') {
+        let mut out = String::new();
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => return Some(out),
+                '\\' => match chars.next()? { 'n' => out.push('\n'), 't' => out.push('\t'), c => out.push(c) },
+                c => out.push(c),
+            }
+        }
+        return None;
+    }
+    Some(rest.split([',', '}', ']']).next()?.trim().to_string())
+}
+
+// ===== Listening =====
+
+/// What the tracker noticed.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Heard { NowPlaying(Listen), Scrobble(Listen) }
+
+struct Playing { key: String, listen: Option<Listen>, position: Duration, listened: Duration, scrobbled: bool }
+
+/// Follows the track that is playing and adds up how long it has really been heard.
+#[derive(Default)]
+pub(crate) struct ListenTracker { current: Option<Playing> }
+
+impl ListenTracker {
+    /// Look at the player: `key` identifies the loaded track (`None` when nothing scrobbleable is loaded),
+    /// `describe` gives its listen the first time it is seen playing.
+    pub(crate) fn update(&mut self, key: Option<&str>, position: Duration, playing: bool, describe: impl FnOnce() -> Option<Listen>) -> Option<Heard> {
+        let Some(key) = key else { self.current = None; return None };
+        // Repeat-one plays the same track again from the top
+        let restarted = self.current.as_ref().is_some_and(|p| p.key == key && p.scrobbled && position + MAX_STEP < p.position && position < MAX_STEP);
+        if self.current.as_ref().is_none_or(|p| p.key != key) || restarted {
+            if !playing { return None; }
+            let listen = describe().map(|listen| Listen { started: unix_now() - position.as_secs() as i64, ..listen });
+            self.current = Some(Playing { key: key.to_string(), listen: listen.clone(), position, listened: Duration::ZERO, scrobbled: false });
+            return listen.map(Heard::NowPlaying);
+        }
+        let current = self.current.as_mut()?;
+        let step = position.checked_sub(current.position);
+        current.position = position;
+        if !playing || current.scrobbled { return None; }
+        if let Some(step) = step.filter(|s| *s <= MAX_STEP) { current.listened += step; }
+        let listen = current.listen.as_ref()?;
+        if current.listened < listen.threshold()? { return None; }
+        current.scrobbled = true;
+        Some(Heard::Scrobble(listen.clone()))
+    }
+}
+
+// ===== Queue =====
+
+/// A scrobble waiting for one account.
+#[derive(Clone, Debug, PartialEq)]
+struct Queued { account: String, listen: Listen }
+
+/// A finished submission, applied in `Scrobbler::poll`.
+enum Sent {
+    Scrobbles { account: String, count: usize, result: Result<(), SubmitError> },
+    SignedIn(Result<ScrobbleAccount, String>),
+}
+
+/// The accounts scrobbles go to and the listens waiting for them. Submissions and sign-ins run on background threads.
+#[derive(Default)]
+pub(crate) struct Scrobbler {
+    accounts: Vec<ScrobbleAccount>,
+    queue: Vec<Queued>,
+    /// Accounts with a batch in flight.
+    sending: Vec<String>,
+    retry_at: Option<Instant>,
+    backoff: Duration,
+    signing_in: bool,
+    /// Why the last submission failed, until one succeeds.
+    error: Option<String>,
+    done: Arc<Mutex<Vec<Sent>>>,
+}
+
+/// Identifies an account's entries in the queue.
+fn account_id(account: &ScrobbleAccount) -> String { format!("{}|{}|{}", account.api.key(), account.user, account.url) }
+
+fn queue_path() -> Option<PathBuf> { config_dir().map(|dir| dir.join(QUEUE_FILE)) }
+
+impl Scrobbler {
+    /// Scrobble to `accounts`, picking up the listens left queued last time.
+    pub(crate) fn load(accounts: Vec<ScrobbleAccount>) -> Self {
+        let mut scrobbler = Self { accounts, ..Default::default() };
+        let text = queue_path().and_then(|p| std::fs::read_to_string(p).ok()).unwrap_or_default();
+        // `ACCOUNT<TAB>STARTED<TAB>SECONDS<TAB>ARTIST<TAB>TITLE<TAB>ALBUM`
+        for line in text.lines() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [account, started, seconds, artist, title, album] = fields[..] else { continue };
+            let Ok(started) = started.parse() else { continue };
+            let duration = seconds.parse().ok().map(Duration::from_secs);
+            let listen = Listen { artist: artist.into(), title: title.into(), album: album.into(), duration, started };
+            scrobbler.queue.push(Queued { account: account.into(), listen });
+        }
+        scrobbler.forget_unknown();
+        scrobbler
+    }
+
+    fn save(&self) {
+        let Some(path) = queue_path() else { return };
+        let clean = |s: &str| s.replace(['\t', '\r', '\n'], " ");
+        let out: String = self.queue.iter().map(|q| {
+            let seconds = q.listen.duration.map(|d| d.as_secs().to_string()).unwrap_or_default();
+            format!("{}\t{}\t{seconds}\t{}\t{}\t{}\n", q.account, q.listen.started, clean(&q.listen.artist), clean(&q.listen.title), clean(&q.listen.album))
+        }).collect();
+        let result = if out.is_empty() && !path.exists() { Ok(()) } else { std::fs::write(&path, out) };
+        if let Err(e) = result { log::warn!("Failed to save the scrobble queue: {e}"); }
+    }
+
+    /// Drop queued listens for accounts that were removed.
+    fn forget_unknown(&mut self) {
+        let ids: Vec<String> = self.accounts.iter().map(account_id).collect();
+        self.queue.retain(|q| ids.contains(&q.account));
+    }
+
+    pub(crate) fn accounts(&self) -> &[ScrobbleAccount] { &self.accounts }
+
+    /// Scrobble to `accounts` from now on. Listens still queued for an account that was removed are dropped.
+    pub(crate) fn set_accounts(&mut self, accounts: Vec<ScrobbleAccount>) {
+        self.accounts = accounts;
+        self.forget_unknown();
+        self.save();
+    }
+
+    fn spawn(&self, job: impl FnOnce() -> Sent + Send + 'static) {
+        let done = self.done.clone();
+        let spawned = std::thread::Builder::new().name("scrobble".into()).spawn(move || {
+            let sent = job();
+            done.lock().unwrap().push(sent);
+        });
+        if let Err(e) = spawned { log::warn!("Failed to start scrobbling: {e}"); }
+    }
+
+    /// Sign in in the background; `poll` adds the account once the service has accepted it.
+    pub(crate) fn sign_in(&mut self, job: impl FnOnce() -> Result<ScrobbleAccount, String> + Send + 'static) {
+        self.signing_in = true;
+        self.spawn(move || Sent::SignedIn(job()));
+    }
+
+    pub(crate) fn is_signing_in(&self) -> bool { self.signing_in }
+
+    /// Tell every account what started playing. Failures are only logged: the moment has passed by the next try.
+    pub(crate) fn now_playing(&self, listen: &Listen) {
+        for account in self.accounts.clone() {
+            let listen = listen.clone();
+            let spawned = std::thread::Builder::new().name("now-playing".into()).spawn(move || {
+                if let Err(e) = send_now_playing(&account, &listen) { log::warn!("Now playing update failed: {e}"); }
+            });
+            if let Err(e) = spawned { log::warn!("Failed to send now playing: {e}"); }
+        }
+    }
+
+    /// Queue `listen` for every account and try to send it.
+    pub(crate) fn scrobble(&mut self, listen: Listen) {
+        if self.accounts.is_empty() { return; }
+        self.queue.extend(self.accounts.iter().map(|a| Queued { account: account_id(a), listen: listen.clone() }));
+        self.save();
+        // A new listen is a good moment to try again, unless a failure is still fresh
+        if self.retry_at.is_none_or(|at| Instant::now() >= at) { self.flush(); }
+    }
+
+    /// Send the next batch for every account that has listens waiting and none in flight.
+    pub(crate) fn flush(&mut self) {
+        self.retry_at = None;
+        for account in self.accounts.clone() {
+            let id = account_id(&account);
+            if self.sending.contains(&id) { continue; }
+            let batch: Vec<Listen> = self.queue.iter().filter(|q| q.account == id).take(BATCH).map(|q| q.listen.clone()).collect();
+            if batch.is_empty() { continue; }
+            self.sending.push(id.clone());
+            self.spawn(move || Sent::Scrobbles { count: batch.len(), result: submit_scrobbles(&account, &batch), account: id });
+        }
+    }
+
+    /// Apply finished submissions and sign-ins, and retry failed ones when it is time. Returns messages for the
+    /// status line; empty if nothing happened.
+    pub(crate) fn poll(&mut self) -> Vec<String> {
+        let done = std::mem::take(&mut *self.done.lock().unwrap());
+        let mut messages = Vec::new();
+        let mut changed = false;
+        for sent in done {
+            match sent {
+                Sent::Scrobbles { account, count, result } => {
+                    self.sending.retain(|a| *a != account);
+                    let sent = match result {
+                        Ok(()) => { self.error = None; self.backoff = Duration::ZERO; true }
+                        Err(SubmitError::Rejected(e)) => { messages.push(format!("Scrobbles rejected: {e}")); true }
+                        Err(SubmitError::Retry(e)) => {
+                            // Back off while the service stays unreachable
+                            self.backoff = (self.backoff * 2).clamp(RETRY_MIN, RETRY_MAX);
+                            self.retry_at = Some(Instant::now() + self.backoff);
+                            if self.error.as_ref() != Some(&e) { messages.push(format!("Scrobbling paused: {e}")); }
+                            self.error = Some(e);
+                            false
+                        }
+                    };
+                    if sent {
+                        // The batch was the account's oldest entries; newer ones may have been queued meanwhile
+                        let mut left = count;
+                        self.queue.retain(|q| if left > 0 && q.account == account { left -= 1; false } else { true });
+                        changed = true;
+                    }
+                }
+                Sent::SignedIn(result) => {
+                    self.signing_in = false;
+                    match result {
+                        Ok(account) => {
+                            messages.push(format!("Scrobbling to {}", account.label()));
+                            self.accounts.retain(|a| account_id(a) != account_id(&account));
+                            self.accounts.push(account);
+                        }
+                        Err(e) => messages.push(format!("Sign-in failed: {e}")),
+                    }
+                }
+            }
+        }
+        if changed {
+            self.save();
+            // Keep going while there is a backlog
+            if self.retry_at.is_none() { self.flush(); }
+        }
+        if self.retry_at.is_some_and(|at| Instant::now() >= at) { self.flush(); }
+        messages
+    }
+
+    /// Scrobbles waiting to be sent, and why they are waiting, for the settings panel.
+    pub(crate) fn status_text(&self) -> String {
+        let waiting = self.queue.len();
+        match (&self.error, waiting) {
+            (_, 0) => String::new(),
+            (Some(e), n) => format!("{n} scrobble(s) waiting: {e}"),
+            (None, n) => format!("{n} scrobble(s) waiting"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{reply, serve, Request};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn listen(artist: &str, title: &str) -> Listen {
+        Listen { artist: artist.into(), title: title.into(), album: "Album".into(), duration: Some(Duration::from_secs(200)), started: 1_700_000_000 }
+    }
+
+    #[test]
+    fn labels_give_artist_and_title() {
+        let listen = Listen::from_label("03. Artist - Song - Live.flac", Some("flac"), None).unwrap();
+        assert_eq!((listen.artist.as_str(), listen.title.as_str()), ("Artist", "Song - Live"));
+        assert_eq!(Listen::from_label("Just a title.mp3", Some("mp3"), None), None);
+        assert_eq!(Listen::from_label(" - Title", None, None), None);
+    }
+
+    #[test]
+    fn only_time_really_heard_counts() {
+        let mut tracker = ListenTracker::default();
+        let secs = Duration::from_secs;
+        let describe = || Some(listen("Artist", "Song"));
+        // Nothing is reported for a track loaded but paused
+        assert_eq!(tracker.update(Some("a"), secs(0), false, describe), None);
+        assert!(matches!(tracker.update(Some("a"), secs(0), true, describe), Some(Heard::NowPlaying(_))));
+        for t in 1..=60 { assert_eq!(tracker.update(Some("a"), secs(t), true, describe), None); }
+        // A seek past the middle doesn't count as listening
+        assert_eq!(tracker.update(Some("a"), secs(150), true, describe), None);
+        for t in 151..=189 { assert_eq!(tracker.update(Some("a"), secs(t), true, describe), None); }
+        assert!(matches!(tracker.update(Some("a"), secs(190), true, describe), Some(Heard::Scrobble(_))));
+        assert_eq!(tracker.update(Some("a"), secs(199), true, describe), None);
+        // Repeat-one starts the same track over
+        assert!(matches!(tracker.update(Some("a"), secs(0), true, describe), Some(Heard::NowPlaying(_))));
+        // Short tracks are never scrobbled
+        let short = || Some(Listen { duration: Some(secs(20)), ..listen("Artist", "Jingle") });
+        tracker.update(Some("b"), secs(0), true, short);
+        assert!((1..20).all(|t| tracker.update(Some("b"), secs(t), true, short).is_none()));
+    }
+
+    /// Parameters of each request a server accepted.
+    type Received = Arc<Mutex<Vec<Vec<(String, String)>>>>;
+
+    /// A Last.fm-style API at `/2.0/` for user `demo` (password `pw`) with key `key` and secret `secret`. The first
+    /// `outages` scrobble submissions get a 503 page; the parameters of the others are kept in `received`.
+    fn lastfm_server(outages: usize, received: Received) -> String {
+        let outages = AtomicUsize::new(outages);
+        let url = serve(move |request, stream| {
+            let lfm = |body: &str| format!(r#"<?xml version="1.0" encoding="utf-8"?><lfm status="ok">{body}</lfm>"#);
+            let error = |code: u32, message: &str| format!(r#"<lfm status="failed"><error code="{code}">{message}</error></lfm>"#);
+            assert_eq!((request.method.as_str(), request.path()), ("POST", "/2.0/"));
+            let param = |name: &str| request.param(name).unwrap_or_default();
+            let mut params = request.params();
+            let (method, signature) = (param("method"), param("api_sig"));
+            if param("api_key") != "key" { return reply(stream, 403, "text/xml", error(10, "Invalid API key").as_bytes()); }
+            params.retain(|(n, _)| n != "api_sig" && n != "format");
+            params.sort();
+            let signed: String = params.iter().flat_map(|(n, v)| [n.as_str(), v.as_str()]).chain(["secret"]).collect();
+            if signature != hex(&md5(signed.as_bytes())) { return reply(stream, 403, "text/xml", error(13, "Invalid method signature supplied").as_bytes()); }
+            let body = match method.as_str() {
+                "auth.getMobileSession" if param("username") == "demo" && param("password") == "pw" => lfm("<session><name>Demo</name><key>session-key</key></session>"),
+                "auth.getMobileSession" => return reply(stream, 403, "text/xml", error(4, "Authentication Failed").as_bytes()),
+                _ if param("sk") != "session-key" => return reply(stream, 403, "text/xml", error(9, "Invalid session key").as_bytes()),
+                "track.scrobble" if outages.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).is_ok() => {
+                    return reply(stream, 503, "text/html", b"<html>Service Unavailable");
+                }
+                "track.scrobble" | "track.updateNowPlaying" if param("artist[0]") == "Nobody" || param("artist") == "Nobody" => {
+                    return reply(stream, 400, "text/xml", error(6, "Invalid parameters").as_bytes());
+                }
+                "track.scrobble" | "track.updateNowPlaying" => lfm(""),
+                _ => return reply(stream, 400, "text/xml", error(3, "Invalid Method").as_bytes()),
+            };
+            received.lock().unwrap().push(params);
+            reply(stream, 200, "text/xml", body.as_bytes())
+        });
+        format!("{url}/2.0/")
+    }
+
+    #[test]
+    fn lastfm_sign_in_and_scrobbles_are_signed() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let url = lastfm_server(0, received.clone());
+        assert_eq!(lastfm_sign_in(&url, "key", "secret", "demo", "wrong").unwrap_err(), "Authentication Failed (error 4)");
+        assert_eq!(lastfm_sign_in(&url, "key", "other", "demo", "pw").unwrap_err(), "Invalid method signature supplied (error 13)");
+        let account = lastfm_sign_in(&url, "key", "secret", "demo", "pw").unwrap();
+        assert_eq!((account.user.as_str(), account.key.as_str()), ("Demo", "session-key"));
+        send_now_playing(&account, &listen("Artist", "Song")).unwrap();
+        submit_scrobbles(&account, &[listen("Artist", "Song"), Listen { album: String::new(), duration: None, ..listen("Other & Co", "Tune") }]).unwrap();
+        let received = received.lock().unwrap();
+        let scrobble: Vec<(&str, &str)> = received[2].iter().map(|(n, v)| (n.as_str(), v.as_str())).filter(|(n, _)| n.contains('[')).collect();
+        assert_eq!(scrobble, [
+            ("album[0]", "Album"), ("artist[0]", "Artist"), ("artist[1]", "Other & Co"), ("duration[0]", "200"),
+            ("timestamp[0]", "1700000000"), ("timestamp[1]", "1700000000"), ("track[0]", "Song"), ("track[1]", "Tune"),
+        ]);
+    }
+
+    #[test]
+    fn lastfm_failures_are_retried_unless_the_listens_are_bad() {
+        let url = lastfm_server(1, Arc::default());
+        let account = ScrobbleAccount { api: ScrobbleApi::LastFm, url: url.clone(), user: "demo".into(), key: "session-key".into(), api_key: "key".into(), api_secret: "secret".into() };
+        assert_eq!(submit_scrobbles(&account, &[listen("Artist", "Song")]), Err(SubmitError::Retry(format!("{url}: HTTP 503"))));
+        assert_eq!(submit_scrobbles(&account, &[listen("Artist", "Song")]), Ok(()));
+        assert_eq!(submit_scrobbles(&account, &[listen("Nobody", "Song")]), Err(SubmitError::Rejected("Invalid parameters (error 6)".into())));
+        let expired = ScrobbleAccount { key: "old".into(), ..account };
+        assert_eq!(submit_scrobbles(&expired, &[listen("Artist", "Song")]), Err(SubmitError::Retry("Invalid session key (error 9)".into())));
+    }
+
+    /// A ListenBrainz-style API accepting token `good`; submitted bodies are kept in `received`.
+    fn listenbrainz_server(received: Arc<Mutex<Vec<String>>>) -> String {
+        serve(move |request: &Request, stream| {
+            let authorized = request.header("authorization") == Some("Token good");
+            let (status, body) = match (request.method.as_str(), request.path()) {
+                ("GET", "/1/validate-token") if authorized => (200, r#"{"code": 200, "message": "Token valid.", "valid": true, "user_name": "demo"}"#),
+                ("GET", "/1/validate-token") => (200, r#"{"code": 200, "message": "Token invalid.", "valid": false}"#),
+                ("POST", "/1/submit-listens") if !authorized => (401, r#"{"code": 401, "error": "Invalid authorization token."}"#),
+                ("POST", "/1/submit-listens") => {
+                    let body = String::from_utf8(request.body.clone()).unwrap();
+                    let bad = body.contains("\"artist_name\":\"\"");
+                    received.lock().unwrap().push(body);
+                    if bad { (400, r#"{"code": 400, "error": "artist_name is required"}"#) } else { (200, r#"{"status": "ok"}"#) }
+                }
+                _ => (404, r#"{"code": 404, "error": "Not found"}"#),
+            };
+            reply(stream, status, "application/json", body.as_bytes())
+        })
+    }
+
+    #[test]
+    fn listenbrainz_submits_json_listens() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let url = listenbrainz_server(received.clone());
+        assert_eq!(listenbrainz_sign_in(&url, "bad").unwrap_err(), "Token invalid.");
+        let account = listenbrainz_sign_in(&format!("{url}/"), "good").unwrap();
+        assert_eq!((account.user.as_str(), account.url.as_str(), account.key.as_str()), ("demo", url.as_str(), "good"));
+        send_now_playing(&account, &listen("Artist", "Song")).unwrap();
+        submit_scrobbles(&account, &[Listen { title: "Say \"hi\"\n".into(), ..listen("Artist", "") }]).unwrap();
+        submit_scrobbles(&account, &[listen("A", "1"), listen("B", "2")]).unwrap();
+        let version = env!("CARGO_PKG_VERSION");
+        let metadata = |title: &str| format!(r#""track_metadata":{{"artist_name":"Artist","track_name":{title},"release_name":"Album","additional_info":{{"submission_client":"RustAudioPlayer","submission_client_version":"{version}","duration_ms":200000}}}}"#);
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received[0], format!(r#"{{"listen_type":"playing_now","payload":[{{{}}}]}}"#, metadata("\"Song\"")));
+        assert_eq!(received[1], format!(r#"{{"listen_type":"single","payload":[{{"listened_at":1700000000,{}}}]}}"#, metadata(r#""Say \"hi\"\n""#)));
+        assert!(received[2].starts_with(r#"{"listen_type":"import","payload":[{"listened_at""#));
+        assert_eq!(json_field(&received[1], "track_name").as_deref(), Some("Say \"hi\"\n"));
+        let rejected = submit_scrobbles(&account, &[listen("", "Song")]);
+        assert_eq!(rejected, Err(SubmitError::Rejected(format!("{url}/1/submit-listens: artist_name is required"))));
+        let revoked = ScrobbleAccount { key: "revoked".into(), ..account };
+        assert_eq!(submit_scrobbles(&revoked, &[listen("A", "1")]), Err(SubmitError::Retry(format!("{url}/1/submit-listens: Invalid authorization token."))));
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::dsp::ResampleQuality;
+
+// Persistent player settings stored as simple `key=value` lines.
+// The file lives in `AUDIO_PLAYER_CONFIG_DIR` (or the working directory), next to the default "music" folder.
+const SETTINGS_FILE: &str = "player_settings.conf";
+
+#[derive(Clone, Default)]
+pub struct Settings {
+    /// Preferred output device name; `None` means the system default.
+    pub output_device: Option<String>,
+    /// EQ gains (dB) remembered per output device name.
+    pub eq_profiles: HashMap<String, [f32; 10]>,
+    /// Resume playback automatically once a lost output device is reopened.
+    pub auto_resume_output: bool,
+    pub resample_quality: ResampleQuality,
+    /// Reopen the output at the track's own sample rate when the device supports it.
+    pub match_native_rate: bool,
+    /// Skip silence at the start and end of tracks.
+    pub trim_silence: bool,
+    /// Level below which track edges count as silent; `None` uses the built-in default.
+    pub silence_threshold_db: Option<f32>,
+    /// Album folders that are played untrimmed (e.g. live or continuous-mix albums).
+    pub keep_silence_dirs: Vec<PathBuf>,
+    /// Shorten pauses in speech.
+    pub smart_speed: bool,
+    /// Longest pause smart speed keeps; `None` uses the built-in default.
+    pub smart_speed_max_pause: Option<Duration>,
+    /// SoundFont for MIDI playback; `None` uses the first one found.
+    pub soundfont: Option<PathBuf>,
+    /// Favorite internet radio stations, in the user's order.
+    pub stations: Vec<Station>,
+    /// Daily radio recordings that run without playback.
+    pub record_schedules: Vec<RecordSchedule>,
+    /// Subsonic-compatible server browsed as a remote library.
+    pub subsonic: Option<SubsonicAccount>,
+    /// Services listens are scrobbled to.
+    pub scrobble_accounts: Vec<ScrobbleAccount>,
+    /// Lyrics timing shift per file, in milliseconds the lyrics are shown early.
+    pub lyrics_offsets: HashMap<PathBuf, i64>,
+}
+
+/// A saved internet radio station.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Station {
+    pub name: String,
+    pub url: String,
+}
+
+/// A radio station recorded every day from `start_minute` (minutes after local midnight) for `minutes`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordSchedule {
+    pub name: String,
+    pub url: String,
+    pub start_minute: u32,
+    pub minutes: u32,
+}
+
+impl RecordSchedule {
+    pub fn label(&self) -> &str { if self.name.is_empty() { &self.url } else { &self.name } }
+
+    /// Start time as `HH:MM`.
+    pub fn start_text(&self) -> String { format!("{:02}:{:02}", self.start_minute / 60, self.start_minute % 60) }
+
+    /// Parse `HH:MM+MINUTES|Name|URL`.
+    fn parse(value: &str) -> Option<Self> {
+        let (when, rest) = value.trim().split_once('|')?;
+        let (name, url) = rest.rsplit_once('|')?;
+        let (start, minutes) = when.split_once('+')?;
+        Some(Self {
+            name: name.trim().to_string(), url: url.trim().to_string(),
+            start_minute: parse_clock(start)?, minutes: minutes.trim().parse().ok().filter(|m| *m > 0)?,
+        })
+    }
+}
+
+/// Sign-in for a Subsonic-compatible server. Only the salted token is kept, never the password, though the token
+/// grants the same access, so treat it like one.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SubsonicAccount {
+    /// Server address without the `/rest` part.
+    pub url: String,
+    pub user: String,
+    pub salt: String,
+    /// `md5(password + salt)` as hex.
+    pub token: String,
+    /// Highest stream bitrate in kbit/s; 0 leaves it to the server.
+    pub max_bitrate: u32,
+    /// Format to transcode streams to (e.g. `mp3`, `opus`); empty plays the original.
+    pub format: String,
+}
+
+/// The web API a scrobbling service speaks.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ScrobbleApi {
+    /// The Last.fm 2.0 web service, also offered by Libre.fm and self-hosted servers such as Maloja.
+    #[default]
+    LastFm,
+    ListenBrainz,
+}
+
+impl ScrobbleApi {
+    pub const ALL: [ScrobbleApi; 2] = [ScrobbleApi::LastFm, ScrobbleApi::ListenBrainz];
+    pub fn label(self) -> &'static str { match self { ScrobbleApi::LastFm => "Last.fm", ScrobbleApi::ListenBrainz => "ListenBrainz" } }
+    pub(crate) fn key(self) -> &'static str { match self { ScrobbleApi::LastFm => "lastfm", ScrobbleApi::ListenBrainz => "listenbrainz" } }
+    pub(crate) fn from_key(key: &str) -> Option<Self> { Self::ALL.into_iter().find(|a| a.key() == key) }
+}
+
+/// Sign-in for a scrobbling service. Only the session key (Last.fm) or user token (ListenBrainz) is kept.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScrobbleAccount {
+    pub api: ScrobbleApi,
+    /// API root, e.g. `https://ws.audioscrobbler.com/2.0/` or `https://api.listenbrainz.org`.
+    pub url: String,
+    pub user: String,
+    /// Session key (Last.fm) or user token (ListenBrainz).
+    pub key: String,
+    /// Application key and secret the Last.fm API signs requests with; unused by ListenBrainz.
+    pub api_key: String,
+    pub api_secret: String,
+}
+
+impl ScrobbleAccount {
+    /// "user on Service (url)", for the settings panel.
+    pub fn label(&self) -> String { format!("{} on {} ({})", self.user, self.api.label(), self.url) }
+
+    /// Parse `API|USER|KEY|API_KEY|API_SECRET|URL`.
+    fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.trim().splitn(6, '|').map(str::trim);
+        let api = ScrobbleApi::from_key(parts.next()?)?;
+        let (user, key, api_key, api_secret, url) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        (!url.is_empty() && !key.is_empty()).then(|| Self { api, url: url.into(), user: user.into(), key: key.into(), api_key: api_key.into(), api_secret: api_secret.into() })
+    }
+}
+
+/// `HH:MM` as minutes after midnight.
+pub fn parse_clock(text: &str) -> Option<u32> {
+    let (h, m) = text.trim().split_once(':')?;
+    let (h, m): (u32, u32) = (h.trim().parse().ok()?, m.trim().parse().ok()?);
+    (h < 24 && m < 60).then_some(h * 60 + m)
+}
+
+/// Folder for the settings file and other saved state.
+pub(crate) fn config_dir() -> Option<PathBuf> {
+    std::env::var("AUDIO_PLAYER_CONFIG_DIR").ok().map(PathBuf::from).or_else(|| std::env::current_dir().ok())
+}
+
+fn settings_path() -> Option<PathBuf> { config_dir().map(|dir| dir.join(SETTINGS_FILE)) }
+
+fn parse_gains(value: &str) -> Option<[f32; 10]> {
+    let mut gains = [0.0f32; 10];
+    let mut parts = value.split(',');
+    for g in gains.iter_mut() { *g = parts.next()?.trim().parse().ok()?; }
+    Some(gains)
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        let mut settings = Settings::default();
+        let Some(text) = settings_path().and_then(|p| std::fs::read_to_string(p).ok()) else { return settings; };
+        for line in text.lines() {
+            let Some((key, value)) = line.split_once('=') else { continue; };
+            match key.trim() {
+                "output_device" => { let v = value.trim(); if !v.is_empty() { settings.output_device = Some(v.to_string()); } }
+                "auto_resume_output" => settings.auto_resume_output = value.trim() == "true",
+                "resample_quality" => settings.resample_quality = ResampleQuality::from_key(value.trim()).unwrap_or_default(),
+                "match_native_rate" => settings.match_native_rate = value.trim() == "true",
+                "trim_silence" => settings.trim_silence = value.trim() == "true",
+                "silence_threshold_db" => settings.silence_threshold_db = value.trim().parse().ok(),
+                "smart_speed" => settings.smart_speed = value.trim() == "true",
+                "smart_speed_max_pause_ms" => settings.smart_speed_max_pause = value.trim().parse().ok().map(Duration::from_millis),
+                "soundfont" => { let v = value.trim(); if !v.is_empty() { settings.soundfont = Some(PathBuf::from(v)); } }
+                "keep_silence" => { let v = value.trim(); if !v.is_empty() { settings.keep_silence_dirs.push(PathBuf::from(v)); } }
+                // `station=Name|URL`; the URL can't contain a raw `|`, the name can
+                "station" => if let Some((name, url)) = value.trim().rsplit_once('|') && !url.is_empty() {
+                    settings.stations.push(Station { name: name.trim().to_string(), url: url.trim().to_string() });
+                },
+                "record_schedule" => settings.record_schedules.extend(RecordSchedule::parse(value)),
+                // `subsonic=USER|SALT|TOKEN|URL`, then `subsonic_transcode=KBITS|FORMAT`
+                "subsonic" => {
+                    let mut parts = value.trim().splitn(4, '|').map(str::trim);
+                    if let (Some(user), Some(salt), Some(token), Some(url)) = (parts.next(), parts.next(), parts.next(), parts.next()) && !url.is_empty() {
+                        settings.subsonic = Some(SubsonicAccount { url: url.into(), user: user.into(), salt: salt.into(), token: token.into(), ..Default::default() });
+                    }
+                }
+                "subsonic_transcode" => if let Some(account) = settings.subsonic.as_mut() && let Some((kbits, format)) = value.trim().split_once('|') {
+                    account.max_bitrate = kbits.trim().parse().unwrap_or(0);
+                    account.format = format.trim().to_string();
+                },
+                "scrobble" => settings.scrobble_accounts.extend(ScrobbleAccount::parse(value)),
+                // `lyrics_offset=MS|PATH`
+                "lyrics_offset" => if let Some((ms, path)) = value.trim().split_once('|') && let Ok(ms) = ms.trim().parse() {
+                    settings.lyrics_offsets.insert(PathBuf::from(path.trim()), ms);
+                },
+                k => {
+                    if let Some(device) = k.strip_prefix("eq.")
+                        && let Some(gains) = parse_gains(value) { settings.eq_profiles.insert(device.to_string(), gains); }
+                }
+            }
+        }
+        settings
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = settings_path().ok_or("No settings directory available")?;
+        let mut out = String::new();
+        if let Some(dev) = &self.output_device { out.push_str(&format!("output_device={dev}\n")); }
+        out.push_str(&format!("auto_resume_output={}\n", self.auto_resume_output));
+        out.push_str(&format!("resample_quality={}\n", self.resample_quality.key()));
+        out.push_str(&format!("match_native_rate={}\n", self.match_native_rate));
+        out.push_str(&format!("trim_silence={}\n", self.trim_silence));
+        if let Some(db) = self.silence_threshold_db { out.push_str(&format!("silence_threshold_db={db}\n")); }
+        out.push_str(&format!("smart_speed={}\n", self.smart_speed));
+        if let Some(max) = self.smart_speed_max_pause { out.push_str(&format!("smart_speed_max_pause_ms={}\n", max.as_millis())); }
+        if let Some(sf) = &self.soundfont { out.push_str(&format!("soundfont={}\n", sf.display())); }
+        for dir in &self.keep_silence_dirs { out.push_str(&format!("keep_silence={}\n", dir.display())); }
+        for station in &self.stations { out.push_str(&format!("station={}|{}\n", station.name, station.url)); }
+        for s in &self.record_schedules { out.push_str(&format!("record_schedule={}+{}|{}|{}\n", s.start_text(), s.minutes, s.name, s.url)); }
+        if let Some(a) = &self.subsonic {
+            out.push_str(&format!("subsonic={}|{}|{}|{}\n", a.user, a.salt, a.token, a.url));
+            out.push_str(&format!("subsonic_transcode={}|{}\n", a.max_bitrate, a.format));
+        }
+        for a in &self.scrobble_accounts {
+            out.push_str(&format!("scrobble={}|{}|{}|{}|{}|{}\n", a.api.key(), a.user, a.key, a.api_key, a.api_secret, a.url));
+        }
+        let mut offsets: Vec<_> = self.lyrics_offsets.iter().filter(|(_, ms)| **ms != 0).collect();
+        offsets.sort();
+        for (path, ms) in offsets { out.push_str(&format!("lyrics_offset={ms}|{}\n", path.display())); }
+        let mut profiles: Vec<_> = self.eq_profiles.iter().collect();
+        profiles.sort_by(|a, b| a.0.cmp(b.0));
+        for (device, gains) in profiles {
+            let values = gains.iter().map(|g| format!("{g:.2}")).collect::<Vec<_>>().join(",");
+            out.push_str(&format!("eq.{device}={values}\n"));
+        }
+        std::fs::write(&path, out).map_err(|e| format!("Failed to save settings: {e}"))
+    }
+}
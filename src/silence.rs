@@ -0,0 +1,162 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use rodio::Source;
+
+use crate::decode::open_track;
+
+// Leading/trailing silence detection. Tracks are analysed on a background thread when they are played or queued
+// next (the trailing edge can only be found by decoding the whole file) and the results are cached for the session.
+
+/// Silence found at the edges of a track.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct SilenceBounds {
+    pub lead: Duration,
+    pub trail: Duration,
+}
+
+/// Edge threshold used when the settings don't override it.
+pub(crate) const DEFAULT_THRESHOLD_DB: f32 = -60.0;
+
+// Audio kept on each side of the detected edge so fades and breaths aren't clipped.
+const EDGE_MARGIN: Duration = Duration::from_millis(50);
+
+/// Decode `path` and measure how long it stays below `threshold_db` (dBFS) at the start and end.
+pub(crate) fn analyze(path: &Path, threshold_db: f32) -> Result<SilenceBounds, String> {
+    let mut decoder = open_track(path, Duration::ZERO, None)?.source;
+    let channels = decoder.channels().max(1) as u64;
+    let rate = decoder.sample_rate().max(1) as f64;
+    let threshold = 10f32.powf(threshold_db / 20.0);
+    let (mut frames, mut first_loud, mut last_loud) = (0u64, None::<u64>, 0u64);
+    let mut in_frame = 0u64;
+    let mut loud = false;
+    for x in decoder.by_ref() {
+        loud |= x.abs() > threshold;
+        in_frame += 1;
+        if in_frame == channels {
+            if loud { first_loud.get_or_insert(frames); last_loud = frames; }
+            frames += 1;
+            in_frame = 0;
+            loud = false;
+        }
+    }
+    let to_time = |f: u64| Duration::from_secs_f64(f as f64 / rate);
+    // A completely silent file is left alone rather than trimmed to nothing
+    let Some(first) = first_loud else { return Ok(SilenceBounds::default()) };
+    let lead = to_time(first).saturating_sub(EDGE_MARGIN);
+    let trail = to_time(frames.saturating_sub(last_loud + 1)).saturating_sub(EDGE_MARGIN);
+    Ok(SilenceBounds { lead, trail })
+}
+
+struct AnalyzerState {
+    cache: HashMap<PathBuf, Option<SilenceBounds>>,
+    queue: VecDeque<PathBuf>,
+    worker_running: bool,
+}
+
+/// Background silence analysis with a per-session cache.
+#[derive(Clone)]
+pub(crate) struct SilenceAnalyzer {
+    state: Arc<(Mutex<AnalyzerState>, Condvar)>,
+    threshold_db: f32,
+}
+
+impl SilenceAnalyzer {
+    pub(crate) fn new(threshold_db: f32) -> Self {
+        let state = AnalyzerState { cache: HashMap::new(), queue: VecDeque::new(), worker_running: false };
+        Self { state: Arc::new((Mutex::new(state), Condvar::new())), threshold_db }
+    }
+
+    /// Cached result: `None` if not analysed yet, `Some(None)` if the file couldn't be analysed.
+    pub(crate) fn get(&self, path: &Path) -> Option<Option<SilenceBounds>> {
+        self.state.0.lock().ok()?.cache.get(path).copied()
+    }
+
+    /// Queue `path` for analysis; `urgent` puts it ahead of the rest (e.g. the track that is playing).
+    pub(crate) fn request(&self, path: &Path, urgent: bool) {
+        let (lock, cvar) = &*self.state;
+        let mut st = lock.lock().unwrap();
+        if st.cache.contains_key(path) { return; }
+        if urgent {
+            st.queue.retain(|p| p != path);
+            st.queue.push_front(path.to_path_buf());
+        } else if !st.queue.iter().any(|p| p == path) {
+            st.queue.push_back(path.to_path_buf());
+        }
+        if !st.worker_running {
+            st.worker_running = true;
+            let this = self.clone();
+            std::thread::spawn(move || this.work());
+        }
+        cvar.notify_one();
+    }
+
+    fn work(&self) {
+        let (lock, cvar) = &*self.state;
+        loop {
+            let path = {
+                let mut st = lock.lock().unwrap();
+                loop {
+                    if let Some(p) = st.queue.pop_front() { break p; }
+                    let (guard, timeout) = cvar.wait_timeout(st, Duration::from_secs(30)).unwrap();
+                    st = guard;
+                    // Let the thread go when there is nothing left to do; `request` starts a new one
+                    if timeout.timed_out() && st.queue.is_empty() { st.worker_running = false; return; }
+                }
+            };
+            let result = analyze(&path, self.threshold_db).inspect_err(|e| log::warn!("Silence analysis failed for {}: {e}", path.display()));
+            lock.lock().unwrap().cache.insert(path, result.ok());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{WavFormat, WavWriter};
+
+    /// An 8 kHz mono WAV of `parts` in order: (milliseconds, sine amplitude), in a fresh folder.
+    fn wav(name: &str, parts: &[(u64, f32)]) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("silence-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("track.wav");
+        let mut out = WavWriter::new(std::fs::File::create(&path).unwrap(), WavFormat::Float32, 1, 8000).unwrap();
+        for &(ms, amplitude) in parts {
+            for n in 0..ms * 8 { out.write_sample(amplitude * (n as f32 * 0.3).sin()).unwrap(); }
+        }
+        out.finish().unwrap();
+        (dir, path)
+    }
+
+    fn assert_ms(actual: Duration, expected_ms: u64) {
+        let diff = actual.as_secs_f64() * 1000.0 - expected_ms as f64;
+        assert!(diff.abs() <= 2.0, "expected about {expected_ms} ms, got {actual:?}");
+    }
+
+    #[test]
+    fn edges_below_the_threshold_count_as_silence() {
+        // -70 dBFS hiss after the music
+        let (dir, path) = wav("edges", &[(500, 0.0), (1000, 0.5), (300, 0.0003)]);
+        let bounds = analyze(&path, -60.0).unwrap();
+        assert_ms(bounds.lead, 450);
+        assert_ms(bounds.trail, 250);
+        // A lower threshold hears the hiss
+        let bounds = analyze(&path, -80.0).unwrap();
+        assert_ms(bounds.lead, 450);
+        assert_ms(bounds.trail, 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn silent_file_is_not_trimmed() {
+        let (dir, path) = wav("silent", &[(500, 0.0)]);
+        let bounds = analyze(&path, -60.0).unwrap();
+        assert_eq!((bounds.lead, bounds.trail), (Duration::ZERO, Duration::ZERO));
+        std::fs::write(&path, b"not audio").unwrap();
+        assert!(analyze(&path, -60.0).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
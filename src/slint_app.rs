@@ -5,156 +5,529 @@ use std::time::{Duration, Instant};
 use slint::SharedString;
 use rand::seq::SliceRandom;
 
+use crate::artwork::{folder_image, ArtLoader};
+use crate::chapters::{read_chapters, Chapter};
+use crate::cue::read_cue;
+use crate::decode::{open_track_guarded, DecodeStatus};
+use crate::lyrics::{embed_lyrics, read_lyrics, save_sidecar, sidecar, LrcEditor, LyricLine, Lyrics};
+use crate::midi::{find_soundfonts, is_midi};
+use crate::net::{is_url, show_path};
+use crate::podcast::{Podcasts, PODCASTS_DIR};
+use crate::subsonic::{Browser, Page, Row, SubsonicClient};
+use crate::upnp::{DidlEntry, Dlna};
+use crate::playlist::{scan_playlists, write_m3u8, write_xspf, Playlist, PlaylistEntry};
+use crate::radio::{StreamInfo, StreamMeta};
+use crate::record::{sanitize, Scheduler, RECORDINGS_DIR};
+use crate::scrobble::{lastfm_sign_in, listenbrainz_sign_in, Heard, Listen, ListenTracker, Scrobbler, LASTFM_URL, LISTENBRAINZ_URL};
+use crate::dsp::{apply_dsp, DspSettings, Equalizer, ResampleQuality, SkipCounter, SourceEnd, Until, DEFAULT_MAX_PAUSE};
+use crate::output::{backend_from_env, OutputBackend};
+use crate::settings::{parse_clock, RecordSchedule, ScrobbleApi, Settings, Station};
+use crate::silence::{SilenceAnalyzer, DEFAULT_THRESHOLD_DB};
+use crate::tag_edit::{CoverEdit, TagEdit, TagSaver};
+use crate::tags::{TagField, TagLoader, TrackInfo};
+
 slint::include_modules!();
 
-// Simple audio engine using rodio + symphonia. Ported from iced app with minimal changes.
-// ===== Equalizer implementation (10-band peaking filters) =====
-#[derive(Clone, Copy)]
-struct BiquadCoeffs { b0: f32, b1: f32, b2: f32, a1: f32, a2: f32 }
-#[derive(Clone, Copy, Default)]
-struct BiquadState { z1: f32, z2: f32 }
-impl BiquadState {
-    fn process(&mut self, x: f32, c: BiquadCoeffs) -> f32 {
-        let y = c.b0 * x + self.z1;
-        self.z1 = c.b1 * x - c.a1 * y + self.z2;
-        self.z2 = c.b2 * x - c.a2 * y;
-        y
-    }
-}
-fn peaking_eq(sr: f32, f0: f32, q: f32, gain_db: f32) -> BiquadCoeffs {
-    let a = 10f32.powf(gain_db / 40.0);
-    let w0 = 2.0 * std::f32::consts::PI * (f0 / sr);
-    let alpha = w0.sin() / (2.0 * q);
-    let cosw = w0.cos();
-    let b0 = 1.0 + alpha * a;
-    let b1 = -2.0 * cosw;
-    let b2 = 1.0 - alpha * a;
-    let a0 = 1.0 + alpha / a;
-    let a1 = -2.0 * cosw;
-    let a2 = 1.0 - alpha / a;
-    let inv_a0 = 1.0 / a0;
-    BiquadCoeffs { b0: b0 * inv_a0, b1: b1 * inv_a0, b2: b2 * inv_a0, a1: a1 * inv_a0, a2: a2 * inv_a0 }
-}
-
-#[derive(Clone)]
-struct Equalizer { gains_db: Arc<Mutex<[f32; 10]>> }
-impl Default for Equalizer { fn default() -> Self { Self { gains_db: Arc::new(Mutex::new([0.0; 10])) } } }
-impl Equalizer { fn set_gains_db(&self, gains: [f32; 10]) { if let Ok(mut g) = self.gains_db.lock() { *g = gains; } } fn snapshot(&self) -> [f32; 10] { self.gains_db.lock().map(|g| *g).unwrap_or([0.0;10]) } }
-
-struct EqSource<S: rodio::Source<Item = f32>> {
-    inner: S,
-    coeffs: [BiquadCoeffs; 10],
-    l: [BiquadState; 10],
-    r: [BiquadState; 10],
-    next_left: bool,
-}
-impl<S: rodio::Source<Item = f32>> EqSource<S> {
-    fn new(inner: S, gains_db: [f32; 10]) -> Self {
-        let sr = inner.sample_rate() as f32;
-        let freqs = [31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0];
-        let q = 1.0;
-        let mut coeffs = [BiquadCoeffs { b0:1.0, b1:0.0, b2:0.0, a1:0.0, a2:0.0 }; 10];
-        for i in 0..10 { coeffs[i] = peaking_eq(sr, freqs[i], q, gains_db[i]); }
-        Self { inner, coeffs, l: [BiquadState::default(); 10], r: [BiquadState::default(); 10], next_left: true }
-    }
-}
-impl<S: rodio::Source<Item = f32>> Iterator for EqSource<S> { type Item = f32; fn next(&mut self) -> Option<Self::Item> { let mut x = self.inner.next()?; if self.next_left { for i in 0..10 { x = self.l[i].process(x, self.coeffs[i]); } } else { for i in 0..10 { x = self.r[i].process(x, self.coeffs[i]); } } self.next_left = !self.next_left; Some(x) } }
-impl<S: rodio::Source<Item = f32>> rodio::Source for EqSource<S> { fn channels(&self) -> u16 { self.inner.channels() } fn sample_rate(&self) -> u32 { self.inner.sample_rate() } fn current_span_len(&self) -> Option<usize> { self.inner.current_span_len() } fn total_duration(&self) -> Option<Duration> { self.inner.total_duration() } }
+// How far before a line the editor plays from when previewing or after a nudge
+const LRC_PREVIEW_LEAD: Duration = Duration::from_secs(2);
 
+// Simple audio engine using rodio + symphonia. Ported from iced app with minimal changes.
 // ===== Audio Engine =====
 struct AudioEngine {
-    // Lazily initialized to avoid failing UI startup on platforms where audio output isn't immediately available (e.g., Android).
-    stream: Option<rodio::stream::OutputStream>,
-    sink: Option<rodio::Sink>,
+    // Where samples go: a cpal device by default, or a null/WAV sink when running headless.
+    output: Box<dyn OutputBackend>,
     current_path: Option<PathBuf>,
+    // Title of the item being played, for streams whose URL means nothing to the user.
+    current_title: Option<String>,
+    // Tags of the library track being played
+    current_info: Option<TrackInfo>,
+    // Length of the whole file.
     duration: Option<Duration>,
-    start_instant: Option<Instant>,
-    paused_at: Option<Duration>,
-    position_offset: Duration,
+    // Part of the file that makes up the track (file time). Positions and durations seen by the UI are relative to it.
+    start_offset: Duration,
+    end_offset: Option<Duration>,
+    // Bounds given by a CUE sheet for the current virtual track; `None` for whole files.
+    track_range: Option<TrackRange>,
+    // Keep the source running past `end_offset` because the next track continues in the same file.
+    follow_on: bool,
+    // File time at which the current source started; the output reports how much of it has played since.
+    source_start: Duration,
+    source_end: SourceEnd,
+    source_channels: u16,
+    // Chapter marks of the current file (file time).
+    chapters: Vec<Chapter>,
+    // Lyrics of the current file (file time)
+    lyrics: Option<Lyrics>,
     eq: Equalizer,
+    // Preferred output device name (None = system default).
+    preferred_device: Option<String>,
+    // Pending reconnect after a fatal stream error.
+    output_lost: Option<OutputLoss>,
+    auto_resume: bool,
+    resample_quality: ResampleQuality,
+    // Reopen the output at the track's rate instead of converting, when the device allows it
+    match_native_rate: bool,
+    source_rate: Option<u32>,
+    trim_silence: bool,
+    // Album folders that keep their silence even when trimming is on
+    keep_silence_dirs: Vec<PathBuf>,
+    silence: SilenceAnalyzer,
+    // Smart speed: longest pause kept, when enabled
+    max_pause: Option<Duration>,
+    // Source time the current source's pause compressor has dropped, and the total from earlier sources this session
+    skipped: SkipCounter,
+    time_saved: Duration,
+    // SoundFont used to render MIDI files
+    soundfont: Option<PathBuf>,
+    // Reports a current source that stopped early because the file is broken
+    decode_status: DecodeStatus,
+    // Files that failed to play this session and why; next/previous and auto-advance skip them
+    failures: Vec<(PathBuf, String)>,
+    // Station and title of the internet radio stream that is playing
+    stream: Option<StreamInfo>,
+    // Where recordings of radio streams are saved
+    recordings_dir: Option<PathBuf>,
+    // Recordings finished since the library last took them in
+    recorded: Vec<PathBuf>,
 }
 
+// State kept while waiting for an output device to come back.
+struct OutputLoss { resume_playing: bool, backoff: Duration, retry_at: Instant }
+
 impl AudioEngine {
-    fn new() -> Self {
+    fn with_output(output: Box<dyn OutputBackend>) -> Self {
         Self {
-            stream: None,
-            sink: None,
+            output,
             current_path: None,
             duration: None,
-            start_instant: None,
-            paused_at: None,
-            position_offset: Duration::ZERO,
+            start_offset: Duration::ZERO,
+            end_offset: None,
+            track_range: None,
+            follow_on: false,
+            source_start: Duration::ZERO,
+            source_end: SourceEnd::default(),
+            source_channels: 2,
+            chapters: Vec::new(),
+            lyrics: None,
             eq: Equalizer::default(),
+            preferred_device: None,
+            output_lost: None,
+            auto_resume: false,
+            resample_quality: ResampleQuality::default(),
+            match_native_rate: false,
+            source_rate: None,
+            trim_silence: false,
+            keep_silence_dirs: Vec::new(),
+            silence: SilenceAnalyzer::new(DEFAULT_THRESHOLD_DB),
+            max_pause: None,
+            skipped: SkipCounter::default(),
+            time_saved: Duration::ZERO,
+            soundfont: None,
+            decode_status: DecodeStatus::default(),
+            failures: Vec::new(),
+            stream: None,
+            recordings_dir: None,
+            recorded: Vec::new(),
+            current_title: None,
+            current_info: None,
+        }
+    }
+
+    /// Names of the devices the output backend can play to.
+    fn list_output_devices(&self) -> Vec<String> { self.output.list_devices() }
+
+    fn active_device(&self) -> Option<String> { self.output.device_name() }
+
+    fn ensure_stream(&mut self) -> Result<(), String> { self.output.ensure_open(self.preferred_device.as_deref()) }
+
+    /// Check for a failed output stream and drive reconnect attempts. Returns a status message when something changed.
+    fn poll_output(&mut self) -> Option<String> {
+        if self.output.is_open() && let Some(reason) = self.output.take_error() {
+            // Freeze the position and drop the dead stream; playback resumes from here once a device is back
+            let resume_playing = self.is_playing();
+            let (position, file_position) = (self.current_position(), self.file_position());
+            let device = self.output.device_name().unwrap_or_else(|| "output".to_string());
+            self.output.close();
+            self.time_saved += std::mem::take(&mut self.skipped).get();
+            self.source_start = file_position;
+            // The position now accounts for the dead source's padding; keep only a failure it may have reported
+            let status = std::mem::take(&mut self.decode_status);
+            if let Some(reason) = status.take_error() { self.decode_status.fail(reason); }
+            self.output_lost = Some(OutputLoss { resume_playing, backoff: Duration::from_millis(500), retry_at: Instant::now() + Duration::from_millis(500) });
+            return Some(format!("Lost audio device '{device}': {reason}. Paused at {}", format_time(position)));
+        }
+        let loss = self.output_lost.as_mut()?;
+        if Instant::now() < loss.retry_at { return None; }
+        match self.reconnect_output(false) {
+            Ok(msg) => Some(msg),
+            Err(e) => {
+                let loss = self.output_lost.as_mut()?;
+                loss.backoff = (loss.backoff * 2).min(Duration::from_secs(30));
+                loss.retry_at = Instant::now() + loss.backoff;
+                Some(format!("{e}. Retrying in {}s", loss.backoff.as_secs().max(1)))
+            }
         }
     }
 
-    fn ensure_stream(&mut self) -> Result<(), String> {
-        if self.stream.is_none() {
-            let stream = rodio::OutputStreamBuilder::open_default_stream()
-                .map_err(|e| format!("Audio output error: {e}"))?;
-            self.stream = Some(stream);
+    /// Try to reopen output after a loss. `force_play` resumes playback regardless of the auto-resume option.
+    fn reconnect_output(&mut self, force_play: bool) -> Result<String, String> {
+        let Some(loss) = self.output_lost.as_ref() else { return Ok(String::new()); };
+        let play = force_play || (self.auto_resume && loss.resume_playing);
+        self.ensure_stream()?;
+        let device = self.active_device().unwrap_or_default();
+        if let Some(path) = self.current_path.clone() {
+            // Stay in the lost state until the track is playing again, so a failed reopen is retried
+            let position = self.current_position();
+            self.play_from(&path, position, !play)?;
         }
+        self.output_lost = None;
+        Ok(if play { format!("Audio restored on '{device}'") } else { format!("Audio restored on '{device}' (paused)") })
+    }
+
+    /// Key used for per-device settings such as EQ profiles.
+    fn device_profile_key(&self) -> String { self.preferred_device.clone().unwrap_or_else(|| "default".to_string()) }
+
+    /// Switch the output device, keeping the current track and position.
+    fn set_output_device(&mut self, name: Option<String>) -> Result<(), String> {
+        let resume = self.current_path.clone().map(|p| (p, self.current_position(), self.output.is_paused()));
+        self.output.close();
+        self.output_lost = None;
+        self.preferred_device = name;
+        self.ensure_stream()?;
+        if let Some((path, pos, paused)) = resume { self.play_from(&path, pos, paused)?; }
         Ok(())
     }
 
     fn stop(&mut self) {
-        if let Some(sink) = self.sink.take() { sink.stop(); }
+        self.output.stop();
+        self.time_saved += std::mem::take(&mut self.skipped).get();
+        self.output_lost = None;
         self.current_path = None;
+        self.current_title = None;
+        self.current_info = None;
         self.duration = None;
-        self.start_instant = None;
-        self.paused_at = None;
-        self.position_offset = Duration::ZERO;
+        self.start_offset = Duration::ZERO;
+        self.end_offset = None;
+        self.track_range = None;
+        self.follow_on = false;
+        self.source_start = Duration::ZERO;
+        self.chapters.clear();
+        self.lyrics = None;
+        if let Some(stream) = self.stream.take() { self.recorded.extend(stream.stop_recording()); }
+    }
+
+    /// Whether silence trimming applies to the loaded track at `path`.
+    fn trims(&self, path: &Path) -> bool {
+        // CUE tracks are cut out of continuous albums, so they are never trimmed
+        self.track_range.is_none() && self.trims_file(path)
+    }
+
+    /// Whether silence trimming applies to whole files at `path` (on, and its album isn't excluded).
+    fn trims_file(&self, path: &Path) -> bool {
+        // Streams can't be analysed
+        self.trim_silence && !is_url(path) && !path.parent().is_some_and(|dir| self.keep_silence_dirs.iter().any(|d| d == dir))
+    }
+
+    /// Start and end of the playable part of `path` in file time. Untrimmed until the analysis is done.
+    fn track_bounds(&self, path: &Path) -> (Duration, Option<Duration>) {
+        if let Some(range) = self.track_range { return (range.start, range.end); }
+        if !self.trims(path) { return (Duration::ZERO, None); }
+        match self.silence.get(path) {
+            Some(Some(b)) => (b.lead, self.duration.map(|d| d.saturating_sub(b.trail))),
+            Some(None) => (Duration::ZERO, None),
+            None => { self.silence.request(path, true); (Duration::ZERO, None) }
+        }
+    }
+
+    /// Start analysing `item` ahead of time if it will be trimmed, so its bounds are ready when it starts.
+    fn prepare_trim(&self, item: &SongItem) {
+        if item.range.is_none() && self.trims_file(&item.path) { self.silence.request(&item.path, false); }
+    }
+
+    /// Pick up a silence analysis that finished after the track started. Only the end can still move;
+    /// the start has already played.
+    fn refresh_trim(&mut self) {
+        let Some(path) = self.current_path.as_ref() else { return };
+        if self.end_offset.is_some() || !self.trims(path) { return; }
+        if let Some(Some(b)) = self.silence.get(path) {
+            self.end_offset = self.duration.map(|d| d.saturating_sub(b.trail));
+            self.update_source_end();
+        }
+    }
+
+    /// Cut the playing source at `end_offset`, unless playback is meant to run on into the next track.
+    fn update_source_end(&self) {
+        let rate = self.source_rate.unwrap_or(44_100) as f64;
+        let end = self.end_offset.filter(|_| !self.follow_on)
+            .map(|end| (end.saturating_sub(self.source_start).as_secs_f64() * rate).round() as u64 * self.source_channels as u64);
+        self.source_end.set(end);
+    }
+
+    /// Whether `item` is the track that is loaded right now.
+    fn is_current(&self, item: &SongItem) -> bool { self.current_path.as_ref() == Some(&item.path) && self.track_range == item.range }
+
+    /// `item` starts exactly where the current track ends in the same file, so it can continue without restarting.
+    fn is_adjacent(&self, item: &SongItem) -> bool {
+        self.current_path.as_ref() == Some(&item.path) && item.range.is_some_and(|r| self.end_offset == Some(r.start))
+    }
+
+    /// Let the source run on past the current track's end (see `is_adjacent`), or cut it there.
+    fn set_follow_on(&mut self, follow_on: bool) {
+        if self.follow_on != follow_on && self.track_range.is_some() { self.follow_on = follow_on; self.update_source_end(); }
+    }
+
+    /// Chapters of the current track in track time. A CUE track only sees the marks inside its range; trimmed
+    /// silence before the first mark just moves it to the start.
+    fn track_chapters(&self) -> Vec<Chapter> {
+        let end = self.end_offset.unwrap_or(Duration::MAX);
+        let mut chapters: Vec<Chapter> = self.chapters.iter()
+            .filter(|c| c.start < end && (self.track_range.is_none() || c.start >= self.start_offset))
+            .map(|c| Chapter { start: c.start.saturating_sub(self.start_offset), title: c.title.clone() })
+            .collect();
+        chapters.dedup_by(|b, a| { if b.start == a.start { a.title = b.title.clone(); true } else { false } });
+        chapters
+    }
+
+    fn current_chapter(&self, chapters: &[Chapter]) -> Option<usize> {
+        let pos = self.current_position();
+        chapters.iter().rposition(|c| c.start <= pos)
+    }
+
+    fn seek_chapter(&mut self, index: usize) -> Result<(), String> {
+        let Some(start) = self.track_chapters().get(index).map(|c| c.start) else { return Ok(()) };
+        self.seek_to(start)
+    }
+
+    fn next_chapter(&mut self) -> Result<(), String> {
+        let chapters = self.track_chapters();
+        let next = self.current_chapter(&chapters).map_or(0, |i| i + 1);
+        if next < chapters.len() { self.seek_to(chapters[next].start) } else { Ok(()) }
+    }
+
+    /// Back to the start of the current chapter, or to the previous one when already near its start.
+    fn prev_chapter(&mut self) -> Result<(), String> {
+        let chapters = self.track_chapters();
+        let Some(cur) = self.current_chapter(&chapters) else { return self.seek_to(Duration::ZERO) };
+        let into = self.current_position().saturating_sub(chapters[cur].start);
+        let index = if into > Duration::from_secs(3) || cur == 0 { cur } else { cur - 1 };
+        self.seek_to(chapters[index].start)
+    }
+
+    /// The lyric line being sung and how many bytes of it have been, with the user's timing shift `offset_ms`.
+    fn current_lyric(&self, offset_ms: i64) -> Option<(usize, usize)> { self.lyrics.as_ref()?.current(self.file_position(), offset_ms) }
+
+    fn seek_lyric(&mut self, index: usize, offset_ms: i64) -> Result<(), String> {
+        let Some(start) = self.lyrics.as_ref().and_then(|l| l.line_position(index, offset_ms)) else { return Ok(()) };
+        self.seek_to(start.saturating_sub(self.start_offset))
+    }
+
+    /// Seek to a moment before `position` (file time), so a synced line can be heard coming in.
+    fn seek_before(&mut self, position: Duration) -> Result<(), String> {
+        self.seek_to(position.saturating_sub(self.start_offset).saturating_sub(LRC_PREVIEW_LEAD))
+    }
+
+    /// The user's lyrics timing shift for the current file.
+    fn lyrics_offset(&self, settings: &Settings) -> i64 {
+        self.current_path.as_ref().and_then(|p| settings.lyrics_offsets.get(p).copied()).unwrap_or(0)
+    }
+
+    /// Start `item` from its beginning. A file that plays again is taken off the failure list.
+    fn play_item(&mut self, item: &SongItem) -> Result<(), String> {
+        // Virtual tracks share a file, so force the bounds to be recomputed
+        self.current_path = None;
+        self.track_range = item.range;
+        self.follow_on = false;
+        self.play_from(&item.path, Duration::ZERO, false)?;
+        self.current_title = Some(item.title.clone()).filter(|t| !t.is_empty());
+        self.current_info = Some(item.info.clone()).filter(|i| *i != TrackInfo::default());
+        self.failures.retain(|(p, _)| p != &item.path);
+        Ok(())
+    }
+
+    /// Play the first of `candidates` that can be played, skipping files that already failed this session and
+    /// any that fail now. Returns the index played (`None` if there was none) and how many were skipped.
+    fn play_first_playable(&mut self, songs: &[SongItem], candidates: impl IntoIterator<Item = usize>) -> Result<(Option<usize>, usize), String> {
+        let mut skipped = 0;
+        for index in candidates {
+            let Some(item) = songs.get(index) else { continue };
+            if self.is_broken(&item.path) { skipped += 1; continue; }
+            match self.play_item(item) {
+                Ok(()) => return Ok((Some(index), skipped)),
+                // Not the file's fault (e.g. no output device), so other files won't fare better
+                Err(e) if !self.is_broken(&item.path) => return Err(e),
+                Err(_) => skipped += 1,
+            }
+        }
+        Ok((None, skipped))
+    }
+
+    /// Remember that `path` can't be played, and why.
+    fn record_failure(&mut self, path: &Path, reason: &str) {
+        log::warn!("Can't play {}: {reason}", show_path(path));
+        self.failures.retain(|(p, _)| p != path);
+        self.failures.push((path.to_path_buf(), reason.to_string()));
+    }
+
+    fn is_broken(&self, path: &Path) -> bool { self.failures.iter().any(|(p, _)| p == path) }
+
+    /// Pick up a source that stopped early because its file is broken. Returns a status message once per failure.
+    fn check_decode_failure(&mut self) -> Option<String> {
+        let reason = self.decode_status.take_error()?;
+        let path = self.current_path.clone()?;
+        self.record_failure(&path, &reason);
+        Some(format!("Stopped {}: {reason}", file_label(&path)))
+    }
+
+    /// Move on to `item` without touching the output; the source is already playing it (see `is_adjacent`).
+    fn continue_into(&mut self, item: &SongItem) {
+        let Some(range) = item.range else { return };
+        self.track_range = Some(range);
+        self.current_title = Some(item.title.clone()).filter(|t| !t.is_empty());
+        self.current_info = Some(item.info.clone()).filter(|i| *i != TrackInfo::default());
+        (self.start_offset, self.end_offset) = (range.start, range.end);
+        self.follow_on = false;
+        self.update_source_end();
     }
 
     fn play_from(&mut self, path: &Path, position: Duration, resume_paused: bool) -> Result<(), String> {
         use rodio::Source as _;
-        if let Some(sink) = self.sink.take() { sink.stop(); }
+        self.output.stop();
+        // Each source gets a fresh counter so `current_position` only adds what the playing source dropped
+        self.time_saved += std::mem::take(&mut self.skipped).get();
 
-        let file = std::fs::File::open(path).map_err(|e| format!("Failed to open file: {e}"))?;
-        let decoder = rodio::Decoder::try_from(file).map_err(|e| format!("Failed to decode audio: {e}"))?;
         let same_track = self.current_path.as_ref().is_some_and(|p| p == path);
-        if !same_track || self.duration.is_none() {
-            self.duration = decoder.total_duration().or_else(|| probe_duration_with_symphonia(path));
+        // A recording carries on when the same stream is reopened (e.g. after an output device change)
+        let keep_recording = self.is_recording();
+        self.stop_recording();
+        // The start of the track doesn't depend on the file's duration, which is only known once it's open
+        // Streams always (re)join live
+        let start = if is_url(path) { Duration::ZERO } else if same_track { self.start_offset + position } else { self.track_bounds(path).0 + position };
+        let track = match open_track_guarded(path, start, self.soundfont.as_deref()) {
+            Ok(track) => track,
+            Err(e) => { self.record_failure(path, &e); return Err(e); }
+        };
+        if is_url(path) {
+            // Streams are live: no length, chapters, lyrics or trimming
+            self.duration = track.duration;
+            (self.start_offset, self.end_offset) = (Duration::ZERO, None);
+            self.chapters.clear();
+            self.lyrics = None;
+        } else {
+            if !same_track || self.duration.is_none() {
+                self.duration = track.duration.or_else(|| probe_duration_with_symphonia(path));
+            }
+            if !same_track {
+                (self.start_offset, self.end_offset) = self.track_bounds(path);
+                self.chapters = read_chapters(path);
+                self.lyrics = read_lyrics(path);
+            }
         }
 
         // Ensure we have an audio output stream before attempting to play
         self.ensure_stream()?;
+        let source_rate = track.source.sample_rate();
+        if self.match_native_rate && !self.output.request_sample_rate(source_rate) {
+            // The device can't run at the track's rate (or reopening failed); convert instead
+            self.ensure_stream()?;
+        }
 
-        let source = decoder.skip_duration(position);
-        // Apply EQ to f32 samples (Decoder outputs f32 in rodio 0.21)
-        let gains = self.eq.snapshot();
-        let source = EqSource::new(source, gains);
-        let stream = self.stream.as_ref().ok_or("Audio stream not initialized")?;
-        let sink = rodio::Sink::connect_new(&stream.mixer());
-        sink.append(source);
-        self.sink = Some(sink);
+        self.source_start = start;
+        self.source_rate = Some(source_rate);
+        self.source_channels = track.source.channels();
+        self.source_end = SourceEnd::default();
+        self.decode_status = track.status.clone();
+        self.stream = track.stream.clone();
+        if keep_recording && same_track && let Err(e) = self.start_recording() { log::warn!("{e}"); }
+        self.update_source_end();
+        let source = Until::new(track.source, self.source_end.clone());
+        // Apply the DSP chain to f32 samples (decoders and renderers all produce f32), resampling to the output rate
+        let source = apply_dsp(source, &self.dsp_settings(), self.output.sample_rate(), &self.skipped);
+        self.output.play(source, resume_paused)?;
         self.current_path = Some(path.to_path_buf());
-        self.position_offset = position;
-        self.paused_at = None;
-        self.start_instant = Some(Instant::now());
-
-        if resume_paused { if let Some(s) = &self.sink { s.pause(); } }
+        self.output_lost = None;
         Ok(())
     }
 
-    fn play_file(&mut self, path: &Path) -> Result<(), String> { self.play_from(path, Duration::ZERO, false) }
-    fn pause(&mut self) { if let Some(s) = &self.sink { if !s.is_paused() { s.pause(); self.paused_at = Some(self.current_position()); self.start_instant = None; } } }
-    fn resume(&mut self) { if let Some(s) = &self.sink { if s.is_paused() { s.play(); if let Some(p) = self.paused_at.take() { self.position_offset = p; } self.start_instant = Some(Instant::now()); } } }
+    /// What the radio station reports about the stream that is playing, if it is one.
+    fn stream_meta(&self) -> Option<StreamMeta> { self.stream.as_ref().map(StreamInfo::get) }
+
+    /// Save the playing stream to the recordings folder, one file per song.
+    fn start_recording(&self) -> Result<(), String> {
+        let stream = self.stream.as_ref().ok_or("Play a radio station to record it")?;
+        stream.start_recording(self.recordings_dir.as_deref().ok_or("No music folder to save recordings in")?)
+    }
+
+    fn is_recording(&self) -> bool { self.stream.as_ref().is_some_and(StreamInfo::is_recording) }
+
+    /// Stop recording the playing stream, keeping its files for the library. Returns how many there were.
+    fn stop_recording(&mut self) -> usize {
+        let files = self.stream.as_ref().map(StreamInfo::stop_recording).unwrap_or_default();
+        self.recorded.extend_from_slice(&files);
+        files.len()
+    }
+
+    /// Recording status for the radio panel.
+    fn recording_text(&self) -> String {
+        match self.stream.as_ref().map(StreamInfo::recording_status) {
+            Some(Ok(Some(file))) => format!("Recording to {}", file_label(&file)),
+            Some(Err(e)) => e,
+            _ if self.is_recording() => "Recording: waiting for audio".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn dsp_settings(&self) -> DspSettings { DspSettings { eq_gains: self.eq.snapshot(), resample_quality: self.resample_quality, max_pause: self.max_pause, live_eq: Some(self.eq.clone()) } }
+
+    /// Time smart speed has cut from pauses since the player started.
+    fn time_saved(&self) -> Duration { self.time_saved + self.skipped.get() }
+
+    /// Restart the current track at the current position, e.g. after DSP settings changed.
+    fn reload(&mut self) -> Result<(), String> {
+        let Some(path) = self.current_path.clone() else { return Ok(()) };
+        let (pos, paused) = (self.current_position(), self.is_paused());
+        self.play_from(&path, pos, paused)
+    }
+
+    /// Track and output sample rates, e.g. "44.1 kHz → 48 kHz · Sinc (best)".
+    fn rate_text(&self) -> String {
+        let Some(src) = self.source_rate.filter(|_| self.current_path.is_some()) else { return String::new() };
+        let khz = |r: u32| format!("{} kHz", r as f32 / 1000.0);
+        match self.output.sample_rate() {
+            Some(out) if out != src => format!("{} → {} · {}", khz(src), khz(out), self.resample_quality.label()),
+            _ => format!("{} (native)", khz(src)),
+        }
+    }
+
+    fn pause(&mut self) { self.output.pause(); }
+    fn resume(&mut self) {
+        // While the output is gone, "play" means: try to reconnect now
+        if self.output_lost.is_some() { let _ = self.reconnect_output(true); return; }
+        self.output.resume();
+    }
     fn seek_to(&mut self, position: Duration) -> Result<(), String> {
-        let clamped = if let Some(d) = self.duration { position.min(d) } else { position };
-        if let Some(path) = self.current_path.clone() {
-            let was_paused = self.sink.as_ref().is_some_and(|s| s.is_paused());
+        let clamped = if let Some(d) = self.total_duration() { position.min(d) } else { position };
+        if let Some(path) = self.current_path.clone().filter(|p| !is_url(p)) {
+            let was_paused = self.is_paused();
             if (self.current_position().as_secs_f32() - clamped.as_secs_f32()).abs() < 0.01 { return Ok(()); }
             self.play_from(&path, clamped, was_paused)
         } else { Ok(()) }
     }
-    fn is_playing(&self) -> bool { self.sink.as_ref().map(|s| !s.is_paused() && !s.empty()).unwrap_or(false) }
-    fn total_duration(&self) -> Option<Duration> { self.duration }
-    fn current_position(&self) -> Duration {
-        if let Some(paused) = self.paused_at { paused }
-        else if let Some(start) = self.start_instant { self.position_offset + start.elapsed() }
-        else { self.position_offset }
+    fn is_paused(&self) -> bool { self.output.is_paused() }
+    fn is_playing(&self) -> bool { self.output.has_source() && !self.output.is_paused() && !self.output.is_finished() }
+    /// Nothing is loaded, or the loaded track has already played out.
+    fn is_idle(&self) -> bool { !self.output.has_source() || self.output.is_finished() }
+    /// The current track played to its end (and wasn't paused), so the next one should start.
+    fn track_finished(&self) -> bool {
+        if !self.output.has_source() || self.output.is_paused() { return false; }
+        // With follow-on the source plays past the end, so the boundary is detected here
+        self.output.is_finished() || self.end_offset.is_some() && self.total_duration().is_some_and(|d| self.current_position() >= d)
     }
+    /// Length of the track as played, i.e. without trimmed silence.
+    fn total_duration(&self) -> Option<Duration> { self.end_offset.or(self.duration).map(|end| end.saturating_sub(self.start_offset)) }
+    /// Position in track time. Pauses dropped by smart speed count as played and silence played while the decoder
+    /// caught up doesn't, so this stays aligned with the file.
+    fn current_position(&self) -> Duration { self.file_position().saturating_sub(self.start_offset) }
+    fn file_position(&self) -> Duration { (self.source_start + self.output.elapsed() + self.skipped.get()).saturating_sub(self.decode_status.padding()) }
 }
 
 fn probe_duration_with_symphonia(path: &Path) -> Option<Duration> {
@@ -192,11 +565,141 @@ fn probe_duration_with_symphonia(path: &Path) -> Option<Duration> {
     None
 }
 
-#[derive(Clone)]
-struct SongItem { title: String, path: PathBuf }
+/// A library track, playlist entry or stream. `title` falls back to the file name when the tags have none.
+#[derive(Clone, Default)]
+struct SongItem { title: String, path: PathBuf, range: Option<TrackRange>, info: TrackInfo }
+
+impl SongItem {
+    /// A library file, titled by its tags or else by its name.
+    fn file(path: PathBuf, info: TrackInfo) -> Self {
+        let title = if info.title.is_empty() { path.file_name().and_then(|n| n.to_str()).unwrap_or("Unknown").to_string() } else { info.title.clone() };
+        Self { title, path, range: None, info }
+    }
+
+    /// Fill in what was read from its file: a library file takes the tags too, a CUE track (which its sheet
+    /// describes) only the format.
+    fn set_info(&mut self, info: &TrackInfo) {
+        if self.range.is_none() { return *self = Self::file(std::mem::take(&mut self.path), info.clone()); }
+        let read = info.clone();
+        self.info = TrackInfo { codec: read.codec, sample_rate: read.sample_rate, bit_depth: read.bit_depth, bitrate: read.bitrate, ..std::mem::take(&mut self.info) };
+    }
+
+    /// Whether the search box text `query` (lowercase) matches the title, artist or album.
+    fn matches(&self, query: &str) -> bool {
+        [&self.title, &self.info.artist, &self.info.album_artist, &self.info.album].iter().any(|s| s.to_lowercase().contains(query))
+    }
+}
+
+/// Part of a file that forms a virtual track (from a CUE sheet). `end: None` runs to the end of the file.
+#[derive(Clone, Copy, PartialEq)]
+struct TrackRange { start: Duration, end: Option<Duration> }
+
+/// A playlist (or remote album) being played: next/previous and auto-advance stay inside it instead of the library.
+struct PlayQueue { playlist: Option<usize>, items: Vec<SongItem>, index: usize }
+
+/// The tag editor: the library songs ticked for editing, the changes not saved yet, and the saving in progress.
+#[derive(Default)]
+struct TagEditor { marked: Vec<usize>, edit: TagEdit, saver: TagSaver }
+
+const EXTS: &[&str] = &["mp3","flac","wav","ogg","opus","aac","m4a","m4b","mka","alac","aiff","aif","mid","midi","dsf","dff"];
+
+/// List the playable files in `dir`. Files covered by a CUE sheet are replaced by the sheet's tracks. Only CUE sheets are
+/// read here: file tags and formats are left for a `TagLoader`, so a large library doesn't hold up the window.
+fn scan_songs(dir: &Path) -> Vec<SongItem> {
+    let Ok(entries) = std::fs::read_dir(dir) else { return Vec::new() };
+    let paths: Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.is_file()).collect();
+    let has_ext = |p: &Path, exts: &[&str]| p.extension().and_then(|s| s.to_str()).is_some_and(|ext| exts.iter().any(|x| x.eq_ignore_ascii_case(ext)));
+    // (sort key, item): files sort by name, CUE tracks by sheet name and then in sheet order
+    let mut items: Vec<(String, SongItem)> = Vec::new();
+    let mut covered: Vec<PathBuf> = Vec::new();
+    for cue_path in paths.iter().filter(|p| has_ext(p, &["cue"])) {
+        let sheet = match read_cue(cue_path) {
+            Ok(sheet) => sheet,
+            Err(e) => { log::warn!("{e}"); continue; }
+        };
+        let key = cue_path.file_name().and_then(|n| n.to_str()).unwrap_or_default().to_lowercase();
+        for track in sheet.tracks {
+            // Rippers often convert the WAV a sheet was made for; fall back to an audio file with the same stem
+            let file = if track.file.is_file() { track.file.clone() } else {
+                match paths.iter().find(|p| has_ext(p, EXTS) && p.file_stem() == track.file.file_stem()) {
+                    Some(p) => p.clone(),
+                    None => { log::warn!("{}: missing file {}", cue_path.display(), track.file.display()); continue; }
+                }
+            };
+            let name = track.title.clone().unwrap_or_else(|| format!("Track {}", track.number));
+            let performer = track.performer.as_ref().or(sheet.performer.as_ref());
+            let title = match performer {
+                Some(performer) => format!("{:02}. {performer} - {name}", track.number),
+                None => format!("{:02}. {name}", track.number),
+            };
+            let info = TrackInfo {
+                title: name, artist: performer.cloned().unwrap_or_default(), album: sheet.title.clone().unwrap_or_default(),
+                album_artist: sheet.performer.clone().unwrap_or_default(), track: Some(track.number),
+                duration: track.end.map(|end| end.saturating_sub(track.start)), ..Default::default()
+            };
+            let range = Some(TrackRange { start: track.start, end: track.end });
+            if !covered.contains(&file) { covered.push(file.clone()); }
+            items.push((key.clone(), SongItem { title, path: file, range, info }));
+        }
+    }
+    for p in paths.iter().filter(|p| has_ext(p, EXTS) && !covered.contains(p)) {
+        let name = p.file_name().and_then(|n| n.to_str()).unwrap_or("Unknown").to_lowercase();
+        items.push((name, SongItem::file(p.clone(), TrackInfo::default())));
+    }
+    // Stable sort keeps sheet order within a CUE
+    items.sort_by(|a, b| a.0.cmp(&b.0));
+    items.into_iter().map(|(_, item)| item).collect()
+}
+
+fn file_label(path: &Path) -> String {
+    if is_url(path) { return show_path(path); }
+    path.file_name().map_or_else(|| path.display().to_string(), |n| n.to_string_lossy().into_owned())
+}
+
+/// The entries of `order` after `cur` (before it when going backwards), continuing round from the other end
+/// when `wrap` is set. If `cur` isn't in `order`, a wrapping walk covers all of it and a plain one nothing.
+fn walk_from(order: &[usize], cur: usize, forward: bool, wrap: bool) -> Vec<usize> {
+    let (before, after) = match order.iter().position(|&x| x == cur) {
+        Some(p) => (&order[..p], &order[p + 1..]),
+        None if wrap => (order, &[][..]),
+        None => return Vec::new(),
+    };
+    match (forward, wrap) {
+        (true, true) => after.iter().chain(before).copied().collect(),
+        (true, false) => after.to_vec(),
+        (false, true) => before.iter().rev().chain(after.iter().rev()).copied().collect(),
+        (false, false) => before.iter().rev().copied().collect(),
+    }
+}
 
 fn format_time(dur: Duration) -> String { let secs = dur.as_secs(); format!("{:02}:{:02}", secs / 60, secs % 60) }
 
+/// The library rows at `indices` (the search results), with the tracks in `marked` ticked for the tag editor.
+fn update_song_list(ui: &AppWindow, songs: &[SongItem], indices: &[usize], marked: &[usize]) {
+    let rows = indices.iter().filter_map(|&i| Some(Song { marked: marked.contains(&i), ..song_row(songs.get(i)?) })).collect::<Vec<_>>();
+    ui.set_songs(slint::ModelRc::new(slint::VecModel::from(rows)));
+}
+
+/// The indices of the songs matching the search box text `query`.
+fn filter_songs(songs: &[SongItem], query: &str) -> Vec<usize> {
+    let query = query.to_lowercase();
+    songs.iter().enumerate().filter(|(_, item)| query.is_empty() || item.matches(&query)).map(|(i, _)| i).collect()
+}
+
+/// The library row for `item`; unknown numbers are 0 and unknown text empty.
+fn song_row(item: &SongItem) -> Song {
+    let info = &item.info;
+    let number = |n: Option<u32>| n.map_or(0, |n| n as i32);
+    Song {
+        title: item.title.clone().into(), artist: info.artist.clone().into(), album: info.album.clone().into(),
+        album_artist: info.album_artist.clone().into(), track: number(info.track), disc: number(info.disc),
+        year: info.year.unwrap_or(0), genre: info.genre.clone().into(), composer: info.composer.clone().into(),
+        duration: info.duration.map(format_time).unwrap_or_default().into(), codec: info.codec.to_uppercase().into(),
+        sample_rate: number(info.sample_rate), bit_depth: number(info.bit_depth), bitrate: number(info.bitrate),
+        subtitle: info.subtitle().into(), format: info.format_text().into(), marked: false,
+    }
+}
+
 pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     // Try creating the UI with the default renderer. If that fails (common on some Android devices
     // when OpenGL ES initialization fails), retry with the software renderer to avoid a black screen.
@@ -214,40 +717,85 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     let music_dir = std::env::var("AUDIO_PLAYER_MUSIC_DIR").ok().map(PathBuf::from)
         .or_else(|| std::env::current_dir().ok().map(|p| p.join("music")));
 
-    let songs: Vec<SongItem> = music_dir.as_ref()
-        .and_then(|dir| std::fs::read_dir(dir).ok())
-        .map(|entries| {
-            let mut v: Vec<SongItem> = entries.filter_map(|e| e.ok()).filter_map(|e| {
-                let p = e.path();
-                if p.is_file() {
-                    if let Some(ext) = p.extension().and_then(|s| s.to_str()) {
-                        const EXTS: &[&str] = &["mp3","flac","wav","ogg","opus","aac","m4a","alac","aiff","aif"]; 
-                        if EXTS.iter().any(|x| x.eq_ignore_ascii_case(ext)) {
-                            let title = p.file_name().and_then(|n| n.to_str()).unwrap_or("Unknown").to_string();
-                            return Some(SongItem{ title, path: p });
-                        }
-                    }
-                }
-                None
-            }).collect();
-            v.sort_by(|a,b| a.title.to_lowercase().cmp(&b.title.to_lowercase()));
-            v
-        })
-        .unwrap_or_default();
+    let mut songs: Vec<SongItem> = music_dir.as_deref().map(scan_songs).unwrap_or_default();
+    // Radio recordings are kept in their own folder and listed after the rest of the library
+    let recordings_dir = music_dir.as_ref().map(|dir| dir.join(RECORDINGS_DIR));
+    songs.extend(recordings_dir.as_deref().map(scan_songs).unwrap_or_default());
 
+    let playlists = Arc::new(Mutex::new(music_dir.as_deref().map(scan_playlists).unwrap_or_default()));
+    let play_queue = Arc::new(Mutex::new(None::<PlayQueue>));
+    let podcasts = Arc::new(Mutex::new(Podcasts::load(&music_dir.clone().unwrap_or_default().join(PODCASTS_DIR))));
+    // Feed URL, guid and file/URL of the podcast episode that is playing, to keep its position and played state
+    let playing_episode = Arc::new(Mutex::new(None::<(String, String, PathBuf)>));
+    let settings = Arc::new(Mutex::new(Settings::load()));
+    let remote = Arc::new(Mutex::new(settings.lock().unwrap().subsonic.clone().map(|a| Browser::new(SubsonicClient::from_account(a)))));
+    let dlna = Arc::new(Mutex::new(Dlna::default()));
+    let scrobbler = Arc::new(Mutex::new(Scrobbler::load(settings.lock().unwrap().scrobble_accounts.clone())));
+    let lrc_editor: Arc<Mutex<Option<LrcEditor>>> = Arc::new(Mutex::new(None));
+    let soundfonts = find_soundfonts(music_dir.as_deref());
     let filtered_indices = Arc::new(Mutex::new((0..songs.len()).collect::<Vec<usize>>()));
     let shuffle_order = Arc::new(Mutex::new(Vec::<usize>::new()));
     let repeat_one = Arc::new(Mutex::new(false));
     let shuffle = Arc::new(Mutex::new(false));
     let eq_gains = Arc::new(Mutex::new([0.0f32; 10]));
 
-    let model_songs = songs.iter().map(|s| Song{ title: SharedString::from(s.title.clone())}).collect::<Vec<_>>();
-    ui.set_songs(slint::ModelRc::new(slint::VecModel::from(model_songs)));
+    update_song_list(&ui, &songs, &filtered_indices.lock().unwrap(), &[]);
 
     // Don't fail the UI if audio backend isn't ready; initialize audio lazily on first playback.
-    let engine = Arc::new(Mutex::new(AudioEngine::new()));
+    // AUDIO_PLAYER_OUTPUT=null|null-fast|wav:<path> runs without a sound card.
+    let engine = Arc::new(Mutex::new(AudioEngine::with_output(backend_from_env())));
+    {
+        let settings = settings.lock().unwrap();
+        let mut eng = engine.lock().unwrap();
+        eng.preferred_device = settings.output_device.clone();
+        eng.auto_resume = settings.auto_resume_output;
+        eng.resample_quality = settings.resample_quality;
+        eng.match_native_rate = settings.match_native_rate;
+        eng.trim_silence = settings.trim_silence;
+        eng.recordings_dir = recordings_dir.clone();
+        eng.soundfont = settings.soundfont.clone().filter(|p| p.is_file()).or_else(|| soundfonts.first().cloned());
+        let mut names = soundfonts.iter().map(|p| SharedString::from(p.file_name().and_then(|n| n.to_str()).unwrap_or_default())).collect::<Vec<_>>();
+        if names.is_empty() { names.push(SharedString::from("(no .sf2 found)")); }
+        ui.set_soundfonts(slint::ModelRc::new(slint::VecModel::from(names)));
+        ui.set_soundfont_index(eng.soundfont.as_ref().and_then(|sf| soundfonts.iter().position(|p| p == sf)).unwrap_or(0) as i32);
+        eng.max_pause = settings.smart_speed.then(|| settings.smart_speed_max_pause.unwrap_or(DEFAULT_MAX_PAUSE));
+        ui.set_smart_speed(settings.smart_speed);
+        ui.set_smart_speed_max_pause_ms(settings.smart_speed_max_pause.unwrap_or(DEFAULT_MAX_PAUSE).as_millis() as i32);
+        eng.keep_silence_dirs = settings.keep_silence_dirs.clone();
+        eng.silence = SilenceAnalyzer::new(settings.silence_threshold_db.unwrap_or(DEFAULT_THRESHOLD_DB));
+        ui.set_trim_silence(settings.trim_silence);
+        ui.set_auto_resume_output(settings.auto_resume_output);
+        ui.set_resample_qualities(slint::ModelRc::new(slint::VecModel::from(ResampleQuality::ALL.iter().map(|q| SharedString::from(q.label())).collect::<Vec<_>>())));
+        ui.set_resample_quality_index(ResampleQuality::ALL.iter().position(|q| *q == settings.resample_quality).unwrap_or(0) as i32);
+        ui.set_match_native_rate(settings.match_native_rate);
+        if let Some(gains) = settings.eq_profiles.get(&eng.device_profile_key()) {
+            *eq_gains.lock().unwrap() = *gains;
+            eng.eq.set_gains_db(*gains);
+        }
+        ui.set_eq_values(slint::ModelRc::new(slint::VecModel::from(eq_slider_values(&eq_gains.lock().unwrap()))));
+        update_station_list(&ui, &settings.stations);
+        if let Some(account) = &settings.subsonic {
+            ui.set_remote_connected(true);
+            ui.set_remote_server(SharedString::from(account.url.as_str()));
+            ui.set_remote_user(SharedString::from(account.user.as_str()));
+            ui.set_remote_format(SharedString::from(account.format.as_str()));
+            ui.set_remote_max_bitrate(account.max_bitrate as i32);
+        }
+        update_schedule_list(&ui, &settings.record_schedules);
+        ui.set_scrobble_apis(slint::ModelRc::new(slint::VecModel::from(ScrobbleApi::ALL.iter().map(|a| SharedString::from(a.label())).collect::<Vec<_>>())));
+        update_scrobble_view(&ui, &scrobbler.lock().unwrap());
+        update_playlist_views(&ui, &playlists.lock().unwrap(), None);
+        let mut podcasts = podcasts.lock().unwrap();
+        update_podcast_views(&ui, &podcasts, None);
+        podcasts.refresh_all();
+    }
+    let output_devices = Arc::new(Mutex::new(Vec::<String>::new()));
+    refresh_output_devices(&ui, &engine.lock().unwrap(), &output_devices);
     // Show an initial status so we can verify UI renders on startup
     ui.set_status_text(SharedString::from(format!("Loaded {} song(s)", songs.len())));
+    // Tags read in the background and saved tag edits update the library in place
+    let songs = Arc::new(Mutex::new(songs));
+    let tag_editor = Arc::new(Mutex::new(TagEditor::default()));
     let selected = Arc::new(Mutex::new(None::<usize>));
     let search = Arc::new(Mutex::new(String::new()));
 
@@ -256,28 +804,51 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
         let engine = engine.clone();
         let songs = songs.clone();
         let selected = selected.clone();
+        let play_queue = play_queue.clone();
+        let filtered_indices = filtered_indices.clone();
+        let tag_editor = tag_editor.clone();
         let ui_handle = ui.as_weak();
         ui.on_request_select(move |index| {
+            let songs = songs.lock().unwrap();
+            // While the tag editor is open, tapping a song ticks it for editing instead of playing it
+            if let Some(ui) = ui_handle.upgrade() && ui.get_tags_visible() {
+                let fi = filtered_indices.lock().unwrap();
+                let Some(&song) = usize::try_from(index).ok().and_then(|i| fi.get(i)) else { return };
+                if songs[song].range.is_some() {
+                    ui.set_status_text(SharedString::from("CUE tracks take their tags from the sheet"));
+                    return;
+                }
+                let mut editor = tag_editor.lock().unwrap();
+                match editor.marked.iter().position(|&i| i == song) {
+                    Some(at) => { editor.marked.remove(at); }
+                    None => editor.marked.push(song),
+                }
+                update_song_list(&ui, &songs, &fi, &editor.marked);
+                update_tag_view(&ui, &songs, &editor);
+                return;
+            }
+            // Rows show the search results, so map the row back to the song as the tag editor does
+            let Some(song) = usize::try_from(index).ok().and_then(|i| filtered_indices.lock().unwrap().get(i).copied()) else { return };
+            *play_queue.lock().unwrap() = None;
             let mut sel = selected.lock().unwrap();
-            *sel = Some(index as usize);
+            *sel = Some(song);
             if let Some(ui) = ui_handle.upgrade() { ui.set_selected_index(index); }
             // Toggle pause/resume if already playing this track
-            if let Ok(mut eng) = engine.lock() {
-                if let Some(cur_idx) = *sel {
-                    if let Some(cur_path) = &eng.current_path { if songs.get(cur_idx).map(|s| &s.path) == Some(cur_path) {
+            if let Ok(mut eng) = engine.lock()
+                && let Some(cur_idx) = *sel {
+                    if songs.get(cur_idx).is_some_and(|s| eng.is_current(s)) {
                         if eng.is_playing() { eng.pause(); } else { eng.resume(); }
                         if let Some(ui) = ui_handle.upgrade() { ui.set_status_text(SharedString::from("Toggled")); }
                         return;
-                    }}
+                    }
                     if let Some(item) = songs.get(cur_idx) {
-                        if let Err(e) = eng.play_file(&item.path) {
-                            if let Some(ui) = ui_handle.upgrade() { ui.set_status_text(SharedString::from(format!("{e}"))); }
+                        if let Err(e) = eng.play_item(item) {
+                            if let Some(ui) = ui_handle.upgrade() { ui.set_status_text(SharedString::from(e.to_string())); }
                         } else {
                             if let Some(ui) = ui_handle.upgrade() { ui.set_status_text(SharedString::from(format!("Playing: {}", item.title))); }
                         }
                     }
                 }
-            }
         });
     }
 
@@ -289,11 +860,14 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
         let filtered_indices = filtered_indices.clone();
         ui.on_request_play_pause(move || {
             if let Ok(mut eng) = engine.lock() {
-                if eng.sink.as_ref().map(|s| s.empty()).unwrap_or(true) {
-                    if let Some(idx) = *selected.lock().unwrap() {
-                        if let Some(item) = songs.get(idx) { let _ = eng.play_file(&item.path); }
-                    } else if let Some(&first) = filtered_indices.lock().unwrap().first() {
-                        if let Some(item) = songs.get(first) { let _ = eng.play_file(&item.path); }
+                if eng.output_lost.is_some() {
+                    eng.resume();
+                } else if eng.is_idle() {
+                    let fi = filtered_indices.lock().unwrap().clone();
+                    let start = selected.lock().unwrap().or_else(|| fi.first().copied());
+                    if let Some(start) = start {
+                        let result = eng.play_first_playable(&songs.lock().unwrap(), std::iter::once(start).chain(walk_from(&fi, start, true, false)));
+                        show_play_result(&ui_handle, &selected, &filtered_indices, result);
                     }
                 } else {
                     if eng.is_playing() { eng.pause(); } else { eng.resume(); }
@@ -311,30 +885,36 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
         let filtered_indices = filtered_indices.clone();
         let shuffle_c = shuffle.clone();
         let shuffle_order_c = shuffle_order.clone();
+        let play_queue = play_queue.clone();
         ui.on_request_prev(move || {
+            if let Some(queue) = play_queue.lock().unwrap().as_mut() && let Ok(mut eng) = engine.lock() {
+                if eng.current_position() > Duration::from_secs(3) {
+                    let _ = eng.seek_to(Duration::ZERO);
+                } else {
+                    let result = eng.play_first_playable(&queue.items, (0..queue.index).rev());
+                    show_queue_result(&ui_handle, queue, result);
+                }
+                if let Some(ui) = ui_handle.upgrade() { ui.set_is_playing(eng.is_playing()); }
+                return;
+            }
             let fi = filtered_indices.lock().unwrap().clone();
             let cur = {
                 let s = selected.lock().unwrap();
                 (*s).or_else(|| fi.first().copied())
             };
-            if let Some(cur_idx) = cur {
-                if let Ok(mut eng) = engine.lock() {
+            if let Some(cur_idx) = cur
+                && let Ok(mut eng) = engine.lock() {
                     if eng.current_position() > Duration::from_secs(3) {
                         let _ = eng.seek_to(Duration::ZERO);
                     } else {
-                        let idx = if *shuffle_c.lock().unwrap() {
-                            // in shuffle mode, pick previous within shuffled list
-                            let so = shuffle_order_c.lock().unwrap().clone();
-                            let pos = so.iter().position(|&x| x == cur_idx).and_then(|p| p.checked_sub(1));
-                            pos.map(|p| so[p]).or_else(|| so.last().copied())
-                        } else {
-                            fi.iter().position(|&x| x == cur_idx).and_then(|p| p.checked_sub(1)).map(|p| fi[p])
-                        };
-                        if let Some(idx) = idx { if let Some(item) = songs.get(idx) { let _ = eng.play_file(&item.path); } if let Some(ui) = ui_handle.upgrade() { ui.set_selected_index(idx as i32); } }
+                        // In shuffle mode go back within the shuffled list, wrapping round
+                        let shuffle = *shuffle_c.lock().unwrap();
+                        let order = if shuffle { shuffle_order_c.lock().unwrap().clone() } else { fi };
+                        let result = eng.play_first_playable(&songs.lock().unwrap(), walk_from(&order, cur_idx, false, shuffle));
+                        show_play_result(&ui_handle, &selected, &filtered_indices, result);
                     }
                     if let Some(ui) = ui_handle.upgrade() { ui.set_is_playing(eng.is_playing()); }
                 }
-            }
         });
     }
 
@@ -346,28 +926,35 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
         let filtered_indices = filtered_indices.clone();
         let shuffle_c2 = shuffle.clone();
         let shuffle_order_c2 = shuffle_order.clone();
+        let play_queue = play_queue.clone();
         ui.on_request_next(move || {
+            if let Some(queue) = play_queue.lock().unwrap().as_mut() && let Ok(mut eng) = engine.lock() {
+                let result = eng.play_first_playable(&queue.items, queue.index + 1..queue.items.len());
+                show_queue_result(&ui_handle, queue, result);
+                if let Some(ui) = ui_handle.upgrade() { ui.set_is_playing(eng.is_playing()); }
+                return;
+            }
             let fi = filtered_indices.lock().unwrap().clone();
             let cur = {
                 let s = selected.lock().unwrap();
                 (*s).or_else(|| fi.first().copied())
             };
-            if let Some(cur_idx) = cur {
-                let idx_opt = if *shuffle_c2.lock().unwrap() {
-                    let so = shuffle_order_c2.lock().unwrap().clone();
-                    so.iter().position(|&x| x == cur_idx).and_then(|p| so.get(p+1)).copied().or_else(|| so.first().copied())
-                } else {
-                    fi.iter().position(|&x| x == cur_idx).and_then(|p| fi.get(p+1)).copied()
-                };
-                if let Some(idx) = idx_opt { if let Ok(mut eng) = engine.lock() { if let Some(item) = songs.get(idx) { let _ = eng.play_file(&item.path); } if let Some(ui) = ui_handle.upgrade() { ui.set_selected_index(idx as i32); ui.set_is_playing(eng.is_playing()); } } }
+            if let Some(cur_idx) = cur && let Ok(mut eng) = engine.lock() {
+                let shuffle = *shuffle_c2.lock().unwrap();
+                let order = if shuffle { shuffle_order_c2.lock().unwrap().clone() } else { fi };
+                let result = eng.play_first_playable(&songs.lock().unwrap(), walk_from(&order, cur_idx, true, shuffle));
+                show_play_result(&ui_handle, &selected, &filtered_indices, result);
+                if let Some(ui) = ui_handle.upgrade() { ui.set_is_playing(eng.is_playing()); }
             }
         });
     }
 
     {
         let engine = engine.clone();
+        let play_queue = play_queue.clone();
         let ui_handle = ui.as_weak();
         ui.on_request_stop(move || {
+            *play_queue.lock().unwrap() = None;
             if let Ok(mut eng) = engine.lock() { eng.stop(); }
             if let Some(ui) = ui_handle.upgrade() { ui.set_is_playing(false); ui.set_time_text(SharedString::new()); }
         });
@@ -377,9 +964,9 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
         let engine = engine.clone();
         let ui_handle = ui.as_weak();
         ui.on_request_seek(move |value| {
-            if let Ok(mut eng) = engine.lock() {
-                if let Some(total) = eng.total_duration() {
-                    let position = Duration::from_secs_f32(total.as_secs_f32() * value as f32);
+            if let Ok(mut eng) = engine.lock()
+                && let Some(total) = eng.total_duration() {
+                    let position = Duration::from_secs_f32(total.as_secs_f32() * value);
                     let _ = eng.seek_to(position);
                     if let Some(ui) = ui_handle.upgrade() {
                         let text = format!("{} / {}", format_time(eng.current_position()), format_time(total));
@@ -387,7 +974,6 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
                         ui.set_progress(value);
                     }
                 }
-            }
         });
     }
 
@@ -396,20 +982,19 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
         let filtered_indices_arc = filtered_indices.clone();
         let ui_handle = ui.as_weak();
         let songs = songs.clone();
+        let tag_editor = tag_editor.clone();
+        let selected = selected.clone();
         ui.on_search_changed(move |text| {
             {
                 let mut s = search.lock().unwrap();
                 *s = text.to_string();
             }
-            let q = search.lock().unwrap().to_lowercase();
+            let songs = songs.lock().unwrap();
             let mut fi = filtered_indices_arc.lock().unwrap();
-            fi.clear();
-            for (i, item) in songs.iter().enumerate() {
-                if q.is_empty() || item.title.to_lowercase().contains(&q) { fi.push(i); }
-            }
+            *fi = filter_songs(&songs, &search.lock().unwrap());
             if let Some(ui) = ui_handle.upgrade() {
-                let items = fi.iter().map(|&i| Song{ title: SharedString::from(songs[i].title.clone()) }).collect::<Vec<_>>();
-                ui.set_songs(slint::ModelRc::new(slint::VecModel::from(items)));
+                update_song_list(&ui, &songs, &fi, &tag_editor.lock().unwrap().marked);
+                show_selection(&ui, &fi, *selected.lock().unwrap());
             }
         });
     }
@@ -418,45 +1003,24 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     {
         let engine = engine.clone();
         let ui_handle = ui.as_weak();
-        let songs = songs.clone();
-        let selected = selected.clone();
-        let filtered_indices = filtered_indices.clone();
-    let repeat_one = repeat_one.clone();
-    let shuffle = shuffle.clone();
-    let shuffle_order = shuffle_order.clone();
+        let mut tags = TagLoader::default();
+        let mut files: Vec<PathBuf> = songs.lock().unwrap().iter().map(|s| s.path.clone()).collect();
+        files.dedup();
+        tags.load(files);
+        let mut poller = Poller {
+            songs: songs.clone(), selected: selected.clone(), filtered_indices: filtered_indices.clone(), search: search.clone(),
+            repeat_one: repeat_one.clone(), shuffle: shuffle.clone(), shuffle_order: shuffle_order.clone(), settings: settings.clone(),
+            play_queue: play_queue.clone(), podcasts: podcasts.clone(), playing_episode: playing_episode.clone(), remote: remote.clone(),
+            dlna: dlna.clone(), scrobbler: scrobbler.clone(), tag_editor: tag_editor.clone(),
+            shown_chapters: (Vec::new(), None), shown_lyrics: (None, None), shown_failures: Vec::new(), art: ArtLoader::default(), tags,
+            scheduler: Scheduler::default(), listens: ListenTracker::default(), ticks: 0,
+        };
         let timer = Box::leak(Box::new(slint::Timer::default()));
         timer.start(slint::TimerMode::Repeated, std::time::Duration::from_millis(200), move || {
-            if let Ok(mut eng) = engine.lock() {
-                if let Some(total) = eng.total_duration() {
-                    let total_secs = total.as_secs_f32().max(0.001);
-                    let ratio = (eng.current_position().as_secs_f32() / total_secs).clamp(0.0, 1.0);
-                    if let Some(ui) = ui_handle.upgrade() {
-                        let text = format!("{} / {}", format_time(eng.current_position()), format_time(total));
-                        ui.set_time_text(SharedString::from(text));
-                        ui.set_progress(ratio as f32);
-                        ui.set_is_playing(eng.is_playing());
-                    }
-                }
-                // Auto-advance
-                if eng.sink.as_ref().map(|s| !s.is_paused() && s.empty()).unwrap_or(false) {
-                    let fi = filtered_indices.lock().unwrap().clone();
-                    let cur_idx = selected.lock().unwrap().or_else(|| fi.first().copied());
-                    if let Some(cur_idx) = cur_idx {
-                        let next_idx_opt = if *repeat_one.lock().unwrap() {
-                            Some(cur_idx)
-                        } else if *shuffle.lock().unwrap() {
-                            let so = shuffle_order.lock().unwrap().clone();
-                            so.iter().position(|&x| x == cur_idx).and_then(|p| so.get(p+1)).copied().or_else(|| so.first().copied())
-                        } else {
-                            fi.iter().position(|&x| x == cur_idx).and_then(|p| fi.get(p+1)).copied()
-                        };
-                        if let Some(next_idx) = next_idx_opt {
-                            if let Some(item) = songs.get(next_idx) { let _ = eng.play_file(&item.path); }
-                            if let Some(ui) = ui_handle.upgrade() { ui.set_selected_index(next_idx as i32); }
-                        }
-                    }
-                }
-            }
+            let Some(ui) = ui_handle.upgrade() else { return };
+            // Podcast state is written out after the engine is unlocked, so playback doesn't wait on the disk
+            let save_podcasts = engine.lock().is_ok_and(|mut eng| poller.tick(&ui, &mut eng));
+            if save_podcasts && let Err(e) = poller.podcasts.lock().unwrap().save() { log::warn!("{e}"); }
         });
     }
 
@@ -496,22 +1060,2078 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
         let eq_gains = eq_gains.clone();
         let engine = engine.clone();
         ui.on_eq_band_changed(move |index, value| {
-            if index >= 0 && index < 10 { let idx = index as usize; let mut gains = eq_gains.lock().unwrap(); gains[idx] = (value - 0.5) * 24.0; }
-            // To apply new EQ, restart at current position if a track is loaded
+            if !(0..10).contains(&index) { return; }
+            let mut gains = eq_gains.lock().unwrap();
+            gains[index as usize] = (value - 0.5) * 24.0;
+            // The playing source follows these gains, so there's nothing to reload
+            if let Ok(eng) = engine.lock() { eng.eq.set_gains_db(*gains); }
+        });
+    }
+    {
+        let eq_gains = eq_gains.clone();
+        let engine = engine.clone();
+        let settings = settings.clone();
+        ui.on_eq_band_released(move || {
+            // Remember the curve for the current output device once the drag ends
+            let key = match engine.lock() { Ok(eng) => eng.device_profile_key(), Err(_) => return };
+            let mut st = settings.lock().unwrap();
+            st.eq_profiles.insert(key, *eq_gains.lock().unwrap());
+            if let Err(e) = st.save() { log::warn!("{e}"); }
+        });
+    }
+
+    {
+        let engine = engine.clone();
+        let output_devices = output_devices.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_toggle_settings(move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                let visible = !ui.get_settings_visible();
+                // Re-enumerate devices when opening the panel so newly attached outputs show up
+                if visible { refresh_output_devices(&ui, &engine.lock().unwrap(), &output_devices); }
+                ui.set_settings_visible(visible);
+            }
+        });
+    }
+    {
+        let engine = engine.clone();
+        let output_devices = output_devices.clone();
+        let settings = settings.clone();
+        let eq_gains = eq_gains.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_output_device_selected(move |index| {
+            // Index 0 is "System default", the rest map onto the enumerated device names
+            let name = if index <= 0 { None } else { output_devices.lock().unwrap().get(index as usize - 1).cloned() };
             if let Ok(mut eng) = engine.lock() {
-                if let Some(path) = eng.current_path.clone() {
-                    let pos = eng.current_position();
-                    let paused = eng.sink.as_ref().map(|s| s.is_paused()).unwrap_or(false);
-                    // Update engine EQ gains
-                    if let Ok(g) = eq_gains.lock() { eng.eq.set_gains_db(*g); }
-                    let _ = eng.play_from(&path, pos, paused);
-                } else {
-                    if let Ok(g) = eq_gains.lock() { eng.eq.set_gains_db(*g); }
+                // Load the EQ profile of the new device before reopening so playback resumes with it
+                let key = name.clone().unwrap_or_else(|| "default".to_string());
+                let mut st = settings.lock().unwrap();
+                if let Some(gains) = st.eq_profiles.get(&key).copied() {
+                    *eq_gains.lock().unwrap() = gains;
+                    eng.eq.set_gains_db(gains);
                 }
+                let result = eng.set_output_device(name.clone());
+                st.output_device = name;
+                if let Err(e) = st.save() { log::warn!("{e}"); }
+                if let Some(ui) = ui_handle.upgrade() {
+                    ui.set_eq_values(slint::ModelRc::new(slint::VecModel::from(eq_slider_values(&eq_gains.lock().unwrap()))));
+                    let text = match result {
+                        Ok(()) => format!("Output: {}", eng.active_device().unwrap_or_default()),
+                        Err(e) => e,
+                    };
+                    ui.set_status_text(SharedString::from(text));
+                    ui.set_is_playing(eng.is_playing());
+                }
+            }
+        });
+    }
+    {
+        let engine = engine.clone();
+        let settings = settings.clone();
+        ui.on_auto_resume_output_toggled(move |enabled| {
+            if let Ok(mut eng) = engine.lock() { eng.auto_resume = enabled; }
+            let mut st = settings.lock().unwrap();
+            st.auto_resume_output = enabled;
+            if let Err(e) = st.save() { log::warn!("{e}"); }
+        });
+    }
+    {
+        let engine = engine.clone();
+        let settings = settings.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_resample_quality_selected(move |index| {
+            let Some(quality) = ResampleQuality::ALL.get(index.max(0) as usize).copied() else { return };
+            if let Ok(mut eng) = engine.lock() {
+                eng.resample_quality = quality;
+                let _ = eng.reload();
+                if let Some(ui) = ui_handle.upgrade() { ui.set_rate_text(SharedString::from(eng.rate_text())); }
+            }
+            let mut st = settings.lock().unwrap();
+            st.resample_quality = quality;
+            if let Err(e) = st.save() { log::warn!("{e}"); }
+        });
+    }
+    {
+        let engine = engine.clone();
+        let settings = settings.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_match_native_rate_toggled(move |enabled| {
+            if let Ok(mut eng) = engine.lock() {
+                eng.match_native_rate = enabled;
+                let _ = eng.reload();
+                if let Some(ui) = ui_handle.upgrade() { ui.set_rate_text(SharedString::from(eng.rate_text())); }
             }
+            let mut st = settings.lock().unwrap();
+            st.match_native_rate = enabled;
+            if let Err(e) = st.save() { log::warn!("{e}"); }
         });
     }
 
-    ui.run()?;
-    Ok(())
+    {
+        let engine = engine.clone();
+        let settings = settings.clone();
+        ui.on_trim_silence_toggled(move |enabled| {
+            // Takes effect from the next track (analysed once it is queued up); the current one keeps playing as it is
+            if let Ok(mut eng) = engine.lock() { eng.trim_silence = enabled; }
+            let mut st = settings.lock().unwrap();
+            st.trim_silence = enabled;
+            if let Err(e) = st.save() { log::warn!("{e}"); }
+        });
+    }
+    {
+        let engine = engine.clone();
+        let settings = settings.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_keep_album_silence_toggled(move |keep| {
+            let Ok(mut eng) = engine.lock() else { return };
+            let Some(dir) = eng.current_path.as_ref().and_then(|p| p.parent()).map(Path::to_path_buf) else { return };
+            eng.keep_silence_dirs.retain(|d| *d != dir);
+            if keep { eng.keep_silence_dirs.push(dir.clone()); }
+            let mut st = settings.lock().unwrap();
+            st.keep_silence_dirs = eng.keep_silence_dirs.clone();
+            if let Err(e) = st.save() { log::warn!("{e}"); }
+            if let Some(ui) = ui_handle.upgrade() {
+                ui.set_status_text(SharedString::from(if keep { format!("Keeping silence in {}", dir.display()) } else { format!("Trimming silence in {}", dir.display()) }));
+            }
+        });
+    }
+    {
+        let engine = engine.clone();
+        let settings = settings.clone();
+        let ui_handle = ui.as_weak();
+        let apply = move |enabled: bool, max_pause_ms: i32| {
+            let max_pause = Duration::from_millis(max_pause_ms.max(50) as u64);
+            if let Ok(mut eng) = engine.lock() {
+                eng.max_pause = enabled.then_some(max_pause);
+                let _ = eng.reload();
+            }
+            let mut st = settings.lock().unwrap();
+            st.smart_speed = enabled;
+            st.smart_speed_max_pause = Some(max_pause);
+            if let Err(e) = st.save() { log::warn!("{e}"); }
+        };
+        let apply = Arc::new(apply);
+        {
+            let apply = apply.clone();
+            let ui_handle = ui_handle.clone();
+            ui.on_smart_speed_toggled(move |enabled| {
+                if let Some(ui) = ui_handle.upgrade() { apply(enabled, ui.get_smart_speed_max_pause_ms()); }
+            });
+        }
+        ui.on_smart_speed_max_pause_edited(move |ms| {
+            if let Some(ui) = ui_handle.upgrade() { apply(ui.get_smart_speed(), ms); }
+        });
+    }
+
+    {
+        let engine = engine.clone();
+        ui.on_next_chapter(move || { if let Ok(mut eng) = engine.lock() { let _ = eng.next_chapter(); } });
+    }
+    {
+        let engine = engine.clone();
+        ui.on_prev_chapter(move || { if let Ok(mut eng) = engine.lock() { let _ = eng.prev_chapter(); } });
+    }
+    {
+        let engine = engine.clone();
+        ui.on_chapter_selected(move |index| { if let Ok(mut eng) = engine.lock() { let _ = eng.seek_chapter(index.max(0) as usize); } });
+    }
+    {
+        let ui_handle = ui.as_weak();
+        ui.on_toggle_chapters(move || {
+            if let Some(ui) = ui_handle.upgrade() { ui.set_chapters_visible(!ui.get_chapters_visible()); }
+        });
+    }
+    {
+        let ui_handle = ui.as_weak();
+        ui.on_toggle_lyrics(move || {
+            if let Some(ui) = ui_handle.upgrade() { ui.set_lyrics_visible(!ui.get_lyrics_visible()); }
+        });
+    }
+    {
+        let engine = engine.clone();
+        let settings = settings.clone();
+        ui.on_lyric_selected(move |index| {
+            if let Ok(mut eng) = engine.lock() {
+                let offset = eng.lyrics_offset(&settings.lock().unwrap());
+                let _ = eng.seek_lyric(index.max(0) as usize, offset);
+            }
+        });
+    }
+    {
+        let engine = engine.clone();
+        let settings = settings.clone();
+        ui.on_lyrics_offset_changed(move |delta| {
+            let Ok(eng) = engine.lock() else { return };
+            let Some(path) = eng.current_path.clone().filter(|_| eng.lyrics.as_ref().is_some_and(Lyrics::is_synced)) else { return };
+            // Remembered per file, so the same shift applies next time it plays
+            let mut st = settings.lock().unwrap();
+            let offset = st.lyrics_offsets.get(&path).copied().unwrap_or(0) + delta as i64;
+            if offset == 0 { st.lyrics_offsets.remove(&path); } else { st.lyrics_offsets.insert(path, offset); }
+            if let Err(e) = st.save() { log::warn!("{e}"); }
+        });
+    }
+    // Lyrics editor: stamps come from the playback position of the track being synced
+    {
+        let engine = engine.clone();
+        let lrc_editor = lrc_editor.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_lrc_start(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let Ok(eng) = engine.lock() else { return };
+            let (Some(path), Some(lyrics)) = (eng.current_path.as_ref().filter(|p| !is_url(p)), eng.lyrics.as_ref()) else {
+                ui.set_status_text(SharedString::from("Play a track with lyrics to sync them"));
+                return;
+            };
+            let mut editor = lrc_editor.lock().unwrap();
+            *editor = Some(LrcEditor::new(path, lyrics));
+            ui.set_lrc_previewing(false);
+            update_lrc_editor(&ui, editor.as_ref());
+        });
+    }
+    {
+        let engine = engine.clone();
+        let lrc_editor = lrc_editor.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_lrc_stamp(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let Ok(eng) = engine.lock() else { return };
+            let mut editor = lrc_editor.lock().unwrap();
+            let Some(ed) = editor.as_mut() else { return };
+            if eng.current_path.as_ref() != Some(&ed.path) {
+                ui.set_status_text(SharedString::from(format!("Play {} to sync its lyrics", file_label(&ed.path))));
+                return;
+            }
+            ed.stamp(eng.file_position());
+            update_lrc_editor(&ui, editor.as_ref());
+        });
+    }
+    {
+        let lrc_editor = lrc_editor.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_lrc_undo(move || {
+            let mut editor = lrc_editor.lock().unwrap();
+            if let Some(ed) = editor.as_mut() { ed.undo(); }
+            if let Some(ui) = ui_handle.upgrade() { update_lrc_editor(&ui, editor.as_ref()); }
+        });
+    }
+    {
+        let engine = engine.clone();
+        let lrc_editor = lrc_editor.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_lrc_nudge(move |delta| {
+            let mut editor = lrc_editor.lock().unwrap();
+            let Some(ed) = editor.as_mut() else { return };
+            ed.nudge(delta as i64);
+            // Replay the nudged line so the change can be heard
+            let start = ed.nudge_target().and_then(|i| ed.lines[i].start);
+            if let Ok(mut eng) = engine.lock() && eng.current_path.as_ref() == Some(&ed.path) && let Some(start) = start {
+                let _ = eng.seek_before(start);
+            }
+            if let Some(ui) = ui_handle.upgrade() { update_lrc_editor(&ui, editor.as_ref()); }
+        });
+    }
+    {
+        let engine = engine.clone();
+        let lrc_editor = lrc_editor.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_lrc_select(move |index| {
+            let mut editor = lrc_editor.lock().unwrap();
+            let Some(ed) = editor.as_mut() else { return };
+            ed.select(index.max(0) as usize);
+            let start = ed.lines.get(ed.current).and_then(|l| l.start);
+            if let Ok(mut eng) = engine.lock() && eng.current_path.as_ref() == Some(&ed.path) && let Some(start) = start {
+                let _ = eng.seek_before(start);
+            }
+            if let Some(ui) = ui_handle.upgrade() { update_lrc_editor(&ui, editor.as_ref()); }
+        });
+    }
+    {
+        let engine = engine.clone();
+        let settings = settings.clone();
+        let lrc_editor = lrc_editor.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_lrc_preview(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let previewing = !ui.get_lrc_previewing();
+            ui.set_lrc_previewing(previewing);
+            let editor = lrc_editor.lock().unwrap();
+            let (Some(ed), true) = (editor.as_ref(), previewing) else { return };
+            let Ok(mut eng) = engine.lock() else { return };
+            if eng.current_path.as_ref() != Some(&ed.path) { return; }
+            // The lyrics pane shows the stamps so far. They are exact, so the user's timing shift is cancelled out
+            let mut preview = ed.preview();
+            preview.offset_ms = -eng.lyrics_offset(&settings.lock().unwrap());
+            eng.lyrics = Some(preview);
+            let from = ed.nudge_target().or(ed.current.checked_sub(1)).and_then(|i| ed.lines.get(i)?.start);
+            let _ = eng.seek_before(from.unwrap_or_default());
+        });
+    }
+    {
+        let engine = engine.clone();
+        let settings = settings.clone();
+        let lrc_editor = lrc_editor.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_lrc_save(move |embed| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut editor = lrc_editor.lock().unwrap();
+            let Some(ed) = editor.as_ref() else { return };
+            let saved = ed.finish().and_then(|lyrics| {
+                if embed { embed_lyrics(&ed.path, &lyrics).map(|_| "the tag".to_string()) } else { save_sidecar(&ed.path, &lyrics).map(|p| file_label(&p)) }
+            });
+            let message = match saved {
+                Ok(place) => {
+                    // The new stamps are exact, so a timing shift set for the old lyrics no longer applies
+                    let mut st = settings.lock().unwrap();
+                    if st.lyrics_offsets.remove(&ed.path).is_some() && let Err(e) = st.save() { log::warn!("{e}"); }
+                    drop(st);
+                    if let Ok(mut eng) = engine.lock() && eng.current_path.as_ref() == Some(&ed.path) { eng.lyrics = read_lyrics(&ed.path); }
+                    let path = ed.path.clone();
+                    *editor = None;
+                    ui.set_lrc_previewing(false);
+                    update_lrc_editor(&ui, None);
+                    match sidecar(&path).filter(|_| embed) {
+                        Some(lrc) => format!("Lyrics saved to the tag, but {} is shown instead", file_label(&lrc)),
+                        None => format!("Synced lyrics saved to {place}"),
+                    }
+                }
+                Err(e) => e,
+            };
+            ui.set_status_text(SharedString::from(message));
+        });
+    }
+    {
+        let engine = engine.clone();
+        let lrc_editor = lrc_editor.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_lrc_cancel(move || {
+            let Some(ed) = lrc_editor.lock().unwrap().take() else { return };
+            // Drop a preview in favour of what is saved
+            if let Ok(mut eng) = engine.lock() && eng.current_path.as_ref() == Some(&ed.path) { eng.lyrics = read_lyrics(&ed.path); }
+            if let Some(ui) = ui_handle.upgrade() {
+                ui.set_lrc_previewing(false);
+                update_lrc_editor(&ui, None);
+            }
+        });
+    }
+    {
+        let ui_handle = ui.as_weak();
+        ui.on_toggle_failures(move || {
+            if let Some(ui) = ui_handle.upgrade() { ui.set_failures_visible(!ui.get_failures_visible()); }
+        });
+    }
+    {
+        let engine = engine.clone();
+        let settings = settings.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_soundfont_selected(move |index| {
+            let Some(path) = soundfonts.get(index.max(0) as usize).cloned() else { return };
+            if let Ok(mut eng) = engine.lock() {
+                eng.soundfont = Some(path.clone());
+                // Re-render a playing MIDI file with the new instruments
+                if eng.current_path.as_deref().is_some_and(is_midi) {
+                    let result = eng.reload();
+                    if let Err(e) = result && let Some(ui) = ui_handle.upgrade() { ui.set_status_text(SharedString::from(e)); }
+                }
+            }
+            let mut st = settings.lock().unwrap();
+            st.soundfont = Some(path);
+            if let Err(e) = st.save() { log::warn!("{e}"); }
+        });
+    }
+
+    // Internet radio favorites
+    {
+        let ui_handle = ui.as_weak();
+        ui.on_toggle_radio(move || {
+            if let Some(ui) = ui_handle.upgrade() { ui.set_radio_visible(!ui.get_radio_visible()); }
+        });
+    }
+    {
+        let engine = engine.clone();
+        let settings = settings.clone();
+        let selected = selected.clone();
+        let play_queue = play_queue.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_station_selected(move |index| {
+            let Some(station) = settings.lock().unwrap().stations.get(index.max(0) as usize).cloned() else { return };
+            let Some(ui) = ui_handle.upgrade() else { return };
+            ui.set_current_station(index);
+            ui.set_station_name(SharedString::from(station.name.as_str()));
+            ui.set_station_url(SharedString::from(station.url.as_str()));
+            let Ok(mut eng) = engine.lock() else { return };
+            *play_queue.lock().unwrap() = None;
+            let item = SongItem { title: station.name.clone(), path: PathBuf::from(&station.url), range: None, ..Default::default() };
+            match eng.play_item(&item) {
+                Ok(()) => {
+                    // The library selection no longer matches what is playing
+                    *selected.lock().unwrap() = None;
+                    ui.set_selected_index(-1);
+                    ui.set_status_text(SharedString::from(format!("Playing: {}", station_label(&station))));
+                }
+                Err(e) => ui.set_status_text(SharedString::from(e)),
+            }
+            ui.set_is_playing(eng.is_playing());
+        });
+    }
+    {
+        let settings = settings.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_station_add(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let Some(station) = station_from_fields(&ui) else { return };
+            let mut st = settings.lock().unwrap();
+            st.stations.push(station);
+            update_station_list(&ui, &st.stations);
+            ui.set_current_station(st.stations.len() as i32 - 1);
+            if let Err(e) = st.save() { log::warn!("{e}"); }
+        });
+    }
+    {
+        let settings = settings.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_station_update(move |index| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let Some(station) = station_from_fields(&ui) else { return };
+            let mut st = settings.lock().unwrap();
+            let Some(slot) = st.stations.get_mut(index.max(0) as usize) else { return };
+            *slot = station;
+            update_station_list(&ui, &st.stations);
+            if let Err(e) = st.save() { log::warn!("{e}"); }
+        });
+    }
+    {
+        let settings = settings.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_station_remove(move |index| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut st = settings.lock().unwrap();
+            let index = index.max(0) as usize;
+            if index >= st.stations.len() { return; }
+            st.stations.remove(index);
+            update_station_list(&ui, &st.stations);
+            ui.set_current_station(-1);
+            if let Err(e) = st.save() { log::warn!("{e}"); }
+        });
+    }
+
+    {
+        let engine = engine.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_toggle_recording(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let Ok(mut eng) = engine.lock() else { return };
+            if eng.is_recording() {
+                let count = eng.stop_recording();
+                ui.set_status_text(SharedString::from(format!("Recorded {count} file(s)")));
+            } else if let Err(e) = eng.start_recording() {
+                ui.set_status_text(SharedString::from(e));
+            }
+            ui.set_recording(eng.is_recording());
+        });
+    }
+    {
+        let settings = settings.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_schedule_add(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let Some(station) = station_from_fields(&ui) else { return };
+            let Some(start_minute) = parse_clock(&ui.get_schedule_time()) else {
+                ui.set_status_text(SharedString::from("Enter the start time as HH:MM"));
+                return;
+            };
+            let mut st = settings.lock().unwrap();
+            st.record_schedules.push(RecordSchedule { name: station.name, url: station.url, start_minute, minutes: ui.get_schedule_minutes().max(1) as u32 });
+            update_schedule_list(&ui, &st.record_schedules);
+            if let Err(e) = st.save() { log::warn!("{e}"); }
+        });
+    }
+    {
+        let settings = settings.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_schedule_remove(move |index| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut st = settings.lock().unwrap();
+            let index = index.max(0) as usize;
+            if index >= st.record_schedules.len() { return; }
+            st.record_schedules.remove(index);
+            update_schedule_list(&ui, &st.record_schedules);
+            ui.set_current_schedule(-1);
+            if let Err(e) = st.save() { log::warn!("{e}"); }
+        });
+    }
+
+    // Playlists
+    {
+        let ui_handle = ui.as_weak();
+        ui.on_toggle_playlists(move || {
+            if let Some(ui) = ui_handle.upgrade() { ui.set_playlists_visible(!ui.get_playlists_visible()); }
+        });
+    }
+    {
+        let playlists = playlists.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_playlist_selected(move |index| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let playlists = playlists.lock().unwrap();
+            let Some(playlist) = playlists.get(index.max(0) as usize) else { return };
+            update_playlist_views(&ui, &playlists, Some(index as usize));
+            ui.set_current_entry(-1);
+            let missing = playlist.missing();
+            let mut text = format!("{}: {} track(s)", playlist.name, playlist.entries.len());
+            if missing > 0 { text += &format!(", {missing} missing"); }
+            ui.set_status_text(SharedString::from(text));
+        });
+    }
+    {
+        let playlists = playlists.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_playlist_new(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let name = ui.get_playlist_name().trim().to_string();
+            if name.is_empty() { return; }
+            let mut playlists = playlists.lock().unwrap();
+            playlists.push(Playlist { name, ..Default::default() });
+            update_playlist_views(&ui, &playlists, Some(playlists.len() - 1));
+            ui.set_current_entry(-1);
+            ui.set_playlist_name(SharedString::new());
+        });
+    }
+    {
+        let engine = engine.clone();
+        let playlists = playlists.clone();
+        let play_queue = play_queue.clone();
+        let selected = selected.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_playlist_play(move |entry| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let index = ui.get_current_playlist().max(0) as usize;
+            let Some(playlist) = playlists.lock().unwrap().get(index).cloned() else { return };
+            let items = playlist.entries.iter().map(|e| SongItem { title: e.label(), path: e.path.clone(), range: None, ..Default::default() }).collect::<Vec<_>>();
+            let mut queue = PlayQueue { playlist: Some(index), items, index: 0 };
+            let Ok(mut eng) = engine.lock() else { return };
+            let result = eng.play_first_playable(&queue.items, entry.max(0) as usize..queue.items.len());
+            let started = matches!(result, Ok((Some(_), _)));
+            show_queue_result(&ui_handle, &mut queue, result);
+            if started {
+                // The library selection no longer matches what is playing
+                *selected.lock().unwrap() = None;
+                ui.set_selected_index(-1);
+                ui.set_status_text(SharedString::from(format!("Playing playlist {}", playlist.name)));
+                *play_queue.lock().unwrap() = Some(queue);
+            }
+            ui.set_is_playing(eng.is_playing());
+        });
+    }
+    {
+        let playlists = playlists.clone();
+        let songs = songs.clone();
+        let selected = selected.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_playlist_add_song(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let songs = songs.lock().unwrap();
+            let Some(song) = selected.lock().unwrap().and_then(|i| songs.get(i)) else {
+                ui.set_status_text(SharedString::from("Select a song in the library first"));
+                return;
+            };
+            let index = ui.get_current_playlist().max(0) as usize;
+            let mut playlists = playlists.lock().unwrap();
+            let Some(playlist) = playlists.get_mut(index) else { return };
+            playlist.entries.push(PlaylistEntry::new(song.path.clone(), Some(song.title.clone()), None));
+            let entry = playlist.entries.len() - 1;
+            update_playlist_views(&ui, &playlists, Some(index));
+            ui.set_current_entry(entry as i32);
+        });
+    }
+    {
+        let playlists = playlists.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_playlist_remove_entry(move |entry| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let index = ui.get_current_playlist().max(0) as usize;
+            let mut playlists = playlists.lock().unwrap();
+            let Some(playlist) = playlists.get_mut(index) else { return };
+            if entry < 0 || entry as usize >= playlist.entries.len() { return; }
+            playlist.entries.remove(entry as usize);
+            let remaining = playlist.entries.len() as i32;
+            update_playlist_views(&ui, &playlists, Some(index));
+            ui.set_current_entry(entry.min(remaining - 1));
+        });
+    }
+    {
+        let playlists = playlists.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_playlist_move_entry(move |entry, delta| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let index = ui.get_current_playlist().max(0) as usize;
+            let mut playlists = playlists.lock().unwrap();
+            let Some(playlist) = playlists.get_mut(index) else { return };
+            let to = entry + delta;
+            if entry < 0 || to < 0 || to as usize >= playlist.entries.len() { return; }
+            playlist.entries.swap(entry as usize, to as usize);
+            update_playlist_views(&ui, &playlists, Some(index));
+            ui.set_current_entry(to);
+        });
+    }
+    {
+        let playlists = playlists.clone();
+        let music_dir = music_dir.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_playlist_export(move |xspf| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let index = ui.get_current_playlist().max(0) as usize;
+            let mut playlists = playlists.lock().unwrap();
+            let Some(playlist) = playlists.get_mut(index) else { return };
+            // Next to the file it came from, so relative entries stay short; new playlists go in the music folder
+            let Some(dir) = playlist.file.as_ref().and_then(|f| f.parent()).map(Path::to_path_buf).or_else(|| music_dir.clone()) else { return };
+            let path = dir.join(format!("{}.{}", sanitize(&playlist.name), if xspf { "xspf" } else { "m3u8" }));
+            let result = if xspf { write_xspf(playlist, &path) } else { write_m3u8(playlist, &path) };
+            match result {
+                Ok(()) => {
+                    ui.set_status_text(SharedString::from(format!("Saved {}", path.display())));
+                    playlist.file = Some(path);
+                }
+                Err(e) => ui.set_status_text(SharedString::from(e)),
+            }
+        });
+    }
+
+    // Podcasts
+    {
+        let ui_handle = ui.as_weak();
+        ui.on_toggle_podcasts(move || {
+            if let Some(ui) = ui_handle.upgrade() { ui.set_podcasts_visible(!ui.get_podcasts_visible()); }
+        });
+    }
+    {
+        let podcasts = podcasts.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_podcast_subscribe(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let url = ui.get_podcast_url().trim().to_string();
+            if !is_url(Path::new(&url)) {
+                ui.set_status_text(SharedString::from("Enter an http:// or https:// feed URL"));
+                return;
+            }
+            let mut pods = podcasts.lock().unwrap();
+            match pods.subscribe(&url) {
+                Ok(index) => {
+                    update_podcast_views(&ui, &pods, Some(index));
+                    show_podcast_rules(&ui, &pods, index);
+                    ui.set_podcast_url(SharedString::new());
+                    ui.set_status_text(SharedString::from("Fetching feed…"));
+                    if let Err(e) = pods.save() { log::warn!("{e}"); }
+                }
+                Err(e) => ui.set_status_text(SharedString::from(e)),
+            }
+        });
+    }
+    {
+        let podcasts = podcasts.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_podcast_selected(move |index| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let pods = podcasts.lock().unwrap();
+            let index = index.max(0) as usize;
+            update_podcast_views(&ui, &pods, Some(index));
+            show_podcast_rules(&ui, &pods, index);
+            if let Some(sub) = pods.subscriptions.get(index) {
+                let status = match &sub.error {
+                    Some(e) => e.clone(),
+                    None if pods.is_refreshing(index) => format!("{}: refreshing…", sub.title),
+                    None => format!("{}: {} episode(s), {} unplayed", sub.title, sub.episodes.len(), pods.unplayed(index)),
+                };
+                ui.set_status_text(SharedString::from(status));
+            }
+        });
+    }
+    {
+        let podcasts = podcasts.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_podcast_unsubscribe(move |index| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut pods = podcasts.lock().unwrap();
+            pods.unsubscribe(index.max(0) as usize);
+            update_podcast_views(&ui, &pods, None);
+            if let Err(e) = pods.save() { log::warn!("{e}"); }
+        });
+    }
+    {
+        let podcasts = podcasts.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_podcast_refresh(move || {
+            podcasts.lock().unwrap().refresh_all();
+            if let Some(ui) = ui_handle.upgrade() { ui.set_status_text(SharedString::from("Refreshing podcasts…")); }
+        });
+    }
+    {
+        let podcasts = podcasts.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_podcast_rules_changed(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut pods = podcasts.lock().unwrap();
+            let index = ui.get_current_podcast().max(0) as usize;
+            pods.set_rules(index, ui.get_podcast_auto_download(), ui.get_podcast_delete_played(), ui.get_podcast_keep().max(0) as u32);
+            update_podcast_views(&ui, &pods, Some(index));
+            if let Err(e) = pods.save() { log::warn!("{e}"); }
+        });
+    }
+    {
+        let podcasts = podcasts.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_episode_selected(move |episode| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let pods = podcasts.lock().unwrap();
+            let index = ui.get_current_podcast().max(0) as usize;
+            let Some(ep) = pods.subscriptions.get(index).and_then(|s| s.episodes.get(episode.max(0) as usize)) else { return };
+            ui.set_current_episode(episode);
+            let length = ep.duration.map(|d| format!(" · {}", format_time(d))).unwrap_or_default();
+            ui.set_episode_notes(SharedString::from(format!("{}\n{}{length}\n\n{}", ep.title, ep.date_text(), ep.notes)));
+        });
+    }
+    {
+        let engine = engine.clone();
+        let podcasts = podcasts.clone();
+        let playing_episode = playing_episode.clone();
+        let play_queue = play_queue.clone();
+        let selected = selected.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_episode_play(move |episode| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let pods = podcasts.lock().unwrap();
+            let (index, episode) = (ui.get_current_podcast().max(0) as usize, episode.max(0) as usize);
+            let Some(sub) = pods.subscriptions.get(index) else { return };
+            let Some(ep) = sub.episodes.get(episode) else { return };
+            // Downloaded episodes play from disk and resume where they were left; others stream from the start
+            let path = pods.file(index, episode).unwrap_or_else(|| PathBuf::from(&ep.url));
+            let state = pods.state(index, episode);
+            let Ok(mut eng) = engine.lock() else { return };
+            let item = SongItem { title: ep.title.clone(), path: path.clone(), range: None, ..Default::default() };
+            match eng.play_item(&item) {
+                Ok(()) => {
+                    if !state.played && !state.position.is_zero() && !is_url(&path) { let _ = eng.seek_to(state.position); }
+                    *play_queue.lock().unwrap() = None;
+                    *selected.lock().unwrap() = None;
+                    ui.set_selected_index(-1);
+                    *playing_episode.lock().unwrap() = Some((sub.url.clone(), ep.guid.clone(), path));
+                    ui.set_status_text(SharedString::from(format!("Playing: {}", ep.title)));
+                }
+                Err(e) => ui.set_status_text(SharedString::from(e)),
+            }
+            ui.set_is_playing(eng.is_playing());
+        });
+    }
+    {
+        let podcasts = podcasts.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_episode_download(move |episode| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut pods = podcasts.lock().unwrap();
+            let index = ui.get_current_podcast().max(0) as usize;
+            pods.download(index, episode.max(0) as usize);
+            update_podcast_views(&ui, &pods, Some(index));
+        });
+    }
+    {
+        let podcasts = podcasts.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_episode_delete_download(move |episode| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut pods = podcasts.lock().unwrap();
+            let index = ui.get_current_podcast().max(0) as usize;
+            if let Err(e) = pods.delete_download(index, episode.max(0) as usize) { ui.set_status_text(SharedString::from(e)); }
+            update_podcast_views(&ui, &pods, Some(index));
+        });
+    }
+    {
+        let podcasts = podcasts.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_episode_toggle_played(move |episode| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut pods = podcasts.lock().unwrap();
+            let (index, episode) = (ui.get_current_podcast().max(0) as usize, episode.max(0) as usize);
+            let played = pods.state(index, episode).played;
+            pods.set_played(index, episode, !played);
+            update_podcast_views(&ui, &pods, Some(index));
+            if let Err(e) = pods.save() { log::warn!("{e}"); }
+        });
+    }
+
+    // Remote library
+    {
+        let remote = remote.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_toggle_remote(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let visible = !ui.get_remote_visible();
+            ui.set_remote_visible(visible);
+            let mut remote = remote.lock().unwrap();
+            // The first look at the server lists its artists
+            if visible && let Some(browser) = remote.as_mut() && browser.page().is_none() { browser.open(Page::Artists, true); }
+            update_remote_view(&ui, remote.as_ref());
+        });
+    }
+    {
+        let remote = remote.clone();
+        let settings = settings.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_remote_connect(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let server = ui.get_remote_server().trim().to_string();
+            if !is_url(Path::new(&server)) {
+                ui.set_status_text(SharedString::from("Enter the server's http:// or https:// address"));
+                return;
+            }
+            let mut client = SubsonicClient::new(&server, &ui.get_remote_user(), &ui.get_remote_password());
+            client.set_transcoding(ui.get_remote_max_bitrate().max(0) as u32, &ui.get_remote_format());
+            ui.set_remote_password(SharedString::new());
+            let mut st = settings.lock().unwrap();
+            st.subsonic = Some(client.account().clone());
+            if let Err(e) = st.save() { log::warn!("{e}"); }
+            let mut browser = Browser::new(SubsonicClient::from_account(client.account().clone()));
+            browser.open(Page::Artists, true);
+            let mut remote = remote.lock().unwrap();
+            *remote = Some(browser);
+            ui.set_remote_connected(true);
+            update_remote_view(&ui, remote.as_ref());
+        });
+    }
+    {
+        let remote = remote.clone();
+        let settings = settings.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_remote_disconnect(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            *remote.lock().unwrap() = None;
+            let mut st = settings.lock().unwrap();
+            st.subsonic = None;
+            if let Err(e) = st.save() { log::warn!("{e}"); }
+            ui.set_remote_connected(false);
+            update_remote_view(&ui, None);
+        });
+    }
+    {
+        let remote = remote.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_remote_show_artists(move || open_remote_page(&ui_handle, &remote, Page::Artists, true));
+    }
+    {
+        let remote = remote.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_remote_show_playlists(move || open_remote_page(&ui_handle, &remote, Page::Playlists, true));
+    }
+    {
+        let remote = remote.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_remote_search(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let query = ui.get_remote_query().trim().to_string();
+            if !query.is_empty() { open_remote_page(&ui_handle, &remote, Page::Search(query), false); }
+        });
+    }
+    {
+        let remote = remote.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_remote_back(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut remote = remote.lock().unwrap();
+            if let Some(browser) = remote.as_mut() && browser.back() { update_remote_view(&ui, Some(browser)); }
+        });
+    }
+    {
+        let engine = engine.clone();
+        let remote = remote.clone();
+        let play_queue = play_queue.clone();
+        let selected = selected.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_remote_row_clicked(move |index| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut remote = remote.lock().unwrap();
+            let Some(browser) = remote.as_mut() else { return };
+            let Some(row) = browser.rows().get(index.max(0) as usize).cloned() else { return };
+            let page = match row {
+                Row::Artist(artist) => Page::Artist(artist),
+                Row::Album(album) => Page::Album(album),
+                Row::Playlist(playlist) => Page::Playlist(playlist),
+                Row::Track(_) => {
+                    // Play the page's tracks from this one on, like a playlist
+                    let (tracks, start) = browser.tracks_from(index as usize);
+                    let client = browser.client();
+                    let items = tracks.iter().map(|t| SongItem { title: t.label(), path: PathBuf::from(client.stream_url(t)), range: None, ..Default::default() }).collect();
+                    let mut queue = PlayQueue { playlist: None, items, index: 0 };
+                    let Ok(mut eng) = engine.lock() else { return };
+                    let result = eng.play_first_playable(&queue.items, start..queue.items.len());
+                    let started = matches!(result, Ok((Some(_), _)));
+                    show_queue_result(&ui_handle, &mut queue, result);
+                    if started {
+                        *selected.lock().unwrap() = None;
+                        ui.set_selected_index(-1);
+                        ui.set_status_text(SharedString::from(format!("Playing from {}", browser.page().map(Page::title).unwrap_or_default())));
+                        *play_queue.lock().unwrap() = Some(queue);
+                    }
+                    ui.set_is_playing(eng.is_playing());
+                    return;
+                }
+            };
+            browser.open(page, false);
+            update_remote_view(&ui, Some(browser));
+        });
+    }
+    {
+        let remote = remote.clone();
+        let settings = settings.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_remote_transcoding_changed(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut remote = remote.lock().unwrap();
+            let Some(browser) = remote.as_mut() else { return };
+            browser.client_mut().set_transcoding(ui.get_remote_max_bitrate().max(0) as u32, &ui.get_remote_format());
+            let mut st = settings.lock().unwrap();
+            st.subsonic = Some(browser.client().account().clone());
+            if let Err(e) = st.save() { log::warn!("{e}"); }
+        });
+    }
+
+    // UPnP/DLNA media servers
+    {
+        let dlna = dlna.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_toggle_dlna(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let visible = !ui.get_dlna_visible();
+            ui.set_dlna_visible(visible);
+            let mut servers = dlna.lock().unwrap();
+            // The first look searches the network
+            if visible && servers.servers().is_empty() && !servers.is_searching() { servers.search(); }
+            update_dlna_view(&ui, &servers);
+        });
+    }
+    {
+        let dlna = dlna.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_dlna_search(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut servers = dlna.lock().unwrap();
+            servers.search();
+            update_dlna_view(&ui, &servers);
+        });
+    }
+    {
+        let dlna = dlna.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_dlna_add(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let location = ui.get_dlna_location().trim().to_string();
+            if !is_url(Path::new(&location)) {
+                ui.set_status_text(SharedString::from("Enter the server's http:// device description URL"));
+                return;
+            }
+            let mut servers = dlna.lock().unwrap();
+            servers.add(&location);
+            ui.set_dlna_location(SharedString::new());
+            update_dlna_view(&ui, &servers);
+        });
+    }
+    {
+        let dlna = dlna.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_dlna_server_selected(move |index| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut servers = dlna.lock().unwrap();
+            servers.select(index.max(0) as usize);
+            update_dlna_view(&ui, &servers);
+        });
+    }
+    {
+        let dlna = dlna.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_dlna_back(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut servers = dlna.lock().unwrap();
+            if servers.back() { update_dlna_view(&ui, &servers); }
+        });
+    }
+    {
+        let engine = engine.clone();
+        let dlna = dlna.clone();
+        let play_queue = play_queue.clone();
+        let selected = selected.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_dlna_row_clicked(move |index| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut servers = dlna.lock().unwrap();
+            let Some(entry) = servers.entries().get(index.max(0) as usize).cloned() else { return };
+            if entry.container {
+                servers.open(&entry);
+                update_dlna_view(&ui, &servers);
+                return;
+            }
+            // Play the container's items from this one on, like a playlist
+            let (entries, start) = servers.items_from(index as usize);
+            let items = entries.iter().filter_map(|e| Some(SongItem { title: e.label(), path: PathBuf::from(e.url.as_ref()?), range: None, ..Default::default() })).collect();
+            let mut queue = PlayQueue { playlist: None, items, index: 0 };
+            servers.show_details(Some(entry));
+            update_dlna_view(&ui, &servers);
+            let Ok(mut eng) = engine.lock() else { return };
+            let result = eng.play_first_playable(&queue.items, start..queue.items.len());
+            let started = matches!(result, Ok((Some(_), _)));
+            show_queue_result(&ui_handle, &mut queue, result);
+            if started {
+                *selected.lock().unwrap() = None;
+                ui.set_selected_index(-1);
+                ui.set_status_text(SharedString::from(format!("Playing from {}", servers.title())));
+                *play_queue.lock().unwrap() = Some(queue);
+            }
+            ui.set_is_playing(eng.is_playing());
+        });
+    }
+
+    // Scrobbling
+    {
+        let scrobbler = scrobbler.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_scrobble_sign_in(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let api = ScrobbleApi::ALL.get(ui.get_scrobble_api_index().max(0) as usize).copied().unwrap_or_default();
+            let url = match ui.get_scrobble_url().trim() {
+                "" => match api { ScrobbleApi::LastFm => LASTFM_URL, ScrobbleApi::ListenBrainz => LISTENBRAINZ_URL }.to_string(),
+                url => url.to_string(),
+            };
+            if !is_url(Path::new(&url)) {
+                ui.set_status_text(SharedString::from("Enter the service's http:// or https:// API address"));
+                return;
+            }
+            let (user, secret) = (ui.get_scrobble_user().trim().to_string(), ui.get_scrobble_password().to_string());
+            let (api_key, api_secret) = (ui.get_scrobble_api_key().trim().to_string(), ui.get_scrobble_api_secret().trim().to_string());
+            let mut scrobbles = scrobbler.lock().unwrap();
+            match api {
+                ScrobbleApi::LastFm if user.is_empty() || api_key.is_empty() || api_secret.is_empty() => {
+                    ui.set_status_text(SharedString::from("Last.fm needs an API key, its secret, a user name and a password"));
+                    return;
+                }
+                ScrobbleApi::LastFm => scrobbles.sign_in(move || lastfm_sign_in(&url, &api_key, &api_secret, &user, &secret)),
+                // ListenBrainz signs in with the user token in the password field
+                ScrobbleApi::ListenBrainz if secret.trim().is_empty() => {
+                    ui.set_status_text(SharedString::from("Enter your ListenBrainz user token"));
+                    return;
+                }
+                ScrobbleApi::ListenBrainz => scrobbles.sign_in(move || listenbrainz_sign_in(&url, secret.trim())),
+            }
+            ui.set_scrobble_password(SharedString::new());
+            update_scrobble_view(&ui, &scrobbles);
+        });
+    }
+    {
+        let scrobbler = scrobbler.clone();
+        let settings = settings.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_scrobble_remove(move |index| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut scrobbles = scrobbler.lock().unwrap();
+            let mut accounts = scrobbles.accounts().to_vec();
+            if index < 0 || index as usize >= accounts.len() { return; }
+            accounts.remove(index as usize);
+            scrobbles.set_accounts(accounts.clone());
+            let mut st = settings.lock().unwrap();
+            st.scrobble_accounts = accounts;
+            if let Err(e) = st.save() { log::warn!("{e}"); }
+            update_scrobble_view(&ui, &scrobbles);
+        });
+    }
+
+    {
+        let songs = songs.clone();
+        let selected = selected.clone();
+        let filtered_indices = filtered_indices.clone();
+        let tag_editor = tag_editor.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_toggle_tags(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let visible = !ui.get_tags_visible();
+            ui.set_tags_visible(visible);
+            let songs = songs.lock().unwrap();
+            let mut editor = tag_editor.lock().unwrap();
+            // Opening starts from the selected song; closing drops the ticks and unsaved changes
+            let selected = selected.lock().unwrap().filter(|&i| songs.get(i).is_some_and(|s| s.range.is_none()));
+            editor.marked = if visible { selected.into_iter().collect() } else { Vec::new() };
+            editor.edit = TagEdit::default();
+            update_song_list(&ui, &songs, &filtered_indices.lock().unwrap(), &editor.marked);
+            update_tag_view(&ui, &songs, &editor);
+        });
+    }
+    {
+        let songs = songs.clone();
+        let filtered_indices = filtered_indices.clone();
+        let tag_editor = tag_editor.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_tag_mark_all(move |all| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let songs = songs.lock().unwrap();
+            let fi = filtered_indices.lock().unwrap();
+            let mut editor = tag_editor.lock().unwrap();
+            // All the songs the search shows, e.g. an album
+            editor.marked = if all { fi.iter().copied().filter(|&i| songs[i].range.is_none()).collect() } else { Vec::new() };
+            update_song_list(&ui, &songs, &fi, &editor.marked);
+            update_tag_view(&ui, &songs, &editor);
+        });
+    }
+    {
+        let tag_editor = tag_editor.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_tag_field_edited(move |index, text| {
+            let Some(&field) = usize::try_from(index).ok().and_then(|i| TagField::ALL.get(i)) else { return };
+            let mut editor = tag_editor.lock().unwrap();
+            editor.edit.set(field, &text);
+            if let Some(ui) = ui_handle.upgrade() { ui.set_tag_dirty(true); }
+        });
+    }
+    {
+        let songs = songs.clone();
+        let tag_editor = tag_editor.clone();
+        let music_dir = music_dir.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_tag_cover(move |source| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let songs = songs.lock().unwrap();
+            let mut editor = tag_editor.lock().unwrap();
+            let image = match source.as_str() {
+                "remove" => { editor.edit.cover = CoverEdit::Remove; None }
+                // The cover image next to the first ticked song
+                "folder" => match editor.marked.first().and_then(|&i| songs[i].path.parent().and_then(folder_image)) {
+                    Some(image) => Some(image),
+                    None => { ui.set_status_text(SharedString::from("No cover image in the song's folder")); return; }
+                },
+                // A file path, relative to the music folder
+                _ => Some(music_dir.clone().unwrap_or_default().join(ui.get_tag_cover_path().as_str())),
+            };
+            if let Some(image) = image {
+                let cover = std::fs::read(&image).map_err(|e| format!("Can't read {}: {e}", image.display())).and_then(|data| {
+                    let edit = TagEdit { cover: CoverEdit::Set(data), ..Default::default() };
+                    edit.check().map(|()| edit.cover)
+                });
+                match cover {
+                    Ok(cover) => editor.edit.cover = cover,
+                    Err(e) => { ui.set_status_text(SharedString::from(e)); return; }
+                }
+            }
+            update_tag_view(&ui, &songs, &editor);
+        });
+    }
+    {
+        let songs = songs.clone();
+        let tag_editor = tag_editor.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_tag_revert(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut editor = tag_editor.lock().unwrap();
+            editor.edit = TagEdit::default();
+            update_tag_view(&ui, &songs.lock().unwrap(), &editor);
+        });
+    }
+    {
+        let songs = songs.clone();
+        let tag_editor = tag_editor.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_tag_save(move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let songs = songs.lock().unwrap();
+            let mut editor = tag_editor.lock().unwrap();
+            if editor.marked.is_empty() || editor.edit.is_empty() { return; }
+            let paths = editor.marked.iter().filter_map(|&i| songs.get(i)).map(|s| s.path.clone()).collect::<Vec<_>>();
+            let (count, edit) = (paths.len(), editor.edit.clone());
+            match editor.saver.save(paths, edit) {
+                Ok(()) => {
+                    ui.set_tag_saving(true);
+                    ui.set_status_text(SharedString::from(format!("Saving the tags of {count} file(s)…")));
+                }
+                Err(e) => ui.set_status_text(SharedString::from(e)),
+            }
+        });
+    }
+
+    ui.run()?;
+    // Keep the resume position of an episode that was playing
+    if let Err(e) = podcasts.lock().unwrap().save() { log::warn!("{e}"); }
+    Ok(())
+}
+
+// ===== UI timer =====
+
+/// What the 200 ms UI timer looks after: the shared state it reads, and what each feature last showed.
+struct Poller {
+    songs: Arc<Mutex<Vec<SongItem>>>,
+    selected: Arc<Mutex<Option<usize>>>,
+    filtered_indices: Arc<Mutex<Vec<usize>>>,
+    search: Arc<Mutex<String>>,
+    repeat_one: Arc<Mutex<bool>>,
+    shuffle: Arc<Mutex<bool>>,
+    shuffle_order: Arc<Mutex<Vec<usize>>>,
+    settings: Arc<Mutex<Settings>>,
+    play_queue: Arc<Mutex<Option<PlayQueue>>>,
+    podcasts: Arc<Mutex<Podcasts>>,
+    playing_episode: Arc<Mutex<Option<(String, String, PathBuf)>>>,
+    remote: Arc<Mutex<Option<Browser>>>,
+    dlna: Arc<Mutex<Dlna>>,
+    scrobbler: Arc<Mutex<Scrobbler>>,
+    tag_editor: Arc<Mutex<TagEditor>>,
+    // Chapter list and track length last pushed to the UI
+    shown_chapters: (Vec<Chapter>, Option<Duration>),
+    // Lyrics and the current line (with how much of it is sung) last pushed to the UI
+    shown_lyrics: (Option<Lyrics>, Option<(usize, usize)>),
+    // Failure list last pushed to the UI
+    shown_failures: Vec<(PathBuf, String)>,
+    art: ArtLoader,
+    tags: TagLoader,
+    // Scheduled radio recordings run from here, whether or not anything is playing
+    scheduler: Scheduler,
+    listens: ListenTracker,
+    ticks: u32,
+}
+
+impl Poller {
+    /// One tick. Returns whether the podcast state should be saved, which is left until the engine is unlocked.
+    fn tick(&mut self, ui: &AppWindow, eng: &mut AudioEngine) -> bool {
+        self.ticks = self.ticks.wrapping_add(1);
+        self.poll_playback(ui, eng);
+        self.poll_recording(ui, eng);
+        self.poll_failures(ui, eng);
+        let save_podcasts = self.poll_podcasts(ui, eng);
+        self.poll_browsers(ui);
+        self.poll_tags(ui, eng);
+        self.poll_scrobbling(ui, eng);
+        self.advance(ui, eng);
+        save_podcasts
+    }
+
+    /// Filter the library again and redraw it, after its rows changed.
+    fn refresh_library(&self, ui: &AppWindow, songs: &[SongItem], editor: &TagEditor) {
+        let mut fi = self.filtered_indices.lock().unwrap();
+        *fi = filter_songs(songs, &self.search.lock().unwrap());
+        update_song_list(ui, songs, &fi, &editor.marked);
+        show_selection(ui, &fi, *self.selected.lock().unwrap());
+    }
+
+    /// The output device, position, chapters, lyrics and art of what is playing.
+    fn poll_playback(&mut self, ui: &AppWindow, eng: &mut AudioEngine) {
+        // Detect a dead output device and retry with backoff
+        if let Some(msg) = eng.poll_output() { ui.set_status_text(SharedString::from(msg)); }
+        eng.refresh_trim();
+        let chapters = eng.track_chapters();
+        let current = eng.current_chapter(&chapters);
+        ui.set_current_chapter(current.map_or(-1, |i| i as i32));
+        ui.set_chapter_title(SharedString::from(current.map(|i| chapters[i].title.clone()).unwrap_or_default()));
+        let total = eng.total_duration();
+        if (&chapters, total) != (&self.shown_chapters.0, self.shown_chapters.1) {
+            update_chapter_list(ui, &chapters, total);
+            self.shown_chapters = (chapters, total);
+        }
+        let offset = eng.lyrics_offset(&self.settings.lock().unwrap());
+        let current = eng.current_lyric(offset);
+        if (&eng.lyrics, current) != (&self.shown_lyrics.0, self.shown_lyrics.1) {
+            update_lyrics(ui, eng.lyrics.as_ref(), current);
+            self.shown_lyrics = (eng.lyrics.clone(), current);
+        }
+        ui.set_lyrics_offset_text(SharedString::from(lyrics_offset_text(offset)));
+        // Album art follows the playing file
+        if let Some(thumb) = self.art.poll(eng.current_path.as_deref().filter(|p| !is_url(p))) {
+            let image = thumb.and_then(|p| slint::Image::load_from_path(&p).ok());
+            ui.set_has_art(image.is_some());
+            ui.set_art(image.unwrap_or_default());
+        }
+        if let Some(total) = total {
+            let total_secs = total.as_secs_f32().max(0.001);
+            let ratio = (eng.current_position().as_secs_f32() / total_secs).clamp(0.0, 1.0);
+            let text = format!("{} / {}", format_time(eng.current_position()), format_time(total));
+            ui.set_time_text(SharedString::from(text));
+            ui.set_progress(ratio);
+            ui.set_is_playing(eng.is_playing());
+            ui.set_rate_text(SharedString::from(eng.rate_text()));
+            let saved = eng.time_saved();
+            ui.set_time_saved_text(SharedString::from(if saved.is_zero() { String::new() } else { format!("Smart speed saved {}", format_time(saved)) }));
+            ui.set_keep_album_silence(eng.current_path.as_ref().and_then(|p| p.parent()).is_some_and(|dir| eng.keep_silence_dirs.iter().any(|d| d == dir)));
+        } else if eng.stream.is_some() {
+            // Live radio has no length: show how long it has been playing
+            ui.set_time_text(SharedString::from(format!("LIVE {}", format_time(eng.current_position()))));
+            ui.set_progress(0.0);
+            ui.set_is_playing(eng.is_playing());
+            ui.set_rate_text(SharedString::from(eng.rate_text()));
+        }
+    }
+
+    /// What the radio station reports, and recordings: scheduled ones start, and finished ones join the library.
+    fn poll_recording(&mut self, ui: &AppWindow, eng: &mut AudioEngine) {
+        let now_playing = eng.stream_meta().map(|m| {
+            let station = m.station.or_else(|| eng.current_title.clone()).unwrap_or_else(|| eng.current_path.as_deref().map(file_label).unwrap_or_default());
+            match m.status.or(m.title) { Some(text) => format!("{station} — {text}"), None => station }
+        });
+        ui.set_now_playing(SharedString::from(now_playing.unwrap_or_default()));
+        if let Some(dir) = &eng.recordings_dir {
+            let schedules = self.settings.lock().unwrap().record_schedules.clone();
+            for msg in self.scheduler.poll(&schedules, dir) { ui.set_status_text(SharedString::from(msg)); }
+        }
+        // Like the ones found at startup
+        let recorded: Vec<PathBuf> = eng.recorded.drain(..).chain(self.scheduler.take_recorded()).collect();
+        if !recorded.is_empty() {
+            let mut songs = self.songs.lock().unwrap();
+            songs.extend(recorded.iter().map(|path| SongItem::file(path.clone(), TrackInfo::default())));
+            self.tags.load(recorded);
+            self.refresh_library(ui, &songs, &self.tag_editor.lock().unwrap());
+        }
+        let mut recording = vec![eng.recording_text()];
+        recording.extend(self.scheduler.active().into_iter().map(|label| format!("Scheduled recording: {label}")));
+        ui.set_recording(eng.is_recording());
+        ui.set_recording_text(SharedString::from(recording.into_iter().filter(|t| !t.is_empty()).collect::<Vec<_>>().join("\n")));
+    }
+
+    fn poll_failures(&mut self, ui: &AppWindow, eng: &mut AudioEngine) {
+        if let Some(msg) = eng.check_decode_failure() { ui.set_status_text(SharedString::from(msg)); }
+        if eng.failures != self.shown_failures {
+            self.shown_failures = eng.failures.clone();
+            let entries = eng.failures.iter().map(|(p, reason)| SharedString::from(format!("{} — {reason}", file_label(p)))).collect::<Vec<_>>();
+            ui.set_failed_files(slint::ModelRc::new(slint::VecModel::from(entries)));
+        }
+    }
+
+    /// Apply finished feed refreshes and downloads, and follow the episode that is playing. Returns whether the
+    /// podcast state should be saved.
+    fn poll_podcasts(&mut self, ui: &AppWindow, eng: &mut AudioEngine) -> bool {
+        let mut pods = self.podcasts.lock().unwrap();
+        let messages = pods.poll();
+        let mut save = !messages.is_empty();
+        let mut playing = self.playing_episode.lock().unwrap();
+        if let Some((url, guid, path)) = playing.clone() {
+            match pods.find(&url, &guid) {
+                Some((index, episode)) if eng.current_path.as_ref() == Some(&path) => {
+                    if eng.track_finished() {
+                        // Episodes don't run on into the library
+                        pods.set_played(index, episode, true);
+                        eng.stop();
+                        *playing = None;
+                        save = true;
+                    } else {
+                        pods.set_position(index, episode, eng.current_position());
+                        save |= self.ticks.is_multiple_of(50);
+                    }
+                }
+                _ => {
+                    *playing = None;
+                    save = true;
+                }
+            }
+        }
+        if let Some(msg) = messages.last() { ui.set_status_text(SharedString::from(msg.as_str())); }
+        if !messages.is_empty() || pods.is_downloading() && self.ticks.is_multiple_of(5) {
+            update_podcast_views(ui, &pods, usize::try_from(ui.get_current_podcast()).ok());
+        }
+        save
+    }
+
+    /// Remote library pages and media server searches and listings, which load in the background.
+    fn poll_browsers(&mut self, ui: &AppWindow) {
+        if let Some(browser) = self.remote.lock().unwrap().as_mut() && let Some(result) = browser.poll() {
+            if let Err(e) = result { ui.set_status_text(SharedString::from(e)); }
+            update_remote_view(ui, Some(browser));
+        }
+        let servers = &mut *self.dlna.lock().unwrap();
+        if let Some(result) = servers.poll() {
+            if let Err(e) = result { ui.set_status_text(SharedString::from(e)); }
+            update_dlna_view(ui, servers);
+        }
+    }
+
+    /// Tags read in the background fill in the library rows as they come; saved ones refresh the rows, and the
+    /// details of the playing track.
+    fn poll_tags(&mut self, ui: &AppWindow, eng: &mut AudioEngine) {
+        let read = self.tags.poll();
+        if !read.is_empty() {
+            let mut songs = self.songs.lock().unwrap();
+            let read: std::collections::HashMap<_, _> = read.into_iter().collect();
+            for item in songs.iter_mut() {
+                let Some(info) = read.get(&item.path) else { continue };
+                item.set_info(info);
+                if eng.is_current(item) { (eng.current_title, eng.current_info) = (Some(item.title.clone()), Some(item.info.clone())); }
+            }
+            let editor = self.tag_editor.lock().unwrap();
+            self.refresh_library(ui, &songs, &editor);
+            update_tag_view(ui, &songs, &editor);
+            if !self.tags.loading() { ui.set_status_text(SharedString::from(format!("Read the tags of {} song(s)", songs.len()))); }
+        }
+        let mut editor = self.tag_editor.lock().unwrap();
+        let Some(saved) = editor.saver.poll() else { return };
+        let mut songs = self.songs.lock().unwrap();
+        let mut failed = Vec::new();
+        for (path, result) in saved {
+            let info = match result {
+                Ok(info) => info,
+                Err(e) => { log::warn!("{e}"); failed.push(e); continue; }
+            };
+            for item in songs.iter_mut().filter(|s| s.path == path && s.range.is_none()) { *item = SongItem::file(path.clone(), info.clone()); }
+            if eng.current_path.as_ref() == Some(&path) && eng.track_range.is_none() {
+                eng.current_title = Some(SongItem::file(path, info.clone()).title);
+                eng.current_info = Some(info);
+                self.art.reload();
+            }
+        }
+        // Failed files keep the changes, to try again
+        if failed.is_empty() { editor.edit = TagEdit::default(); }
+        self.refresh_library(ui, &songs, &editor);
+        update_tag_view(ui, &songs, &editor);
+        ui.set_status_text(SharedString::from(match failed.first() {
+            None => "Tags saved".to_string(),
+            Some(e) => format!("{} file(s) not saved: {e}", failed.len()),
+        }));
+    }
+
+    /// Scrobbling goes by what has really been heard. Radio and podcast episodes aren't scrobbled.
+    fn poll_scrobbling(&mut self, ui: &AppWindow, eng: &mut AudioEngine) {
+        let mut scrobbles = self.scrobbler.lock().unwrap();
+        let scrobbling = !scrobbles.accounts().is_empty() && eng.stream.is_none() && self.playing_episode.lock().unwrap().is_none();
+        let key = eng.current_path.as_ref().filter(|_| scrobbling)
+            .map(|path| format!("{}#{}", path.display(), eng.track_range.map_or(0, |r| r.start.as_millis())));
+        let heard = self.listens.update(key.as_deref(), eng.current_position(), eng.is_playing(), || {
+            let path = eng.current_path.as_deref()?;
+            // Tags first; untagged files may still be named "Artist - Title"
+            if let Some(listen) = eng.current_info.as_ref().and_then(|info| Listen::from_info(info, eng.total_duration())) { return Some(listen); }
+            let label = eng.current_title.clone().unwrap_or_else(|| file_label(path));
+            Listen::from_label(&label, path.extension().and_then(|e| e.to_str()), eng.total_duration())
+        });
+        match heard {
+            Some(Heard::NowPlaying(listen)) => scrobbles.now_playing(&listen),
+            Some(Heard::Scrobble(listen)) => scrobbles.scrobble(listen),
+            None => {}
+        }
+        let messages = scrobbles.poll();
+        if !messages.is_empty() {
+            // A finished sign-in adds an account
+            let mut st = self.settings.lock().unwrap();
+            if st.scrobble_accounts != scrobbles.accounts() {
+                st.scrobble_accounts = scrobbles.accounts().to_vec();
+                if let Err(e) = st.save() { log::warn!("{e}"); }
+            }
+        }
+        if let Some(msg) = messages.last() { ui.set_status_text(SharedString::from(msg.as_str())); }
+        if !messages.is_empty() || ui.get_settings_visible() && self.ticks.is_multiple_of(5) { update_scrobble_view(ui, &scrobbles); }
+    }
+
+    /// Auto-advance. The next track is worked out on every tick so a CUE track can keep the source running into the
+    /// following one and switch over without a gap. Radio doesn't advance into the library.
+    fn advance(&mut self, ui: &AppWindow, eng: &mut AudioEngine) {
+        let mut queue = self.play_queue.lock().unwrap();
+        if let Some(q) = queue.as_mut() {
+            if let Some(item) = q.items.get(q.index + 1) { eng.prepare_trim(item); }
+            if eng.current_path.is_some() && eng.track_finished() {
+                let candidates: Vec<usize> = if *self.repeat_one.lock().unwrap() { vec![q.index] } else { (q.index + 1..q.items.len()).collect() };
+                let result = eng.play_first_playable(&q.items, candidates);
+                if !matches!(result, Ok((Some(_), _))) { eng.stop(); }
+                show_queue_result(&ui.as_weak(), q, result);
+                if eng.current_path.is_none() { *queue = None; }
+            }
+            return;
+        }
+        if eng.current_path.is_none() || eng.stream.is_some() { return; }
+        let songs = self.songs.lock().unwrap();
+        let fi = self.filtered_indices.lock().unwrap().clone();
+        let candidates = self.selected.lock().unwrap().or_else(|| fi.first().copied()).map_or_else(Vec::new, |cur_idx| {
+            if *self.repeat_one.lock().unwrap() {
+                vec![cur_idx]
+            } else if *self.shuffle.lock().unwrap() {
+                walk_from(&self.shuffle_order.lock().unwrap(), cur_idx, true, true)
+            } else {
+                walk_from(&fi, cur_idx, true, false)
+            }
+        });
+        let next = candidates.first().and_then(|&i| songs.get(i).map(|item| (i, item)));
+        if let Some((_, item)) = next { eng.prepare_trim(item); }
+        let adjacent = next.is_some_and(|(_, item)| eng.is_adjacent(item));
+        eng.set_follow_on(adjacent);
+        let Some((next_idx, item)) = next.filter(|_| eng.track_finished()) else { return };
+        if adjacent {
+            eng.continue_into(item);
+            show_selection(ui, &fi, Some(next_idx));
+            *self.selected.lock().unwrap() = Some(next_idx);
+        } else {
+            let result = eng.play_first_playable(&songs, candidates);
+            // Nothing left to play: stop instead of retrying on every tick
+            if !matches!(result, Ok((Some(_), _))) { eng.stop(); }
+            show_play_result(&ui.as_weak(), &self.selected, &self.filtered_indices, result);
+        }
+    }
+}
+
+// ===== UI helpers =====
+
+/// Convert EQ gains in dB to the 0..1 slider scale used by the UI (0.5 = 0 dB).
+fn eq_slider_values(gains: &[f32; 10]) -> Vec<f32> { gains.iter().map(|g| g / 24.0 + 0.5).collect() }
+
+/// Highlight the row of song `selected` among the rows shown for the song indices `filtered`, if it is there.
+fn show_selection(ui: &AppWindow, filtered: &[usize], selected: Option<usize>) {
+    ui.set_selected_index(selected.and_then(|song| filtered.iter().position(|&i| i == song)).map_or(-1, |row| row as i32));
+}
+
+/// Show the outcome of `play_first_playable` and select the track that started.
+fn show_play_result(ui_handle: &slint::Weak<AppWindow>, selected: &Mutex<Option<usize>>, filtered: &Mutex<Vec<usize>>, result: Result<(Option<usize>, usize), String>) {
+    let Some(ui) = ui_handle.upgrade() else { return };
+    match result {
+        Ok((Some(index), skipped)) => {
+            *selected.lock().unwrap() = Some(index);
+            show_selection(&ui, &filtered.lock().unwrap(), Some(index));
+            if skipped > 0 { ui.set_status_text(SharedString::from(format!("Skipped {skipped} file(s) that can't be played"))); }
+        }
+        Ok((None, 0)) => {}
+        Ok((None, skipped)) => ui.set_status_text(SharedString::from(format!("No playable track found ({skipped} skipped)"))),
+        Err(e) => ui.set_status_text(SharedString::from(e)),
+    }
+}
+
+fn station_label(station: &Station) -> &str { if station.name.is_empty() { &station.url } else { &station.name } }
+
+fn update_station_list(ui: &AppWindow, stations: &[Station]) {
+    let names = stations.iter().map(|s| SharedString::from(station_label(s))).collect::<Vec<_>>();
+    ui.set_stations(slint::ModelRc::new(slint::VecModel::from(names)));
+}
+
+/// Push the tag editor to the UI. A changed field shows its new value, and one that differs between the ticked songs
+/// is shown as mixed.
+fn update_tag_view(ui: &AppWindow, songs: &[SongItem], editor: &TagEditor) {
+    let tracks = editor.marked.iter().filter_map(|&i| songs.get(i)).collect::<Vec<_>>();
+    let rows = TagField::ALL.iter().map(|&field| {
+        let values = tracks.iter().map(|t| t.info.field(field)).collect::<Vec<_>>();
+        let (value, mixed) = match editor.edit.get(field) {
+            Some(value) => (value.to_string(), false),
+            None if values.windows(2).all(|w| w[0] == w[1]) => (values.first().cloned().unwrap_or_default(), false),
+            None => (String::new(), true),
+        };
+        TagRow { label: field.label().into(), value: value.into(), mixed }
+    }).collect::<Vec<_>>();
+    ui.set_tag_fields(slint::ModelRc::new(slint::VecModel::from(rows)));
+    ui.set_tag_targets(SharedString::from(match tracks.as_slice() {
+        [] => "Tap songs in the library to edit their tags".to_string(),
+        [track] => file_label(&track.path),
+        tracks => format!("{} songs", tracks.len()),
+    }));
+    ui.set_tag_cover_text(SharedString::from(match &editor.edit.cover {
+        CoverEdit::Keep => String::new(),
+        CoverEdit::Remove => "The cover will be removed".to_string(),
+        CoverEdit::Set(data) => format!("New cover ({} KB)", data.len().div_ceil(1024)),
+    }));
+    ui.set_tag_dirty(!editor.edit.is_empty());
+    ui.set_tag_saving(editor.saver.saving());
+}
+
+/// Push the playlist names and, for `current`, its entries to the UI.
+fn update_playlist_views(ui: &AppWindow, playlists: &[Playlist], current: Option<usize>) {
+    let names = playlists.iter().map(|p| SharedString::from(format!("{} ({})", p.name, p.entries.len()))).collect::<Vec<_>>();
+    ui.set_playlists(slint::ModelRc::new(slint::VecModel::from(names)));
+    let entries = current.and_then(|i| playlists.get(i)).map(|p| p.entries.iter().map(|e| {
+        let duration = e.duration.map(|d| format!("  {}", format_time(d))).unwrap_or_default();
+        SharedString::from(if e.missing { format!("⚠ missing: {}", e.path.display()) } else { format!("{}{duration}", e.label()) })
+    }).collect::<Vec<_>>()).unwrap_or_default();
+    ui.set_playlist_entries(slint::ModelRc::new(slint::VecModel::from(entries)));
+    ui.set_current_playlist(current.map_or(-1, |i| i as i32));
+}
+
+/// Push the subscriptions and, for `current`, its episodes to the UI.
+fn update_podcast_views(ui: &AppWindow, pods: &Podcasts, current: Option<usize>) {
+    let names = pods.subscriptions.iter().enumerate().map(|(i, s)| {
+        let title = if s.title.is_empty() { &s.url } else { &s.title };
+        SharedString::from(match (&s.error, pods.unplayed(i)) {
+            (Some(_), _) => format!("⚠ {title}"),
+            (None, 0) => title.to_string(),
+            (None, n) => format!("{title} ({n})"),
+        })
+    }).collect::<Vec<_>>();
+    ui.set_podcasts(slint::ModelRc::new(slint::VecModel::from(names)));
+    let current = current.filter(|&i| i < pods.subscriptions.len());
+    let episodes = current.map(|i| pods.subscriptions[i].episodes.iter().enumerate().map(|(e, ep)| {
+        let state = pods.state(i, e);
+        let mut text = format!("{} {}  {}", if state.played { "✓" } else { "●" }, ep.date_text(), ep.title);
+        if let Some(d) = ep.duration { text += &format!("  {}", format_time(d)); }
+        match pods.download_progress(i, e) {
+            Some(Some(done)) => text += &format!("  ⬇ {:.0}%", done * 100.0),
+            Some(None) => text += "  ⬇",
+            None if pods.file(i, e).is_some() => text += "  💾",
+            None => {}
+        }
+        if !state.played && !state.position.is_zero() { text += &format!("  ⏯ {}", format_time(state.position)); }
+        SharedString::from(text)
+    }).collect::<Vec<_>>()).unwrap_or_default();
+    if current != usize::try_from(ui.get_current_podcast()).ok() {
+        ui.set_current_episode(-1);
+        ui.set_episode_notes(SharedString::new());
+    }
+    ui.set_episodes(slint::ModelRc::new(slint::VecModel::from(episodes)));
+    ui.set_current_podcast(current.map_or(-1, |i| i as i32));
+}
+
+fn show_podcast_rules(ui: &AppWindow, pods: &Podcasts, index: usize) {
+    let Some(sub) = pods.subscriptions.get(index) else { return };
+    ui.set_podcast_auto_download(sub.auto_download);
+    ui.set_podcast_delete_played(sub.delete_played);
+    ui.set_podcast_keep(sub.keep as i32);
+}
+
+/// Push the remote library's current page to the UI.
+fn update_remote_view(ui: &AppWindow, browser: Option<&Browser>) {
+    let rows = browser.map(|b| b.rows().iter().map(|r| SharedString::from(r.label())).collect::<Vec<_>>()).unwrap_or_default();
+    ui.set_remote_rows(slint::ModelRc::new(slint::VecModel::from(rows)));
+    ui.set_remote_title(SharedString::from(browser.and_then(Browser::page).map(Page::title).unwrap_or_default()));
+    ui.set_remote_loading(browser.is_some_and(Browser::is_loading));
+    ui.set_remote_can_back(browser.is_some_and(Browser::can_go_back));
+    let cover = browser.and_then(Browser::cover).and_then(|path| slint::Image::load_from_path(path).ok());
+    ui.set_remote_has_cover(cover.is_some());
+    ui.set_remote_cover(cover.unwrap_or_default());
+}
+
+fn open_remote_page(ui_handle: &slint::Weak<AppWindow>, remote: &Mutex<Option<Browser>>, page: Page, root: bool) {
+    let Some(ui) = ui_handle.upgrade() else { return };
+    let mut remote = remote.lock().unwrap();
+    let Some(browser) = remote.as_mut() else { return };
+    browser.open(page, root);
+    update_remote_view(&ui, Some(browser));
+}
+
+/// Push the scrobbling accounts and the state of the queue to the settings panel.
+fn update_scrobble_view(ui: &AppWindow, scrobbler: &Scrobbler) {
+    let accounts = scrobbler.accounts().iter().map(|a| SharedString::from(a.label())).collect::<Vec<_>>();
+    ui.set_scrobble_accounts(slint::ModelRc::new(slint::VecModel::from(accounts)));
+    ui.set_scrobble_signing_in(scrobbler.is_signing_in());
+    ui.set_scrobble_status(SharedString::from(scrobbler.status_text()));
+}
+
+/// Push the media server list, the open container and the chosen entry's details to the UI.
+fn update_dlna_view(ui: &AppWindow, dlna: &Dlna) {
+    let servers = dlna.servers().iter().map(|s| SharedString::from(s.name.as_str())).collect::<Vec<_>>();
+    ui.set_dlna_servers(slint::ModelRc::new(slint::VecModel::from(servers)));
+    ui.set_dlna_server_index(dlna.server().map_or(-1, |i| i as i32));
+    ui.set_dlna_searching(dlna.is_searching());
+    ui.set_dlna_title(SharedString::from(dlna.title()));
+    ui.set_dlna_loading(dlna.is_loading());
+    ui.set_dlna_can_back(dlna.can_go_back());
+    let rows = dlna.entries().iter().map(|e| SharedString::from(dlna_row(e))).collect::<Vec<_>>();
+    ui.set_dlna_rows(slint::ModelRc::new(slint::VecModel::from(rows)));
+    let details = dlna.details().map(|e| {
+        let mut lines = vec![e.title.clone()];
+        let credits = [e.artist.as_str(), e.album.as_str()].into_iter().filter(|s| !s.is_empty()).collect::<Vec<_>>().join(" · ");
+        if !credits.is_empty() { lines.push(credits); }
+        if let Some(art) = &e.album_art { lines.push(format!("Album art: {art}")); }
+        lines.join("\n")
+    });
+    ui.set_dlna_details(SharedString::from(details.unwrap_or_default()));
+    let art = dlna.art().and_then(|path| slint::Image::load_from_path(path).ok());
+    ui.set_dlna_has_art(art.is_some());
+    ui.set_dlna_art(art.unwrap_or_default());
+}
+
+fn dlna_row(entry: &DidlEntry) -> String {
+    if entry.container {
+        return match entry.child_count { Some(n) => format!("📁 {} ({n})", entry.title), None => format!("📁 {}", entry.title) };
+    }
+    let credits = [entry.artist.as_str(), entry.album.as_str()].into_iter().filter(|s| !s.is_empty()).collect::<Vec<_>>().join(" · ");
+    let credits = if credits.is_empty() { credits } else { format!(" – {credits}") };
+    let length = entry.duration.map(|d| format!("  {}:{:02}", d.as_secs() / 60, d.as_secs() % 60)).unwrap_or_default();
+    format!("🎵 {}{credits}{length}", entry.title)
+}
+
+/// Show the outcome of playing from a playlist and select the entry that started.
+fn show_queue_result(ui_handle: &slint::Weak<AppWindow>, queue: &mut PlayQueue, result: Result<(Option<usize>, usize), String>) {
+    let Some(ui) = ui_handle.upgrade() else { return };
+    match result {
+        Ok((Some(index), skipped)) => {
+            queue.index = index;
+            if queue.playlist.is_some_and(|p| ui.get_current_playlist() == p as i32) { ui.set_current_entry(index as i32); }
+            if skipped > 0 { ui.set_status_text(SharedString::from(format!("Skipped {skipped} file(s) that can't be played"))); }
+        }
+        Ok((None, 0)) => {}
+        Ok((None, skipped)) => ui.set_status_text(SharedString::from(format!("No playable track found ({skipped} skipped)"))),
+        Err(e) => ui.set_status_text(SharedString::from(e)),
+    }
+}
+
+fn update_schedule_list(ui: &AppWindow, schedules: &[RecordSchedule]) {
+    let entries = schedules.iter().map(|s| SharedString::from(format!("{}  {} min  {}", s.start_text(), s.minutes, s.label()))).collect::<Vec<_>>();
+    ui.set_record_schedules(slint::ModelRc::new(slint::VecModel::from(entries)));
+}
+
+/// The station typed into the radio panel, if the URL is a usable http(s) address.
+fn station_from_fields(ui: &AppWindow) -> Option<Station> {
+    let url = ui.get_station_url().trim().to_string();
+    if !is_url(Path::new(&url)) || url.contains('|') {
+        ui.set_status_text(SharedString::from("Enter an http:// or https:// stream URL"));
+        return None;
+    }
+    Some(Station { name: ui.get_station_name().trim().to_string(), url })
+}
+
+/// Push the chapter list ("mm:ss  Title") and the slider tick positions (0..1 of `total`) to the UI.
+fn update_chapter_list(ui: &AppWindow, chapters: &[Chapter], total: Option<Duration>) {
+    let entries = chapters.iter().map(|c| SharedString::from(format!("{}  {}", format_time(c.start), c.title))).collect::<Vec<_>>();
+    let ticks = match total.filter(|t| !t.is_zero()) {
+        Some(total) => chapters.iter().filter(|c| !c.start.is_zero()).map(|c| (c.start.as_secs_f32() / total.as_secs_f32()).min(1.0)).collect(),
+        None => Vec::new(),
+    };
+    ui.set_chapters(slint::ModelRc::new(slint::VecModel::from(entries)));
+    ui.set_chapter_ticks(slint::ModelRc::new(slint::VecModel::from(ticks)));
+}
+
+fn update_lyrics(ui: &AppWindow, lyrics: Option<&Lyrics>, current: Option<(usize, usize)>) {
+    let lines = lyrics.map_or(&[][..], |l| l.lines.as_slice());
+    let rows = lines.iter().enumerate().map(|(i, line)| lyric_row(line, current.filter(|c| c.0 == i).map(|c| c.1))).collect::<Vec<_>>();
+    ui.set_lyrics(slint::ModelRc::new(slint::VecModel::from(rows)));
+    ui.set_lyrics_synced(lyrics.is_some_and(Lyrics::is_synced));
+    ui.set_current_lyric(current.map_or(-1, |c| c.0 as i32));
+}
+
+// The current line is split where singing has got to
+fn lyric_row(line: &LyricLine, sung: Option<usize>) -> LyricRow {
+    let at = sung.unwrap_or(0).min(line.text.len());
+    LyricRow { sung: SharedString::from(&line.text[..at]), rest: SharedString::from(&line.text[at..]) }
+}
+
+fn update_lrc_editor(ui: &AppWindow, editor: Option<&LrcEditor>) {
+    ui.set_lrc_editing(editor.is_some());
+    let Some(ed) = editor else { return };
+    // The line a nudge moves has its time highlighted
+    let target = ed.nudge_target();
+    let rows = ed.lines.iter().enumerate().map(|(i, line)| {
+        let time = line.start.map_or_else(|| "--:--.--".to_string(), |t| format!("{}.{:02}", format_time(t), t.subsec_millis() / 10));
+        if target == Some(i) { LyricRow { sung: SharedString::from(format!("{time}  ")), rest: SharedString::from(line.text.as_str()) } }
+        else { LyricRow { sung: SharedString::new(), rest: SharedString::from(format!("{time}  {}", line.text)) } }
+    }).collect::<Vec<_>>();
+    ui.set_lrc_lines(slint::ModelRc::new(slint::VecModel::from(rows)));
+    ui.set_lrc_current(ed.current as i32);
+    let next = match ed.lines.get(ed.current) {
+        Some(_) => "tap Stamp as the highlighted line starts",
+        None => "all lines done: preview, then save",
+    };
+    ui.set_lrc_progress(SharedString::from(format!("{} of {} lines timed — {next}", ed.stamped(), ed.lines.len())));
+}
+
+fn lyrics_offset_text(offset_ms: i64) -> String {
+    match offset_ms {
+        0 => "Lyrics timing as written".to_string(),
+        ms if ms > 0 => format!("Lyrics {:.1} s earlier", ms as f64 / 1000.0),
+        ms => format!("Lyrics {:.1} s later", -ms as f64 / 1000.0),
+    }
+}
+
+fn refresh_output_devices(ui: &AppWindow, engine: &AudioEngine, devices: &Mutex<Vec<String>>) {
+    let preferred = engine.preferred_device.as_deref();
+    let names = engine.list_output_devices();
+    let mut entries = vec![SharedString::from("System default")];
+    entries.extend(names.iter().map(|n| SharedString::from(n.as_str())));
+    // A remembered device that is currently unplugged is still listed so the choice stays visible
+    let mut all = names;
+    if let Some(p) = preferred && !all.iter().any(|n| n == p) {
+        entries.push(SharedString::from(format!("{p} (missing)")));
+        all.push(p.to_string());
+    }
+    let index = preferred.and_then(|p| all.iter().position(|n| n == p)).map(|i| i + 1).unwrap_or(0);
+    *devices.lock().unwrap() = all;
+    ui.set_output_devices(slint::ModelRc::new(slint::VecModel::from(entries)));
+    ui.set_output_device_index(index as i32);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{WavFormat, WavWriter};
+    use crate::output::{BoxedSource, Pace, ThreadOutput};
+
+    // The engine owns its output, so the test keeps a handle to the same manually paced one to advance it
+    struct Shared(Arc<Mutex<ThreadOutput>>, Arc<Mutex<Faults>>);
+
+    // Device trouble to simulate on a `Shared` output
+    #[derive(Default)]
+    struct Faults { stream_error: Option<String>, refuse_open: bool }
+
+    impl OutputBackend for Shared {
+        fn ensure_open(&mut self, preferred: Option<&str>) -> Result<(), String> {
+            if self.1.lock().unwrap().refuse_open { return Err("Audio output error: no output device available".to_string()); }
+            self.0.lock().unwrap().ensure_open(preferred)
+        }
+        fn is_open(&self) -> bool { self.0.lock().unwrap().is_open() }
+        fn close(&mut self) { self.0.lock().unwrap().close() }
+        fn device_name(&self) -> Option<String> { self.0.lock().unwrap().device_name() }
+        fn list_devices(&self) -> Vec<String> { self.0.lock().unwrap().list_devices() }
+        fn play(&mut self, source: BoxedSource, paused: bool) -> Result<(), String> { self.0.lock().unwrap().play(source, paused) }
+        fn stop(&mut self) { self.0.lock().unwrap().stop() }
+        fn pause(&mut self) { self.0.lock().unwrap().pause() }
+        fn resume(&mut self) { self.0.lock().unwrap().resume() }
+        fn is_paused(&self) -> bool { self.0.lock().unwrap().is_paused() }
+        fn has_source(&self) -> bool { self.0.lock().unwrap().has_source() }
+        fn is_finished(&self) -> bool { self.0.lock().unwrap().is_finished() }
+        fn elapsed(&self) -> Duration { self.0.lock().unwrap().elapsed() }
+        fn take_error(&mut self) -> Option<String> { self.1.lock().unwrap().stream_error.take() }
+        fn sample_rate(&self) -> Option<u32> { self.0.lock().unwrap().sample_rate() }
+    }
+
+    fn manual_engine() -> (AudioEngine, Arc<Mutex<ThreadOutput>>) { let (eng, output, _) = faulty_engine(); (eng, output) }
+
+    fn faulty_engine() -> (AudioEngine, Arc<Mutex<ThreadOutput>>, Arc<Mutex<Faults>>) {
+        let output = Arc::new(Mutex::new(ThreadOutput::null(Pace::Manual)));
+        let faults = Arc::new(Mutex::new(Faults::default()));
+        (AudioEngine::with_output(Box::new(Shared(output.clone(), faults.clone()))), output, faults)
+    }
+
+    // Make the pending reconnect attempt due now instead of waiting out the backoff
+    fn retry_now(eng: &mut AudioEngine) { eng.output_lost.as_mut().unwrap().retry_at = Instant::now(); }
+
+    fn advance(output: &Mutex<ThreadOutput>, ms: u64) { output.lock().unwrap().advance(Duration::from_millis(ms)); }
+
+    /// A library of 8 kHz mono WAV files in a fresh folder, `ms` long each; `None` makes a file that isn't audio.
+    /// Files stay under one decoder chunk so playback never depends on the decoding thread's timing.
+    fn library(name: &str, lengths: &[Option<u64>]) -> (PathBuf, Vec<SongItem>) {
+        let dir = std::env::temp_dir().join(format!("engine-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let songs = lengths.iter().enumerate().map(|(i, ms)| {
+            let path = dir.join(format!("{i}.wav"));
+            match ms {
+                Some(ms) => {
+                    let mut wav = WavWriter::new(std::fs::File::create(&path).unwrap(), WavFormat::Pcm16, 1, 8000).unwrap();
+                    for n in 0..ms * 8 { wav.write_sample((n as f32 * 0.05).sin() * 0.5).unwrap(); }
+                    wav.finish().unwrap();
+                }
+                None => std::fs::write(&path, b"not a wav file").unwrap(),
+            }
+            SongItem::file(path, TrackInfo::default())
+        }).collect();
+        (dir, songs)
+    }
+
+    fn assert_near(actual: Duration, expected_ms: u64) {
+        let diff = actual.as_secs_f64() * 1000.0 - expected_ms as f64;
+        assert!(diff.abs() <= 10.0, "expected about {expected_ms} ms, got {actual:?}");
+    }
+
+    #[test]
+    fn play_pause_and_seek() {
+        let (mut eng, output) = manual_engine();
+        let (dir, songs) = library("seek", &[Some(500)]);
+        eng.play_item(&songs[0]).unwrap();
+        assert!(eng.is_playing());
+        assert_near(eng.total_duration().unwrap(), 500);
+        advance(&output, 200);
+        assert_near(eng.current_position(), 200);
+
+        eng.pause();
+        advance(&output, 200);
+        assert!(eng.is_paused() && !eng.is_playing());
+        assert_near(eng.current_position(), 200);
+        eng.resume();
+        advance(&output, 100);
+        assert_near(eng.current_position(), 300);
+
+        eng.seek_to(Duration::from_millis(100)).unwrap();
+        assert_near(eng.current_position(), 100);
+        advance(&output, 100);
+        assert_near(eng.current_position(), 200);
+        // Seeking while paused stays paused
+        eng.pause();
+        advance(&output, 10);
+        eng.seek_to(Duration::from_millis(400)).unwrap();
+        assert!(eng.is_paused());
+        assert_near(eng.current_position(), 400);
+        assert!(!eng.track_finished());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn finished_track_advances_past_broken_files() {
+        let (mut eng, output) = manual_engine();
+        let (dir, songs) = library("advance", &[Some(300), None, Some(300)]);
+        let order = [0, 1, 2];
+        eng.play_item(&songs[0]).unwrap();
+        advance(&output, 200);
+        assert!(!eng.track_finished());
+        advance(&output, 200);
+        assert!(eng.track_finished());
+
+        // What the timer does at the end of a track
+        let result = eng.play_first_playable(&songs, walk_from(&order, 0, true, false));
+        assert_eq!(result, Ok((Some(2), 1)));
+        assert_eq!(eng.current_path.as_ref(), Some(&songs[2].path));
+        assert!(eng.is_broken(&songs[1].path) && eng.is_playing());
+        assert_near(eng.current_position(), 0);
+
+        // At the end of the list there is nothing left
+        advance(&output, 400);
+        assert!(eng.track_finished());
+        assert_eq!(eng.play_first_playable(&songs, walk_from(&order, 2, true, false)), Ok((None, 0)));
+        // Going back skips the broken file without trying it again
+        assert_eq!(eng.play_first_playable(&songs, walk_from(&order, 2, false, false)), Ok((Some(0), 1)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn stop_unloads_the_track() {
+        let (mut eng, output) = manual_engine();
+        let (dir, songs) = library("stop", &[Some(300)]);
+        eng.play_item(&songs[0]).unwrap();
+        advance(&output, 100);
+        eng.stop();
+        assert!(eng.is_idle() && !eng.is_playing() && !eng.track_finished());
+        assert_eq!(eng.current_path, None);
+        assert_eq!(eng.total_duration(), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn lost_device_pauses_until_reopened() {
+        let (mut eng, output, faults) = faulty_engine();
+        let (dir, songs) = library("lost", &[Some(500)]);
+        eng.auto_resume = true;
+        eng.play_item(&songs[0]).unwrap();
+        advance(&output, 200);
+        faults.lock().unwrap().stream_error = Some("device unplugged".to_string());
+        let msg = eng.poll_output().unwrap();
+        assert!(msg.starts_with("Lost audio device 'null': device unplugged"), "{msg}");
+        assert!(!eng.is_playing() && !output.lock().unwrap().is_open());
+        assert_near(eng.current_position(), 200);
+        // Nothing happens before the first retry is due
+        assert_eq!(eng.poll_output(), None);
+
+        // A failed reopen stays paused and backs off
+        faults.lock().unwrap().refuse_open = true;
+        retry_now(&mut eng);
+        assert_eq!(eng.poll_output().as_deref(), Some("Audio output error: no output device available. Retrying in 1s"));
+        assert_eq!(eng.output_lost.as_ref().unwrap().backoff, Duration::from_secs(1));
+        retry_now(&mut eng);
+        eng.poll_output();
+        assert_eq!(eng.output_lost.as_ref().unwrap().backoff, Duration::from_secs(2));
+        advance(&output, 100);
+        assert!(!eng.is_playing());
+        assert_near(eng.current_position(), 200);
+
+        faults.lock().unwrap().refuse_open = false;
+        retry_now(&mut eng);
+        assert_eq!(eng.poll_output().as_deref(), Some("Audio restored on 'null'"));
+        assert!(eng.output_lost.is_none() && eng.is_playing());
+        advance(&output, 100);
+        assert_near(eng.current_position(), 300);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn lost_device_comes_back_paused_without_auto_resume() {
+        let (mut eng, output, faults) = faulty_engine();
+        let (dir, songs) = library("lost-paused", &[Some(500)]);
+        eng.play_item(&songs[0]).unwrap();
+        advance(&output, 100);
+        faults.lock().unwrap().stream_error = Some("device unplugged".to_string());
+        eng.poll_output().unwrap();
+        retry_now(&mut eng);
+        assert_eq!(eng.poll_output().as_deref(), Some("Audio restored on 'null' (paused)"));
+        assert!(eng.is_paused());
+        advance(&output, 100);
+        assert_near(eng.current_position(), 100);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn trimmed_silence_shortens_the_track() {
+        let (mut eng, output) = manual_engine();
+        let (dir, _) = library("trim", &[]);
+        // Half a second of silence, a second of tone, 300 ms of silence
+        let path = dir.join("edges.wav");
+        let mut wav = WavWriter::new(std::fs::File::create(&path).unwrap(), WavFormat::Pcm16, 1, 8000).unwrap();
+        for n in 0..14_400 { wav.write_sample(if (4000..12_000).contains(&n) { (n as f32 * 0.05).sin() * 0.5 } else { 0.0 }).unwrap(); }
+        wav.finish().unwrap();
+        let song = SongItem::file(path.clone(), TrackInfo::default());
+        eng.trim_silence = true;
+        eng.prepare_trim(&song);
+        let started = Instant::now();
+        while eng.silence.get(&path).is_none() {
+            assert!(started.elapsed() < Duration::from_secs(10), "silence analysis didn't finish");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        eng.play_item(&song).unwrap();
+        // 50 ms of each edge is kept
+        assert_near(eng.start_offset, 450);
+        assert_near(eng.total_duration().unwrap(), 1100);
+        assert_near(eng.current_position(), 0);
+        advance(&output, 1000);
+        assert!(!eng.track_finished());
+        advance(&output, 150);
+        assert!(eng.track_finished());
+
+        // An excluded album plays in full
+        eng.keep_silence_dirs.push(dir.clone());
+        eng.play_item(&song).unwrap();
+        assert_near(eng.total_duration().unwrap(), 1800);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn smart_speed_position_stays_in_file_time() {
+        let (mut eng, output) = manual_engine();
+        let (dir, _) = library("smart-speed", &[]);
+        // Half a second of tone, two seconds of pause, half a second of tone
+        let path = dir.join("pause.wav");
+        let mut wav = WavWriter::new(std::fs::File::create(&path).unwrap(), WavFormat::Pcm16, 1, 8000).unwrap();
+        for n in 0..24_000 { wav.write_sample(if (4000..20_000).contains(&n) { 0.0 } else { (n as f32 * 0.3).sin() * 0.5 }).unwrap(); }
+        wav.finish().unwrap();
+        eng.max_pause = Some(Duration::from_millis(500));
+        eng.play_item(&SongItem::file(path, TrackInfo::default())).unwrap();
+        // Let the decoder get ahead so the output never waits on it
+        std::thread::sleep(Duration::from_millis(100));
+        advance(&output, 900);
+        assert_near(eng.current_position(), 900);
+        assert_eq!(eng.skipped.get(), Duration::ZERO);
+        // Past the pause: a second of output has covered 2.5 s of the file
+        advance(&output, 200);
+        assert_near(eng.skipped.get(), 1500);
+        assert_near(eng.current_position(), 2600);
+        // Seeking restarts the count from file time
+        eng.seek_to(Duration::from_millis(2700)).unwrap();
+        assert_near(eng.current_position(), 2700);
+        assert!(eng.time_saved() >= Duration::from_millis(1490));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn track_that_breaks_mid_way_is_skipped_afterwards() {
+        let (mut eng, output) = manual_engine();
+        let (dir, mut songs) = library("mid-way", &[Some(300), Some(300)]);
+        // Three good MP3 frames followed by more corrupt ones than the decoder tolerates
+        let mut frame = vec![0u8; 417];
+        frame[..4].copy_from_slice(&[0xff, 0xfb, 0x90, 0x64]);
+        let corrupt = [&frame[..4], &[0xff; 413][..]].concat();
+        let path = dir.join("broken.mp3");
+        std::fs::write(&path, [frame.repeat(3), corrupt.repeat(150)].concat()).unwrap();
+        songs.insert(1, SongItem::file(path.clone(), TrackInfo::default()));
+        let order = [0, 1, 2];
+
+        eng.play_item(&songs[0]).unwrap();
+        advance(&output, 400);
+        assert!(eng.track_finished());
+        // The broken file opens fine and plays its good start
+        assert_eq!(eng.play_first_playable(&songs, walk_from(&order, 0, true, false)), Ok((Some(1), 0)));
+        std::thread::sleep(Duration::from_millis(100));
+        advance(&output, 200);
+        assert!(eng.track_finished());
+        let msg = eng.check_decode_failure().unwrap();
+        assert!(msg.starts_with("Stopped broken.mp3: Too many decoding errors"), "{msg}");
+        assert!(eng.is_broken(&path));
+        assert_eq!(eng.check_decode_failure(), None);
+
+        // Auto-advance moves on, and going back passes over it
+        assert_eq!(eng.play_first_playable(&songs, walk_from(&order, 1, true, false)), Ok((Some(2), 0)));
+        assert_eq!(eng.play_first_playable(&songs, walk_from(&order, 2, false, false)), Ok((Some(0), 1)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn songs_without_a_title_tag_are_named_after_the_file() {
+        let path = PathBuf::from("/music/01 Intro.mp3");
+        let tagged = TrackInfo { title: "Intro".into(), artist: "Band".into(), ..Default::default() };
+        assert_eq!(SongItem::file(path.clone(), tagged.clone()).title, "Intro");
+        let untitled = TrackInfo { artist: "Band".into(), ..Default::default() };
+        assert_eq!(SongItem::file(path.clone(), untitled).title, "01 Intro.mp3");
+        // Tags read later replace the file name
+        let mut song = SongItem::file(path.clone(), TrackInfo::default());
+        song.set_info(&tagged);
+        assert_eq!((song.title.as_str(), song.path.as_path(), song.info.artist.as_str()), ("Intro", path.as_path(), "Band"));
+    }
 }
@@ -0,0 +1,521 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use roxmltree::Node;
+
+use crate::net::{cached_image, encode_query, fetch, hex, image_extension, md5, redact_url, save_image, COVER_CACHE_DIR};
+use crate::record::sanitize;
+use crate::settings::{config_dir, SubsonicAccount};
+
+// A remote library on a Subsonic-compatible server (Subsonic, Navidrome, Airsonic, Gonic…), browsed through the
+// REST API. Requests are signed with a salted token rather than the password, but the token and salt (kept in the
+// settings) can be replayed like one, so URLs carrying them are redacted wherever they're logged or shown. Remote
+// tracks are played from their `stream` URL through the same network path as radio, and cover art is cached on disk.
+
+const API_VERSION: &str = "1.16.1";
+const CLIENT_NAME: &str = "RustAudioPlayer";
+/// Size (pixels) cover art is requested and cached at.
+const COVER_SIZE: u32 = 300;
+const SEARCH_LIMIT: &str = "50";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Artist {
+    pub id: String,
+    pub name: String,
+    pub album_count: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Album {
+    pub id: String,
+    pub name: String,
+    pub artist: String,
+    pub year: Option<u32>,
+    pub song_count: u32,
+    pub cover_art: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Track {
+    pub id: String,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    /// Track number on the album.
+    pub track: Option<u32>,
+    pub duration: Option<Duration>,
+    pub cover_art: Option<String>,
+}
+
+impl Track {
+    /// "Artist - Title", as local files without tags are listed.
+    pub fn label(&self) -> String { if self.artist.is_empty() { self.title.clone() } else { format!("{} - {}", self.artist, self.title) } }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RemotePlaylist {
+    pub id: String,
+    pub name: String,
+    pub song_count: u32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SearchResults {
+    pub artists: Vec<Artist>,
+    pub albums: Vec<Album>,
+    pub tracks: Vec<Track>,
+}
+
+/// A signed-in connection to a server. Cheap to clone.
+#[derive(Clone, Debug)]
+pub struct SubsonicClient {
+    account: SubsonicAccount,
+    cover_dir: Option<PathBuf>,
+}
+
+impl SubsonicClient {
+    /// A client for the server at `url` (without `/rest`) signing in as `user`. Nothing is sent until the first request.
+    pub fn new(url: &str, user: &str, password: &str) -> Self {
+        let salt = format!("{:016x}", rand::random::<u64>());
+        let token = hex(&md5(format!("{password}{salt}").as_bytes()));
+        let url = url.trim().trim_end_matches('/').trim_end_matches("/rest").to_string();
+        Self { account: SubsonicAccount { url, user: user.trim().to_string(), salt, token, ..Default::default() }, cover_dir: None }
+    }
+
+    /// A client for a saved account, caching covers in the settings folder.
+    pub(crate) fn from_account(account: SubsonicAccount) -> Self { Self { account, cover_dir: config_dir().map(|dir| dir.join(COVER_CACHE_DIR)) } }
+
+    pub(crate) fn account(&self) -> &SubsonicAccount { &self.account }
+
+    /// Ask the server to transcode streams: `max_bitrate` in kbit/s (0 for no limit) and a target `format` such as
+    /// `mp3` or `opus` (empty for the original; `raw` turns transcoding off).
+    pub fn set_transcoding(&mut self, max_bitrate: u32, format: &str) {
+        self.account.max_bitrate = max_bitrate;
+        self.account.format = format.trim().to_string();
+    }
+
+    /// Cache cover art in `dir`. Without a cache, covers aren't fetched.
+    pub fn set_cover_cache(&mut self, dir: PathBuf) { self.cover_dir = Some(dir); }
+
+    fn url(&self, method: &str, params: &[(&str, &str)]) -> String {
+        let a = &self.account;
+        let mut url = format!("{}/rest/{method}?u={}&t={}&s={}&v={API_VERSION}&c={CLIENT_NAME}", a.url, encode_query(&a.user), a.token, a.salt);
+        for (name, value) in params { url += &format!("&{name}={}", encode_query(value)); }
+        url
+    }
+
+    /// Download `url`, keeping the credentials in it out of error messages.
+    fn get(&self, url: &str) -> Result<Vec<u8>, String> {
+        fetch(url, None).map_err(|e| format!("{}: {}", self.account.url, e.strip_prefix(&redact_url(url)).map_or(e.as_str(), |e| e.trim_start_matches(": "))))
+    }
+
+    /// Call `method` and hand the response element to `read`.
+    fn call<T>(&self, method: &str, params: &[(&str, &str)], read: impl FnOnce(Node) -> T) -> Result<T, String> {
+        let data = self.get(&self.url(method, params))?;
+        self.response(&String::from_utf8_lossy(&data), read)
+    }
+
+    fn response<T>(&self, text: &str, read: impl FnOnce(Node) -> T) -> Result<T, String> {
+        let doc = roxmltree::Document::parse(text).map_err(|e| format!("{}: invalid response: {e}", self.account.url))?;
+        let root = doc.root_element();
+        if !root.has_tag_name("subsonic-response") { return Err(format!("{} is not a Subsonic server", self.account.url)); }
+        if root.attribute("status") != Some("ok") {
+            let error = root.children().find(|n| n.has_tag_name("error"));
+            let message = error.and_then(|e| e.attribute("message")).unwrap_or("request failed");
+            return Err(match error.and_then(|e| e.attribute("code")) { Some(code) => format!("{message} (error {code})"), None => message.to_string() });
+        }
+        Ok(read(root))
+    }
+
+    /// Check the address and the sign-in. Returns a description of the server.
+    pub fn ping(&self) -> Result<String, String> {
+        self.call("ping", &[], |root| {
+            let api = root.attribute("version").unwrap_or("?");
+            let server = match (root.attribute("type"), root.attribute("serverVersion")) {
+                (Some(kind), Some(version)) => format!("{kind} {version}, API {api}"),
+                _ => format!("Subsonic API {api}"),
+            };
+            if root.attribute("openSubsonic") == Some("true") { format!("{server}, OpenSubsonic") } else { server }
+        })
+    }
+
+    pub fn artists(&self) -> Result<Vec<Artist>, String> { self.call("getArtists", &[], |root| elements(root, "artist").map(artist).collect()) }
+
+    pub fn albums(&self, artist_id: &str) -> Result<Vec<Album>, String> {
+        self.call("getArtist", &[("id", artist_id)], |root| elements(root, "album").map(album).collect())
+    }
+
+    pub fn album_tracks(&self, album_id: &str) -> Result<Vec<Track>, String> {
+        self.call("getAlbum", &[("id", album_id)], |root| elements(root, "song").map(track).collect())
+    }
+
+    pub fn playlists(&self) -> Result<Vec<RemotePlaylist>, String> {
+        self.call("getPlaylists", &[], |root| elements(root, "playlist").map(|n| RemotePlaylist {
+            id: text(n, "id"), name: text(n, "name"), song_count: number(n, "songCount").unwrap_or(0),
+        }).collect())
+    }
+
+    pub fn playlist_tracks(&self, playlist_id: &str) -> Result<Vec<Track>, String> {
+        self.call("getPlaylist", &[("id", playlist_id)], |root| elements(root, "entry").map(track).collect())
+    }
+
+    /// Artists, albums and tracks matching `query`.
+    pub fn search(&self, query: &str) -> Result<SearchResults, String> {
+        let params = [("query", query), ("artistCount", SEARCH_LIMIT), ("albumCount", SEARCH_LIMIT), ("songCount", SEARCH_LIMIT)];
+        self.call("search3", &params, |root| SearchResults {
+            artists: elements(root, "artist").map(artist).collect(),
+            albums: elements(root, "album").map(album).collect(),
+            tracks: elements(root, "song").map(track).collect(),
+        })
+    }
+
+    /// URL that plays `track`, with the transcoding settings applied.
+    pub fn stream_url(&self, track: &Track) -> String {
+        let bitrate = self.account.max_bitrate.to_string();
+        let mut params = vec![("id", track.id.as_str())];
+        if self.account.max_bitrate > 0 { params.push(("maxBitRate", &bitrate)); }
+        if !self.account.format.is_empty() { params.push(("format", &self.account.format)); }
+        self.url("stream", &params)
+    }
+
+    /// Cover art `id` as an image file in the cache, downloaded the first time it's needed.
+    pub fn cover_art(&self, id: &str) -> Result<PathBuf, String> {
+        let dir = self.cover_dir.as_ref().ok_or("No cover art cache")?;
+        // Ids are only unique per server
+        let stem = format!("{}-{}", &hex(&md5(self.account.url.as_bytes()))[..8], sanitize(id));
+        if let Some(path) = cached_image(dir, &stem) { return Ok(path); }
+        let data = self.get(&self.url("getCoverArt", &[("id", id), ("size", &COVER_SIZE.to_string())]))?;
+        // Failures come back as an XML response instead of an image
+        if image_extension(&data).is_none() && let Err(e) = self.response(&String::from_utf8_lossy(&data), |_| ()) { return Err(e); }
+        save_image(dir, &stem, &data)
+    }
+}
+
+/// Elements named `name` anywhere under `root`.
+fn elements<'a, 'input>(root: Node<'a, 'input>, name: &'static str) -> impl Iterator<Item = Node<'a, 'input>> {
+    root.descendants().filter(move |n| n.has_tag_name(name))
+}
+
+fn text(node: Node, name: &str) -> String { node.attribute(name).unwrap_or_default().trim().to_string() }
+
+fn number(node: Node, name: &str) -> Option<u32> { node.attribute(name)?.trim().parse().ok() }
+
+fn artist(node: Node) -> Artist { Artist { id: text(node, "id"), name: text(node, "name"), album_count: number(node, "albumCount").unwrap_or(0) } }
+
+fn album(node: Node) -> Album {
+    // Folder-based responses call the album's name its title
+    let name = node.attribute("name").or(node.attribute("title")).unwrap_or_default().trim().to_string();
+    Album {
+        id: text(node, "id"), name, artist: text(node, "artist"), year: number(node, "year"),
+        song_count: number(node, "songCount").unwrap_or(0), cover_art: node.attribute("coverArt").map(str::to_string),
+    }
+}
+
+fn track(node: Node) -> Track {
+    Track {
+        id: text(node, "id"), title: text(node, "title"), artist: text(node, "artist"), album: text(node, "album"),
+        track: number(node, "track"), duration: number(node, "duration").map(|s| Duration::from_secs(s.into())),
+        cover_art: node.attribute("coverArt").map(str::to_string),
+    }
+}
+
+// ===== Browsing =====
+
+/// A page of the remote library panel.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Page { Artists, Artist(Artist), Album(Album), Playlists, Playlist(RemotePlaylist), Search(String) }
+
+impl Page {
+    pub(crate) fn title(&self) -> String {
+        match self {
+            Page::Artists => "Artists".into(),
+            Page::Artist(a) => a.name.clone(),
+            Page::Album(a) if a.artist.is_empty() => a.name.clone(),
+            Page::Album(a) => format!("{} – {}", a.artist, a.name),
+            Page::Playlists => "Playlists".into(),
+            Page::Playlist(p) => p.name.clone(),
+            Page::Search(query) => format!("Search: {query}"),
+        }
+    }
+}
+
+/// An entry on a page.
+#[derive(Clone, Debug)]
+pub(crate) enum Row { Artist(Artist), Album(Album), Playlist(RemotePlaylist), Track(Track) }
+
+impl Row {
+    pub(crate) fn label(&self) -> String {
+        match self {
+            Row::Artist(a) => format!("👤 {} ({})", a.name, a.album_count),
+            Row::Album(a) => {
+                let year = a.year.map(|y| format!(" ({y})")).unwrap_or_default();
+                if a.artist.is_empty() { format!("💿 {}{year}", a.name) } else { format!("💿 {} – {}{year}", a.artist, a.name) }
+            }
+            Row::Playlist(p) => format!("📃 {} ({})", p.name, p.song_count),
+            Row::Track(t) => {
+                let number = t.track.map(|n| format!("{n:02}. ")).unwrap_or_default();
+                let length = t.duration.map(|d| format!("  {}:{:02}", d.as_secs() / 60, d.as_secs() % 60)).unwrap_or_default();
+                format!("{number}{}{length}", t.label())
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Loaded { rows: Vec<Row>, cover: Option<PathBuf> }
+
+/// A finished load, tagged with the request it answers.
+struct Delivery { generation: u64, result: Result<Loaded, String> }
+
+fn load_page(client: &SubsonicClient, page: &Page) -> Result<Loaded, String> {
+    let tracks = |tracks: Vec<Track>| tracks.into_iter().map(Row::Track).collect::<Vec<_>>();
+    let rows: Vec<Row> = match page {
+        Page::Artists => client.artists()?.into_iter().map(Row::Artist).collect(),
+        Page::Artist(a) => client.albums(&a.id)?.into_iter().map(Row::Album).collect(),
+        Page::Album(a) => tracks(client.album_tracks(&a.id)?),
+        Page::Playlists => client.playlists()?.into_iter().map(Row::Playlist).collect(),
+        Page::Playlist(p) => tracks(client.playlist_tracks(&p.id)?),
+        Page::Search(query) => {
+            let found = client.search(query)?;
+            let artists = found.artists.into_iter().map(Row::Artist);
+            let albums = found.albums.into_iter().map(Row::Album);
+            artists.chain(albums).chain(found.tracks.into_iter().map(Row::Track)).collect()
+        }
+    };
+    let cover_id: Option<String> = match page {
+        Page::Album(a) => a.cover_art.clone(),
+        Page::Playlist(_) => rows.iter().find_map(|r| if let Row::Track(t) = r { t.cover_art.clone() } else { None }),
+        _ => None,
+    };
+    // A missing cover doesn't stop the page from showing
+    let cover = cover_id.and_then(|id| client.cover_art(&id).inspect_err(|e| log::warn!("Cover art {id}: {e}")).ok());
+    Ok(Loaded { rows, cover })
+}
+
+/// Navigation through a server's library. Pages load on a background thread; `poll` picks up the result.
+pub(crate) struct Browser {
+    client: SubsonicClient,
+    /// Pages opened, the current one last, with their content once loaded.
+    pages: Vec<(Page, Option<Loaded>)>,
+    /// The load in flight (its generation), and where it delivers.
+    loading: Option<u64>,
+    generation: u64,
+    done: Arc<Mutex<Option<Delivery>>>,
+}
+
+impl Browser {
+    pub(crate) fn new(client: SubsonicClient) -> Self {
+        Self { client, pages: Vec::new(), loading: None, generation: 0, done: Arc::default() }
+    }
+
+    pub(crate) fn client(&self) -> &SubsonicClient { &self.client }
+
+    pub(crate) fn client_mut(&mut self) -> &mut SubsonicClient { &mut self.client }
+
+    /// Show `page`, on top of the current one or, with `root`, in place of all of them.
+    pub(crate) fn open(&mut self, page: Page, root: bool) {
+        if root { self.pages.clear(); }
+        self.pages.push((page, None));
+        self.reload();
+    }
+
+    /// Return to the previous page. `false` when already at the first one.
+    pub(crate) fn back(&mut self) -> bool {
+        if self.pages.len() < 2 { return false; }
+        self.pages.pop();
+        if self.pages.last().is_some_and(|(_, loaded)| loaded.is_none()) { self.reload(); } else { self.loading = None; }
+        true
+    }
+
+    /// Load the current page again.
+    pub(crate) fn reload(&mut self) {
+        let Some((page, _)) = self.pages.last() else { return };
+        self.generation += 1;
+        self.loading = Some(self.generation);
+        let (client, page, done, generation) = (self.client.clone(), page.clone(), self.done.clone(), self.generation);
+        let spawned = std::thread::Builder::new().name("subsonic".into()).spawn(move || {
+            let result = load_page(&client, &page);
+            *done.lock().unwrap() = Some(Delivery { generation, result });
+        });
+        if let Err(e) = spawned { *self.done.lock().unwrap() = Some(Delivery { generation: self.generation, result: Err(format!("Failed to start loading: {e}")) }); }
+    }
+
+    /// Apply a finished load: `Some(Ok)` when the current page's rows arrived, `Some(Err)` if loading failed.
+    pub(crate) fn poll(&mut self) -> Option<Result<(), String>> {
+        let Delivery { generation, result } = self.done.lock().unwrap().take()?;
+        // Results for pages the user has since left are dropped
+        if self.loading != Some(generation) { return None; }
+        self.loading = None;
+        let loaded = match result { Ok(loaded) => loaded, Err(e) => return Some(Err(e)) };
+        if let Some((_, slot)) = self.pages.last_mut() { *slot = Some(loaded); }
+        Some(Ok(()))
+    }
+
+    pub(crate) fn is_loading(&self) -> bool { self.loading.is_some() }
+
+    pub(crate) fn can_go_back(&self) -> bool { self.pages.len() > 1 }
+
+    pub(crate) fn page(&self) -> Option<&Page> { self.pages.last().map(|(page, _)| page) }
+
+    pub(crate) fn rows(&self) -> &[Row] { self.pages.last().and_then(|(_, l)| l.as_ref()).map_or(&[], |l| &l.rows) }
+
+    pub(crate) fn cover(&self) -> Option<&Path> { self.pages.last().and_then(|(_, l)| l.as_ref()?.cover.as_deref()) }
+
+    /// The tracks on the current page, and where row `index` is among them (for playing on from it).
+    pub(crate) fn tracks_from(&self, index: usize) -> (Vec<&Track>, usize) {
+        let start = self.rows().iter().take(index).filter(|r| matches!(r, Row::Track(_))).count();
+        (self.rows().iter().filter_map(|r| if let Row::Track(t) = r { Some(t) } else { None }).collect(), start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{reply, serve, Request};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Instant;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\nnot really";
+
+    fn ok(body: &str) -> String {
+        format!(r#"<subsonic-response xmlns="http://subsonic.org/restapi" status="ok" version="1.16.1" type="navidrome" serverVersion="0.53" openSubsonic="true">{body}</subsonic-response>"#)
+    }
+
+    fn failed(code: u32, message: &str) -> String {
+        format!(r#"<subsonic-response status="failed" version="1.16.1"><error code="{code}" message="{message}"/></subsonic-response>"#)
+    }
+
+    /// Answers like a server with one artist, album and playlist, for user `demo` with password `secret`.
+    fn respond(request: &Request) -> Vec<u8> {
+        let param = |name: &str| request.param(name).unwrap_or_default();
+        let salt = param("s");
+        if param("u") != "demo" || salt.len() < 6 || param("t") != hex(&md5(format!("secret{salt}").as_bytes())) {
+            return failed(40, "Wrong username or password").into_bytes();
+        }
+        assert_eq!((param("v").as_str(), param("c").as_str()), (API_VERSION, CLIENT_NAME));
+        let song = r#"id="s1" title="Song &amp; Dance" artist="The Band" album="First" track="2" duration="185" coverArt="al-1""#;
+        let body = match (request.path(), param("id").as_str()) {
+            ("/music/rest/ping", _) => String::new(),
+            ("/music/rest/getArtists", _) => r#"<artists><index name="T"><artist id="ar-1" name="The Band" albumCount="1"/></index></artists>"#.into(),
+            ("/music/rest/getArtist", "ar-1") => r#"<artist id="ar-1" name="The Band"><album id="al-1" name="First" artist="The Band" year="1999" songCount="1" coverArt="al-1"/></artist>"#.into(),
+            ("/music/rest/getAlbum", "al-1") => format!(r#"<album id="al-1" name="First"><song {song}/></album>"#),
+            ("/music/rest/getPlaylists", _) => r#"<playlists><playlist id="pl-1" name="Mix" songCount="1"/></playlists>"#.into(),
+            ("/music/rest/getPlaylist", "pl-1") => format!(r#"<playlist id="pl-1" name="Mix"><entry {song}/></playlist>"#),
+            ("/music/rest/search3", _) if param("query") == "band dance" => format!(r#"<searchResult3><artist id="ar-1" name="The Band"/><song {song}/></searchResult3>"#),
+            ("/music/rest/getCoverArt", "al-1") => return PNG.to_vec(),
+            _ => return failed(70, "Not found").into_bytes(),
+        };
+        ok(&body).into_bytes()
+    }
+
+    fn server() -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let url = serve(move |request, stream| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            reply(stream, 200, "text/xml", &respond(request))
+        });
+        (format!("{url}/music"), requests)
+    }
+
+    #[test]
+    fn signs_in_with_a_salted_token() {
+        let (url, _) = server();
+        let client = SubsonicClient::new(&format!(" {url}/rest/ "), "demo", "secret");
+        assert_eq!(client.account().url, url);
+        assert_ne!(client.account().token, SubsonicClient::new(&url, "demo", "secret").account().token, "each sign-in gets its own salt");
+        assert_eq!(client.ping().unwrap(), "navidrome 0.53, API 1.16.1, OpenSubsonic");
+        assert_eq!(SubsonicClient::new(&url, "demo", "wrong").ping().unwrap_err(), "Wrong username or password (error 40)");
+    }
+
+    #[test]
+    fn lists_the_library() {
+        let (url, _) = server();
+        let client = SubsonicClient::new(&url, "demo", "secret");
+        assert_eq!(client.artists().unwrap(), [Artist { id: "ar-1".into(), name: "The Band".into(), album_count: 1 }]);
+        let albums = client.albums("ar-1").unwrap();
+        assert_eq!((albums[0].name.as_str(), albums[0].year, albums[0].cover_art.as_deref()), ("First", Some(1999), Some("al-1")));
+        let tracks = client.album_tracks("al-1").unwrap();
+        assert_eq!(tracks[0].label(), "The Band - Song & Dance");
+        assert_eq!((tracks[0].track, tracks[0].duration), (Some(2), Some(Duration::from_secs(185))));
+        assert_eq!(client.playlists().unwrap()[0].song_count, 1);
+        assert_eq!(client.playlist_tracks("pl-1").unwrap(), tracks);
+        let found = client.search("band dance").unwrap();
+        assert_eq!((found.artists.len(), found.albums.len(), found.tracks.len()), (1, 0, 1));
+        assert_eq!(client.album_tracks("missing").unwrap_err(), "Not found (error 70)");
+    }
+
+    #[test]
+    fn stream_urls_carry_the_transcoding_settings() {
+        let mut client = SubsonicClient::new("http://host", "demo user", "secret");
+        let track = Track { id: "a b".into(), ..Default::default() };
+        let plain = client.stream_url(&track);
+        assert!(plain.starts_with("http://host/rest/stream?u=demo%20user&t="), "{plain}");
+        assert!(plain.ends_with("&id=a%20b"), "{plain}");
+        client.set_transcoding(128, " opus ");
+        assert!(client.stream_url(&track).ends_with("&id=a%20b&maxBitRate=128&format=opus"));
+    }
+
+    #[test]
+    fn covers_are_cached() {
+        let (url, requests) = server();
+        let dir = std::env::temp_dir().join(format!("subsonic-{}-covers", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut client = SubsonicClient::new(&url, "demo", "secret");
+        assert_eq!(client.cover_art("al-1").unwrap_err(), "No cover art cache");
+        client.set_cover_cache(dir.clone());
+        let path = client.cover_art("al-1").unwrap();
+        assert_eq!((path.extension().unwrap(), std::fs::read(&path).unwrap()), ("png".as_ref(), PNG.to_vec()));
+        let before = requests.load(Ordering::SeqCst);
+        assert_eq!(client.cover_art("al-1").unwrap(), path);
+        assert_eq!(requests.load(Ordering::SeqCst), before);
+        // The error comes back as XML rather than an image
+        assert_eq!(client.cover_art("other").unwrap_err(), "Not found (error 70)");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn errors_name_the_server_without_the_credentials() {
+        let html = serve(|_, stream| reply(stream, 200, "text/html", b"<html><body>Hello</body></html>"));
+        assert_eq!(SubsonicClient::new(&html, "demo", "secret").ping().unwrap_err(), format!("{html} is not a Subsonic server"));
+        let missing = serve(|_, stream| reply(stream, 404, "text/plain", b""));
+        let client = SubsonicClient::new(&missing, "demo", "secret");
+        let error = client.ping().unwrap_err();
+        assert!(error.starts_with(&format!("{missing}: ")) && !error.contains(&client.account().token), "{error}");
+    }
+
+    #[test]
+    fn browser_loads_pages_in_the_background() {
+        let (url, _) = server();
+        let mut browser = Browser::new(SubsonicClient::new(&url, "demo", "secret"));
+        let wait = |browser: &mut Browser| {
+            let started = Instant::now();
+            loop {
+                if let Some(result) = browser.poll() { return result; }
+                assert!(started.elapsed() < Duration::from_secs(10), "page didn't load");
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        };
+        browser.open(Page::Artists, true);
+        assert!(browser.is_loading());
+        wait(&mut browser).unwrap();
+        let Some(Row::Artist(artist)) = browser.rows().first().cloned() else { panic!("no artist") };
+        browser.open(Page::Artist(artist), false);
+        wait(&mut browser).unwrap();
+        let Some(Row::Album(album)) = browser.rows().first().cloned() else { panic!("no album") };
+        assert_eq!(Row::Album(album.clone()).label(), "💿 The Band – First (1999)");
+        browser.open(Page::Album(album), false);
+        wait(&mut browser).unwrap();
+        assert_eq!(browser.rows().iter().map(Row::label).collect::<Vec<_>>(), ["02. The Band - Song & Dance  3:05"]);
+        // Without a cover cache the page shows without one
+        assert_eq!(browser.cover(), None);
+        assert_eq!(browser.tracks_from(0).0.len(), 1);
+        // Earlier pages are kept, so going back doesn't load them again
+        assert!(browser.back() && browser.back());
+        assert!(!browser.is_loading() && !browser.can_go_back());
+        assert_eq!(browser.page(), Some(&Page::Artists));
+        browser.open(Page::Album(Album { id: "missing".into(), ..Default::default() }), false);
+        assert_eq!(wait(&mut browser).unwrap_err(), "Not found (error 70)");
+    }
+}
@@ -0,0 +1,1191 @@
+use std::fs::File;
+use std::io::{BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::id3::{read_id3, tag_len, write_id3};
+use crate::tags::{read_track_info, TagField, TrackInfo};
+
+// Tag writing for the tag editor: ID3v2.3/2.4 for MP3, Vorbis comments and PICTURE blocks for FLAC, the comment
+// header of Ogg Vorbis and Opus, `ilst` atoms for MP4/M4A, and APEv2 for Monkey's Audio, WavPack and Musepack. Only
+// the edited fields change; the rest of the file is copied as it is, to a new file next to it that is then renamed
+// over the original, so a failed write leaves the old file intact.
+
+/// New cover art for the edited tracks.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum CoverEdit {
+    #[default]
+    Keep,
+    /// Remove every picture.
+    Remove,
+    /// JPEG, PNG, GIF or WebP data to store as the front cover; other kinds of picture are kept.
+    Set(Vec<u8>),
+}
+
+/// Changes for one or more tracks. Fields not listed keep their value, and an empty value removes the field.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TagEdit {
+    pub fields: Vec<(TagField, String)>,
+    pub cover: CoverEdit,
+}
+
+impl TagEdit {
+    /// Change `field` to `value`, replacing an earlier change to it.
+    pub fn set(&mut self, field: TagField, value: &str) {
+        self.fields.retain(|(f, _)| *f != field);
+        self.fields.push((field, value.trim().to_string()));
+    }
+
+    /// The new value of `field`, if it is changed.
+    pub fn get(&self, field: TagField) -> Option<&str> {
+        self.fields.iter().find(|(f, _)| *f == field).map(|(_, v)| v.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool { self.fields.is_empty() && self.cover == CoverEdit::Keep }
+
+    /// Reject values the fields can't hold: track and disc are `N` or `N/TOTAL`, and a year starts with 4 digits.
+    pub fn check(&self) -> Result<(), String> {
+        for (field, value) in self.fields.iter().filter(|(_, v)| !v.is_empty()) {
+            let valid = match field {
+                TagField::Track | TagField::Disc => split_number(value).is_some(),
+                TagField::Year => value.len() >= 4 && value.bytes().take(4).all(|b| b.is_ascii_digit()),
+                _ => true,
+            };
+            if !valid { return Err(format!("Invalid {}: {value}", field.label().to_lowercase())); }
+        }
+        if let CoverEdit::Set(data) = &self.cover && image_mime(data).is_none() { return Err("The cover must be a JPEG, PNG, GIF or WebP image".into()); }
+        Ok(())
+    }
+}
+
+/// Apply `edit` to the tags of `path`, in the tag format of its file type.
+pub fn write_tags(path: &Path, edit: &TagEdit) -> Result<(), String> {
+    edit.check()?;
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+    match ext.as_str() {
+        "mp3" => write_mp3(path, edit),
+        "flac" => write_flac(path, edit),
+        "ogg" | "oga" | "opus" => write_ogg(path, edit),
+        "m4a" | "m4b" | "mp4" | "alac" => write_mp4(path, edit),
+        "ape" | "wv" | "mpc" => write_ape(path, edit),
+        _ => Err(format!("{}: can't write tags to .{ext} files", path.display())),
+    }
+}
+
+// `3` or `3/12`
+fn split_number(value: &str) -> Option<(u32, Option<u32>)> {
+    let (number, total) = match value.split_once('/') { Some((n, t)) => (n, Some(t)), None => (value, None) };
+    let total = match total { Some(t) => Some(t.trim().parse().ok()?), None => None };
+    Some((number.trim().parse().ok()?, total))
+}
+
+// A new `3` keeps the total of an old `2/12`
+fn with_total(value: &str, old: Option<&str>) -> String {
+    match (split_number(value), old.and_then(split_number)) {
+        (Some((number, None)), Some((_, Some(total)))) => format!("{number}/{total}"),
+        _ => value.to_string(),
+    }
+}
+
+fn image_mime(data: &[u8]) -> Option<&'static str> {
+    match data {
+        [0xff, 0xd8, ..] => Some("image/jpeg"),
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        _ => None,
+    }
+}
+
+// Whether a picture of `kind` (3 is the front cover; `None` when unknown) survives `cover`
+fn keep_picture(cover: &CoverEdit, kind: Option<u32>) -> bool {
+    match cover {
+        CoverEdit::Keep => true,
+        CoverEdit::Remove => false,
+        CoverEdit::Set(_) => kind != Some(3),
+    }
+}
+
+/// A piece of a rewritten file: new bytes, or a range of the old file.
+pub(crate) enum Part<'a> {
+    New(&'a [u8]),
+    Old(Range<u64>),
+}
+
+/// Write `parts` to a file next to `path` and rename it over `path`, so a failed write can't cut the audio short.
+pub(crate) fn rewrite(path: &Path, parts: &[Part]) -> Result<(), String> {
+    let tmp = path.with_extension("tag-tmp");
+    let write = || -> std::io::Result<()> {
+        let mut old = File::open(path)?;
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        for part in parts {
+            match part {
+                Part::New(data) => out.write_all(data)?,
+                Part::Old(range) => {
+                    old.seek(SeekFrom::Start(range.start))?;
+                    let len = range.end - range.start;
+                    if std::io::copy(&mut (&mut old).take(len), &mut out)? != len { return Err(std::io::ErrorKind::UnexpectedEof.into()); }
+                }
+            }
+        }
+        let out = out.into_inner().map_err(|e| e.into_error())?;
+        out.sync_all()?;
+        // The new file would otherwise get default permissions in place of the original's
+        std::fs::set_permissions(&tmp, old.metadata()?.permissions())
+    };
+    write().and_then(|()| std::fs::rename(&tmp, path)).map_err(|e| {
+        let _ = std::fs::remove_file(&tmp);
+        format!("Failed to write {}: {e}", path.display())
+    })
+}
+
+// ===== MP3 (ID3v2) =====
+
+fn id3_frame(field: TagField) -> &'static str {
+    match field {
+        TagField::Title => "TIT2",
+        TagField::Artist => "TPE1",
+        TagField::Album => "TALB",
+        TagField::AlbumArtist => "TPE2",
+        TagField::Track => "TRCK",
+        TagField::Disc => "TPOS",
+        TagField::Year => "TDRC",
+        TagField::Genre => "TCON",
+        TagField::Composer => "TCOM",
+    }
+}
+
+fn write_mp3(path: &Path, edit: &TagEdit) -> Result<(), String> {
+    // A file without a tag gets a 2.4 one; 2.2 and 2.3 tags are written back as 2.3
+    let mut tag = read_id3(path).unwrap_or_default();
+    for (field, value) in &edit.fields {
+        match field {
+            TagField::Track | TagField::Disc => {
+                let id = id3_frame(*field);
+                let value = with_total(value, tag.text(id).as_deref());
+                tag.set_text(id, &value);
+            }
+            // 2.3 has a year frame, 2.4 a date
+            TagField::Year if tag.version == 3 => {
+                tag.set_text("TDRC", "");
+                tag.set_text("TYER", value.get(..4).unwrap_or(value));
+            }
+            TagField::Year => {
+                tag.set_text("TYER", "");
+                tag.set_text("TDRC", value);
+            }
+            _ => tag.set_text(id3_frame(*field), value),
+        }
+    }
+    match &edit.cover {
+        CoverEdit::Keep => {}
+        CoverEdit::Remove => tag.set_frame("APIC", None),
+        CoverEdit::Set(data) => tag.set_picture(image_mime(data).unwrap_or_default(), data),
+    }
+    write_id3(path, &tag)?;
+    // Some players prefer an APEv2 tag at the end of an MP3 to its ID3 tag; keep one in step
+    if read_ape(path).is_some() { write_ape(path, edit)?; }
+    Ok(())
+}
+
+// ===== FLAC =====
+
+const FLAC_STREAMINFO: u8 = 0;
+const FLAC_PADDING: u8 = 1;
+const FLAC_COMMENT: u8 = 4;
+const FLAC_PICTURE: u8 = 6;
+// Room left for later edits in place by other taggers
+const PADDING_SIZE: usize = 4096;
+
+// A metadata block: type and data
+type FlacBlock = (u8, Vec<u8>);
+
+// The metadata blocks and where the audio starts
+fn read_flac_blocks(path: &Path) -> Result<(Vec<FlacBlock>, u64), String> {
+    let err = |e: std::io::Error| format!("Failed to read {}: {e}", path.display());
+    let mut r = std::io::BufReader::new(File::open(path).map_err(err)?);
+    let (mut magic, mut start) = ([0u8; 4], 0);
+    r.read_exact(&mut magic).map_err(err)?;
+    // Some taggers put an ID3 tag in front, which FLAC doesn't allow but decoders skip; so do we
+    if &magic[..3] == b"ID3" {
+        let mut header = [0u8; 10];
+        header[..4].copy_from_slice(&magic);
+        r.read_exact(&mut header[4..]).map_err(err)?;
+        start = tag_len(&header);
+        r.seek(SeekFrom::Start(start)).map_err(err)?;
+        r.read_exact(&mut magic).map_err(err)?;
+    }
+    if &magic != b"fLaC" { return Err(format!("{} isn't a FLAC file", path.display())); }
+    let (mut blocks, mut pos) = (Vec::new(), start + 4);
+    loop {
+        let mut header = [0u8; 4];
+        r.read_exact(&mut header).map_err(err)?;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        let mut data = vec![0; len];
+        r.read_exact(&mut data).map_err(err)?;
+        blocks.push((header[0] & 0x7f, data));
+        pos += 4 + len as u64;
+        if header[0] & 0x80 != 0 { return Ok((blocks, pos)); }
+    }
+}
+
+fn write_flac(path: &Path, edit: &TagEdit) -> Result<(), String> {
+    let (blocks, audio_start) = read_flac_blocks(path)?;
+    let len = std::fs::metadata(path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?.len();
+    let Some(((FLAC_STREAMINFO, streaminfo), rest)) = blocks.split_first() else { return Err(format!("{}: no STREAMINFO block", path.display())) };
+    let mut comments = rest.iter().find(|(kind, _)| *kind == FLAC_COMMENT).and_then(|(_, data)| VorbisComments::parse(data)).unwrap_or_default();
+    comments.apply(edit, false);
+    // STREAMINFO stays first; the comments follow it and padding goes last
+    let mut new_blocks = vec![(FLAC_STREAMINFO, streaminfo.clone()), (FLAC_COMMENT, comments.render())];
+    new_blocks.extend(rest.iter().filter(|(kind, data)| match *kind {
+        FLAC_PADDING | FLAC_COMMENT => false,
+        FLAC_PICTURE => keep_picture(&edit.cover, picture_type(data)),
+        _ => true,
+    }).cloned());
+    if let CoverEdit::Set(data) = &edit.cover { new_blocks.push((FLAC_PICTURE, picture_block(data))); }
+    new_blocks.push((FLAC_PADDING, vec![0; PADDING_SIZE]));
+
+    // A leading ID3 tag is dropped rather than left out of step with the comments
+    let mut head = b"fLaC".to_vec();
+    for (i, (kind, data)) in new_blocks.iter().enumerate() {
+        if data.len() >= 1 << 24 { return Err(format!("{}: a metadata block is too large for FLAC", path.display())); }
+        head.push(kind | if i + 1 == new_blocks.len() { 0x80 } else { 0 });
+        head.extend(&(data.len() as u32).to_be_bytes()[1..]);
+        head.extend(data);
+    }
+    rewrite(path, &[Part::New(&head), Part::Old(audio_start..len)])
+}
+
+/// A FLAC PICTURE block, also stored base64-encoded in Ogg comments, holding `data` as the front cover.
+fn picture_block(data: &[u8]) -> Vec<u8> {
+    let mime = image_mime(data).unwrap_or_default();
+    let (width, height) = image::ImageReader::new(Cursor::new(data)).with_guessed_format().ok()
+        .and_then(|r| r.into_dimensions().ok()).unwrap_or((0, 0));
+    let mut out = Vec::new();
+    out.extend(3u32.to_be_bytes());
+    out.extend((mime.len() as u32).to_be_bytes());
+    out.extend(mime.as_bytes());
+    // No description; size, colour depth, and no palette
+    for n in [0, width, height, 24, 0, data.len() as u32] { out.extend(n.to_be_bytes()); }
+    out.extend(data);
+    out
+}
+
+fn picture_type(block: &[u8]) -> Option<u32> { Some(u32::from_be_bytes(block.get(..4)?.try_into().ok()?)) }
+
+// ===== Vorbis comments =====
+
+const PICTURE_KEY: &str = "METADATA_BLOCK_PICTURE";
+
+/// A Vorbis comment block (FLAC, Ogg Vorbis and Opus): the vendor string and `KEY=value` comments.
+#[derive(Default)]
+struct VorbisComments {
+    vendor: String,
+    comments: Vec<(String, String)>,
+}
+
+impl VorbisComments {
+    fn parse(data: &[u8]) -> Option<Self> { Self::parse_prefix(data).map(|(comments, _)| comments) }
+
+    // The comments and the length they take, for Opus headers that carry more data behind them
+    fn parse_prefix(data: &[u8]) -> Option<(Self, usize)> {
+        let mut p = 0;
+        let mut take = |n: usize| { let bytes = data.get(p..p + n)?; p += n; Some(bytes) };
+        let len = |bytes: &[u8]| u32::from_le_bytes(bytes.try_into().unwrap()) as usize;
+        let vendor_len = len(take(4)?);
+        let vendor = String::from_utf8_lossy(take(vendor_len)?).into_owned();
+        let mut comments = Vec::new();
+        for _ in 0..len(take(4)?) {
+            let len = len(take(4)?);
+            let comment = String::from_utf8_lossy(take(len)?).into_owned();
+            if let Some((key, value)) = comment.split_once('=') { comments.push((key.to_string(), value.to_string())); }
+        }
+        Some((Self { vendor, comments }, p))
+    }
+
+    fn render(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend((self.vendor.len() as u32).to_le_bytes());
+        out.extend(self.vendor.as_bytes());
+        out.extend((self.comments.len() as u32).to_le_bytes());
+        for (key, value) in &self.comments {
+            out.extend(((key.len() + 1 + value.len()) as u32).to_le_bytes());
+            out.extend(key.as_bytes());
+            out.push(b'=');
+            out.extend(value.as_bytes());
+        }
+        out
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.comments.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)).map(|(_, v)| v.as_str())
+    }
+
+    // Replace the comments under any of `keys` with one under the first, or remove them
+    fn set(&mut self, keys: &[&str], value: Option<String>) {
+        self.comments.retain(|(k, _)| !keys.iter().any(|key| k.eq_ignore_ascii_case(key)));
+        if let Some(value) = value.filter(|v| !v.is_empty()) { self.comments.push((keys[0].to_string(), value)); }
+    }
+
+    /// Apply `edit`. Ogg streams keep pictures in comments (`pictures`); FLAC has blocks for them.
+    fn apply(&mut self, edit: &TagEdit, pictures: bool) {
+        for (field, value) in &edit.fields {
+            let keys: &[&str] = match field {
+                TagField::Title => &["TITLE"],
+                TagField::Artist => &["ARTIST"],
+                TagField::Album => &["ALBUM"],
+                TagField::AlbumArtist => &["ALBUMARTIST", "ALBUM ARTIST"],
+                TagField::Year => &["DATE", "YEAR"],
+                TagField::Genre => &["GENRE"],
+                TagField::Composer => &["COMPOSER"],
+                // The total has a comment of its own
+                TagField::Track | TagField::Disc => {
+                    let (key, total_keys): (&str, &[&str]) = if *field == TagField::Track {
+                        ("TRACKNUMBER", &["TRACKTOTAL", "TOTALTRACKS"])
+                    } else {
+                        ("DISCNUMBER", &["DISCTOTAL", "TOTALDISCS"])
+                    };
+                    let value = with_total(value, self.get(key));
+                    let (number, total) = split_number(&value).unzip();
+                    self.set(&[key], number.map(|n| n.to_string()));
+                    if let Some(total) = total.flatten() { self.set(total_keys, Some(total.to_string())); }
+                    continue;
+                }
+            };
+            self.set(keys, Some(value.clone()));
+        }
+        if pictures && edit.cover != CoverEdit::Keep {
+            self.comments.retain(|(key, value)| {
+                if key.eq_ignore_ascii_case("COVERART") || key.eq_ignore_ascii_case("COVERARTMIME") { return false; }
+                !key.eq_ignore_ascii_case(PICTURE_KEY) || keep_picture(&edit.cover, base64_decode(value).as_deref().and_then(picture_type))
+            });
+            if let CoverEdit::Set(data) = &edit.cover { self.comments.push((PICTURE_KEY.into(), base64_encode(&picture_block(data)))); }
+        }
+    }
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            out.push(if i <= chunk.len() { BASE64[(n >> (18 - 6 * i)) as usize & 63] as char } else { '=' });
+        }
+    }
+    out
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    let (mut n, mut bits) = (0u32, 0);
+    for c in text.bytes().filter(|&c| c != b'=' && !c.is_ascii_whitespace()) {
+        n = n << 6 | BASE64.iter().position(|&b| b == c)? as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((n >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+// ===== Ogg Vorbis / Opus =====
+
+/// One Ogg page.
+#[derive(Clone, Default)]
+struct OggPage {
+    header_type: u8,
+    granule: u64,
+    serial: u32,
+    sequence: u32,
+    segments: Vec<u8>,
+    data: Vec<u8>,
+}
+
+impl OggPage {
+    // The page at `at` and where the next one starts
+    fn parse(data: &[u8], at: usize) -> Option<(Self, usize)> {
+        let header = data.get(at..at + 27).filter(|h| h.starts_with(b"OggS"))?;
+        let count = header[26] as usize;
+        let segments = data.get(at + 27..at + 27 + count)?.to_vec();
+        let start = at + 27 + count;
+        let end = start + segments.iter().map(|&s| s as usize).sum::<usize>();
+        let page = Self {
+            header_type: header[5],
+            granule: u64::from_le_bytes(header[6..14].try_into().ok()?),
+            serial: u32::from_le_bytes(header[14..18].try_into().ok()?),
+            sequence: u32::from_le_bytes(header[18..22].try_into().ok()?),
+            segments,
+            data: data.get(start..end)?.to_vec(),
+        };
+        Some((page, end))
+    }
+
+    fn render(&self) -> Vec<u8> {
+        let mut out = b"OggS\0".to_vec();
+        out.push(self.header_type);
+        out.extend(self.granule.to_le_bytes());
+        out.extend(self.serial.to_le_bytes());
+        out.extend(self.sequence.to_le_bytes());
+        out.extend([0; 4]);
+        out.push(self.segments.len() as u8);
+        out.extend(&self.segments);
+        out.extend(&self.data);
+        let crc = ogg_crc(&out);
+        out[22..26].copy_from_slice(&crc.to_le_bytes());
+        out
+    }
+}
+
+const OGG_CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut r = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            r = if r & 0x8000_0000 != 0 { (r << 1) ^ 0x04c1_1db7 } else { r << 1 };
+            bit += 1;
+        }
+        table[i] = r;
+        i += 1;
+    }
+    table
+};
+
+fn ogg_crc(data: &[u8]) -> u32 { data.iter().fold(0, |crc, &b| (crc << 8) ^ OGG_CRC_TABLE[((crc >> 24) as u8 ^ b) as usize]) }
+
+// Lay header `packets` out on pages of up to 255 segments, numbered from `sequence`
+fn paginate(packets: &[Vec<u8>], serial: u32, sequence: u32) -> Vec<OggPage> {
+    let mut pages = Vec::new();
+    let mut page = OggPage { serial, ..Default::default() };
+    for packet in packets {
+        let mut laces = vec![255u8; packet.len() / 255];
+        laces.push((packet.len() % 255) as u8);
+        let mut at = 0;
+        for lace in laces {
+            if page.segments.len() == 255 {
+                // A packet that runs on is flagged on the next page
+                let continued = page.segments.last() == Some(&255);
+                pages.push(std::mem::replace(&mut page, OggPage { serial, header_type: continued as u8, ..Default::default() }));
+            }
+            page.segments.push(lace);
+            page.data.extend(&packet[at..at + lace as usize]);
+            at += lace as usize;
+        }
+    }
+    pages.push(page);
+    for (i, page) in pages.iter_mut().enumerate() {
+        page.sequence = sequence + i as u32;
+        // Header pages are at granule 0; a page where no packet ends has none
+        page.granule = if page.segments.iter().any(|&lace| lace < 255) { 0 } else { u64::MAX };
+    }
+    pages
+}
+
+fn write_ogg(path: &Path, edit: &TagEdit) -> Result<(), String> {
+    // Read whole: when the header takes more or fewer pages, every page after it is renumbered
+    let data = std::fs::read(path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+    let invalid = || format!("{} isn't an Ogg Vorbis or Opus file", path.display());
+    let (first, mut pos) = OggPage::parse(&data, 0).ok_or_else(invalid)?;
+    let first_end = pos;
+    // Vorbis has comment and setup headers after the identification one, Opus only the comments
+    let (header_count, magic): (usize, &[u8]) = if first.data.starts_with(b"\x01vorbis") {
+        (2, b"\x03vorbis")
+    } else if first.data.starts_with(b"OpusHead") {
+        (1, b"OpusTags")
+    } else {
+        return Err(invalid());
+    };
+    let (mut packets, mut packet, mut old_pages, mut sequence) = (Vec::new(), Vec::new(), 0, None);
+    while packets.len() < header_count {
+        let (page, next) = OggPage::parse(&data, pos).ok_or_else(invalid)?;
+        if page.serial != first.serial { return Err(format!("{}: multiplexed Ogg streams aren't supported", path.display())); }
+        sequence.get_or_insert(page.sequence);
+        let mut at = 0;
+        for &lace in &page.segments {
+            packet.extend(&page.data[at..at + lace as usize]);
+            at += lace as usize;
+            if lace < 255 { packets.push(std::mem::take(&mut packet)); }
+        }
+        (pos, old_pages) = (next, old_pages + 1);
+    }
+    // The headers must end their last page, as the specs require
+    if packets.len() != header_count || !packet.is_empty() { return Err(format!("{}: unexpected Ogg header layout", path.display())); }
+    let comment = packets[0].strip_prefix(magic).ok_or_else(invalid)?;
+    let (mut comments, used) = VorbisComments::parse_prefix(comment).ok_or_else(invalid)?;
+    comments.apply(edit, true);
+    // Vorbis ends the header with a framing bit; Opus may carry other data after the comments
+    let tail = if header_count == 2 { &[1u8][..] } else { &comment[used..] };
+    packets[0] = [magic, &comments.render(), tail].concat();
+
+    let pages = paginate(&packets, first.serial, sequence.unwrap_or(1));
+    let shift = pages.len() as i64 - old_pages as i64;
+    let mut out = data[..first_end].to_vec();
+    for page in &pages { out.extend(page.render()); }
+    if shift == 0 {
+        return rewrite(path, &[Part::New(&out), Part::Old(pos as u64..data.len() as u64)]);
+    }
+    while pos < data.len() {
+        let Some((mut page, next)) = OggPage::parse(&data, pos) else {
+            // Trailing data that isn't a page is kept as it is
+            out.extend(&data[pos..]);
+            break;
+        };
+        if page.serial == first.serial {
+            page.sequence = (page.sequence as i64 + shift) as u32;
+            out.extend(page.render());
+        } else {
+            out.extend(&data[pos..next]);
+        }
+        pos = next;
+    }
+    rewrite(path, &[Part::New(&out)])
+}
+
+// ===== MP4 =====
+
+// The atoms in `data`: type, range, and header length
+fn mp4_atoms(data: &[u8]) -> Vec<([u8; 4], Range<usize>, usize)> {
+    let mut atoms = Vec::new();
+    let mut p = 0;
+    while p + 8 <= data.len() {
+        let size = u32::from_be_bytes(data[p..p + 4].try_into().unwrap()) as usize;
+        let kind: [u8; 4] = data[p + 4..p + 8].try_into().unwrap();
+        let (size, header) = match size {
+            0 => (data.len() - p, 8),
+            1 if p + 16 <= data.len() => (u64::from_be_bytes(data[p + 8..p + 16].try_into().unwrap()) as usize, 16),
+            n => (n, 8),
+        };
+        if size < header || size > data.len() - p { break; }
+        atoms.push((kind, p..p + size, header));
+        p += size;
+    }
+    atoms
+}
+
+fn mp4_atom(kind: &[u8; 4], body: &[u8]) -> Vec<u8> { [&(body.len() as u32 + 8).to_be_bytes()[..], kind, body].concat() }
+
+// The body of the first `kind` child in `data`
+fn mp4_child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    mp4_atoms(data).into_iter().find(|(k, ..)| k == kind).map(|(_, range, header)| &data[range.start + header..range.end])
+}
+
+// `data` with its first `kind` child replaced by `atom`, or with `atom` added
+fn mp4_replace(data: &[u8], kind: &[u8; 4], atom: &[u8]) -> Vec<u8> {
+    match mp4_atoms(data).into_iter().find(|(k, ..)| k == kind) {
+        Some((_, range, _)) => [&data[..range.start], atom, &data[range.end..]].concat(),
+        None => [data, atom].concat(),
+    }
+}
+
+fn ilst_key(field: TagField) -> [u8; 4] {
+    *match field {
+        TagField::Title => b"\xa9nam",
+        TagField::Artist => b"\xa9ART",
+        TagField::Album => b"\xa9alb",
+        TagField::AlbumArtist => b"aART",
+        TagField::Track => b"trkn",
+        TagField::Disc => b"disk",
+        TagField::Year => b"\xa9day",
+        TagField::Genre => b"\xa9gen",
+        TagField::Composer => b"\xa9wrt",
+    }
+}
+
+// An `ilst` item holding one `data` atom of the given well-known type (0 binary, 1 UTF-8, 13 JPEG, 14 PNG)
+fn ilst_item(key: &[u8; 4], kind: u32, payload: &[u8]) -> Vec<u8> {
+    mp4_atom(key, &mp4_atom(b"data", &[&kind.to_be_bytes()[..], &[0; 4], payload].concat()))
+}
+
+fn edit_ilst(ilst: &[u8], edit: &TagEdit) -> Result<Vec<u8>, String> {
+    let mut items: Vec<([u8; 4], Vec<u8>)> = mp4_atoms(ilst).into_iter().map(|(kind, range, _)| (kind, ilst[range].to_vec())).collect();
+    // The payload of an item's `data` atom, after its type and locale
+    let payload = |items: &[([u8; 4], Vec<u8>)], key: &[u8; 4]| -> Option<Vec<u8>> {
+        let (_, item) = items.iter().find(|(k, _)| k == key)?;
+        mp4_child(mp4_child(&item[..], key)?, b"data")?.get(8..).map(<[u8]>::to_vec)
+    };
+    for (field, value) in &edit.fields {
+        let key = ilst_key(*field);
+        let item = match field {
+            _ if value.is_empty() => None,
+            // Number and total as 16-bit integers; a track also has two bytes of padding
+            TagField::Track | TagField::Disc => {
+                let old = payload(&items, &key).filter(|p| p.len() >= 6).map(|p| u16::from_be_bytes([p[4], p[5]]));
+                let (number, total) = split_number(value).unwrap_or_default();
+                let total = total.map_or(old.unwrap_or(0), |t| t as u16);
+                let mut data = [[0, 0], (number as u16).to_be_bytes(), total.to_be_bytes()].concat();
+                if *field == TagField::Track { data.extend([0, 0]); }
+                Some(ilst_item(&key, 0, &data))
+            }
+            _ => Some(ilst_item(&key, 1, value.as_bytes())),
+        };
+        // A numeric genre would hide the text one
+        items.retain(|(k, _)| *k != key && !(*field == TagField::Genre && k == b"gnre"));
+        if let Some(item) = item { items.push((key, item)); }
+    }
+    match &edit.cover {
+        CoverEdit::Keep => {}
+        CoverEdit::Remove => items.retain(|(k, _)| k != b"covr"),
+        CoverEdit::Set(data) => {
+            items.retain(|(k, _)| k != b"covr");
+            let item = match image_mime(data) {
+                Some("image/jpeg") => ilst_item(b"covr", 13, data),
+                Some("image/png") => ilst_item(b"covr", 14, data),
+                // MP4 only knows JPEG and PNG covers
+                _ => {
+                    let image = image::load_from_memory(data).map_err(|e| format!("Can't decode picture: {e}"))?;
+                    let mut png = Vec::new();
+                    image.write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png).map_err(|e| format!("Can't convert picture: {e}"))?;
+                    ilst_item(b"covr", 14, &png)
+                }
+            };
+            items.push((*b"covr", item));
+        }
+    }
+    Ok(items.into_iter().flat_map(|(_, item)| item).collect())
+}
+
+// The body of `moov` with `edit` applied to moov > udta > meta > ilst, creating whichever are missing
+fn edit_moov(moov: &[u8], edit: &TagEdit) -> Result<Vec<u8>, String> {
+    let udta = mp4_child(moov, b"udta").unwrap_or_default();
+    // `meta` is a full box in MP4 but a plain one in QuickTime files
+    let (version, meta) = match mp4_child(udta, b"meta") {
+        Some(meta) if meta.get(4..8) == Some(b"hdlr") => (&[][..], meta.to_vec()),
+        Some(meta) if meta.len() >= 4 => (&meta[..4], meta[4..].to_vec()),
+        _ => (&[0u8; 4][..], mp4_atom(b"hdlr", b"\0\0\0\0\0\0\0\0mdirappl\0\0\0\0\0\0\0\0\0")),
+    };
+    let ilst = mp4_atom(b"ilst", &edit_ilst(mp4_child(&meta, b"ilst").unwrap_or_default(), edit)?);
+    let meta = mp4_atom(b"meta", &[version, &mp4_replace(&meta, b"ilst", &ilst)].concat());
+    let udta = mp4_atom(b"udta", &mp4_replace(udta, b"meta", &meta));
+    Ok(mp4_replace(moov, b"udta", &udta))
+}
+
+// Move the chunk offsets (`stco`/`co64`) in a `moov` body that point past `after` by `shift` bytes
+fn shift_chunk_offsets(data: &mut [u8], after: u64, shift: i64) -> Result<(), String> {
+    for (kind, range, header) in mp4_atoms(data) {
+        let body = &mut data[range.start + header..range.end];
+        match &kind {
+            b"trak" | b"mdia" | b"minf" | b"stbl" => shift_chunk_offsets(body, after, shift)?,
+            b"stco" | b"co64" => {
+                let width = if &kind == b"stco" { 4 } else { 8 };
+                for entry in body.get_mut(8..).unwrap_or_default().chunks_exact_mut(width) {
+                    let offset = entry.iter().fold(0u64, |n, &b| n << 8 | b as u64);
+                    if offset < after { continue; }
+                    let moved = offset as i64 + shift;
+                    if width == 4 && !(0..=u32::MAX as i64).contains(&moved) { return Err("Chunk offsets overflow; the file is too large to retag".into()); }
+                    entry.copy_from_slice(&moved.to_be_bytes()[8 - width..]);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn write_mp4(path: &Path, edit: &TagEdit) -> Result<(), String> {
+    let err = |e: std::io::Error| format!("Failed to read {}: {e}", path.display());
+    let mut f = File::open(path).map_err(err)?;
+    let len = f.metadata().map_err(err)?.len();
+    // Top-level atoms by their headers; only `moov` is read
+    let mut top = Vec::new();
+    let mut pos = 0u64;
+    while pos + 8 <= len {
+        let mut header = [0u8; 16];
+        f.seek(SeekFrom::Start(pos)).map_err(err)?;
+        f.read_exact(&mut header[..8]).map_err(err)?;
+        let size = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            0 => len - pos,
+            1 => { f.read_exact(&mut header[8..]).map_err(err)?; u64::from_be_bytes(header[8..].try_into().unwrap()) }
+            n => n as u64,
+        };
+        if size < 8 || size > len - pos { break; }
+        top.push((<[u8; 4]>::try_from(&header[4..8]).unwrap(), pos..pos + size));
+        pos += size;
+    }
+    let Some((_, moov)) = top.iter().find(|(kind, _)| kind == b"moov").cloned() else { return Err(format!("{} isn't an MP4 file", path.display())) };
+    if top.iter().any(|(kind, _)| kind == b"moof") { return Err(format!("{}: fragmented MP4 files aren't supported", path.display())); }
+    let mut data = vec![0; (moov.end - moov.start) as usize];
+    f.seek(SeekFrom::Start(moov.start)).map_err(err)?;
+    f.read_exact(&mut data).map_err(err)?;
+    let (_, range, header) = mp4_atoms(&data).into_iter().next().ok_or_else(|| format!("{}: damaged moov atom", path.display()))?;
+    let mut body = edit_moov(&data[range.start + header..range.end], edit)?;
+    // Offsets into the media data are absolute, so data stored after `moov` moves with its new size
+    let shift = body.len() as i64 + 8 - data.len() as i64;
+    if shift != 0 && top.iter().any(|(kind, range)| kind == b"mdat" && range.start > moov.start) {
+        shift_chunk_offsets(&mut body, moov.end, shift)?;
+    }
+    let moov_atom = mp4_atom(b"moov", &body);
+    rewrite(path, &[Part::Old(0..moov.start), Part::New(&moov_atom), Part::Old(moov.end..len)])
+}
+
+// ===== APEv2 =====
+
+const APE_COVER_KEY: &str = "Cover Art (Front)";
+
+/// An APEv2 tag: items as key, flags (bit 1 set for binary values) and value.
+#[derive(Default)]
+pub(crate) struct ApeTag {
+    items: Vec<(String, u32, Vec<u8>)>,
+}
+
+impl ApeTag {
+    /// A text item; multiple values are joined with ", ".
+    pub(crate) fn text(&self, key: &str) -> Option<String> {
+        let (_, _, value) = self.items.iter().find(|(k, flags, _)| k.eq_ignore_ascii_case(key) && flags & 6 == 0)?;
+        let values: Vec<String> = value.split(|&b| b == 0).map(|v| String::from_utf8_lossy(v).trim().to_string()).filter(|v| !v.is_empty()).collect();
+        (!values.is_empty()).then(|| values.join(", "))
+    }
+
+    fn set(&mut self, key: &str, flags: u32, value: Option<Vec<u8>>) {
+        self.items.retain(|(k, ..)| !k.eq_ignore_ascii_case(key));
+        if let Some(value) = value { self.items.push((key.to_string(), flags, value)); }
+    }
+
+    fn render(&self) -> Vec<u8> {
+        let mut items = Vec::new();
+        for (key, flags, value) in &self.items {
+            items.extend((value.len() as u32).to_le_bytes());
+            items.extend(flags.to_le_bytes());
+            items.extend(key.as_bytes());
+            items.push(0);
+            items.extend(value);
+        }
+        // Header and footer differ only in the "this is the header" flag
+        let size = items.len() as u32 + 32;
+        let header = |flags: u32| [&b"APETAGEX"[..], &2000u32.to_le_bytes(), &size.to_le_bytes(),
+            &(self.items.len() as u32).to_le_bytes(), &flags.to_le_bytes(), &[0; 8]].concat();
+        [header(0xa000_0000), items, header(0x8000_0000)].concat()
+    }
+}
+
+pub(crate) fn ape_key(field: TagField) -> &'static str {
+    match field {
+        TagField::Title => "Title",
+        TagField::Artist => "Artist",
+        TagField::Album => "Album",
+        TagField::AlbumArtist => "Album Artist",
+        TagField::Track => "Track",
+        TagField::Disc => "Disc",
+        TagField::Year => "Year",
+        TagField::Genre => "Genre",
+        TagField::Composer => "Composer",
+    }
+}
+
+// The APEv2 tag at the end of a file (before an ID3v1 tag, if there is one) and its range. Without a tag, the
+// range is empty at the place for one.
+fn find_ape(path: &Path) -> Result<(Option<ApeTag>, Range<u64>), String> {
+    let err = |e: std::io::Error| format!("Failed to read {}: {e}", path.display());
+    let mut f = File::open(path).map_err(err)?;
+    let mut end = f.metadata().map_err(err)?.len();
+    let mut read_at = |pos: u64, buf: &mut [u8]| f.seek(SeekFrom::Start(pos)).and_then(|_| f.read_exact(buf)).map_err(err);
+    let mut id3v1 = [0u8; 3];
+    if end >= 128 && read_at(end - 128, &mut id3v1).is_ok() && &id3v1 == b"TAG" { end -= 128; }
+    let mut footer = [0u8; 32];
+    if end < 32 || read_at(end - 32, &mut footer).is_err() || &footer[..8] != b"APETAGEX" { return Ok((None, end..end)); }
+    let le = |i: usize| u32::from_le_bytes(footer[i..i + 4].try_into().unwrap());
+    let (size, count, flags) = (le(12) as u64, le(16), le(20));
+    let start = end.checked_sub(size + if flags & 0x8000_0000 != 0 { 32 } else { 0 }).filter(|_| size >= 32)
+        .ok_or_else(|| format!("{}: damaged APE tag", path.display()))?;
+    let mut items = vec![0u8; size as usize - 32];
+    read_at(end - size, &mut items)?;
+    let mut tag = ApeTag::default();
+    let mut p = 0;
+    for _ in 0..count {
+        let Some(head) = items.get(p..p + 8) else { break };
+        let (len, flags) = (u32::from_le_bytes(head[..4].try_into().unwrap()) as usize, u32::from_le_bytes(head[4..].try_into().unwrap()));
+        let Some(key_len) = items.get(p + 8..).and_then(|rest| rest.iter().position(|&b| b == 0)) else { break };
+        let key = String::from_utf8_lossy(&items[p + 8..p + 8 + key_len]).into_owned();
+        let Some(value) = items.get(p + 9 + key_len..p + 9 + key_len + len) else { break };
+        tag.items.push((key, flags, value.to_vec()));
+        p += 9 + key_len + len;
+    }
+    Ok((Some(tag), start..end))
+}
+
+/// The APEv2 tag of `path`, if it has one.
+pub(crate) fn read_ape(path: &Path) -> Option<ApeTag> { find_ape(path).ok()?.0 }
+
+fn write_ape(path: &Path, edit: &TagEdit) -> Result<(), String> {
+    let (tag, range) = find_ape(path)?;
+    let mut tag = tag.unwrap_or_default();
+    for (field, value) in &edit.fields {
+        let key = ape_key(*field);
+        let value = if matches!(field, TagField::Track | TagField::Disc) { with_total(value, tag.text(key).as_deref()) } else { value.clone() };
+        tag.set(key, 0, (!value.is_empty()).then(|| value.into_bytes()));
+    }
+    match &edit.cover {
+        CoverEdit::Keep => {}
+        CoverEdit::Remove => tag.items.retain(|(key, ..)| !key.to_ascii_lowercase().starts_with("cover art")),
+        // A file name, then the picture
+        CoverEdit::Set(data) => {
+            let ext = image_mime(data).and_then(|m| m.strip_prefix("image/")).unwrap_or("jpg").replace("jpeg", "jpg");
+            tag.set(APE_COVER_KEY, 2, Some([format!("cover.{ext}\0").as_bytes(), data].concat()));
+        }
+    }
+    let len = std::fs::metadata(path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?.len();
+    rewrite(path, &[Part::Old(0..range.start), Part::New(&tag.render()), Part::Old(range.end..len)])
+}
+
+// ===== Background saving =====
+
+// A saved file with its tags read back, or why it couldn't be saved
+type Saved = (PathBuf, Result<TrackInfo, String>);
+
+/// Writes tag edits in the background and reads the saved tags back for the library.
+#[derive(Default)]
+pub(crate) struct TagSaver {
+    saving: bool,
+    done: Arc<Mutex<Option<Vec<Saved>>>>,
+}
+
+impl TagSaver {
+    pub(crate) fn saving(&self) -> bool { self.saving }
+
+    /// Apply `edit` to each of `paths`, one after the other.
+    pub(crate) fn save(&mut self, paths: Vec<PathBuf>, edit: TagEdit) -> Result<(), String> {
+        if self.saving { return Err("Tags are still being saved".into()); }
+        edit.check()?;
+        let done = self.done.clone();
+        std::thread::Builder::new().name("tag-writer".into()).spawn(move || {
+            let saved = paths.into_iter().map(|path| {
+                let result = write_tags(&path, &edit).map(|()| read_track_info(&path));
+                (path, result)
+            }).collect();
+            *done.lock().unwrap() = Some(saved);
+        }).map_err(|e| format!("Can't save tags: {e}"))?;
+        self.saving = true;
+        Ok(())
+    }
+
+    /// The files saved since the last call, once the whole batch is done.
+    pub(crate) fn poll(&mut self) -> Option<Vec<Saved>> {
+        let saved = self.done.lock().unwrap().take()?;
+        self.saving = false;
+        Some(saved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::id3::Id3Tag;
+
+    #[test]
+    fn flac_files_behind_an_id3_tag_are_tagged() {
+        let path = std::env::temp_dir().join(format!("tag_edit-{}-id3.flac", std::process::id()));
+        let mut id3 = Id3Tag::default();
+        id3.set_text("TIT2", "Stale");
+        // STREAMINFO as the last metadata block, then some "audio"
+        let flac = [&id3.render()[..], b"fLaC", &[0x80, 0, 0, 34], &[0; 34], b"audio"].concat();
+        std::fs::write(&path, flac).unwrap();
+        #[cfg(unix)]
+        std::fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(0o640)).unwrap();
+        let mut edit = TagEdit::default();
+        edit.set(TagField::Title, "Fresh");
+        write_tags(&path, &edit).unwrap();
+        let (blocks, audio_start) = read_flac_blocks(&path).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        assert!(data.starts_with(b"fLaC") && data.ends_with(b"audio") && audio_start == data.len() as u64 - 5);
+        let comments = blocks.iter().find(|(kind, _)| *kind == FLAC_COMMENT).and_then(|(_, data)| VorbisComments::parse(data)).unwrap();
+        assert!(comments.render().windows(11).any(|w| w == b"TITLE=Fresh"));
+        #[cfg(unix)]
+        assert_eq!(std::os::unix::fs::PermissionsExt::mode(&std::fs::metadata(&path).unwrap().permissions()) & 0o777, 0o640);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tag_edit-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn edit(fields: &[(TagField, &str)]) -> TagEdit {
+        let mut edit = TagEdit::default();
+        for (field, value) in fields { edit.set(*field, value); }
+        edit
+    }
+
+    // Write `edit` and read the tags back as the library does
+    fn retag(path: &Path, edit: &TagEdit) -> TrackInfo {
+        write_tags(path, edit).unwrap();
+        read_track_info(path)
+    }
+
+    fn png() -> Vec<u8> {
+        let mut out = Cursor::new(Vec::new());
+        image::RgbImage::new(2, 2).write_to(&mut out, image::ImageFormat::Png).unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn mp3_tags_keep_their_id3_version() {
+        let dir = temp_dir("mp3");
+        let audio = [&[0xff, 0xfb, 0x90, 0x64][..], &[0; 413]].concat().repeat(20);
+        // No tag yet: it gets a 2.4 one, and an APEv2 tag at the end is kept in step
+        let path = dir.join("new.mp3");
+        std::fs::write(&path, [&audio[..], &ApeTag::default().render()].concat()).unwrap();
+        let info = retag(&path, &edit(&[(TagField::Title, "Fresh"), (TagField::Artist, "Band"), (TagField::Track, "3/12"), (TagField::Year, "2001-05-21")]));
+        assert_eq!((info.title.as_str(), info.artist.as_str(), info.track, info.year), ("Fresh", "Band", Some(3), Some(2001)));
+        let tag = read_id3(&path).unwrap();
+        assert_eq!((tag.version, tag.text("TDRC").as_deref(), tag.text("TYER")), (4, Some("2001-05-21"), None));
+        assert_eq!(read_ape(&path).unwrap().text("Title").as_deref(), Some("Fresh"));
+        // A new number keeps the total, and an empty value removes the field
+        let info = retag(&path, &edit(&[(TagField::Track, "4"), (TagField::Title, "")]));
+        assert_eq!((info.title.as_str(), info.track), ("", Some(4)));
+        assert_eq!(read_id3(&path).unwrap().text("TRCK").as_deref(), Some("4/12"));
+        assert_eq!(read_ape(&path).unwrap().text("Title"), None);
+        let data = std::fs::read(&path).unwrap();
+        assert!(data.windows(audio.len()).any(|w| w == audio));
+
+        // A 2.3 tag stays 2.3, with a year frame and UTF-16 text
+        let path = dir.join("old.mp3");
+        let mut tag = Id3Tag::parse(b"ID3\x03\0\0\0\0\0\0").unwrap();
+        tag.set_text("TIT2", "Old");
+        tag.set_text("TYER", "1990");
+        std::fs::write(&path, [tag.render(), audio.clone()].concat()).unwrap();
+        let info = retag(&path, &edit(&[(TagField::Title, "Café"), (TagField::Year, "2001-05-21")]));
+        assert_eq!((info.title.as_str(), info.year), ("Café", Some(2001)));
+        let tag = read_id3(&path).unwrap();
+        assert_eq!((tag.version, tag.text("TYER").as_deref(), tag.text("TDRC")), (3, Some("2001"), None));
+        assert!(std::fs::read(&path).unwrap().ends_with(&audio));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    // One frame of 4096 silent 16-bit stereo samples at 44.1 kHz, which readers look for after the metadata
+    fn flac_frame() -> Vec<u8> {
+        let crc = |data: &[u8], poly: u16, bits: u32| data.iter().fold(0u16, |crc, &b| {
+            (0..8).fold(crc ^ (b as u16) << (bits - 8), |c, _| if c & 1 << (bits - 1) != 0 { (c << 1) ^ poly } else { c << 1 })
+        }) & ((1u32 << bits) - 1) as u16;
+        // Frame 0 with its header checksum, then a constant subframe of 0 per channel
+        let mut frame = vec![0xff, 0xf8, 0xc9, 0x18, 0];
+        frame.push(crc(&frame, 0x07, 8) as u8);
+        frame.extend([0; 6]);
+        frame.extend(crc(&frame, 0x8005, 16).to_be_bytes());
+        frame
+    }
+
+    #[test]
+    fn flac_comments_and_pictures_are_replaced() {
+        let dir = temp_dir("flac");
+        let path = dir.join("song.flac");
+        // 4096-sample blocks, 44.1 kHz, stereo, 16-bit, one second
+        let format = 44100u64 << 44 | 1 << 41 | 15 << 36 | 44100;
+        let streaminfo = [&4096u16.to_be_bytes()[..], &4096u16.to_be_bytes(), &[0; 6], &format.to_be_bytes(), &[0; 16]].concat();
+        let comments = VorbisComments { vendor: "test".into(), comments: vec![("TITLE".into(), "Old".into()), ("TRACKTOTAL".into(), "12".into())] };
+        let back_cover = [&4u32.to_be_bytes()[..], &picture_block(&png())[4..]].concat();
+        let blocks = [(FLAC_STREAMINFO, streaminfo), (FLAC_COMMENT, comments.render()), (FLAC_PICTURE, back_cover.clone()), (FLAC_PADDING, vec![0; 100])];
+        let mut flac = b"fLaC".to_vec();
+        for (i, (kind, data)) in blocks.iter().enumerate() {
+            flac.push(kind | if i == blocks.len() - 1 { 0x80 } else { 0 });
+            flac.extend(&(data.len() as u32).to_be_bytes()[1..]);
+            flac.extend(data);
+        }
+        let audio = flac_frame();
+        std::fs::write(&path, [&flac[..], &audio].concat()).unwrap();
+
+        let mut change = edit(&[(TagField::Title, "Fresh"), (TagField::Track, "3"), (TagField::AlbumArtist, "Various")]);
+        change.cover = CoverEdit::Set(png());
+        let info = retag(&path, &change);
+        assert_eq!((info.title.as_str(), info.album_artist.as_str(), info.track), ("Fresh", "Various", Some(3)));
+        assert_eq!((info.sample_rate, info.bit_depth, info.duration), (Some(44100), Some(16), Some(std::time::Duration::from_secs(1))));
+        let (blocks, audio_start) = read_flac_blocks(&path).unwrap();
+        let kinds: Vec<u8> = blocks.iter().map(|(kind, _)| *kind).collect();
+        assert_eq!(kinds, [FLAC_STREAMINFO, FLAC_COMMENT, FLAC_PICTURE, FLAC_PICTURE, FLAC_PADDING]);
+        let comments = VorbisComments::parse(&blocks[1].1).unwrap();
+        assert_eq!((comments.get("TRACKNUMBER"), comments.get("TRACKTOTAL")), (Some("3"), Some("12")));
+        // The back cover stays beside the new front one
+        assert_eq!(blocks[2].1, back_cover);
+        assert_eq!(picture_type(&blocks[3].1), Some(3));
+        assert_eq!(std::fs::read(&path).unwrap()[audio_start as usize..], audio);
+
+        let change = TagEdit { cover: CoverEdit::Remove, ..Default::default() };
+        assert_eq!(retag(&path, &change).title, "Fresh");
+        assert!(read_flac_blocks(&path).unwrap().0.iter().all(|(kind, _)| *kind != FLAC_PICTURE));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    // The pages of an Ogg file
+    fn ogg_pages(data: &[u8]) -> Vec<OggPage> {
+        let (mut pages, mut pos) = (Vec::new(), 0);
+        while let Some((page, next)) = OggPage::parse(data, pos) {
+            // Parsing and rendering again gives the same bytes only if the checksum was right
+            assert_eq!(page.render(), data[pos..next]);
+            pages.push(page);
+            pos = next;
+        }
+        assert_eq!(pos, data.len());
+        pages
+    }
+
+    #[test]
+    fn ogg_headers_are_repaginated_and_pages_renumbered() {
+        let dir = temp_dir("ogg");
+        let comments = VorbisComments { vendor: "test".into(), comments: vec![("TITLE".into(), "Old".into())] };
+        let vorbis_ident = [&b"\x01vorbis"[..], &0u32.to_le_bytes(), &[2], &44100u32.to_le_bytes(), &[0; 12], &[0xb8, 1]].concat();
+        let vorbis_headers = vec![[&b"\x03vorbis"[..], &comments.render(), &[1]].concat(), b"\x05vorbis setup".to_vec()];
+        let opus_ident = [&b"OpusHead"[..], &[1, 2], &312u16.to_le_bytes(), &48000u32.to_le_bytes(), &[0; 3]].concat();
+        // Opus may keep data of its own after the comments
+        let opus_headers = vec![[&b"OpusTags"[..], &comments.render(), b"extra"].concat()];
+        for (name, ident, headers) in [("song.ogg", vorbis_ident, vorbis_headers), ("song.opus", opus_ident, opus_headers)] {
+            let serial = 0x1234;
+            let mut pages = paginate(&[ident], serial, 0);
+            pages[0].header_type = 2;
+            pages.extend(paginate(&headers, serial, 1));
+            for (i, packet) in [b"\x00audio one", b"\x00audio two", b"\x00audio end"].iter().enumerate() {
+                let sequence = pages.len() as u32;
+                let mut page = paginate(&[packet.to_vec()], serial, sequence).remove(0);
+                (page.granule, page.header_type) = (1000 * (i as u64 + 1), if i == 2 { 4 } else { 0 });
+                pages.push(page);
+            }
+            let path = dir.join(name);
+            std::fs::write(&path, pages.iter().flat_map(OggPage::render).collect::<Vec<_>>()).unwrap();
+            let audio = |pages: &[OggPage]| pages[pages.len() - 3..].iter().map(|p| (p.data.clone(), p.granule, p.header_type)).collect::<Vec<_>>();
+
+            // Too long for one page: the header takes two and the audio pages move up one
+            let long = "x".repeat(70_000);
+            let info = retag(&path, &edit(&[(TagField::Title, "Fresh"), (TagField::Composer, &long)]));
+            assert_eq!((info.title.as_str(), info.composer.len()), ("Fresh", long.len()), "{name}");
+            let new_pages = ogg_pages(&std::fs::read(&path).unwrap());
+            assert_eq!(new_pages.len(), pages.len() + 1, "{name}");
+            assert!(new_pages.iter().enumerate().all(|(i, p)| p.sequence == i as u32 && p.serial == serial), "{name}");
+            assert_eq!(new_pages[2].header_type, 1, "{name}: the comments run on from the page before");
+            assert_eq!(audio(&new_pages), audio(&pages), "{name}");
+            let comment = packet_at(&new_pages, 1);
+            assert!(comment.ends_with(if name.ends_with(".opus") { b"extra" } else { b"\x01" }), "{name}");
+
+            // And back down again
+            let info = retag(&path, &edit(&[(TagField::Composer, "")]));
+            assert_eq!((info.title.as_str(), info.composer.as_str()), ("Fresh", ""), "{name}");
+            let new_pages = ogg_pages(&std::fs::read(&path).unwrap());
+            assert!(new_pages.iter().enumerate().all(|(i, p)| p.sequence == i as u32), "{name}");
+            assert_eq!(new_pages.len(), pages.len(), "{name}");
+            assert_eq!(audio(&new_pages), audio(&pages), "{name}");
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    // Packet `index` of the stream, put together from its segments
+    fn packet_at(pages: &[OggPage], index: usize) -> Vec<u8> {
+        let mut packets = vec![Vec::new()];
+        for page in pages {
+            let mut at = 0;
+            for &lace in &page.segments {
+                packets.last_mut().unwrap().extend(&page.data[at..at + lace as usize]);
+                at += lace as usize;
+                if lace < 255 { packets.push(Vec::new()); }
+            }
+        }
+        packets.swap_remove(index)
+    }
+
+    // A 16-bit stereo PCM MP4 with four samples of `AUDIO`, its chunk offset stored as `co64` or `stco`
+    fn mp4_file(moov_first: bool, co64: bool) -> Vec<u8> {
+        const AUDIO: &[u8; 16] = b"AUDIO-DATA-16B!!";
+        let full = |body: &[u8]| [&[0; 4][..], body].concat();
+        let build = |offset: u64| {
+            let sowt = mp4_atom(b"sowt", &[&[0; 6][..], &1u16.to_be_bytes(), &[0; 8], &2u16.to_be_bytes(), &16u16.to_be_bytes(), &[0; 4], &(44100u32 << 16).to_be_bytes()].concat());
+            let offsets = if co64 { mp4_atom(b"co64", &full(&[&1u32.to_be_bytes()[..], &offset.to_be_bytes()].concat())) } else { mp4_atom(b"stco", &full(&[1, offset as u32].map(u32::to_be_bytes).concat())) };
+            let stbl = mp4_atom(b"stbl", &[
+                mp4_atom(b"stsd", &full(&[&1u32.to_be_bytes()[..], &sowt].concat())),
+                mp4_atom(b"stts", &full(&[1, 4, 1].map(u32::to_be_bytes).concat())),
+                mp4_atom(b"stsc", &full(&[1, 1, 4, 1].map(u32::to_be_bytes).concat())),
+                mp4_atom(b"stsz", &full(&[4, 4].map(u32::to_be_bytes).concat())),
+                offsets,
+            ].concat());
+            let mdia = mp4_atom(b"mdia", &[
+                mp4_atom(b"mdhd", &full(&[&[0, 0, 44100, 4].map(u32::to_be_bytes).concat()[..], &[0; 4]].concat())),
+                mp4_atom(b"hdlr", &full(b"\0\0\0\0soun\0\0\0\0\0\0\0\0\0\0\0\0\0")),
+                mp4_atom(b"minf", &stbl),
+            ].concat());
+            let tkhd = mp4_atom(b"tkhd", &full(&[&[0, 0, 1, 0, 4, 0, 0].map(u32::to_be_bytes).concat()[..], &[0; 6]].concat()));
+            let mvhd = mp4_atom(b"mvhd", &full(&[&[0, 0, 44100, 4, 0x10000].map(u32::to_be_bytes).concat()[..], &[1, 0]].concat()));
+            mp4_atom(b"moov", &[mvhd, mp4_atom(b"trak", &[tkhd, mdia].concat())].concat())
+        };
+        let ftyp = mp4_atom(b"ftyp", b"M4A \0\0\0\0M4A isom");
+        let mdat = mp4_atom(b"mdat", AUDIO);
+        if moov_first {
+            let offset = (ftyp.len() + build(0).len() + 8) as u64;
+            [ftyp, build(offset), mdat].concat()
+        } else {
+            let offset = (ftyp.len() + 8) as u64;
+            [ftyp, mdat, build(offset)].concat()
+        }
+    }
+
+    // The audio the chunk offset in `data` points at
+    fn mp4_audio(data: &[u8]) -> &[u8] {
+        let stbl = [b"trak", b"mdia", b"minf", b"stbl"].iter().fold(mp4_child(data, b"moov").unwrap(), |atom, kind| mp4_child(atom, kind).unwrap());
+        let offset = match (mp4_child(stbl, b"stco"), mp4_child(stbl, b"co64")) {
+            (Some(stco), _) => u32::from_be_bytes(stco[8..12].try_into().unwrap()) as usize,
+            (_, Some(co64)) => u64::from_be_bytes(co64[8..16].try_into().unwrap()) as usize,
+            _ => panic!("no chunk offsets"),
+        };
+        &data[offset..offset + 16]
+    }
+
+    #[test]
+    fn mp4_tags_are_created_and_chunk_offsets_follow_the_audio() {
+        let dir = temp_dir("mp4");
+        for (moov_first, co64) in [(true, false), (true, true), (false, false), (false, true)] {
+            let case = format!("moov first: {moov_first}, co64: {co64}");
+            let path = dir.join("song.m4a");
+            let original = mp4_file(moov_first, co64);
+            assert_eq!(mp4_audio(&original), b"AUDIO-DATA-16B!!");
+            std::fs::write(&path, &original).unwrap();
+            assert_eq!(read_track_info(&path).sample_rate, Some(44100), "{case}");
+
+            // No udta, meta or ilst to begin with
+            let info = retag(&path, &edit(&[(TagField::Title, "Fresh"), (TagField::Artist, "Band"), (TagField::Track, "3/12"), (TagField::Disc, "1/2")]));
+            assert_eq!((info.title.as_str(), info.artist.as_str(), info.track), ("Fresh", "Band", Some(3)), "{case}");
+            assert_eq!((info.sample_rate, info.codec.as_str()), (Some(44100), "pcm_s16le"), "{case}");
+            let data = std::fs::read(&path).unwrap();
+            assert_eq!(mp4_audio(&data), b"AUDIO-DATA-16B!!", "{case}");
+
+            // An existing ilst is edited in place; a new number keeps the total
+            let info = retag(&path, &edit(&[(TagField::Track, "4"), (TagField::Artist, "")]));
+            assert_eq!((info.title.as_str(), info.artist.as_str(), info.track), ("Fresh", "", Some(4)), "{case}");
+            let data = std::fs::read(&path).unwrap();
+            assert_eq!(mp4_audio(&data), b"AUDIO-DATA-16B!!", "{case}");
+            let ilst = [b"moov", b"udta", b"meta"].iter().fold(&data[..], |atom, kind| mp4_child(atom, kind).unwrap());
+            let item = |key: &[u8; 4]| mp4_child(mp4_child(mp4_child(&ilst[4..], b"ilst").unwrap(), key).unwrap(), b"data").unwrap()[8..].to_vec();
+            assert_eq!(item(b"trkn"), [0, 0, 0, 4, 0, 12, 0, 0], "{case}");
+            // Symphonia only reads the 8-byte form, so the 6-byte disc number iTunes writes is checked here
+            assert_eq!(item(b"disk"), [0, 0, 0, 1, 0, 2], "{case}");
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ape_tags_are_written_before_an_id3v1_tag() {
+        let dir = temp_dir("ape");
+        let path = dir.join("song.wv");
+        let id3v1 = [&b"TAG"[..], &[b'x'; 125]].concat();
+        std::fs::write(&path, [&b"wvpk audio"[..], &id3v1].concat()).unwrap();
+        let info = retag(&path, &edit(&[(TagField::Title, "Fresh"), (TagField::Track, "3/12"), (TagField::Genre, "Jazz")]));
+        assert_eq!((info.title.as_str(), info.track, info.genre.as_str()), ("Fresh", Some(3), "Jazz"));
+        let size = std::fs::metadata(&path).unwrap().len();
+
+        // The tag is replaced, not added to
+        let mut change = edit(&[(TagField::Track, "4"), (TagField::Genre, "")]);
+        change.cover = CoverEdit::Set(png());
+        let info = retag(&path, &change);
+        assert_eq!((info.title.as_str(), info.track, info.genre.as_str()), ("Fresh", Some(4), ""));
+        let tag = read_ape(&path).unwrap();
+        assert_eq!(tag.text("Track").as_deref(), Some("4/12"));
+        let cover = tag.items.iter().find(|(key, ..)| key == APE_COVER_KEY).unwrap();
+        assert_eq!((cover.1, cover.2.strip_prefix(b"cover.png\0")), (2, Some(&png()[..])));
+        write_tags(&path, &TagEdit { cover: CoverEdit::Remove, ..Default::default() }).unwrap();
+        // Less the genre item: its length and flags, key and value
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), size - (8 + 6 + 4));
+        let data = std::fs::read(&path).unwrap();
+        assert!(data.starts_with(b"wvpk audio") && data.ends_with(&id3v1));
+        assert_eq!(find_ape(&path).unwrap().1.end, data.len() as u64 - 128);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
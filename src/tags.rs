@@ -0,0 +1,328 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::dsd::{is_dsd, read_dsd_format, read_dsd_tag};
+use crate::id3::Id3Tag;
+use crate::tag_edit::{ape_key, read_ape};
+
+// Track metadata for the library: tags (title, artist, album...) and stream properties (codec, rate, length), read
+// with Symphonia's probe, which only looks at the headers. DSD files, which Symphonia can't open, are read by hand,
+// and so are the APEv2 tags of Monkey's Audio, WavPack and Musepack files (tags only; those can't be played).
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrackInfo {
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub album_artist: String,
+    pub track: Option<u32>,
+    pub disc: Option<u32>,
+    pub year: Option<i32>,
+    pub genre: String,
+    pub composer: String,
+    pub duration: Option<Duration>,
+    /// Short codec name, e.g. `flac`, `mp3`, `aac`.
+    pub codec: String,
+    pub sample_rate: Option<u32>,
+    pub bit_depth: Option<u32>,
+    /// Average over the whole file (tags and pictures included), in kbit/s.
+    pub bitrate: Option<u32>,
+}
+
+impl TrackInfo {
+    /// "Artist — Album" for the line under the title; either may be missing.
+    pub fn subtitle(&self) -> String {
+        let artist = if self.artist.is_empty() { &self.album_artist } else { &self.artist };
+        [artist.as_str(), self.album.as_str()].into_iter().filter(|s| !s.is_empty()).collect::<Vec<_>>().join(" — ")
+    }
+
+    /// "FLAC 44.1 kHz 16-bit 902 kbps", leaving out what isn't known.
+    pub fn format_text(&self) -> String {
+        let mut parts = Vec::new();
+        if !self.codec.is_empty() { parts.push(self.codec.to_uppercase()); }
+        if let Some(rate) = self.sample_rate { parts.push(format!("{} kHz", rate as f64 / 1000.0)); }
+        if let Some(bits) = self.bit_depth { parts.push(format!("{bits}-bit")); }
+        if let Some(kbps) = self.bitrate { parts.push(format!("{kbps} kbps")); }
+        parts.join(" ")
+    }
+
+    /// The value of `field` as text, empty when unknown.
+    pub fn field(&self, field: TagField) -> String {
+        let number = |n: Option<u32>| n.map(|n| n.to_string()).unwrap_or_default();
+        match field {
+            TagField::Title => self.title.clone(),
+            TagField::Artist => self.artist.clone(),
+            TagField::Album => self.album.clone(),
+            TagField::AlbumArtist => self.album_artist.clone(),
+            TagField::Track => number(self.track),
+            TagField::Disc => number(self.disc),
+            TagField::Year => self.year.map(|y| y.to_string()).unwrap_or_default(),
+            TagField::Genre => self.genre.clone(),
+            TagField::Composer => self.composer.clone(),
+        }
+    }
+
+    // Multi-valued fields (several ARTIST comments, say) are joined; single ones keep the first value
+    fn set(&mut self, field: TagField, value: &str) {
+        let value = value.trim().trim_matches('\0');
+        if value.is_empty() { return; }
+        let join = |s: &mut String| if s.is_empty() { *s = value.to_string() } else if !s.split(", ").any(|v| v == value) { s.push_str(", "); s.push_str(value); };
+        let first = |s: &mut String| if s.is_empty() { *s = value.to_string() };
+        match field {
+            TagField::Title => first(&mut self.title),
+            TagField::Artist => join(&mut self.artist),
+            TagField::Album => first(&mut self.album),
+            TagField::AlbumArtist => first(&mut self.album_artist),
+            TagField::Track => self.track = self.track.or_else(|| leading_number(value)),
+            TagField::Disc => self.disc = self.disc.or_else(|| leading_number(value)),
+            // `2001`, `2001-05-21`, `2001-05-21T10:00`
+            TagField::Year => self.year = self.year.or_else(|| value.get(..4)?.parse().ok()),
+            TagField::Genre => join(&mut self.genre),
+            TagField::Composer => join(&mut self.composer),
+        }
+    }
+}
+
+/// The fields the library shows and the tag editor writes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TagField { Title, Artist, Album, AlbumArtist, Track, Disc, Year, Genre, Composer }
+
+impl TagField {
+    pub const ALL: [TagField; 9] = [
+        TagField::Title, TagField::Artist, TagField::Album, TagField::AlbumArtist, TagField::Track, TagField::Disc,
+        TagField::Year, TagField::Genre, TagField::Composer,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            TagField::Title => "Title",
+            TagField::Artist => "Artist",
+            TagField::Album => "Album",
+            TagField::AlbumArtist => "Album artist",
+            TagField::Track => "Track",
+            TagField::Disc => "Disc",
+            TagField::Year => "Year",
+            TagField::Genre => "Genre",
+            TagField::Composer => "Composer",
+        }
+    }
+}
+
+// `3` and `3/12` are both track 3
+fn leading_number(value: &str) -> Option<u32> {
+    let digits: String = value.trim().chars().take_while(char::is_ascii_digit).collect();
+    digits.parse().ok().filter(|&n| n > 0)
+}
+
+/// Tags and stream properties of `path`. Anything that can't be read is left empty.
+pub fn read_track_info(path: &Path) -> TrackInfo {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+    let mut info = if is_dsd(path) {
+        dsd_info(path)
+    } else if matches!(ext.as_str(), "ape" | "wv" | "mpc") {
+        ape_info(path, &ext)
+    } else {
+        probe_info(path).unwrap_or_default()
+    };
+    if let (Some(duration), Ok(meta)) = (info.duration.filter(|d| !d.is_zero()), std::fs::metadata(path)) {
+        info.bitrate = Some((meta.len() as f64 * 8.0 / duration.as_secs_f64() / 1000.0).round() as u32);
+    }
+    info
+}
+
+/// Reads the tags of library files in the background, so the list can show file names straight away.
+#[derive(Default)]
+pub(crate) struct TagLoader {
+    pending: usize,
+    done: Arc<Mutex<Vec<(PathBuf, TrackInfo)>>>,
+}
+
+impl TagLoader {
+    /// Read the tags of `paths`, one after the other.
+    pub(crate) fn load(&mut self, paths: Vec<PathBuf>) {
+        let (count, done) = (paths.len(), self.done.clone());
+        let spawned = std::thread::Builder::new().name("tag-reader".into()).spawn(move || {
+            for path in paths {
+                let info = read_track_info(&path);
+                done.lock().unwrap().push((path, info));
+            }
+        });
+        match spawned {
+            Ok(_) => self.pending += count,
+            Err(e) => log::warn!("Can't read tags: {e}"),
+        }
+    }
+
+    pub(crate) fn loading(&self) -> bool { self.pending > 0 }
+
+    /// The files read since the last call.
+    pub(crate) fn poll(&mut self) -> Vec<(PathBuf, TrackInfo)> {
+        let read = std::mem::take(&mut *self.done.lock().unwrap());
+        self.pending -= read.len();
+        read
+    }
+}
+
+/// The fields of an ID3v2 tag (DSD files).
+pub(crate) fn id3_info(tag: &Id3Tag) -> TrackInfo {
+    let mut info = TrackInfo::default();
+    let frames = [
+        ("TIT2", TagField::Title), ("TPE1", TagField::Artist), ("TALB", TagField::Album), ("TPE2", TagField::AlbumArtist),
+        ("TRCK", TagField::Track), ("TPOS", TagField::Disc), ("TDRC", TagField::Year), ("TYER", TagField::Year),
+        ("TCON", TagField::Genre), ("TCOM", TagField::Composer),
+    ];
+    for (id, field) in frames {
+        if let Some(text) = tag.text(id) { info.set(field, &text); }
+    }
+    info
+}
+
+fn dsd_info(path: &Path) -> TrackInfo {
+    let mut info = read_dsd_tag(path).map(|tag| id3_info(&tag)).unwrap_or_default();
+    info.codec = "dsd".into();
+    if let Some((rate, _, duration)) = read_dsd_format(path) {
+        (info.sample_rate, info.bit_depth, info.duration) = (Some(rate), Some(1), Some(duration));
+    }
+    info
+}
+
+fn ape_info(path: &Path, codec: &str) -> TrackInfo {
+    let mut info = TrackInfo { codec: codec.to_string(), ..Default::default() };
+    if let Some(tag) = read_ape(path) {
+        for field in TagField::ALL {
+            if let Some(text) = tag.text(ape_key(field)) { info.set(field, &text); }
+        }
+    }
+    info
+}
+
+fn probe_info(path: &Path) -> Option<TrackInfo> {
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::{MetadataOptions, StandardTagKey, Tag};
+    use symphonia::core::probe::Hint;
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) { hint.with_extension(ext); }
+    let mss = MediaSourceStream::new(Box::new(File::open(path).ok()?), Default::default());
+    let mut probed = symphonia::default::get_probe().format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default()).ok()?;
+    let mut tags: Vec<Tag> = Vec::new();
+    if let Some(rev) = probed.metadata.get().as_ref().and_then(|m| m.current().cloned()) { tags.extend(rev.tags().to_vec()); }
+    if let Some(rev) = probed.format.metadata().current() { tags.extend(rev.tags().to_vec()); }
+
+    let mut info = TrackInfo::default();
+    for tag in &tags {
+        let field = match tag.std_key {
+            Some(StandardTagKey::TrackTitle) => TagField::Title,
+            Some(StandardTagKey::Artist) => TagField::Artist,
+            Some(StandardTagKey::Album) => TagField::Album,
+            Some(StandardTagKey::AlbumArtist) => TagField::AlbumArtist,
+            Some(StandardTagKey::TrackNumber) => TagField::Track,
+            Some(StandardTagKey::DiscNumber) => TagField::Disc,
+            Some(StandardTagKey::Date | StandardTagKey::ReleaseDate | StandardTagKey::OriginalDate) => TagField::Year,
+            Some(StandardTagKey::Genre) => TagField::Genre,
+            Some(StandardTagKey::Composer) => TagField::Composer,
+            // Vorbis comments Symphonia doesn't map
+            _ if tag.key.eq_ignore_ascii_case("YEAR") => TagField::Year,
+            _ => continue,
+        };
+        info.set(field, &tag.value.to_string());
+    }
+
+    let track = probed.format.default_track().or_else(|| probed.format.tracks().iter().find(|t| t.codec_params.sample_rate.is_some()))?;
+    let params = &track.codec_params;
+    info.codec = symphonia::default::get_codecs().get_codec(params.codec).map(|c| c.short_name.to_string()).unwrap_or_default();
+    info.sample_rate = params.sample_rate;
+    info.bit_depth = params.bits_per_sample.or(params.bits_per_coded_sample);
+    info.duration = match (params.time_base, params.n_frames, params.sample_rate) {
+        (Some(tb), Some(frames), _) => { let t = tb.calc_time(frames); Some(Duration::from_secs(t.seconds) + Duration::from_secs_f64(t.frac)) }
+        (None, Some(frames), Some(rate)) => Some(Duration::from_secs_f64(frames as f64 / rate as f64)),
+        _ => None,
+    };
+    Some(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tags-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // `frames` silent 128 kbit/s 44.1 kHz MPEG frames
+    fn mp3_audio(frames: usize) -> Vec<u8> { [&[0xff, 0xfb, 0x90, 0x64][..], &[0; 413]].concat().repeat(frames) }
+
+    // An APEv2 tag with text items, footer only, as most taggers write it
+    fn ape_tag(items: &[(&str, &str)]) -> Vec<u8> {
+        let body: Vec<u8> = items.iter().flat_map(|(key, value)| {
+            [&(value.len() as u32).to_le_bytes()[..], &[0; 4], key.as_bytes(), &[0], value.as_bytes()].concat()
+        }).collect();
+        let size = body.len() as u32 + 32;
+        [&body[..], b"APETAGEX", &2000u32.to_le_bytes(), &size.to_le_bytes(), &(items.len() as u32).to_le_bytes(), &[0; 12]].concat()
+    }
+
+    #[test]
+    fn numbers_are_read_up_to_the_total() {
+        assert_eq!(leading_number("3/12"), Some(3));
+        assert_eq!(leading_number(" 07 "), Some(7));
+        assert_eq!(leading_number("0/12"), None);
+        assert_eq!(leading_number("/12"), None);
+        assert_eq!(leading_number("A1"), None);
+    }
+
+    #[test]
+    fn id3_tags_and_stream_of_an_mp3() {
+        let dir = temp_dir("mp3");
+        let mut tag = Id3Tag::default();
+        for (id, text) in [("TIT2", "Song"), ("TPE1", "Artist"), ("TALB", "Album"), ("TRCK", "3/12"), ("TPOS", "2/2"), ("TDRC", "2001-05-21"), ("TCON", "Jazz")] {
+            tag.set_text(id, text);
+        }
+        let path = dir.join("song.mp3");
+        std::fs::write(&path, [tag.render(), mp3_audio(100)].concat()).unwrap();
+        let info = read_track_info(&path);
+        assert_eq!((info.title.as_str(), info.artist.as_str(), info.album.as_str(), info.genre.as_str()), ("Song", "Artist", "Album", "Jazz"));
+        assert_eq!((info.track, info.disc, info.year), (Some(3), Some(2), Some(2001)));
+        assert_eq!((info.codec.as_str(), info.sample_rate), ("mp3", Some(44100)));
+        let seconds = info.duration.unwrap().as_secs_f64();
+        assert!((seconds - 100.0 * 1152.0 / 44100.0).abs() < 0.05, "{seconds} s");
+        // Worked out from the file size, so the tag adds a little
+        assert!((128..=135).contains(&info.bitrate.unwrap()), "{:?}", info.bitrate);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ape_tags_are_read_before_an_id3v1_tag() {
+        let dir = temp_dir("ape");
+        let items = [("TITLE", "Song"), ("Artist", "One\0Two"), ("Track", "3/12"), ("Disc", "1/2"), ("Year", "1999")];
+        let id3v1 = [&b"TAG"[..], &[0; 125]].concat();
+        let path = dir.join("song.wv");
+        std::fs::write(&path, [&b"wvpk not really"[..], &ape_tag(&items), &id3v1].concat()).unwrap();
+        let info = read_track_info(&path);
+        // Keys in any letter case, several values joined
+        assert_eq!((info.title.as_str(), info.artist.as_str()), ("Song", "One, Two"));
+        assert_eq!((info.track, info.disc, info.year), (Some(3), Some(1), Some(1999)));
+        // Only the tags are read: no length, so no bitrate
+        assert_eq!((info.codec.as_str(), info.duration, info.bitrate), ("wv", None, None));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn untagged_files_leave_the_tags_empty() {
+        let dir = temp_dir("untagged");
+        let (mp3, ape) = (dir.join("01 Intro.mp3"), dir.join("02 Outro.ape"));
+        std::fs::write(&mp3, mp3_audio(10)).unwrap();
+        std::fs::write(&ape, b"MAC not really").unwrap();
+        // Empty, so the library falls back to the file name
+        let info = read_track_info(&mp3);
+        assert_eq!((info.title.as_str(), info.artist.as_str(), info.track, info.codec.as_str()), ("", "", None, "mp3"));
+        assert_eq!(read_track_info(&ape), TrackInfo { codec: "ape".into(), ..Default::default() });
+        assert_eq!(read_track_info(&dir.join("missing.flac")), TrackInfo::default());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,96 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+
+// A minimal HTTP/1.1 server on a free local port, for testing the network sources against canned responses.
+// Each connection carries one request and runs on its own thread, so a handler can stream for as long as it likes.
+
+/// A request as the handler sees it.
+pub(crate) struct Request {
+    pub method: String,
+    /// Path and query, as sent.
+    pub target: String,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+
+    pub(crate) fn path(&self) -> &str { self.target.split('?').next().unwrap_or_default() }
+
+    /// Query and, for form posts, body parameters, decoded.
+    pub(crate) fn params(&self) -> Vec<(String, String)> {
+        let query = self.target.split_once('?').map(|(_, q)| q).unwrap_or_default();
+        let form = std::str::from_utf8(&self.body).unwrap_or_default();
+        query.split('&').chain(form.split('&')).filter_map(|pair| pair.split_once('=')).map(|(n, v)| (decode(n), decode(v))).collect()
+    }
+
+    pub(crate) fn param(&self, name: &str) -> Option<String> { self.params().into_iter().find(|(n, _)| n == name).map(|(_, v)| v) }
+}
+
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], value.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+            (b'%', Some(b)) => { out.push(b); i += 2; }
+            (b'+', _) => out.push(b' '),
+            (b, _) => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Start a server answering every request with `handler`. Returns its base URL (`http://127.0.0.1:PORT`).
+pub(crate) fn serve(handler: impl Fn(&Request, &mut TcpStream) -> io::Result<()> + Send + Sync + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let handler = Arc::new(handler);
+    std::thread::Builder::new().name("test-server".into()).spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let handler = handler.clone();
+            std::thread::spawn(move || {
+                let mut stream = stream;
+                if let Ok(request) = read_request(&stream) { let _ = handler(&request, &mut stream); }
+            });
+        }
+    }).unwrap();
+    base
+}
+
+fn read_request(stream: &TcpStream) -> io::Result<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or_default().to_string(), parts.next().unwrap_or_default().to_string());
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() { break; }
+        if let Some((name, value)) = line.split_once(':') { headers.push((name.trim().to_string(), value.trim().to_string())); }
+    }
+    let mut request = Request { method, target, headers, body: Vec::new() };
+    let length = request.header("content-length").and_then(|v| v.parse().ok()).unwrap_or(0);
+    request.body.resize(length, 0);
+    reader.read_exact(&mut request.body)?;
+    Ok(request)
+}
+
+/// Send a status line and headers; the body, if any, follows.
+pub(crate) fn head(stream: &mut TcpStream, status: u16, headers: &[(&str, &str)]) -> io::Result<()> {
+    let mut head = format!("HTTP/1.1 {status} Test\r\nConnection: close\r\n");
+    for (name, value) in headers { head += &format!("{name}: {value}\r\n"); }
+    stream.write_all(format!("{head}\r\n").as_bytes())
+}
+
+/// Send a whole response.
+pub(crate) fn reply(stream: &mut TcpStream, status: u16, content_type: &str, body: &[u8]) -> io::Result<()> {
+    head(stream, status, &[("Content-Type", content_type), ("Content-Length", &body.len().to_string())])?;
+    stream.write_all(body)
+}
@@ -0,0 +1,523 @@
+use std::net::UdpSocket;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use roxmltree::Node;
+
+use crate::net::{agent, cached_image, fetch, hex, md5, resolve_url, save_image, COVER_CACHE_DIR};
+use crate::settings::config_dir;
+
+// UPnP/DLNA media servers on the local network. Servers are found with an SSDP M-SEARCH, described by their device
+// XML, and browsed through the ContentDirectory service's SOAP `Browse` action, which answers in DIDL-Lite. Audio
+// items are played from their `res` URL through the same network path as radio.
+
+/// Where M-SEARCH requests go. `AUDIO_PLAYER_SSDP_ADDR` overrides it (e.g. to search one host directly).
+const SSDP_ADDR: &str = "239.255.255.250:1900";
+const MEDIA_SERVER: &str = "urn:schemas-upnp-org:device:MediaServer:1";
+/// Seconds servers may wait before answering (the MX header).
+const SSDP_MX: u64 = 2;
+/// How long discovery listens for answers.
+pub const DISCOVERY_TIME: Duration = Duration::from_secs(3);
+/// Object id of a ContentDirectory's root container.
+pub const ROOT_ID: &str = "0";
+/// Entries asked for per Browse request.
+const PAGE_SIZE: usize = 200;
+const SOAP_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// A server's ContentDirectory, read from its device description.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MediaServer {
+    pub name: String,
+    /// URL of the device description.
+    pub location: String,
+    pub control_url: String,
+    pub service_type: String,
+}
+
+/// A container or audio item in a ContentDirectory listing.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DidlEntry {
+    pub id: String,
+    pub parent_id: String,
+    pub title: String,
+    pub container: bool,
+    pub child_count: Option<u32>,
+    pub artist: String,
+    pub album: String,
+    pub album_art: Option<String>,
+    pub track: Option<u32>,
+    /// Audio resource to play (items only).
+    pub url: Option<String>,
+    pub duration: Option<Duration>,
+    /// UPnP class, such as `object.item.audioItem.musicTrack`.
+    pub class: String,
+}
+
+impl DidlEntry {
+    /// "Artist - Title", as local files without tags are listed.
+    pub fn label(&self) -> String { if self.artist.is_empty() { self.title.clone() } else { format!("{} - {}", self.artist, self.title) } }
+}
+
+/// Search the network for media servers and return the URLs of their device descriptions. Requests go to `target`
+/// (`host:port`), or to the SSDP multicast group.
+pub fn discover(target: Option<&str>, wait: Duration) -> Result<Vec<String>, String> {
+    let target = target.map(str::to_string).or_else(|| std::env::var("AUDIO_PLAYER_SSDP_ADDR").ok()).unwrap_or_else(|| SSDP_ADDR.into());
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| format!("Can't open a UDP socket: {e}"))?;
+    let _ = socket.set_multicast_ttl_v4(2);
+    let request = format!("M-SEARCH * HTTP/1.1\r\nHOST: {SSDP_ADDR}\r\nMAN: \"ssdp:discover\"\r\nMX: {SSDP_MX}\r\nST: {MEDIA_SERVER}\r\n\r\n");
+    // UDP may drop a request, so it goes out twice
+    for _ in 0..2 { socket.send_to(request.as_bytes(), target.as_str()).map_err(|e| format!("Can't search on {target}: {e}"))?; }
+    let deadline = Instant::now() + wait;
+    let mut locations: Vec<String> = Vec::new();
+    let mut buf = [0u8; 2048];
+    while let Some(left) = deadline.checked_duration_since(Instant::now()).filter(|d| !d.is_zero()) {
+        socket.set_read_timeout(Some(left)).map_err(|e| e.to_string())?;
+        let Ok((len, _)) = socket.recv_from(&mut buf) else { break };
+        let reply = String::from_utf8_lossy(&buf[..len]);
+        if !reply.starts_with("HTTP/1.1 200") && !reply.starts_with("HTTP/1.0 200") { continue; }
+        let location = reply.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.trim().eq_ignore_ascii_case("location").then(|| value.trim().to_string())
+        });
+        if let Some(location) = location && !locations.contains(&location) { locations.push(location); }
+    }
+    Ok(locations)
+}
+
+impl MediaServer {
+    /// Read the device description at `location` and find its ContentDirectory service.
+    pub fn describe(location: &str) -> Result<Self, String> {
+        let data = fetch(location, None)?;
+        let text = String::from_utf8_lossy(&data);
+        let doc = roxmltree::Document::parse(&text).map_err(|e| format!("{location}: invalid device description: {e}"))?;
+        let root = doc.root_element();
+        let service = root.descendants().filter(|n| n.has_tag_name("service"))
+            .find(|s| child_text(*s, "serviceType").contains(":service:ContentDirectory:"))
+            .ok_or(format!("{location} has no ContentDirectory service"))?;
+        // The device that offers the service, which may be embedded in another
+        let device = service.ancestors().find(|n| n.has_tag_name("device"));
+        let name = device.map(|d| child_text(d, "friendlyName")).filter(|n| !n.is_empty()).unwrap_or_else(|| location.to_string());
+        let base = root.children().find(|n| n.has_tag_name("URLBase")).and_then(|n| n.text()).map(str::trim).filter(|b| !b.is_empty()).unwrap_or(location);
+        let control = child_text(service, "controlURL");
+        if control.is_empty() { return Err(format!("{location}: ContentDirectory has no control URL")); }
+        Ok(Self { name, location: location.to_string(), control_url: resolve_url(base, &control), service_type: child_text(service, "serviceType") })
+    }
+
+    /// The containers and audio items in container `object_id` (`ROOT_ID` for the top level).
+    pub fn browse(&self, object_id: &str) -> Result<Vec<DidlEntry>, String> {
+        let mut entries = Vec::new();
+        let mut start = 0;
+        loop {
+            let page = self.browse_page(object_id, start)?;
+            entries.extend(page.entries);
+            start += page.returned;
+            if page.returned == 0 || page.total.is_none_or(|total| start >= total) { break; }
+        }
+        Ok(entries)
+    }
+
+    fn browse_page(&self, object_id: &str, start: usize) -> Result<BrowsePage, String> {
+        let body = format!(concat!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+            "<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">",
+            "<s:Body><u:Browse xmlns:u=\"{service}\"><ObjectID>{id}</ObjectID><BrowseFlag>BrowseDirectChildren</BrowseFlag>",
+            "<Filter>*</Filter><StartingIndex>{start}</StartingIndex><RequestedCount>{count}</RequestedCount><SortCriteria></SortCriteria>",
+            "</u:Browse></s:Body></s:Envelope>"), service = xml_escape(&self.service_type), id = xml_escape(object_id), start = start, count = PAGE_SIZE);
+        let url = &self.control_url;
+        let mut response = agent().post(url)
+            .header("Content-Type", "text/xml; charset=\"utf-8\"")
+            .header("SOAPAction", format!("\"{}#Browse\"", self.service_type))
+            // Faults come with status 500 and say what went wrong
+            .config().http_status_as_error(false).timeout_global(Some(SOAP_TIMEOUT)).build()
+            .send(body.as_str()).map_err(|e| format!("{url}: {e}"))?;
+        let status = response.status().as_u16();
+        let text = response.body_mut().read_to_string().map_err(|e| format!("{url}: {e}"))?;
+        let doc = roxmltree::Document::parse(&text).map_err(|e| if status == 200 { format!("{url}: invalid response: {e}") } else { format!("{url}: HTTP {status}") })?;
+        let root = doc.root_element();
+        if let Some(error) = root.descendants().find(|n| n.has_tag_name("UPnPError")) {
+            return Err(format!("{} (UPnP error {})", child_text(error, "errorDescription"), child_text(error, "errorCode")));
+        }
+        if status != 200 { return Err(format!("{url}: HTTP {status}")); }
+        let field = |name: &str| root.descendants().find(|n| n.has_tag_name(name)).map(|n| n.text().unwrap_or_default().trim().to_string());
+        let didl = field("Result").ok_or(format!("{url}: Browse response has no result"))?;
+        let number = |name: &str| field(name).and_then(|v| v.parse::<usize>().ok());
+        Ok(BrowsePage { entries: parse_didl(&didl, url)?, returned: number("NumberReturned").unwrap_or(0), total: number("TotalMatches").filter(|t| *t > 0) })
+    }
+}
+
+struct BrowsePage { entries: Vec<DidlEntry>, returned: usize, total: Option<usize> }
+
+/// Containers and audio items in a DIDL-Lite document. Links are resolved against `base`.
+fn parse_didl(text: &str, base: &str) -> Result<Vec<DidlEntry>, String> {
+    if text.is_empty() { return Ok(Vec::new()); }
+    let doc = roxmltree::Document::parse(text).map_err(|e| format!("Invalid DIDL-Lite: {e}"))?;
+    let entries = doc.root_element().children().filter(Node::is_element).filter_map(|node| {
+        let container = node.has_tag_name("container");
+        if !container && !node.has_tag_name("item") { return None; }
+        let class = child_text(node, "class");
+        // The first audio resource; servers often offer transcoded variants after the original
+        let audio = node.children().filter(|n| n.has_tag_name("res")).find(|res| {
+            let mime = res.attribute("protocolInfo").and_then(|p| p.split(':').nth(2)).unwrap_or_default();
+            mime.starts_with("audio/") || mime.is_empty() && class.starts_with("object.item.audioItem")
+        });
+        let url = audio.and_then(|res| res.text()).map(str::trim).filter(|u| !u.is_empty()).map(|u| resolve_url(base, u));
+        if !container && url.is_none() { return None; }
+        let artist = [child_text(node, "artist"), child_text(node, "creator")].into_iter().find(|a| !a.is_empty()).unwrap_or_default();
+        let album_art = Some(child_text(node, "albumArtURI")).filter(|a| !a.is_empty()).map(|a| resolve_url(base, &a));
+        Some(DidlEntry {
+            id: node.attribute("id").unwrap_or_default().to_string(),
+            parent_id: node.attribute("parentID").unwrap_or_default().to_string(),
+            title: child_text(node, "title"),
+            container,
+            child_count: node.attribute("childCount").and_then(|c| c.trim().parse().ok()),
+            artist,
+            album: child_text(node, "album"),
+            album_art,
+            track: child_text(node, "originalTrackNumber").parse().ok(),
+            url,
+            duration: audio.and_then(|res| res.attribute("duration")).and_then(parse_duration),
+            class,
+        })
+    }).collect();
+    Ok(entries)
+}
+
+/// A DIDL-Lite duration, `H+:MM:SS[.F+]`.
+fn parse_duration(text: &str) -> Option<Duration> {
+    let mut parts = text.trim().split(':');
+    let (hours, minutes, seconds) = (parts.next()?.parse::<u64>().ok()?, parts.next()?.parse::<u64>().ok()?, parts.next()?.parse::<f64>().ok()?);
+    if parts.next().is_some() || !seconds.is_finite() || seconds < 0.0 { return None; }
+    Some(Duration::from_secs(hours * 3600 + minutes * 60) + Duration::from_secs_f64(seconds))
+}
+
+/// Text of the first child element named `name` (any namespace).
+fn child_text(node: Node, name: &str) -> String {
+    node.children().find(|n| n.has_tag_name(name)).and_then(|n| n.text()).unwrap_or_default().trim().to_string()
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Album art at `url` as an image file in `dir`, downloaded the first time it's needed.
+pub(crate) fn album_art(url: &str, dir: &Path) -> Result<PathBuf, String> {
+    let stem = format!("upnp-{}", &hex(&md5(url.as_bytes()))[..16]);
+    if let Some(path) = cached_image(dir, &stem) { return Ok(path); }
+    save_image(dir, &stem, &fetch(url, None)?).map_err(|e| format!("{url}: {e}"))
+}
+
+// ===== Browsing =====
+
+/// What a background job produced.
+enum Loaded { Servers(Vec<MediaServer>), Server(MediaServer), Entries(Vec<DidlEntry>), Art(PathBuf) }
+
+/// A finished job, tagged with the request it answers.
+struct Delivery { generation: u64, result: Result<Loaded, String> }
+
+/// A container opened on the current server, with its entries once loaded.
+struct Level { id: String, title: String, entries: Option<Vec<DidlEntry>> }
+
+/// Servers found on the network and navigation through the selected one. Discovery, listings and album art load
+/// on background threads; `poll` picks up the results.
+#[derive(Default)]
+pub(crate) struct Dlna {
+    servers: Vec<MediaServer>,
+    server: Option<usize>,
+    /// Containers opened, the current one last.
+    path: Vec<Level>,
+    /// The entry whose details are shown, and its album art once downloaded.
+    details: Option<DidlEntry>,
+    art: Option<PathBuf>,
+    /// Jobs in flight (their generation), one of each kind at a time.
+    searching: Option<u64>,
+    adding: Option<u64>,
+    loading: Option<u64>,
+    art_loading: Option<u64>,
+    generation: u64,
+    done: Arc<Mutex<Vec<Delivery>>>,
+}
+
+impl Dlna {
+    fn spawn(&mut self, job: impl FnOnce() -> Result<Loaded, String> + Send + 'static) -> u64 {
+        self.generation += 1;
+        let (done, generation) = (self.done.clone(), self.generation);
+        let spawned = std::thread::Builder::new().name("upnp".into()).spawn(move || {
+            let result = job();
+            done.lock().unwrap().push(Delivery { generation, result });
+        });
+        if let Err(e) = spawned { self.done.lock().unwrap().push(Delivery { generation, result: Err(format!("Failed to start loading: {e}")) }); }
+        generation
+    }
+
+    /// Look for servers on the network. Ones found are added to the list.
+    pub(crate) fn search(&mut self) {
+        self.searching = Some(self.spawn(|| {
+            let servers: Vec<MediaServer> = discover(None, DISCOVERY_TIME)?.iter()
+                .filter_map(|location| MediaServer::describe(location).inspect_err(|e| log::warn!("{e}")).ok()).collect();
+            if servers.is_empty() { Err("No media servers found".into()) } else { Ok(Loaded::Servers(servers)) }
+        }));
+    }
+
+    /// Add the server whose device description is at `location`, for networks where discovery doesn't get through.
+    pub(crate) fn add(&mut self, location: &str) {
+        let location = location.trim().to_string();
+        self.adding = Some(self.spawn(move || MediaServer::describe(&location).map(Loaded::Server)));
+    }
+
+    /// Browse server `index` from its root container.
+    pub(crate) fn select(&mut self, index: usize) {
+        let Some(server) = self.servers.get(index) else { return };
+        self.server = Some(index);
+        self.path = vec![Level { id: ROOT_ID.into(), title: server.name.clone(), entries: None }];
+        self.show_details(None);
+        self.reload();
+    }
+
+    /// Open container `entry` below the current one.
+    pub(crate) fn open(&mut self, entry: &DidlEntry) {
+        self.path.push(Level { id: entry.id.clone(), title: entry.title.clone(), entries: None });
+        self.show_details(Some(entry.clone()));
+        self.reload();
+    }
+
+    /// Return to the parent container. `false` when already at the top.
+    pub(crate) fn back(&mut self) -> bool {
+        if self.path.len() < 2 { return false; }
+        self.path.pop();
+        self.show_details(None);
+        if self.path.last().is_some_and(|level| level.entries.is_none()) { self.reload(); } else { self.loading = None; }
+        true
+    }
+
+    /// List the current container again.
+    pub(crate) fn reload(&mut self) {
+        let (Some(server), Some(level)) = (self.server.and_then(|i| self.servers.get(i)), self.path.last()) else { return };
+        let (server, id) = (server.clone(), level.id.clone());
+        self.loading = Some(self.spawn(move || server.browse(&id).map(Loaded::Entries)));
+    }
+
+    /// Show `entry`'s metadata, fetching its album art in the background.
+    pub(crate) fn show_details(&mut self, entry: Option<DidlEntry>) {
+        self.art = None;
+        self.art_loading = None;
+        let url = entry.as_ref().and_then(|e| e.album_art.clone());
+        self.details = entry;
+        let (Some(url), Some(dir)) = (url, config_dir().map(|dir| dir.join(COVER_CACHE_DIR))) else { return };
+        self.art_loading = Some(self.spawn(move || album_art(&url, &dir).map(Loaded::Art)));
+    }
+
+    /// Apply finished jobs: `Some(Ok)` when something new arrived, `Some(Err)` if a job failed.
+    pub(crate) fn poll(&mut self) -> Option<Result<(), String>> {
+        let deliveries = std::mem::take(&mut *self.done.lock().unwrap());
+        let mut outcome = None;
+        for Delivery { generation, result } in deliveries {
+            // Results nobody waits for any more are dropped
+            let slot = [&mut self.searching, &mut self.adding, &mut self.loading, &mut self.art_loading].into_iter().find(|job| **job == Some(generation));
+            let Some(slot) = slot else { continue };
+            *slot = None;
+            let loaded = match result {
+                Ok(loaded) => loaded,
+                Err(e) => { outcome = Some(Err(e)); continue; }
+            };
+            match loaded {
+                Loaded::Servers(servers) => for server in servers { self.add_server(server); },
+                Loaded::Server(server) => { let index = self.add_server(server); self.select(index); }
+                Loaded::Entries(entries) => if let Some(level) = self.path.last_mut() { level.entries = Some(entries); },
+                Loaded::Art(path) => self.art = Some(path),
+            }
+            if outcome.is_none() { outcome = Some(Ok(())); }
+        }
+        // Nothing to browse until a server is picked; the first one found will do
+        if self.server.is_none() && !self.servers.is_empty() { self.select(0); }
+        outcome
+    }
+
+    /// Add `server` to the list, or refresh the entry with the same description URL. Returns its index.
+    fn add_server(&mut self, server: MediaServer) -> usize {
+        match self.servers.iter().position(|s| s.location == server.location) {
+            Some(index) => { self.servers[index] = server; index }
+            None => { self.servers.push(server); self.servers.len() - 1 }
+        }
+    }
+
+    pub(crate) fn servers(&self) -> &[MediaServer] { &self.servers }
+
+    pub(crate) fn server(&self) -> Option<usize> { self.server }
+
+    pub(crate) fn is_searching(&self) -> bool { self.searching.is_some() || self.adding.is_some() }
+
+    pub(crate) fn is_loading(&self) -> bool { self.loading.is_some() }
+
+    pub(crate) fn can_go_back(&self) -> bool { self.path.len() > 1 }
+
+    /// Names of the containers opened, joined into a path.
+    pub(crate) fn title(&self) -> String { self.path.iter().map(|l| l.title.as_str()).collect::<Vec<_>>().join(" › ") }
+
+    pub(crate) fn entries(&self) -> &[DidlEntry] { self.path.last().and_then(|l| l.entries.as_deref()).unwrap_or_default() }
+
+    pub(crate) fn details(&self) -> Option<&DidlEntry> { self.details.as_ref() }
+
+    pub(crate) fn art(&self) -> Option<&Path> { self.art.as_deref() }
+
+    /// The audio items in the current container, and where entry `index` is among them (for playing on from it).
+    pub(crate) fn items_from(&self, index: usize) -> (Vec<&DidlEntry>, usize) {
+        let start = self.entries().iter().take(index).filter(|e| !e.container).count();
+        (self.entries().iter().filter(|e| !e.container).collect(), start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{reply, serve, Request};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const SERVICE: &str = "urn:schemas-upnp-org:service:ContentDirectory:1";
+
+    fn description(url_base: &str) -> String {
+        format!(r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0"><URLBase>{url_base}</URLBase>
+  <device><friendlyName>Gateway</friendlyName><serviceList><service><serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType></service></serviceList>
+    <deviceList><device><friendlyName>Test Media</friendlyName><serviceList><service>
+      <serviceType>{SERVICE}</serviceType><controlURL>ctl/ContentDir</controlURL>
+    </service></serviceList></device></deviceList>
+  </device>
+</root>"#)
+    }
+
+    fn container(id: &str, title: &str, children: u32) -> String {
+        format!(r#"<container id="{id}" parentID="0" childCount="{children}"><dc:title>{title}</dc:title><upnp:class>object.container.album.musicAlbum</upnp:class></container>"#)
+    }
+
+    fn track(n: u32) -> String {
+        format!(concat!(
+            r#"<item id="t{n}" parentID="al"><dc:title>Song {n}</dc:title><dc:creator>Band</dc:creator><upnp:album>Album</upnp:album>"#,
+            r#"<upnp:originalTrackNumber>{n}</upnp:originalTrackNumber><upnp:albumArtURI>/art/al.jpg</upnp:albumArtURI>"#,
+            r#"<upnp:class>object.item.audioItem.musicTrack</upnp:class>"#,
+            r#"<res protocolInfo="http-get:*:image/jpeg:*">/art/al.jpg</res>"#,
+            r#"<res protocolInfo="http-get:*:audio/flac:*" duration="0:03:05.500">/media/t{n}.flac</res>"#,
+            r#"<res protocolInfo="http-get:*:audio/mpeg:*">/media/t{n}.mp3</res></item>"#), n = n)
+    }
+
+    fn didl(entries: &[String]) -> String {
+        let didl = format!(r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/">{}</DIDL-Lite>"#, entries.concat());
+        xml_escape(&didl)
+    }
+
+    fn soap(body: &str) -> String {
+        format!(r#"<?xml version="1.0"?><s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body>{body}</s:Body></s:Envelope>"#)
+    }
+
+    fn tag<'a>(request: &'a Request, name: &str) -> &'a str {
+        let text = std::str::from_utf8(&request.body).unwrap();
+        let start = text.find(&format!("<{name}>")).unwrap() + name.len() + 2;
+        &text[start..start + text[start..].find('<').unwrap()]
+    }
+
+    /// A server with an album of five tracks and a folder without audio, answering Browse `page_size` entries at a time.
+    fn server(page_size: usize, requests: Arc<AtomicUsize>) -> String {
+        let base = Arc::new(Mutex::new(String::new()));
+        let url_base = base.clone();
+        let url = serve(move |request, stream| {
+            if request.method == "GET" { return reply(stream, 200, "text/xml", description(&url_base.lock().unwrap()).as_bytes()); }
+            requests.fetch_add(1, Ordering::SeqCst);
+            assert_eq!(request.path(), "/ctl/ContentDir");
+            assert_eq!(request.header("soapaction"), Some(format!("\"{SERVICE}#Browse\"").as_str()));
+            let entries: Vec<String> = match tag(request, "ObjectID") {
+                "0" => vec![container("al", "Album &amp; Friends", 5), container("empty", "Photos", 0), "<item id=\"pic\"><dc:title>Picture</dc:title><upnp:class>object.item.imageItem</upnp:class><res protocolInfo=\"http-get:*:image/png:*\">/p.png</res></item>".into()],
+                "al" => (1..=5).map(track).collect(),
+                "empty" => Vec::new(),
+                _ => return reply(stream, 500, "text/xml", soap("<s:Fault><detail><UPnPError><errorCode>701</errorCode><errorDescription>No such object</errorDescription></UPnPError></detail></s:Fault>").as_bytes()),
+            };
+            let start: usize = tag(request, "StartingIndex").parse().unwrap();
+            let page: Vec<String> = entries.iter().skip(start).take(page_size).cloned().collect();
+            let response = format!("<u:BrowseResponse xmlns:u=\"{SERVICE}\"><Result>{}</Result><NumberReturned>{}</NumberReturned><TotalMatches>{}</TotalMatches></u:BrowseResponse>",
+                if page.is_empty() { String::new() } else { didl(&page) }, page.len(), entries.len());
+            reply(stream, 200, "text/xml", soap(&response).as_bytes())
+        });
+        *base.lock().unwrap() = format!("{url}/");
+        url
+    }
+
+    #[test]
+    fn discovery_collects_each_server_once() {
+        let responder = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let target = responder.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 1024];
+            while let Ok((len, from)) = responder.recv_from(&mut buf) {
+                let request = String::from_utf8_lossy(&buf[..len]).to_string();
+                assert!(request.starts_with("M-SEARCH * HTTP/1.1\r\n") && request.contains(&format!("ST: {MEDIA_SERVER}\r\n")), "{request}");
+                for reply in ["HTTP/1.1 404 Not Found\r\nLOCATION: http://wrong/\r\n\r\n", "HTTP/1.1 200 OK\r\nST: x\r\nLocation:  http://one/desc.xml \r\n\r\n", "HTTP/1.1 200 OK\r\nLOCATION: http://two/desc.xml\r\n\r\n"] {
+                    responder.send_to(reply.as_bytes(), from).unwrap();
+                }
+            }
+        });
+        // Both requests are answered, but each server is listed once
+        assert_eq!(discover(Some(&target), Duration::from_millis(500)).unwrap(), ["http://one/desc.xml", "http://two/desc.xml"]);
+    }
+
+    #[test]
+    fn descriptions_name_the_content_directory() {
+        let url = server(10, Arc::default());
+        let server = MediaServer::describe(&format!("{url}/desc.xml")).unwrap();
+        assert_eq!(server, MediaServer { name: "Test Media".into(), location: format!("{url}/desc.xml"), control_url: format!("{url}/ctl/ContentDir"), service_type: SERVICE.into() });
+        let other = serve(|_, stream| reply(stream, 200, "text/xml", b"<root><device><serviceList/></device></root>"));
+        assert_eq!(MediaServer::describe(&other).unwrap_err(), format!("{other} has no ContentDirectory service"));
+    }
+
+    #[test]
+    fn browse_pages_through_the_listing() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let server = MediaServer::describe(&server(2, requests.clone())).unwrap();
+        let base = server.location.clone();
+        let tracks = server.browse("al").unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        assert_eq!(tracks.iter().map(|t| t.track).collect::<Vec<_>>(), [1, 2, 3, 4, 5].map(Some));
+        // The first audio resource is played, not the album art listed before it
+        assert_eq!(tracks[0], DidlEntry {
+            id: "t1".into(), parent_id: "al".into(), title: "Song 1".into(), artist: "Band".into(), album: "Album".into(),
+            album_art: Some(format!("{base}/art/al.jpg")), track: Some(1), url: Some(format!("{base}/media/t1.flac")),
+            duration: Some(Duration::from_millis(185_500)), class: "object.item.audioItem.musicTrack".into(), ..Default::default()
+        });
+        // Items without audio are left out
+        let root = server.browse(ROOT_ID).unwrap();
+        assert_eq!(root.iter().map(|e| (e.title.as_str(), e.container, e.child_count)).collect::<Vec<_>>(), [("Album & Friends", true, Some(5)), ("Photos", true, Some(0))]);
+        assert!(server.browse("empty").unwrap().is_empty());
+        assert_eq!(server.browse("gone").unwrap_err(), "No such object (UPnP error 701)");
+    }
+
+    #[test]
+    fn navigation_keeps_opened_containers() {
+        let url = server(200, Arc::default());
+        let mut dlna = Dlna::default();
+        let wait = |dlna: &mut Dlna| {
+            let started = Instant::now();
+            while dlna.is_searching() || dlna.is_loading() {
+                if let Some(Err(e)) = dlna.poll() { return Err(e); }
+                assert!(started.elapsed() < Duration::from_secs(10), "nothing arrived");
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            Ok(())
+        };
+        dlna.add(&format!(" {url}/desc.xml "));
+        wait(&mut dlna).unwrap();
+        assert_eq!((dlna.servers().len(), dlna.server(), dlna.title().as_str()), (1, Some(0), "Test Media"));
+        let album = dlna.entries()[0].clone();
+        dlna.open(&album);
+        wait(&mut dlna).unwrap();
+        assert_eq!(dlna.title(), "Test Media › Album & Friends");
+        assert_eq!(dlna.details(), Some(&album));
+        let (items, start) = dlna.items_from(2);
+        assert_eq!((items.len(), start, items[2].label().as_str()), (5, 2, "Band - Song 3"));
+        assert!(dlna.back() && !dlna.is_loading() && !dlna.can_go_back());
+        assert_eq!(dlna.entries().len(), 2);
+        // Adding the same server again refreshes it rather than listing it twice
+        dlna.add(&format!("{url}/desc.xml"));
+        wait(&mut dlna).unwrap();
+        assert_eq!(dlna.servers().len(), 1);
+        dlna.open(&DidlEntry { id: "gone".into(), ..Default::default() });
+        assert_eq!(wait(&mut dlna).unwrap_err(), "No such object (UPnP error 701)");
+    }
+}